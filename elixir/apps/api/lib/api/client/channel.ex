@@ -709,14 +709,19 @@ defmodule API.Client.Channel do
   #
   # `connected_gateway_ids` is used to indicate that the client is already connected to some of the gateways,
   # so the gateway can be reused by multiplexing the connection.
+  #
+  # `excluded_gateway_ids` is used by clients requesting a standby gateway for failover,
+  # none of these gateways will be selected and the standby will be in the same site as them.
   def handle_in(
         "create_flow",
         %{
           "resource_id" => resource_id,
           "connected_gateway_ids" => connected_gateway_ids
-        },
+        } = attrs,
         socket
       ) do
+    excluded_gateway_ids = Map.get(attrs, "excluded_gateway_ids", [])
+
     location = {
       socket.assigns.client.last_seen_remote_ip_location_lat,
       socket.assigns.client.last_seen_remote_ip_location_lon
@@ -729,7 +734,9 @@ defmodule API.Client.Channel do
              preload: :group
            ),
          {:ok, gateways} <-
-           filter_compatible_gateways(gateways, socket.assigns.gateway_version_requirement) do
+           filter_compatible_gateways(gateways, socket.assigns.gateway_version_requirement),
+         {:ok, gateways} when gateways != [] <-
+           {:ok, filter_standby_gateways(gateways, excluded_gateway_ids)} do
       gateway = Gateways.load_balance_gateways(location, gateways, connected_gateway_ids)
 
       # TODO: Optimization
@@ -1099,6 +1106,19 @@ defmodule API.Client.Channel do
     end
   end
 
+  defp filter_standby_gateways(gateways, []), do: gateways
+
+  defp filter_standby_gateways(gateways, excluded_gateway_ids) do
+    site_ids =
+      for gateway <- gateways, gateway.id in excluded_gateway_ids, into: MapSet.new() do
+        gateway.group_id
+      end
+
+    Enum.filter(gateways, fn gateway ->
+      gateway.group_id in site_ids and gateway.id not in excluded_gateway_ids
+    end)
+  end
+
   # DEPRECATED IN 1.4
   defp map_and_filter_compatible_resources(resources, client_version) do
     Enum.flat_map(resources, fn resource ->
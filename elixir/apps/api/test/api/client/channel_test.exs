@@ -1268,6 +1268,117 @@ defmodule API.Client.ChannelTest do
       assert String.length(preshared_key) == 44
     end
 
+    test "returns error when all online gateways are excluded", %{
+      dns_resource: resource,
+      dns_resource_policy: policy,
+      membership: membership,
+      gateway: gateway,
+      socket: socket
+    } do
+      :ok = Domain.Gateways.Presence.connect(gateway)
+
+      # Prime cache
+      send(socket.channel_pid, {:created, resource})
+      send(socket.channel_pid, {:created, policy})
+      send(socket.channel_pid, {:created, membership})
+
+      push(socket, "create_flow", %{
+        "resource_id" => resource.id,
+        "connected_gateway_ids" => [gateway.id],
+        "excluded_gateway_ids" => [gateway.id]
+      })
+
+      assert_push "flow_creation_failed", %{
+        reason: :offline,
+        resource_id: resource_id
+      }
+
+      assert resource_id == resource.id
+    end
+
+    test "returns standby gateway from the site of the excluded gateway", %{
+      account: account,
+      dns_resource: resource,
+      dns_resource_policy: policy,
+      membership: membership,
+      gateway_group: gateway_group,
+      gateway: gateway,
+      socket: socket
+    } do
+      standby_gateway = Fixtures.Gateways.create_gateway(account: account, group: gateway_group)
+
+      :ok = Domain.PubSub.Account.subscribe(account.id)
+      :ok = Domain.Gateways.Presence.connect(gateway)
+      :ok = Domain.Gateways.Presence.connect(standby_gateway)
+
+      # Prime cache
+      send(socket.channel_pid, {:created, resource})
+      send(socket.channel_pid, {:created, policy})
+      send(socket.channel_pid, {:created, membership})
+
+      push(socket, "create_flow", %{
+        "resource_id" => resource.id,
+        "connected_gateway_ids" => [gateway.id],
+        "excluded_gateway_ids" => [gateway.id]
+      })
+
+      standby_gateway_id = standby_gateway.id
+
+      assert_receive {{:authorize_flow, ^standby_gateway_id}, {_channel_pid, _socket_ref},
+                      _payload}
+    end
+
+    test "does not return standby gateway from another site of the resource", %{
+      account: account,
+      actor_group: actor_group,
+      membership: membership,
+      gateway_group: gateway_group,
+      gateway: gateway,
+      socket: socket
+    } do
+      other_gateway_group = Fixtures.Gateways.create_group(account: account)
+
+      other_gateway =
+        Fixtures.Gateways.create_gateway(account: account, group: other_gateway_group)
+
+      resource =
+        Fixtures.Resources.create_resource(
+          account: account,
+          connections: [
+            %{gateway_group_id: gateway_group.id},
+            %{gateway_group_id: other_gateway_group.id}
+          ]
+        )
+
+      policy =
+        Fixtures.Policies.create_policy(
+          account: account,
+          actor_group: actor_group,
+          resource: resource
+        )
+
+      :ok = Domain.Gateways.Presence.connect(gateway)
+      :ok = Domain.Gateways.Presence.connect(other_gateway)
+
+      # Prime cache
+      send(socket.channel_pid, {:created, resource})
+      send(socket.channel_pid, {:created, policy})
+      send(socket.channel_pid, {:created, membership})
+
+      push(socket, "create_flow", %{
+        "resource_id" => resource.id,
+        "connected_gateway_ids" => [gateway.id],
+        "excluded_gateway_ids" => [gateway.id]
+      })
+
+      assert_push "flow_creation_failed", %{
+        reason: :offline,
+        resource_id: resource_id
+      }
+
+      assert resource_id == resource.id
+    end
+
     test "returns online gateway connected to an internet resource", %{
       account: account,
       membership: membership,
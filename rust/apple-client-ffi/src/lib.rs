@@ -335,6 +335,9 @@ impl WrappedSession {
                     Event::ResourcesUpdated(resource_views) => {
                         callback_handler.on_update_resources(resource_views);
                     }
+                    Event::ResourceTrafficUpdated(_) => {
+                        // The macOS and iOS apps don't display traffic counters (yet).
+                    }
                     Event::Disconnected(error) => {
                         callback_handler.on_disconnect(error);
                     }
@@ -3,27 +3,38 @@ use axum::http::StatusCode;
 use axum::routing::get;
 use std::net::SocketAddr;
 
+use crate::prometheus_metrics::PrometheusMetrics;
+
 /// Runs an HTTP server that responds to `GET /healthz` with 200 OK or 400 BAD REQUEST, depending on the return value of `is_healthy`.
+///
+/// If given, the metrics are served at `GET /metrics`.
 pub async fn serve(
     addr: impl Into<SocketAddr>,
     is_healthy: impl Fn() -> bool + Clone + Send + Sync + 'static,
+    metrics: Option<PrometheusMetrics>,
 ) -> std::io::Result<()> {
     let addr = addr.into();
 
-    let service = Router::new()
-        .route(
-            "/healthz",
-            get(move || async move {
-                if is_healthy() {
-                    StatusCode::OK
-                } else {
-                    StatusCode::BAD_REQUEST
-                }
-            }),
-        )
-        .into_make_service();
+    let mut router = Router::new().route(
+        "/healthz",
+        get(move || async move {
+            if is_healthy() {
+                StatusCode::OK
+            } else {
+                StatusCode::BAD_REQUEST
+            }
+        }),
+    );
+
+    if let Some(metrics) = metrics {
+        router = router.merge(metrics.router());
+    }
 
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, service).await?;
+    axum::serve(
+        tokio::net::TcpListener::bind(addr).await?,
+        router.into_make_service(),
+    )
+    .await?;
 
     Ok(())
 }
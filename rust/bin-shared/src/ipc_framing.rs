@@ -0,0 +1,61 @@
+//! The framing of our JSON-based IPC protocols.
+//!
+//! Each message is serialised as JSON and prefixed with its length.
+
+use anyhow::{Context as _, Result};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{self, LengthDelimitedCodec},
+};
+
+pub struct Decoder<D> {
+    inner: LengthDelimitedCodec,
+    _decode_type: std::marker::PhantomData<D>,
+}
+
+pub struct Encoder<E> {
+    inner: LengthDelimitedCodec,
+    _encode_type: std::marker::PhantomData<E>,
+}
+
+impl<D> Default for Decoder<D> {
+    fn default() -> Self {
+        Self {
+            inner: LengthDelimitedCodec::new(),
+            _decode_type: Default::default(),
+        }
+    }
+}
+
+impl<E> Default for Encoder<E> {
+    fn default() -> Self {
+        Self {
+            inner: LengthDelimitedCodec::new(),
+            _encode_type: Default::default(),
+        }
+    }
+}
+
+impl<D: serde::de::DeserializeOwned> codec::Decoder for Decoder<D> {
+    type Error = anyhow::Error;
+    type Item = D;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<D>> {
+        let Some(msg) = self.inner.decode(buf)? else {
+            return Ok(None);
+        };
+        let msg = serde_json::from_slice(&msg)
+            .with_context(|| format!("Error while deserializing {}", std::any::type_name::<D>()))?;
+        Ok(Some(msg))
+    }
+}
+
+impl<E: serde::Serialize> codec::Encoder<&E> for Encoder<E> {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, msg: &E, buf: &mut BytesMut) -> Result<()> {
+        let msg = serde_json::to_string(msg)?;
+        self.inner.encode(msg.into(), buf)?;
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 #![cfg_attr(test, allow(clippy::unwrap_used))]
 
 pub mod http_health_check;
+pub mod ipc_framing;
+pub mod prometheus_metrics;
 
 mod dns_control;
 mod network_changes;
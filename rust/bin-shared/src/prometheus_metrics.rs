@@ -0,0 +1,149 @@
+use anyhow::{Context as _, Result};
+use axum::Router;
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use opentelemetry_prometheus::PrometheusExporter;
+use prometheus::{Encoder as _, Registry, TextEncoder};
+use std::net::SocketAddr;
+
+/// Serves our OpenTelemetry metrics at `GET /metrics` for Prometheus to scrape.
+///
+/// Only metrics recorded via a meter provider that has the accompanying [`PrometheusExporter`] registered as a reader are served.
+/// The [`PrometheusExporter`] is meant to be registered in addition to any other exporter, i.e. OTLP.
+#[derive(Clone)]
+pub struct PrometheusMetrics {
+    registry: Registry,
+}
+
+impl PrometheusMetrics {
+    /// Creates a new [`PrometheusMetrics`] and the reader that must be registered with the meter provider.
+    pub fn new() -> Result<(Self, PrometheusExporter)> {
+        let registry = Registry::new();
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()
+            .context("Failed to build Prometheus exporter")?;
+
+        Ok((Self { registry }, exporter))
+    }
+
+    /// A [`Router`] serving `GET /metrics`, to be merged into an existing HTTP server.
+    pub fn router(self) -> Router {
+        Router::new().route("/metrics", get(move || async move { self.render() }))
+    }
+
+    /// Runs a standalone HTTP server on the given listener that only serves `GET /metrics`.
+    pub async fn serve(self, listener: tokio::net::TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router().into_make_service()).await?;
+
+        Ok(())
+    }
+
+    fn render(&self) -> impl IntoResponse {
+        let encoder = TextEncoder::new();
+
+        match encoder.encode_to_string(&self.registry.gather()) {
+            Ok(body) => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+                body,
+            )
+                .into_response(),
+            Err(e) => {
+                tracing::warn!("Failed to encode metrics: {e}");
+
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PrometheusMetricsArgs {
+    /// Serve metrics at `http://<prometheus_metrics_addr>/metrics` for Prometheus to scrape.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(
+        long,
+        env = "FIREZONE_PROMETHEUS_METRICS",
+        hide = true,
+        default_value_t = false
+    )]
+    pub prometheus_metrics: bool,
+
+    /// The address of the local interface where we should serve our metrics.
+    ///
+    /// Metrics may contain per-client series, hence they are only served on loopback by default.
+    #[arg(
+        long,
+        env = "FIREZONE_PROMETHEUS_METRICS_ADDR",
+        hide = true,
+        default_value = "127.0.0.1:9464"
+    )]
+    pub prometheus_metrics_addr: SocketAddr,
+}
+
+impl PrometheusMetricsArgs {
+    /// Starts serving our metrics if enabled and returns the reader that must be registered with the meter provider.
+    pub async fn spawn_server(&self) -> Result<Option<PrometheusExporter>> {
+        if !self.prometheus_metrics {
+            return Ok(None);
+        }
+
+        let addr = self.prometheus_metrics_addr;
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind metrics server to {addr}"))?;
+
+        let (metrics, exporter) = PrometheusMetrics::new()?;
+        tokio::spawn(async move {
+            if let Err(e) = metrics.serve(listener).await {
+                tracing::warn!("Metrics server failed: {e}");
+            }
+        });
+
+        Ok(Some(exporter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use std::future::IntoFuture as _;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    #[tokio::test]
+    async fn scrape_returns_recorded_metrics() {
+        let (metrics, exporter) = PrometheusMetrics::new().unwrap();
+        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+        provider
+            .meter("test")
+            .u64_counter("relayed_packets")
+            .build()
+            .add(3, &[]);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, metrics.router().into_make_service()).into_future());
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(
+            response
+                .lines()
+                .any(|l| l.starts_with("relayed_packets_total") && l.ends_with(" 3")),
+            "{response}"
+        );
+    }
+}
@@ -163,40 +163,51 @@ impl Session {
     }
 
     pub async fn next_event(&self) -> Result<Option<Event>, Error> {
-        match self.events.lock().await.next().await {
-            Some(client_shared::Event::TunInterfaceUpdated {
-                ipv4,
-                ipv6,
-                dns,
-                search_domain,
-                ipv4_routes,
-                ipv6_routes,
-            }) => {
-                let dns = serde_json::to_string(&dns).context("Failed to serialize DNS servers")?;
-                let ipv4_routes = serde_json::to_string(&V4RouteList::new(ipv4_routes))
-                    .context("Failed to serialize IPv4 routes")?;
-                let ipv6_routes = serde_json::to_string(&V6RouteList::new(ipv6_routes))
-                    .context("Failed to serialize IPv6 routes")?;
-
-                Ok(Some(Event::TunInterfaceUpdated {
-                    ipv4: ipv4.to_string(),
-                    ipv6: ipv6.to_string(),
+        let mut events = self.events.lock().await;
+
+        loop {
+            let event = match events.next().await {
+                Some(client_shared::Event::TunInterfaceUpdated {
+                    ipv4,
+                    ipv6,
                     dns,
-                    search_domain: search_domain.map(|d| d.to_string()),
+                    search_domain,
                     ipv4_routes,
                     ipv6_routes,
-                }))
-            }
-            Some(client_shared::Event::ResourcesUpdated(resources)) => {
-                let resources = serde_json::to_string(&resources)
-                    .context("Failed to serialize resource list")?;
-
-                Ok(Some(Event::ResourcesUpdated { resources }))
-            }
-            Some(client_shared::Event::Disconnected(error)) => Ok(Some(Event::Disconnected {
-                error: Arc::new(DisconnectError(error)),
-            })),
-            None => Ok(None),
+                }) => {
+                    let dns =
+                        serde_json::to_string(&dns).context("Failed to serialize DNS servers")?;
+                    let ipv4_routes = serde_json::to_string(&V4RouteList::new(ipv4_routes))
+                        .context("Failed to serialize IPv4 routes")?;
+                    let ipv6_routes = serde_json::to_string(&V6RouteList::new(ipv6_routes))
+                        .context("Failed to serialize IPv6 routes")?;
+
+                    Ok(Some(Event::TunInterfaceUpdated {
+                        ipv4: ipv4.to_string(),
+                        ipv6: ipv6.to_string(),
+                        dns,
+                        search_domain: search_domain.map(|d| d.to_string()),
+                        ipv4_routes,
+                        ipv6_routes,
+                    }))
+                }
+                Some(client_shared::Event::ResourcesUpdated(resources)) => {
+                    let resources = serde_json::to_string(&resources)
+                        .context("Failed to serialize resource list")?;
+
+                    Ok(Some(Event::ResourcesUpdated { resources }))
+                }
+                Some(client_shared::Event::ResourceTrafficUpdated(_)) => {
+                    // The mobile apps don't display traffic counters (yet).
+                    continue;
+                }
+                Some(client_shared::Event::Disconnected(error)) => Ok(Some(Event::Disconnected {
+                    error: Arc::new(DisconnectError(error)),
+                })),
+                None => Ok(None),
+            };
+
+            return event;
         }
     }
 }
@@ -1,27 +1,32 @@
 use crate::PHOENIX_TOPIC;
 use anyhow::{Context as _, Result};
-use connlib_model::{PublicKey, ResourceId, ResourceView};
+use connlib_model::{PublicKey, ResourceId, ResourceTraffic, ResourceView};
 use dns_types::DomainName;
 use firezone_tunnel::messages::RelaysPresence;
 use firezone_tunnel::messages::client::{
-    EgressMessages, FailReason, FlowCreated, FlowCreationFailed, GatewayIceCandidates,
-    GatewaysIceCandidates, IngressMessages, InitClient,
+    EgressMessages, FlowCreated, FlowCreationFailed, GatewayIceCandidates, GatewaysIceCandidates,
+    IngressMessages, InitClient,
 };
-use firezone_tunnel::{ClientTunnel, IpConfig};
+use firezone_tunnel::{ClientTunnel, Diagnosis, IpConfig};
 use ip_network::{Ipv4Network, Ipv6Network};
 use phoenix_channel::{ErrorReply, OutboundRequestId, PhoenixChannel, PublicKeyParam};
 use std::mem;
 use std::net::{Ipv4Addr, Ipv6Addr};
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     io,
     net::IpAddr,
     task::{Context, Poll},
 };
 use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::MissedTickBehavior;
 use tun::Tun;
 
+/// How often we log statistics about our connections to Gateways.
+const LOG_CONNECTION_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct Eventloop {
     tunnel: ClientTunnel,
 
@@ -29,6 +34,8 @@ pub struct Eventloop {
     cmd_rx: tokio::sync::mpsc::UnboundedReceiver<Command>,
     event_tx: tokio::sync::mpsc::Sender<Event>,
 
+    log_connection_stats_interval: tokio::time::Interval,
+
     logged_permission_denied: bool,
 }
 
@@ -39,6 +46,16 @@ pub enum Command {
     SetDns(Vec<IpAddr>),
     SetTun(Box<dyn Tun>),
     SetDisabledResources(BTreeSet<ResourceId>),
+    SetStandbyGatewaysEnabled(bool),
+    StartCapture {
+        path: PathBuf,
+        reply: tokio::sync::oneshot::Sender<Result<()>>,
+    },
+    StopCapture,
+    Diagnose {
+        host: String,
+        reply: tokio::sync::oneshot::Sender<Diagnosis>,
+    },
 }
 
 pub enum Event {
@@ -51,6 +68,10 @@ pub enum Event {
         ipv6_routes: Vec<Ipv6Network>,
     },
     ResourcesUpdated(Vec<ResourceView>),
+    /// How much traffic we exchanged with each resource.
+    ///
+    /// Unlike [`Event::ResourcesUpdated`], this doesn't indicate any change to the resources themselves.
+    ResourceTrafficUpdated(BTreeMap<ResourceId, ResourceTraffic>),
     Disconnected(DisconnectError),
 }
 
@@ -84,11 +105,16 @@ impl Eventloop {
     ) -> Self {
         portal.connect(PublicKeyParam(tunnel.public_key().to_bytes()));
 
+        let mut log_connection_stats_interval =
+            tokio::time::interval(LOG_CONNECTION_STATS_INTERVAL);
+        log_connection_stats_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         Self {
             tunnel,
             portal,
             cmd_rx,
             event_tx,
+            log_connection_stats_interval,
             logged_permission_denied: false,
         }
     }
@@ -108,10 +134,34 @@ impl Eventloop {
                     self.tunnel.state_mut().set_disabled_resources(resources);
                     continue;
                 }
+                Poll::Ready(Some(Command::SetStandbyGatewaysEnabled(enabled))) => {
+                    self.tunnel
+                        .state_mut()
+                        .set_standby_gateways_enabled(enabled);
+                    continue;
+                }
                 Poll::Ready(Some(Command::SetTun(tun))) => {
                     self.tunnel.set_tun(tun);
                     continue;
                 }
+                Poll::Ready(Some(Command::StartCapture { path, reply })) => {
+                    let result = self.start_capture(&path);
+
+                    if let Err(e) = &result {
+                        tracing::warn!(path = %path.display(), "Failed to start packet capture: {e:#}");
+                    }
+
+                    let _ = reply.send(result);
+                    continue;
+                }
+                Poll::Ready(Some(Command::StopCapture)) => {
+                    self.tunnel.stop_capture();
+                    continue;
+                }
+                Poll::Ready(Some(Command::Diagnose { host, reply })) => {
+                    let _ = reply.send(self.tunnel.state_mut().diagnose(&host));
+                    continue;
+                }
                 Poll::Ready(Some(Command::Reset(reason))) => {
                     self.tunnel.reset(&reason);
                     self.portal
@@ -194,10 +244,26 @@ impl Eventloop {
                 Poll::Pending => {}
             }
 
+            if self.log_connection_stats_interval.poll_tick(cx).is_ready() {
+                for (gid, stats) in self.tunnel.state_mut().connection_stats() {
+                    tracing::debug!(%gid, ?stats, "Connection statistics");
+                }
+
+                continue;
+            }
+
             return Poll::Pending;
         }
     }
 
+    fn start_capture(&mut self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create_new(path).context("Failed to create capture file")?;
+
+        self.tunnel.start_capture(Box::new(file))?;
+
+        Ok(())
+    }
+
     fn handle_tunnel_event(&mut self, event: firezone_tunnel::ClientEvent) -> Option<Event> {
         match event {
             firezone_tunnel::ClientEvent::AddedIceCandidates {
@@ -234,6 +300,7 @@ impl Eventloop {
             }
             firezone_tunnel::ClientEvent::ConnectionIntent {
                 connected_gateway_ids,
+                excluded_gateway_ids,
                 resource,
             } => {
                 self.portal.send(
@@ -241,6 +308,7 @@ impl Eventloop {
                     EgressMessages::CreateFlow {
                         resource_id: resource,
                         connected_gateway_ids,
+                        excluded_gateway_ids,
                     },
                 );
 
@@ -249,6 +317,24 @@ impl Eventloop {
             firezone_tunnel::ClientEvent::ResourcesChanged { resources } => {
                 Some(Event::ResourcesUpdated(resources))
             }
+            firezone_tunnel::ClientEvent::ResourceTrafficUpdated { traffic } => {
+                Some(Event::ResourceTrafficUpdated(traffic))
+            }
+            firezone_tunnel::ClientEvent::ConnectionPathChanged {
+                conn_id: gateway_id,
+                old,
+                new,
+            } => {
+                tracing::info!(
+                    %gateway_id,
+                    old = old.map(tracing::field::display),
+                    %new,
+                    "Connection path changed"
+                );
+
+                // The UI learns about the path through the resources routed via this Gateway.
+                None
+            }
             firezone_tunnel::ClientEvent::TunInterfaceUpdated(config) => {
                 Some(Event::TunInterfaceUpdated {
                     ipv4: config.ip.v4,
@@ -387,13 +473,14 @@ impl Eventloop {
             }
             IngressMessages::FlowCreationFailed(FlowCreationFailed {
                 resource_id,
-                reason: FailReason::Offline,
-                ..
+                reason,
+                violated_properties,
             }) => {
-                self.tunnel.state_mut().set_resource_offline(resource_id);
-            }
-            IngressMessages::FlowCreationFailed(FlowCreationFailed { reason, .. }) => {
-                tracing::debug!("Failed to create flow: {reason:?}")
+                self.tunnel.state_mut().on_flow_creation_failed(
+                    resource_id,
+                    reason,
+                    violated_properties,
+                );
             }
         }
     }
@@ -3,6 +3,7 @@ pub use crate::serde_routelist::{V4RouteList, V6RouteList};
 pub use connlib_model::StaticSecret;
 pub use eventloop::{DisconnectError, Event};
 pub use firezone_tunnel::messages::client::{IngressMessages, ResourceDescription};
+pub use firezone_tunnel::{ConnectionState, Diagnosis, FlowFailure, Problem, ResourceDiagnosis};
 
 use anyhow::{Context as _, Result};
 use connlib_model::ResourceId;
@@ -12,6 +13,7 @@ use phoenix_channel::{PhoenixChannel, PublicKeyParam};
 use socket_factory::{SocketFactory, TcpSocket, UdpSocket};
 use std::collections::BTreeSet;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
@@ -102,6 +104,45 @@ impl Session {
             .send(Command::SetDisabledResources(disabled_resources));
     }
 
+    /// Keep a hot standby connection to a second gateway of each site we are connected to.
+    ///
+    /// Resources are switched over to the standby as soon as the connection to their gateway is disconnected.
+    pub fn set_standby_gateways_enabled(&self, enabled: bool) {
+        let _ = self
+            .channel
+            .send(Command::SetStandbyGatewaysEnabled(enabled));
+    }
+
+    /// Starts writing all packets passing through the tunnel to a pcapng file at `path`.
+    ///
+    /// Each packet is annotated with the Resource and Gateway it belongs to.
+    /// Replaces any previously running capture.
+    /// Fails if a file already exists at `path`.
+    pub async fn start_capture(&self, path: PathBuf) -> Result<()> {
+        let (reply, rx) = tokio::sync::oneshot::channel();
+
+        self.channel
+            .send(Command::StartCapture { path, reply })
+            .context("Session has stopped")?;
+
+        rx.await.context("Session has stopped")?
+    }
+
+    pub fn stop_capture(&self) {
+        let _ = self.channel.send(Command::StopCapture);
+    }
+
+    /// Explains whether and how traffic to `host` (a hostname or IP) is routed through the tunnel.
+    pub async fn diagnose(&self, host: String) -> Result<Diagnosis> {
+        let (reply, rx) = tokio::sync::oneshot::channel();
+
+        self.channel
+            .send(Command::Diagnose { host, reply })
+            .context("Session has stopped")?;
+
+        rx.await.context("Session has stopped")
+    }
+
     /// Sets a new [`Tun`] device handle.
     pub fn set_tun(&self, new_tun: Box<dyn Tun>) {
         let _ = self.channel.send(Command::SetTun(new_tun));
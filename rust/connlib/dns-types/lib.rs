@@ -2,11 +2,11 @@
 
 use domain::{
     base::{
-        HeaderCounts, Message, MessageBuilder, ParsedName, Question, RecordSection,
+        HeaderCounts, Message, MessageBuilder, ParsedName, Question, RecordSection, Ttl,
         message_builder::AnswerBuilder, name::FlattenInto,
     },
     dep::octseq::OctetsInto,
-    rdata::AllRecordData,
+    rdata::{AllRecordData, Soa},
 };
 
 pub mod prelude {
@@ -105,6 +105,16 @@ impl Query {
         self.question().qtype()
     }
 
+    /// Whether the query has the DNSSEC OK bit set, i.e. asks for DNSSEC records in the response.
+    pub fn dnssec_ok(&self) -> bool {
+        self.inner.opt().is_some_and(|opt| opt.dnssec_ok())
+    }
+
+    /// Whether the query has the Checking Disabled bit set, i.e. accepts responses that failed DNSSEC validation.
+    pub fn checking_disabled(&self) -> bool {
+        self.inner.header().cd()
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
         self.inner.into_octets()
     }
@@ -134,6 +144,7 @@ impl TryFrom<&[u8]> for Response {
     }
 }
 
+#[derive(Clone)]
 pub struct Response {
     inner: Message<Vec<u8>>,
 }
@@ -210,6 +221,133 @@ impl Response {
         })
     }
 
+    /// The smallest TTL of all records in the answer section.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.records().map(|r| r.ttl().as_secs()).min()
+    }
+
+    /// The TTL for caching this response if it is negative, i.e. NXDOMAIN or NODATA.
+    ///
+    /// This is the minimum of the SOA record's TTL and its MINIMUM field, see <https://www.rfc-editor.org/rfc/rfc2308#section-5>.
+    /// Negative responses without an SOA record must not be cached.
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.inner
+            .authority()
+            .ok()?
+            .limit_to::<Soa<ParsedName<&[u8]>>>()
+            .filter_map(Result::ok)
+            .map(|soa| soa.ttl().as_secs().min(soa.data().minimum().as_secs()))
+            .next()
+    }
+
+    /// Re-creates this response as an answer to the given query, with all TTLs reduced by `elapsed` seconds.
+    ///
+    /// Apart from the ID and the TTLs, the response is preserved as is, including its flags and all sections.
+    /// Useful for answering queries from a cache.
+    pub fn aged(&self, query: &Query, elapsed: u32) -> Response {
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&query.inner, self.response_code())
+            .expect("Vec-backed message builder never fails");
+
+        let header = self.inner.header();
+        answer.header_mut().set_aa(header.aa());
+        answer.header_mut().set_ra(header.ra());
+        answer.header_mut().set_ad(header.ad());
+        answer.header_mut().set_cd(header.cd());
+
+        for record in self.records() {
+            answer
+                .push(aged_record(record, elapsed))
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut authority = answer.authority();
+
+        for record in self.authority_records() {
+            authority
+                .push(aged_record(record, elapsed))
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut additional = authority.additional();
+
+        for record in self.additional_records() {
+            // The TTL of an OPT record carries the extended RCODE and flags, see <https://www.rfc-editor.org/rfc/rfc6891#section-6.1.3>.
+            if record.rtype() == RecordType::OPT {
+                // Responders must not add an OPT record unless the query had one.
+                if query.inner.opt().is_some() {
+                    additional
+                        .push(record)
+                        .expect("Vec-backed message builder never fails");
+                }
+
+                continue;
+            }
+
+            additional
+                .push(aged_record(record, elapsed))
+                .expect("Vec-backed message builder never fails");
+        }
+
+        Response {
+            inner: additional.into_message(),
+        }
+    }
+
+    /// Records in the additional section of this response.
+    ///
+    /// Records that fail to parse are skipped.
+    pub fn additional_records(&self) -> impl Iterator<Item = Record<'_>> {
+        self.inner
+            .additional()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.ok()?.into_any_record::<AllRecordData<_, _>>().ok())
+    }
+
+    /// Re-creates this response with the given records appended to the additional section.
+    pub fn with_additional_records(
+        self,
+        records: impl IntoIterator<Item: Into<OwnedRecord>>,
+    ) -> Response {
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&self.inner, self.response_code())
+            .expect("Vec-backed message builder never fails");
+        answer.header_mut().set_tc(self.truncated());
+
+        for record in self.records() {
+            answer
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut authority = answer.authority();
+
+        for record in self.authority_records() {
+            authority
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut additional = authority.additional();
+
+        for record in self.additional_records() {
+            additional
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        for record in records {
+            additional
+                .push(record.into())
+                .expect("Vec-backed message builder never fails");
+        }
+
+        Response {
+            inner: additional.into_message(),
+        }
+    }
+
     /// Serializes this response into a byte slice.
     ///
     /// The `max_len` parameter specifies the maximum size of the payload.
@@ -246,11 +384,30 @@ impl Response {
         self.inner.sole_question().expect("verified in ctor")
     }
 
+    /// Records in the authority section of this response.
+    ///
+    /// Records that fail to parse are skipped.
+    fn authority_records(&self) -> impl Iterator<Item = Record<'_>> {
+        self.inner
+            .authority()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.ok()?.into_any_record::<AllRecordData<_, _>>().ok())
+    }
+
     fn answer(&self) -> RecordSection<'_, Vec<u8>> {
         self.inner.answer().expect("verified in ctor")
     }
 }
 
+fn aged_record(mut record: Record<'_>, elapsed: u32) -> Record<'_> {
+    record.set_ttl(Ttl::from_secs(
+        record.ttl().as_secs().saturating_sub(elapsed),
+    ));
+
+    record
+}
+
 pub struct ResponseBuilder {
     inner: AnswerBuilder<Vec<u8>>,
 }
@@ -296,7 +453,7 @@ pub enum Error {
 pub mod records {
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    use domain::rdata::{A, Aaaa, Ptr, Srv, Txt, rfc1035::TxtError};
+    use domain::rdata::{A, Aaaa, Cname, Ptr, Srv, Txt, rfc1035::TxtError};
 
     use super::*;
 
@@ -326,6 +483,10 @@ pub mod records {
     pub fn srv(priority: u16, weight: u16, port: u16, target: DomainName) -> OwnedRecordData {
         OwnedRecordData::Srv(Srv::new(priority, weight, port, target))
     }
+
+    pub fn cname(target: DomainName) -> OwnedRecordData {
+        OwnedRecordData::Cname(Cname::new(target))
+    }
 }
 
 #[cfg(test)]
@@ -354,4 +515,171 @@ mod tests {
         assert_eq!(parsed_response.records().count(), 0);
         assert_eq!(parsed_response.domain(), domain);
     }
+
+    #[test]
+    fn aged_response_has_reduced_ttl_and_new_id() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A).with_id(1);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([
+                (domain.clone(), 300, records::a(Ipv4Addr::LOCALHOST)),
+                (domain, 60, records::a(Ipv4Addr::BROADCAST)),
+            ])
+            .build();
+
+        let aged = response.aged(&query.with_id(2), 100);
+
+        assert_eq!(response.min_ttl(), Some(60));
+        assert_eq!(aged.id(), 2);
+        assert_eq!(
+            aged.records()
+                .map(|r| r.ttl().as_secs())
+                .collect::<Vec<_>>(),
+            vec![200, 0]
+        );
+    }
+
+    #[test]
+    fn negative_ttl_is_capped_by_soa_minimum() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+        let query = Query::new(domain.clone(), RecordType::A);
+
+        let mut authority = MessageBuilder::new_vec()
+            .start_answer(&query.inner, ResponseCode::NXDOMAIN)
+            .unwrap()
+            .authority();
+        let soa = Soa::new(
+            domain.clone(),
+            domain.clone(),
+            domain::base::Serial(1),
+            Ttl::from_secs(3600),
+            Ttl::from_secs(600),
+            Ttl::from_secs(86400),
+            Ttl::from_secs(300),
+        );
+        authority
+            .push(domain::base::Record::from((domain, 3600, soa)))
+            .unwrap();
+
+        let response = Response {
+            inner: authority.into_message(),
+        };
+
+        assert_eq!(response.min_ttl(), None);
+        assert_eq!(response.negative_ttl(), Some(300));
+    }
+
+    #[test]
+    fn aged_response_preserves_flags_and_additional_section() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+        let query = query_with_edns(domain.clone());
+
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&query.inner, ResponseCode::NOERROR)
+            .unwrap();
+        answer.header_mut().set_ra(true);
+        answer
+            .push((domain.clone(), 300, records::a(Ipv4Addr::LOCALHOST)))
+            .unwrap();
+        let mut additional = answer.authority().additional();
+        additional
+            .push((domain, 300, records::a(Ipv4Addr::BROADCAST)))
+            .unwrap();
+        additional
+            .opt(|opt| {
+                opt.set_dnssec_ok(true);
+                Ok(())
+            })
+            .unwrap();
+        let response = Response {
+            inner: additional.into_message(),
+        };
+
+        let aged = response.aged(&query, 100);
+
+        assert!(aged.inner.header().ra());
+        assert!(aged.inner.opt().unwrap().dnssec_ok());
+        assert_eq!(
+            aged.additional_records()
+                .filter(|r| r.rtype() == RecordType::A)
+                .map(|r| r.ttl().as_secs())
+                .collect::<Vec<_>>(),
+            vec![200]
+        );
+
+        let aged = response.aged(
+            &Query::new(
+                DomainName::vec_from_str("example.com").unwrap(),
+                RecordType::A,
+            ),
+            100,
+        );
+
+        assert!(aged.inner.opt().is_none());
+    }
+
+    #[test]
+    fn reads_dnssec_flags_of_query() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A);
+        assert!(!query.dnssec_ok());
+        assert!(!query.checking_disabled());
+
+        let mut bytes = query_with_edns(domain).into_bytes();
+        bytes[3] |= 0b0001_0000; // Set the CD bit.
+        let query = Query::parse(&bytes).unwrap();
+
+        assert!(query.dnssec_ok());
+        assert!(query.checking_disabled());
+    }
+
+    #[test]
+    fn negative_response_without_soa_has_no_ttl() {
+        let query = Query::new(
+            DomainName::vec_from_str("example.com").unwrap(),
+            RecordType::A,
+        );
+
+        assert_eq!(Response::nxdomain(&query).negative_ttl(), None);
+    }
+
+    #[test]
+    fn can_add_additional_records() {
+        let domain = DomainName::vec_from_str("_ldap._tcp.example.com").unwrap();
+        let target = DomainName::vec_from_str("dc.example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::SRV).with_id(42);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(domain, 300, records::srv(0, 0, 389, target.clone()))])
+            .build()
+            .with_additional_records([(target.clone(), 1, records::a(Ipv4Addr::LOCALHOST))]);
+
+        let parsed_response = Response::parse(&response.into_bytes(u16::MAX)).unwrap();
+        let additional = parsed_response.additional_records().collect::<Vec<_>>();
+
+        assert_eq!(parsed_response.id(), 42);
+        assert_eq!(parsed_response.records().count(), 1);
+        assert_eq!(additional.len(), 1);
+        assert_eq!(additional[0].owner().to_string(), target.to_string());
+    }
+
+    fn query_with_edns(domain: DomainName) -> Query {
+        let mut question = MessageBuilder::new_vec().question();
+        question.header_mut().set_rd(true);
+        question.push((domain, RecordType::A)).unwrap();
+
+        let mut additional = question.additional();
+        additional
+            .opt(|opt| {
+                opt.set_dnssec_ok(true);
+                Ok(())
+            })
+            .unwrap();
+
+        Query {
+            inner: additional.into_message(),
+        }
+    }
 }
@@ -2,7 +2,9 @@
 
 use crate::{IpPacket, IpPacketBuf, MAX_IP_SIZE};
 use anyhow::{Context as _, Result, bail};
-use etherparse::{Icmpv6Header, Ipv6Header, PacketBuilder, icmpv4, icmpv6};
+use etherparse::{
+    Icmpv6Header, Ipv6Header, PacketBuilder, PacketBuilderStep, TcpHeader, icmpv4, icmpv6,
+};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 /// Helper macro to turn a [`PacketBuilder`] into an [`IpPacket`].
@@ -115,26 +117,20 @@ pub fn tcp_packet<IP>(
 where
     IP: Into<IpAddr>,
 {
-    let TcpFlags { rst } = flags;
-
     match (saddr.into(), daddr.into()) {
         (IpAddr::V4(src), IpAddr::V4(dst)) => {
-            let mut packet =
-                PacketBuilder::ipv4(src.octets(), dst.octets(), 64).tcp(sport, dport, 0, 128);
-
-            if rst {
-                packet = packet.rst();
-            }
+            let packet = with_tcp_flags(
+                PacketBuilder::ipv4(src.octets(), dst.octets(), 64).tcp(sport, dport, 0, 128),
+                flags,
+            );
 
             build!(packet, payload)
         }
         (IpAddr::V6(src), IpAddr::V6(dst)) => {
-            let mut packet =
-                PacketBuilder::ipv6(src.octets(), dst.octets(), 64).tcp(sport, dport, 0, 128);
-
-            if rst {
-                packet = packet.rst();
-            }
+            let packet = with_tcp_flags(
+                PacketBuilder::ipv6(src.octets(), dst.octets(), 64).tcp(sport, dport, 0, 128),
+                flags,
+            );
 
             build!(packet, payload)
         }
@@ -142,8 +138,36 @@ where
     }
 }
 
+fn with_tcp_flags(
+    mut packet: PacketBuilderStep<TcpHeader>,
+    flags: TcpFlags,
+) -> PacketBuilderStep<TcpHeader> {
+    let TcpFlags { syn, ack, fin, rst } = flags;
+
+    if syn {
+        packet = packet.syn();
+    }
+
+    if ack {
+        packet = packet.ack(0);
+    }
+
+    if fin {
+        packet = packet.fin();
+    }
+
+    if rst {
+        packet = packet.rst();
+    }
+
+    packet
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct TcpFlags {
+    pub syn: bool,
+    pub ack: bool,
+    pub fin: bool,
     pub rst: bool,
 }
 
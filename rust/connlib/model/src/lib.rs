@@ -10,7 +10,8 @@ mod view;
 pub use boringtun::x25519::PublicKey;
 pub use boringtun::x25519::StaticSecret;
 pub use view::{
-    CidrResourceView, DnsResourceView, InternetResourceView, ResourceStatus, ResourceView,
+    CidrResourceView, ConnectionPath, DnsResourceView, InternetResourceView, ResourceStatus,
+    ResourceTraffic, ResourceView,
 };
 
 use serde::{Deserialize, Serialize};
@@ -24,6 +24,70 @@ impl fmt::Display for ResourceStatus {
     }
 }
 
+/// How much traffic the Client exchanged with a resource.
+///
+/// Counts IP packets as they enter and leave the tunnel, i.e. before encryption.
+#[derive(
+    Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct ResourceTraffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+}
+
+impl ResourceTraffic {
+    /// Whether any packets have been exchanged with the resource.
+    pub fn is_active(&self) -> bool {
+        self.packets_sent > 0 || self.packets_received > 0
+    }
+}
+
+impl fmt::Display for ResourceTraffic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} sent, ", HumanBytes(self.bytes_sent))?;
+        write!(f, "{} received", HumanBytes(self.bytes_received))
+    }
+}
+
+struct HumanBytes(u64);
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = self.0 as f64;
+
+        for unit in ["B", "kB", "MB", "GB"] {
+            if bytes < 1000.0 {
+                return write!(f, "{bytes:.2} {unit}");
+            }
+
+            bytes /= 1000.0;
+        }
+
+        write!(f, "{bytes:.2} TB")
+    }
+}
+
+/// How packets to a Gateway travel.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionPath {
+    /// Packets are sent directly between the Client and the Gateway.
+    Direct,
+    /// Packets pass through a relay.
+    Relayed,
+}
+
+impl fmt::Display for ConnectionPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionPath::Direct => write!(f, "direct"),
+            ConnectionPath::Relayed => write!(f, "relayed"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ResourceView {
@@ -57,6 +121,42 @@ impl ResourceView {
         }
     }
 
+    pub fn traffic(&self) -> ResourceTraffic {
+        match self {
+            ResourceView::Dns(r) => r.traffic,
+            ResourceView::Cidr(r) => r.traffic,
+            ResourceView::Internet(r) => r.traffic,
+        }
+    }
+
+    pub fn with_traffic(mut self, traffic: ResourceTraffic) -> Self {
+        match &mut self {
+            ResourceView::Dns(r) => r.traffic = traffic,
+            ResourceView::Cidr(r) => r.traffic = traffic,
+            ResourceView::Internet(r) => r.traffic = traffic,
+        }
+
+        self
+    }
+
+    pub fn path(&self) -> Option<ConnectionPath> {
+        match self {
+            ResourceView::Dns(r) => r.path,
+            ResourceView::Cidr(r) => r.path,
+            ResourceView::Internet(r) => r.path,
+        }
+    }
+
+    pub fn with_path(mut self, path: Option<ConnectionPath>) -> Self {
+        match &mut self {
+            ResourceView::Dns(r) => r.path = path,
+            ResourceView::Cidr(r) => r.path = path,
+            ResourceView::Internet(r) => r.path = path,
+        }
+
+        self
+    }
+
     pub fn id(&self) -> ResourceId {
         match self {
             ResourceView::Dns(r) => r.id,
@@ -102,6 +202,13 @@ pub struct DnsResourceView {
     pub sites: Vec<Site>,
 
     pub status: ResourceStatus,
+
+    #[serde(default)]
+    pub traffic: ResourceTraffic,
+
+    /// How packets to the Gateway of this resource travel, [`None`] while we are not connected to one.
+    #[serde(default)]
+    pub path: Option<ConnectionPath>,
 }
 
 /// Description of a resource that maps to a CIDR.
@@ -120,6 +227,13 @@ pub struct CidrResourceView {
     pub sites: Vec<Site>,
 
     pub status: ResourceStatus,
+
+    #[serde(default)]
+    pub traffic: ResourceTraffic,
+
+    /// How packets to the Gateway of this resource travel, [`None`] while we are not connected to one.
+    #[serde(default)]
+    pub path: Option<ConnectionPath>,
 }
 
 /// Description of an Internet resource
@@ -132,6 +246,13 @@ pub struct InternetResourceView {
     pub sites: Vec<Site>,
 
     pub status: ResourceStatus,
+
+    #[serde(default)]
+    pub traffic: ResourceTraffic,
+
+    /// How packets to the Gateway of this resource travel, [`None`] while we are not connected to one.
+    #[serde(default)]
+    pub path: Option<ConnectionPath>,
 }
 
 impl PartialOrd for ResourceView {
@@ -161,7 +282,8 @@ mod tests {
     use itertools::Itertools;
 
     use super::{
-        DnsResourceView, InternetResourceView, ResourceId, ResourceStatus, ResourceView, Site,
+        DnsResourceView, InternetResourceView, ResourceId, ResourceStatus, ResourceTraffic,
+        ResourceView, Site,
     };
 
     fn fake_resource(name: &str, uuid: &str) -> ResourceView {
@@ -175,6 +297,8 @@ mod tests {
                 id: "99ba0c1e-5189-4cfc-a4db-fd6cb1c937fd".parse().unwrap(),
             }],
             status: ResourceStatus::Online,
+            traffic: Default::default(),
+            path: None,
         })
     }
 
@@ -187,9 +311,23 @@ mod tests {
                 id: "99ba0c1e-5189-4cfc-a4db-fd6cb1c937fd".parse().unwrap(),
             }],
             status: ResourceStatus::Offline,
+            traffic: Default::default(),
+            path: None,
         })
     }
 
+    #[test]
+    fn display_traffic() {
+        let traffic = ResourceTraffic {
+            bytes_sent: 1_000,
+            bytes_received: 12_500_000,
+            packets_sent: 10,
+            packets_received: 20,
+        };
+
+        assert_eq!(traffic.to_string(), "1.00 kB sent, 12.50 MB received");
+    }
+
     #[test]
     fn sort_resources_normal() {
         let cloudflare = fake_resource("Cloudflare DNS", "2efe9c25-bd92-49a0-99d7-8b92da014dd5");
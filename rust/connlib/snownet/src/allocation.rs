@@ -1,7 +1,9 @@
 use crate::{
     backoff::{self, ExponentialBackoff},
     channel_data,
+    nat_behaviour::{NatBehaviour, NatDiscovery},
     node::{SessionId, Transmit},
+    transport::Transport,
 };
 use bufferpool::BufferPool;
 use bytecodec::{DecodeExt as _, EncodeExt as _};
@@ -20,9 +22,10 @@ use stun_codec::{
     DecodedMessage, Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId,
     rfc5389::{
         attributes::{
-            ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username, XorMappedAddress,
+            AlternateServer, ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username,
+            XorMappedAddress,
         },
-        errors::{StaleNonce, Unauthorized, UnknownAttribute},
+        errors::{StaleNonce, TryAlternate, Unauthorized, UnknownAttribute},
         methods::BINDING,
     },
     rfc5766::{
@@ -32,6 +35,7 @@ use stun_codec::{
         errors::AllocationMismatch,
         methods::{ALLOCATE, CHANNEL_BIND, REFRESH},
     },
+    rfc5780::attributes::{ChangeRequest, OtherAddress, ResponseOrigin},
     rfc8656::attributes::AdditionalAddressFamily,
 };
 use tracing::{Span, field};
@@ -47,6 +51,12 @@ const REQUEST_MAX_ELAPSED: Duration = Duration::from_secs(8);
 /// It ain't much traffic and with a lower interval, these checks can also help in disconnecting from an unresponsive relay.
 const BINDING_INTERVAL: Duration = Duration::from_secs(25);
 
+/// How long we wait for a response to a NAT behaviour discovery probe.
+///
+/// The probe testing our NAT's filtering is expected to not be answered by restrictive NATs, so we don't wait as long as for other requests.
+/// Within this time, probes are still retransmitted so a single lost packet doesn't skew the result.
+const NAT_PROBE_MAX_ELAPSED: Duration = Duration::from_secs(3);
+
 /// Represents a TURN allocation that refreshes itself.
 ///
 /// Allocations have a lifetime and need to be continuously refreshed to stay active.
@@ -64,6 +74,10 @@ pub struct Allocation {
     /// Once set, we send STUN binding requests at an interval of [`BINDING_INTERVAL`].
     /// This ensures any NAT bindings stay alive even if the allocation is completely idle.
     active_socket: Option<ActiveSocket>,
+    /// How we talk to the relay.
+    ///
+    /// Regardless of the transport, the relay always allocates UDP sockets for us.
+    transport: Transport,
 
     software: Software,
 
@@ -95,6 +109,9 @@ pub struct Allocation {
 
     explicit_failure: Option<FreeReason>,
 
+    /// Discovers our NAT's behaviour if the relay supports RFC 5780.
+    nat_discovery: Option<NatDiscovery>,
+
     buffer_pool: BufferPool<Vec<u8>>,
 }
 
@@ -210,6 +227,8 @@ pub enum FreeReason {
     NoResponseReceived,
     #[error("TURN protocol failure")]
     ProtocolFailure,
+    #[error("relay is draining")]
+    RelayDraining,
 }
 
 impl Allocation {
@@ -221,10 +240,35 @@ impl Allocation {
         now: Instant,
         session_id: SessionId,
         buffer_pool: BufferPool<Vec<u8>>,
+    ) -> Self {
+        Self::with_transport(
+            server,
+            Credentials {
+                username,
+                password,
+                realm,
+                nonce: Default::default(),
+            },
+            Software::new(format!("snownet; session={session_id}"))
+                .expect("description has less then 128 chars"),
+            Transport::Udp,
+            buffer_pool,
+            now,
+        )
+    }
+
+    fn with_transport(
+        server: RelaySocket,
+        credentials: Credentials,
+        software: Software,
+        transport: Transport,
+        buffer_pool: BufferPool<Vec<u8>>,
+        now: Instant,
     ) -> Self {
         let mut allocation = Self {
             server,
             active_socket: None,
+            transport,
             ip4_host_candidate: Default::default(),
             ip6_host_candidate: Default::default(),
             ip4_srflx_candidate: Default::default(),
@@ -234,18 +278,13 @@ impl Allocation {
             buffered_transmits: Default::default(),
             events: Default::default(),
             sent_requests: Default::default(),
-            credentials: Some(Credentials {
-                username,
-                password,
-                realm,
-                nonce: Default::default(),
-            }),
+            credentials: Some(credentials),
             allocation_lifetime: Default::default(),
             channel_bindings: Default::default(),
             buffered_channel_bindings: AllocRingBuffer::new(100),
-            software: Software::new(format!("snownet; session={session_id}"))
-                .expect("description has less then 128 chars"),
+            software,
             explicit_failure: Default::default(),
+            nat_discovery: Default::default(),
             buffer_pool,
         };
 
@@ -254,6 +293,30 @@ impl Allocation {
         allocation
     }
 
+    /// Creates a new [`Allocation`] on the same relay, using the next [`Transport`] to fall back to.
+    ///
+    /// Returns `None` if we have already tried all transports or are lacking credentials.
+    pub fn fallback(&self, now: Instant) -> Option<Self> {
+        let transport = self.transport.fallback()?;
+        let credentials = self.credentials.clone()?;
+
+        Some(Self::with_transport(
+            self.server,
+            Credentials {
+                nonce: None,
+                ..credentials
+            },
+            self.software.clone(),
+            transport,
+            self.buffer_pool.clone(),
+            now,
+        ))
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
     pub fn host_and_server_reflexive_candidates(&self) -> impl Iterator<Item = Candidate> + use<> {
         [
             self.ip4_host_candidate.clone(),
@@ -318,7 +381,7 @@ impl Allocation {
             "`from` and `local` to have the same IP version"
         );
 
-        if !self.server.matches(from) {
+        if !self.server.matches(from) && !self.is_nat_discovery_address(from) {
             return false;
         }
 
@@ -369,6 +432,29 @@ impl Allocation {
             tracing::debug!(target: "wire::turn", ?request, ?response);
         }
 
+        if let Some(discovery) = self
+            .nat_discovery
+            .as_mut()
+            .filter(|d| d.is_probe(transaction_id))
+        {
+            if message.class() != MessageClass::SuccessResponse {
+                tracing::debug!("Relay could not honor NAT behaviour discovery probe");
+                discovery.handle_error(transaction_id);
+
+                return true;
+            }
+
+            let mapped = message
+                .get_attribute::<XorMappedAddress>()
+                .map(|a| a.address());
+
+            if let Some(behaviour) = discovery.handle_response(transaction_id, mapped) {
+                self.log_nat_behaviour(behaviour);
+            }
+
+            return true;
+        }
+
         if let Some(error) = message.get_attribute::<ErrorCode>() {
             // If we sent a nonce but receive 401 instead of 438 then our credentials are invalid.
             if error.code() == Unauthorized::CODEPOINT
@@ -473,6 +559,19 @@ impl Allocation {
                 return true;
             }
 
+            // The relay is draining and doesn't accept new allocations.
+            // Our credentials are only valid for this relay so we cannot follow `ALTERNATE-SERVER` and instead rely on the portal to hand out other relays.
+            if error.code() == TryAlternate::CODEPOINT && message.method() == ALLOCATE {
+                let alternate_server = message
+                    .get_attribute::<AlternateServer>()
+                    .map(|a| a.address());
+
+                tracing::info!(?alternate_server, "Relay does not accept new allocations");
+                self.explicit_failure = Some(FreeReason::RelayDraining);
+
+                return true;
+            }
+
             // Catch-all error handling if none of the above apply.
             match message.method() {
                 ALLOCATE => {
@@ -521,26 +620,45 @@ impl Allocation {
 
         match message.method() {
             BINDING => {
-                // First, see if we need to update our host candidate.
-                let current_host_candidate = match local {
-                    SocketAddr::V4(_) => &mut self.ip4_host_candidate,
-                    SocketAddr::V6(_) => &mut self.ip6_host_candidate,
-                };
+                // Host and server-reflexive candidates describe our UDP socket.
+                // Over a stream transport, we don't learn anything about it.
+                if !self.transport.is_stream() {
+                    // First, see if we need to update our host candidate.
+                    let current_host_candidate = match local {
+                        SocketAddr::V4(_) => &mut self.ip4_host_candidate,
+                        SocketAddr::V6(_) => &mut self.ip6_host_candidate,
+                    };
 
-                let maybe_candidate = Candidate::host(local, Protocol::Udp).ok();
-                if update_candidate(maybe_candidate, current_host_candidate, &mut self.events) {
-                    self.log_update(now);
-                }
+                    let maybe_candidate = Candidate::host(local, Protocol::Udp).ok();
+                    if update_candidate(maybe_candidate, current_host_candidate, &mut self.events) {
+                        self.log_update(now);
+                    }
 
-                // Second, process the binding request itself.
-                let current_srflx_candidate = match original_dst {
-                    SocketAddr::V4(_) => &mut self.ip4_srflx_candidate,
-                    SocketAddr::V6(_) => &mut self.ip6_srflx_candidate,
-                };
+                    // Second, process the binding request itself.
+                    let current_srflx_candidate = match original_dst {
+                        SocketAddr::V4(_) => &mut self.ip4_srflx_candidate,
+                        SocketAddr::V6(_) => &mut self.ip6_srflx_candidate,
+                    };
 
-                let maybe_candidate = message.attributes().find_map(|a| srflx_candidate(local, a));
-                if update_candidate(maybe_candidate, current_srflx_candidate, &mut self.events) {
-                    self.log_update(now);
+                    let maybe_candidate =
+                        message.attributes().find_map(|a| srflx_candidate(local, a));
+                    if update_candidate(maybe_candidate, current_srflx_candidate, &mut self.events)
+                    {
+                        self.log_update(now);
+                    }
+
+                    // Fourth, discover our NAT's behaviour if the relay supports it.
+                    if let Some(other) = message.get_attribute::<OtherAddress>()
+                        && let Some(mapped) = message.get_attribute::<XorMappedAddress>()
+                    {
+                        self.start_nat_discovery(
+                            local,
+                            original_dst,
+                            other.address(),
+                            mapped.address(),
+                            now,
+                        );
+                    }
                 }
 
                 // Third, check if we have already determined which socket to use for this relay.
@@ -730,6 +848,7 @@ impl Allocation {
             tracing::debug!(id = ?request.transaction_id(), %method, %dst, "Request timed out after {backoff_duration:?}, re-sending");
 
             let needs_auth = method != BINDING;
+            let transaction_id = request.transaction_id();
 
             let queued = if needs_auth {
                 self.authenticate_and_queue(request, Some(backoff), now)
@@ -737,6 +856,19 @@ impl Allocation {
                 self.queue(dst, request, Some(backoff), now)
             };
 
+            // A probe going unanswered is part of discovering our NAT's behaviour and says nothing about the relay's reachability.
+            if let Some(discovery) = self
+                .nat_discovery
+                .as_mut()
+                .filter(|d| d.is_probe(transaction_id))
+            {
+                if !queued && let Some(behaviour) = discovery.handle_timeout(transaction_id) {
+                    self.log_nat_behaviour(behaviour);
+                }
+
+                continue;
+            }
+
             // If we have an active socket (i.e. successfully sent at least 1 BINDING request)
             // and we just timed out a message, invalidate the allocation.
             if !queued
@@ -891,6 +1023,7 @@ impl Allocation {
 
         Some(EncodeOk {
             socket: active_socket,
+            transport: self.transport,
         })
     }
 
@@ -938,6 +1071,74 @@ impl Allocation {
         &self.server == socket
     }
 
+    /// The [`NatBehaviour`] discovered against this relay, if it supports RFC 5780 and discovery has completed.
+    pub fn nat_behaviour(&self) -> Option<NatBehaviour> {
+        self.nat_discovery.as_ref()?.behaviour()
+    }
+
+    /// Whether the given address is the relay's other address that answers our NAT behaviour discovery probes.
+    pub fn is_nat_discovery_address(&self, from: SocketAddr) -> bool {
+        self.nat_discovery
+            .as_ref()
+            .is_some_and(|d| d.is_other_address(from))
+    }
+
+    fn start_nat_discovery(
+        &mut self,
+        local: SocketAddr,
+        primary: SocketAddr,
+        other: SocketAddr,
+        mapped: SocketAddr,
+        now: Instant,
+    ) {
+        if self.nat_discovery.is_some() {
+            return;
+        }
+
+        if primary.is_ipv4() != other.is_ipv4() {
+            tracing::debug!(%primary, %other, "Relay advertised other address of different IP version");
+            return;
+        }
+
+        tracing::debug!(%other, "Relay supports NAT behaviour discovery");
+
+        let mapping_probe = make_binding_request(self.software.clone());
+        let mut filtering_probe = make_binding_request(self.software.clone());
+        filtering_probe.add_attribute(ChangeRequest::new(false, true));
+
+        self.nat_discovery = Some(NatDiscovery::new(
+            local,
+            primary,
+            other,
+            mapped,
+            mapping_probe.transaction_id(),
+            filtering_probe.transaction_id(),
+        ));
+
+        self.queue(
+            other,
+            mapping_probe,
+            Some(backoff::new(now, REQUEST_TIMEOUT, NAT_PROBE_MAX_ELAPSED)),
+            now,
+        );
+        self.queue(
+            primary,
+            filtering_probe,
+            Some(backoff::new(now, REQUEST_TIMEOUT, NAT_PROBE_MAX_ELAPSED)),
+            now,
+        );
+    }
+
+    fn log_nat_behaviour(&self, behaviour: NatBehaviour) {
+        tracing::info!(
+            relay = ?self.server,
+            mapping = ?behaviour.mapping,
+            filtering = ?behaviour.filtering,
+            hole_punching_unlikely = %behaviour.is_hole_punching_unlikely(),
+            "Discovered NAT behaviour"
+        );
+    }
+
     fn log_update(&self, now: Instant) {
         tracing::info!(
             host_ip4 = ?self.ip4_host_candidate.as_ref().map(|c| c.addr()),
@@ -979,6 +1180,11 @@ impl Allocation {
         self.channel_bindings.clear();
         self.allocation_lifetime = None;
         self.sent_requests.clear();
+
+        // Any probes in flight are gone, start over with the next BINDING response.
+        if self.nat_behaviour().is_none() {
+            self.nat_discovery = None;
+        }
     }
 
     /// Checks whether the given socket is part of this allocation.
@@ -1138,6 +1344,7 @@ impl Allocation {
             src: None,
             dst,
             payload: self.buffer_pool.pull_initialised(&encode(message)),
+            transport: self.transport,
         });
 
         true
@@ -1175,6 +1382,7 @@ impl Allocation {
 
 pub struct EncodeOk {
     pub socket: SocketAddr,
+    pub transport: Transport,
 }
 
 impl ActiveSocket {
@@ -1396,7 +1604,11 @@ stun_codec::define_attribute_enums!(
         XorPeerAddress,
         ChannelNumber,
         Lifetime,
-        Software
+        Software,
+        ChangeRequest,
+        OtherAddress,
+        ResponseOrigin,
+        AlternateServer
     ]
 );
 
@@ -1648,11 +1860,16 @@ fn display_attr(attr: &Attribute) -> String {
         Attribute::ChannelNumber(inner) => format!("{inner:?}"),
         Attribute::Lifetime(inner) => format!("{inner:?}"),
         Attribute::Software(inner) => format!("Software({})", inner.description()),
+        Attribute::ChangeRequest(inner) => format!("{inner:?}"),
+        Attribute::OtherAddress(inner) => format!("{inner:?}"),
+        Attribute::ResponseOrigin(inner) => format!("{inner:?}"),
+        Attribute::AlternateServer(inner) => format!("{inner:?}"),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::nat_behaviour::{NatFiltering, NatMapping};
     use crate::utils::channel_data_packet_buffer;
 
     use super::*;
@@ -1672,6 +1889,7 @@ mod tests {
 
     const RELAY_V4: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 3478);
     const RELAY_V6: SocketAddrV6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 3478, 0, 0);
+    const RELAY_OTHER_PORT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3479);
     const RELAY_ADDR_IP4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9999);
     const RELAY_ADDR_IP6: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 9999);
 
@@ -2431,6 +2649,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn try_alternate_on_allocate_frees_allocation() {
+        let mut allocation =
+            Allocation::for_test_ip4(Instant::now()).with_binding_response(PEER1, Instant::now());
+
+        let allocate = allocation.next_message().unwrap();
+        allocation
+            .handle_test_input_ip4(try_alternate(&allocate, RELAY_OTHER_PORT), Instant::now());
+
+        assert!(allocation.next_message().is_none());
+        assert_eq!(allocation.can_be_freed(), Some(FreeReason::RelayDraining));
+    }
+
     #[test]
     fn dont_buffer_channel_bindings_twice() {
         let mut allocation =
@@ -2623,6 +2854,61 @@ mod tests {
         assert_eq!(allocation.can_be_freed(), None);
     }
 
+    #[test]
+    fn fallback_allocation_sends_via_stream_transport() {
+        let now = Instant::now();
+        let allocation = Allocation::for_test_ip4(now);
+
+        let mut tcp = allocation.fallback(now).unwrap();
+        let binding = tcp.poll_transmit().unwrap();
+
+        assert_eq!(tcp.transport(), Transport::Tcp);
+        assert_eq!(binding.transport, Transport::Tcp);
+        assert_eq!(binding.dst, SocketAddr::V4(RELAY_V4));
+
+        let tls = tcp.fallback(now).unwrap();
+
+        assert_eq!(tls.transport(), Transport::Tls);
+        assert!(tls.fallback(now).is_none());
+    }
+
+    #[test]
+    fn stream_allocation_only_emits_relay_candidates() {
+        let now = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(now)
+            .fallback(now)
+            .unwrap()
+            .with_binding_response(PEER1, now)
+            .with_allocate_response(&[RELAY_ADDR_IP4], now);
+
+        assert_eq!(
+            allocation.poll_event(),
+            Some(Event::New(
+                Candidate::relayed(RELAY_ADDR_IP4, PEER1, Protocol::Udp).unwrap()
+            ))
+        );
+        assert_eq!(allocation.poll_event(), None);
+    }
+
+    #[test]
+    fn stream_allocation_encodes_channel_data_for_stream() {
+        let now = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(now)
+            .fallback(now)
+            .unwrap()
+            .with_binding_response(PEER1, now)
+            .with_allocate_response(&[RELAY_ADDR_IP4], now);
+
+        allocation.bind_channel(PEER2_IP4, now);
+        let mut buffer = vec![0u8; 4 + 3];
+
+        let encode_ok = allocation
+            .encode_channel_data_header(PEER2_IP4, &mut buffer, now)
+            .unwrap();
+
+        assert_eq!(encode_ok.transport, Transport::Tcp);
+    }
+
     #[test]
     fn relay_socket_matches_v4_socket() {
         let socket = RelaySocket::V4(RELAY_V4);
@@ -2780,6 +3066,120 @@ mod tests {
         );
     }
 
+    #[test]
+    fn discovers_nat_behaviour_if_relay_advertises_other_address() {
+        let now = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(now);
+
+        let binding = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(
+            binding_response_with_other_address(&binding, PEER2_IP4),
+            now,
+        );
+
+        let mapping_probe = allocation.poll_transmit().unwrap();
+        assert_eq!(mapping_probe.dst, RELAY_OTHER_PORT);
+        let filtering_probe = allocation.next_message().unwrap();
+        assert!(filtering_probe.get_attribute::<ChangeRequest>().is_some());
+
+        let mapping_probe = decode(&mapping_probe.payload).unwrap().unwrap();
+        allocation.handle_input(
+            RELAY_OTHER_PORT,
+            PEER1,
+            binding_response(&mapping_probe, PEER2_IP4),
+            now,
+        );
+        assert_eq!(allocation.nat_behaviour(), None);
+
+        allocation.handle_input(
+            RELAY_OTHER_PORT,
+            PEER1,
+            binding_response(&filtering_probe, PEER2_IP4),
+            now,
+        );
+        assert_eq!(
+            allocation.nat_behaviour(),
+            Some(NatBehaviour {
+                mapping: Some(NatMapping::PortIndependent),
+                filtering: Some(NatFiltering::PortIndependent)
+            })
+        );
+    }
+
+    #[test]
+    fn retransmits_lost_filtering_probe() {
+        let now = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(now);
+
+        let binding = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(
+            binding_response_with_other_address(&binding, PEER2_IP4),
+            now,
+        );
+
+        let mapping_probe = allocation.next_message().unwrap();
+        let filtering_probe = allocation.next_message().unwrap();
+        allocation.handle_input(
+            RELAY_OTHER_PORT,
+            PEER1,
+            binding_response(&mapping_probe, PEER2_IP4),
+            now,
+        );
+
+        // The first filtering probe got lost.
+        let now = now + Duration::from_secs(1);
+        allocation.handle_timeout(now);
+        assert_eq!(allocation.nat_behaviour(), None);
+
+        let retransmitted = iter::from_fn(|| allocation.next_message())
+            .find(|m| m.transaction_id() == filtering_probe.transaction_id())
+            .unwrap();
+        allocation.handle_input(
+            RELAY_OTHER_PORT,
+            PEER1,
+            binding_response(&retransmitted, PEER2_IP4),
+            now,
+        );
+
+        assert_eq!(
+            allocation.nat_behaviour().and_then(|b| b.filtering),
+            Some(NatFiltering::PortIndependent)
+        );
+    }
+
+    #[test]
+    fn unanswered_filtering_probe_does_not_invalidate_allocation() {
+        let now = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(now);
+
+        let binding = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(
+            binding_response_with_other_address(&binding, PEER2_IP4),
+            now,
+        );
+
+        let mapping_probe = allocation.next_message().unwrap();
+        let _filtering_probe = allocation.next_message().unwrap();
+        let allocate = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(allocate_response(&allocate, &[RELAY_ADDR_IP4]), now);
+        allocation.handle_input(
+            RELAY_OTHER_PORT,
+            PEER1,
+            binding_response(&mapping_probe, PEER2_IP4),
+            now,
+        );
+
+        for secs in 1..=5 {
+            allocation.handle_timeout(now + Duration::from_secs(secs));
+        }
+
+        assert_eq!(
+            allocation.nat_behaviour().and_then(|b| b.filtering),
+            Some(NatFiltering::AddressAndPortDependent)
+        );
+        assert_eq!(allocation.current_relay_candidates().count(), 1);
+    }
+
     fn ch(peer: SocketAddr, now: Instant) -> Channel {
         Channel {
             peer,
@@ -2824,6 +3224,16 @@ mod tests {
         message
     }
 
+    fn binding_response_with_other_address(
+        request: &Message<Attribute>,
+        srflx_addr: SocketAddr,
+    ) -> Message<Attribute> {
+        let mut message = binding_response(request, srflx_addr);
+        message.add_attribute(OtherAddress::new(RELAY_OTHER_PORT));
+
+        message
+    }
+
     fn unauthorized_response(request: &Message<Attribute>, nonce: &str) -> Message<Attribute> {
         let mut message = Message::new(
             MessageClass::ErrorResponse,
@@ -2861,6 +3271,18 @@ mod tests {
         message
     }
 
+    fn try_alternate(request: &Message<Attribute>, alternate: SocketAddr) -> Message<Attribute> {
+        let mut message = Message::new(
+            MessageClass::ErrorResponse,
+            request.method(),
+            request.transaction_id(),
+        );
+        message.add_attribute(ErrorCode::from(TryAlternate));
+        message.add_attribute(AlternateServer::new(alternate));
+
+        message
+    }
+
     fn allocation_mismatch(request: &Message<Attribute>) -> Message<Attribute> {
         let mut message = Message::new(
             MessageClass::ErrorResponse,
@@ -7,18 +7,22 @@ mod allocation;
 mod backoff;
 mod channel_data;
 mod index;
+mod nat_behaviour;
 mod node;
 mod stats;
+mod transport;
 mod utils;
 
 pub use allocation::RelaySocket;
+pub use nat_behaviour::{NatBehaviour, NatFiltering, NatMapping};
 #[allow(deprecated)] // Rust bug: `expect` doesn't seem to work on imports?
 pub use node::{Answer, Offer};
 pub use node::{
     Client, ClientNode, Credentials, Event, HANDSHAKE_TIMEOUT, NoTurnServers, Node, Server,
     ServerNode, Transmit,
 };
-pub use stats::{ConnectionStats, NodeStats};
+pub use stats::{CandidatePair, CandidateType, ConnectionStats, NodeStats};
+pub use transport::{TURN_TLS_PORT, Transport};
 
 pub fn is_wireguard(payload: &[u8]) -> bool {
     boringtun::noise::Tunn::parse_incoming_packet(payload).is_ok()
@@ -30,3 +34,24 @@ pub(crate) fn is_handshake(payload: &[u8]) -> bool {
     boringtun::noise::Tunn::parse_incoming_packet(payload)
         .is_ok_and(|p| matches!(p, Packet::HandshakeInit(_) | Packet::HandshakeResponse(_)))
 }
+
+pub(crate) fn is_handshake_initiation(payload: &[u8]) -> bool {
+    use boringtun::noise::Packet;
+
+    boringtun::noise::Tunn::parse_incoming_packet(payload)
+        .is_ok_and(|p| matches!(p, Packet::HandshakeInit(_)))
+}
+
+pub(crate) fn is_handshake_response(payload: &[u8]) -> bool {
+    use boringtun::noise::Packet;
+
+    boringtun::noise::Tunn::parse_incoming_packet(payload)
+        .is_ok_and(|p| matches!(p, Packet::HandshakeResponse(_)))
+}
+
+pub(crate) fn is_data(payload: &[u8]) -> bool {
+    use boringtun::noise::Packet;
+
+    boringtun::noise::Tunn::parse_incoming_packet(payload)
+        .is_ok_and(|p| matches!(p, Packet::PacketData(_)))
+}
@@ -0,0 +1,311 @@
+use std::net::SocketAddr;
+use stun_codec::TransactionId;
+
+/// How the NAT in front of us treats our UDP traffic, as discovered against a relay using [RFC 5780](https://www.rfc-editor.org/rfc/rfc5780).
+///
+/// Knowing this helps explain why a connection is always relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatBehaviour {
+    /// [`None`] if the relay never answered on its other address.
+    pub mapping: Option<NatMapping>,
+    /// [`None`] if neither of our probes was answered by the relay's other port, in which case we cannot tell whether our NAT dropped the response.
+    pub filtering: Option<NatFiltering>,
+}
+
+impl NatBehaviour {
+    /// Whether hole-punching through this NAT is unlikely to succeed, forcing us to relay.
+    ///
+    /// If our public address changes with every destination, the address we advertise as our server-reflexive candidate is useless to our peers.
+    pub fn is_hole_punching_unlikely(&self) -> bool {
+        self.mapping == Some(NatMapping::EndpointDependent)
+    }
+}
+
+/// Whether our public address depends on the destination we send to.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc4787#section-4.1>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatMapping {
+    /// The relay observed our local address, i.e. we are not behind a NAT.
+    NoNat,
+    /// Our public address is the same regardless of the IP and port we send to.
+    EndpointIndependent,
+    /// Our public address is the same for different ports on the relay.
+    ///
+    /// The relay's other address has the same IP as its primary one, so we cannot tell whether our public address changes with the destination IP.
+    PortIndependent,
+    /// Our public address changes with the destination we send to.
+    EndpointDependent,
+}
+
+/// Whether our NAT lets through traffic from destinations we haven't sent to.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc4787#section-5>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatFiltering {
+    /// Traffic from a different port than the one we sent to is let through.
+    PortIndependent,
+    /// Traffic from any other port than the one we sent to is dropped.
+    AddressAndPortDependent,
+}
+
+/// The state of discovering our [`NatBehaviour`] against a single relay.
+///
+/// Once the relay advertises an `OTHER-ADDRESS` in a BINDING response, we send two probes:
+///
+/// 1. A BINDING request to the other address, comparing the `XOR-MAPPED-ADDRESS` to the one we got from the primary address.
+/// 2. A BINDING request to the primary address with a `CHANGE-REQUEST` for the port, which the relay answers from its other port.
+#[derive(Debug)]
+pub(crate) struct NatDiscovery {
+    local: SocketAddr,
+    primary: SocketAddr,
+    other: SocketAddr,
+    /// What the relay observed on its primary address.
+    mapped: SocketAddr,
+
+    /// [`Some`] while the probe is in flight.
+    mapping_probe: Option<TransactionId>,
+    /// [`Some`] while the probe is in flight.
+    filtering_probe: Option<TransactionId>,
+
+    mapping: Option<NatMapping>,
+    filtering: Option<NatFiltering>,
+
+    /// Whether the relay answered the probe we sent to its other address.
+    ///
+    /// This tells us that the relay is able to answer from its other port.
+    mapping_probe_answered: bool,
+    /// Whether we gave up on the filtering probe after retransmitting it.
+    filtering_probe_unanswered: bool,
+}
+
+impl NatDiscovery {
+    pub(crate) fn new(
+        local: SocketAddr,
+        primary: SocketAddr,
+        other: SocketAddr,
+        mapped: SocketAddr,
+        mapping_probe: TransactionId,
+        filtering_probe: TransactionId,
+    ) -> Self {
+        Self {
+            local,
+            primary,
+            other,
+            mapped,
+            mapping_probe: Some(mapping_probe),
+            filtering_probe: Some(filtering_probe),
+            mapping: (mapped == local).then_some(NatMapping::NoNat),
+            filtering: None,
+            mapping_probe_answered: false,
+            filtering_probe_unanswered: false,
+        }
+    }
+
+    pub(crate) fn is_probe(&self, id: TransactionId) -> bool {
+        self.mapping_probe == Some(id) || self.filtering_probe == Some(id)
+    }
+
+    /// Whether the relay may answer our probes from this address.
+    pub(crate) fn is_other_address(&self, from: SocketAddr) -> bool {
+        let changed_port = SocketAddr::new(self.primary.ip(), self.other.port());
+
+        from == self.other || from == changed_port
+    }
+
+    /// Handles the response to one of our probes.
+    ///
+    /// Returns the discovered [`NatBehaviour`] once both probes have completed.
+    pub(crate) fn handle_response(
+        &mut self,
+        id: TransactionId,
+        mapped: Option<SocketAddr>,
+    ) -> Option<NatBehaviour> {
+        if self.mapping_probe == Some(id) {
+            self.mapping_probe = None;
+            self.mapping_probe_answered = true;
+
+            if self.mapping.is_none() {
+                self.mapping = mapped.map(|mapped| self.classify_mapping(mapped));
+            }
+        }
+
+        if self.filtering_probe == Some(id) {
+            self.filtering_probe = None;
+            self.filtering = Some(NatFiltering::PortIndependent);
+        }
+
+        self.behaviour()
+    }
+
+    /// Handles a probe that we have given up on after retransmitting it.
+    ///
+    /// Returns the discovered [`NatBehaviour`] once both probes have completed.
+    pub(crate) fn handle_timeout(&mut self, id: TransactionId) -> Option<NatBehaviour> {
+        if self.mapping_probe == Some(id) {
+            self.mapping_probe = None;
+        }
+
+        if self.filtering_probe == Some(id) {
+            self.filtering_probe = None;
+            self.filtering_probe_unanswered = true;
+        }
+
+        self.behaviour()
+    }
+
+    /// Handles an error response to one of our probes.
+    ///
+    /// The relay couldn't honor the probe, which tells us nothing about our NAT.
+    /// If this was the filtering probe, we cannot discover the [`NatBehaviour`] against this relay.
+    pub(crate) fn handle_error(&mut self, id: TransactionId) {
+        if self.mapping_probe == Some(id) {
+            self.mapping_probe = None;
+        }
+
+        if self.filtering_probe == Some(id) {
+            self.filtering_probe = None;
+        }
+    }
+
+    pub(crate) fn behaviour(&self) -> Option<NatBehaviour> {
+        if self.mapping_probe.is_some() || self.filtering_probe.is_some() {
+            return None;
+        }
+
+        let filtering = match self.filtering {
+            Some(filtering) => Some(filtering),
+            // Only if the relay demonstrably answers from its other port can we blame our NAT for dropping the response.
+            None if self.filtering_probe_unanswered => self
+                .mapping_probe_answered
+                .then_some(NatFiltering::AddressAndPortDependent),
+            // The relay couldn't honor our filtering probe.
+            None => return None,
+        };
+
+        Some(NatBehaviour {
+            mapping: self.mapping,
+            filtering,
+        })
+    }
+
+    fn classify_mapping(&self, mapped: SocketAddr) -> NatMapping {
+        if mapped != self.mapped {
+            return NatMapping::EndpointDependent;
+        }
+
+        if self.other.ip() == self.primary.ip() {
+            return NatMapping::PortIndependent;
+        }
+
+        NatMapping::EndpointIndependent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const LOCAL: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 52625);
+    const PUBLIC: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 40000);
+    const RELAY: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)), 3478);
+    const RELAY_OTHER_PORT: SocketAddr =
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)), 3479);
+
+    const MAPPING_PROBE: [u8; 12] = [1; 12];
+    const FILTERING_PROBE: [u8; 12] = [2; 12];
+
+    #[test]
+    fn same_mapping_on_other_port_is_port_independent() {
+        let mut discovery = discovery(PUBLIC);
+
+        assert_eq!(
+            discovery.handle_response(TransactionId::new(MAPPING_PROBE), Some(PUBLIC)),
+            None
+        );
+        let behaviour = discovery
+            .handle_response(TransactionId::new(FILTERING_PROBE), Some(PUBLIC))
+            .unwrap();
+
+        assert_eq!(
+            behaviour,
+            NatBehaviour {
+                mapping: Some(NatMapping::PortIndependent),
+                filtering: Some(NatFiltering::PortIndependent)
+            }
+        );
+        assert!(!behaviour.is_hole_punching_unlikely());
+    }
+
+    #[test]
+    fn different_mapping_on_other_port_is_endpoint_dependent() {
+        let mut discovery = discovery(PUBLIC);
+
+        let other_public = SocketAddr::new(PUBLIC.ip(), 40001);
+
+        assert_eq!(
+            discovery.handle_response(TransactionId::new(MAPPING_PROBE), Some(other_public)),
+            None
+        );
+        let behaviour = discovery
+            .handle_timeout(TransactionId::new(FILTERING_PROBE))
+            .unwrap();
+
+        assert_eq!(
+            behaviour,
+            NatBehaviour {
+                mapping: Some(NatMapping::EndpointDependent),
+                filtering: Some(NatFiltering::AddressAndPortDependent)
+            }
+        );
+        assert!(behaviour.is_hole_punching_unlikely());
+    }
+
+    #[test]
+    fn mapped_local_address_means_no_nat() {
+        let mut discovery = discovery(LOCAL);
+
+        assert_eq!(
+            discovery.handle_timeout(TransactionId::new(MAPPING_PROBE)),
+            None
+        );
+        let behaviour = discovery
+            .handle_response(TransactionId::new(FILTERING_PROBE), Some(LOCAL))
+            .unwrap();
+
+        assert_eq!(behaviour.mapping, Some(NatMapping::NoNat));
+    }
+
+    #[test]
+    fn unanswered_probes_leave_filtering_unknown() {
+        let mut discovery = discovery(PUBLIC);
+
+        assert_eq!(
+            discovery.handle_timeout(TransactionId::new(MAPPING_PROBE)),
+            None
+        );
+        let behaviour = discovery
+            .handle_timeout(TransactionId::new(FILTERING_PROBE))
+            .unwrap();
+
+        assert_eq!(
+            behaviour,
+            NatBehaviour {
+                mapping: None,
+                filtering: None
+            }
+        );
+    }
+
+    fn discovery(mapped: SocketAddr) -> NatDiscovery {
+        NatDiscovery::new(
+            LOCAL,
+            RELAY,
+            RELAY_OTHER_PORT,
+            mapped,
+            TransactionId::new(MAPPING_PROBE),
+            TransactionId::new(FILTERING_PROBE),
+        )
+    }
+}
@@ -1,6 +1,7 @@
-use crate::allocation::{self, Allocation, RelaySocket, Socket};
+use crate::allocation::{self, Allocation, FreeReason, RelaySocket, Socket};
 use crate::index::IndexLfsr;
-use crate::stats::{ConnectionStats, NodeStats};
+use crate::stats::{CandidatePair, CandidateType, ConnectionStats, NodeStats};
+use crate::transport::Transport;
 use crate::utils::channel_data_packet_buffer;
 use anyhow::{Context, Result, anyhow};
 use boringtun::noise::errors::WireGuardError;
@@ -260,9 +261,7 @@ where
                 .remote_credentials()
                 .is_some_and(|c| c == &remote_creds)
             && c.tunnel.remote_static_public() == remote
-            && c.tunnel
-                .preshared_key()
-                .is_some_and(|key| key == preshared_key)
+            && c.preshared_key == preshared_key
         {
             tracing::info!(local = ?local_creds, "Reusing existing connection");
 
@@ -322,8 +321,77 @@ where
         })
     }
 
+    /// Whether ICE has nominated a socket for the connection and not lost connectivity since.
+    ///
+    /// WireGuard sessions of idle connections may have expired, sending a packet will perform a new handshake.
+    pub fn is_connected(&self, cid: TId) -> bool {
+        self.connections.established.get(&cid).is_some_and(|c| {
+            c.disconnected_at.is_none()
+                && matches!(
+                    c.state,
+                    ConnectionState::Connected { .. } | ConnectionState::Idle { .. }
+                )
+        })
+    }
+
     pub fn stats(&self) -> (NodeStats, impl Iterator<Item = (TId, ConnectionStats)> + '_) {
-        (self.stats, self.connections.stats())
+        let node_stats = NodeStats {
+            nat_behaviour: self.allocations.values().find_map(|a| a.nat_behaviour()),
+            ..self.stats
+        };
+
+        (node_stats, self.connections.stats())
+    }
+
+    /// Upgrades the preshared key of a connection with a secret negotiated with the remote.
+    ///
+    /// The new preshared key is derived from the one the connection was created with and the given secret.
+    /// Our current WireGuard session stays active until a handshake using the new key completes.
+    /// Clients initiate this handshake right away.
+    ///
+    /// Upgrading with the same secret again does nothing.
+    #[tracing::instrument(level = "debug", skip_all, fields(%cid))]
+    pub fn upgrade_preshared_key(
+        &mut self,
+        cid: TId,
+        secret: Secret<[u8; 32]>,
+        now: Instant,
+    ) -> Result<()> {
+        let conn = self
+            .connections
+            .get_established_mut(&cid)
+            .with_context(|| format!("Unknown connection {cid}"))?;
+
+        let key = upgraded_preshared_key(&conn.preshared_key, secret.expose_secret());
+
+        if conn.tunnel.preshared_key() == Some(key)
+            || conn
+                .upgraded_tunnel
+                .as_ref()
+                .is_some_and(|t| t.preshared_key() == Some(key))
+        {
+            return Ok(());
+        }
+
+        let remote = conn.remote_pub_key;
+        let tunnel = self.new_tunnel(remote, key, now);
+
+        let conn = self
+            .connections
+            .get_established_mut(&cid)
+            .with_context(|| format!("Unknown connection {cid}"))?;
+
+        conn.upgrade_tunnel(
+            tunnel,
+            self.mode.is_client(),
+            &mut self.allocations,
+            &mut self.buffered_transmits,
+            now,
+        );
+
+        tracing::debug!("Upgrading preshared key");
+
+        Ok(())
     }
 
     #[tracing::instrument(level = "info", skip_all, fields(%cid))]
@@ -510,7 +578,13 @@ where
         self.allocations_drain_events();
 
         for (id, connection) in self.connections.iter_established_mut() {
-            connection.handle_timeout(id, now, &mut self.allocations, &mut self.buffered_transmits);
+            connection.handle_timeout(
+                id,
+                now,
+                &mut self.allocations,
+                &mut self.buffered_transmits,
+                &mut self.pending_events,
+            );
         }
 
         for (id, connection) in self.connections.initial.iter_mut() {
@@ -531,6 +605,20 @@ where
 
         self.allocations
             .retain(|rid, allocation| match allocation.can_be_freed() {
+                // Clients might be on a network that blocks UDP, retry with a stream transport.
+                Some(FreeReason::NoResponseReceived) if self.mode.is_client() => {
+                    let Some(fallback) = allocation.fallback(now) else {
+                        tracing::info!(%rid, "Disconnecting from relay; {}", FreeReason::NoResponseReceived);
+
+                        return false;
+                    };
+
+                    tracing::info!(%rid, from = %allocation.transport(), to = %fallback.transport(), "No response from relay; falling back to different transport");
+
+                    *allocation = fallback;
+
+                    true
+                }
                 Some(e) => {
                     tracing::info!(%rid, "Disconnecting from relay; {e}");
 
@@ -680,6 +768,34 @@ where
             tracing::warn!(%cid, "No TURN servers connected; connection may fail to establish");
         }
 
+        let tunnel = self.new_tunnel(remote, key, now);
+
+        Connection {
+            agent,
+            tunnel,
+            upgraded_tunnel: None,
+            preshared_key: key,
+            next_wg_timer_update: now,
+            stats: Default::default(),
+            pending_binding_requests: AllocRingBuffer::new(16),
+            handshake_initiated_at: None,
+            handshake_responded_at: None,
+            buffer: vec![0; ip_packet::MAX_FZ_PAYLOAD],
+            intent_sent_at,
+            signalling_completed_at: now,
+            remote_pub_key: remote,
+            relay,
+            state: ConnectionState::Connecting {
+                wg_buffer: AllocRingBuffer::new(128),
+                ip_buffer: AllocRingBuffer::new(128),
+            },
+            disconnected_at: None,
+            possible_sockets: BTreeSet::default(),
+            buffer_pool: self.buffer_pool.clone(),
+        }
+    }
+
+    fn new_tunnel(&mut self, remote: PublicKey, key: [u8; 32], now: Instant) -> Tunn {
         let mut tunnel = Tunn::new_at(
             self.private_key.clone(),
             remote,
@@ -702,24 +818,7 @@ where
         // until we have a WireGuard tunnel to send packets into.
         tunnel.set_rekey_attempt_time(Duration::from_secs(15));
 
-        Connection {
-            agent,
-            tunnel,
-            next_wg_timer_update: now,
-            stats: Default::default(),
-            buffer: vec![0; ip_packet::MAX_FZ_PAYLOAD],
-            intent_sent_at,
-            signalling_completed_at: now,
-            remote_pub_key: remote,
-            relay,
-            state: ConnectionState::Connecting {
-                wg_buffer: AllocRingBuffer::new(128),
-                ip_buffer: AllocRingBuffer::new(128),
-            },
-            disconnected_at: None,
-            possible_sockets: BTreeSet::default(),
-            buffer_pool: self.buffer_pool.clone(),
-        }
+        tunnel
     }
 
     /// Tries to handle the packet using one of our [`Allocation`]s.
@@ -733,6 +832,17 @@ where
     /// This heuristic might fail because we are also handling wireguard packets.
     /// Those are fully encrypted and thus any byte pattern may appear at the front of the packet.
     /// We can detect this by further checking the origin of the packet.
+    /// Whether this packet might be a relay answering one of our NAT behaviour discovery probes from its other port.
+    ///
+    /// Only STUN messages qualify, which keeps the linear search off the path of WireGuard data packets.
+    fn is_nat_discovery_response(&self, from: SocketAddr, packet: &[u8]) -> bool {
+        matches!(packet.first(), Some(0..=3))
+            && self
+                .allocations
+                .values()
+                .any(|a| a.is_nat_discovery_address(from))
+    }
+
     fn allocations_try_handle<'p>(
         &mut self,
         from: SocketAddr,
@@ -740,8 +850,8 @@ where
         packet: &'p [u8],
         now: Instant,
     ) -> ControlFlow<(), (SocketAddr, &'p [u8], Option<Socket>)> {
-        if from.port() != 3478 {
-            // Relays always send & receive from port 3478.
+        if from.port() != 3478 && !self.is_nat_discovery_response(from, packet) {
+            // Relays always send & receive from port 3478, except for answering NAT behaviour discovery probes.
             //
             // Some NATs may remap our p2p listening port (i.e. 52625 or another ephemeral one) to a port
             // in the non-ephemeral range. If this happens, there is a chance that a peer is sending
@@ -765,7 +875,7 @@ where
                 let Some(allocation) = self
                     .allocations
                     .values_mut()
-                    .find(|a| a.server().matches(from))
+                    .find(|a| a.server().matches(from) || a.is_nat_discovery_address(from))
                 else {
                     tracing::debug!(
                         %from,
@@ -828,23 +938,33 @@ where
             return ControlFlow::Continue(());
         };
 
-        for (_, agent) in self.connections.agents_mut() {
-            if agent.accepts_message(&message) {
-                agent.handle_packet(
-                    now,
-                    StunPacket {
-                        proto: Protocol::Udp,
-                        source: from,
-                        destination,
-                        message,
-                    },
-                );
+        let Some((cid, agent)) = self
+            .connections
+            .agents_mut()
+            .find(|(_, agent)| agent.accepts_message(&message))
+        else {
+            tracing::trace!(
+                "Packet was a STUN message but no agent handled it. Already disconnected?"
+            );
 
-                return ControlFlow::Break(Ok(()));
-            }
-        }
+            return ControlFlow::Break(Ok(()));
+        };
+
+        agent.handle_packet(
+            now,
+            StunPacket {
+                proto: Protocol::Udp,
+                source: from,
+                destination,
+                message,
+            },
+        );
 
-        tracing::trace!("Packet was a STUN message but no agent handled it. Already disconnected?");
+        if let Some(transaction_id) = binding_success_response_transaction_id(packet)
+            && let Some(connection) = self.connections.get_established_mut(&cid)
+        {
+            connection.on_binding_response(transaction_id, now);
+        }
 
         ControlFlow::Break(Ok(()))
     }
@@ -1495,6 +1615,11 @@ pub enum Event<TId> {
 
     ConnectionEstablished(TId),
 
+    /// ICE lost connectivity to the remote.
+    ///
+    /// Unless ICE recovers within a short grace-period, the connection will fail.
+    ConnectionDisconnected(TId),
+
     /// We failed to establish a connection.
     ///
     /// All state associated with the connection has been cleared.
@@ -1502,6 +1627,16 @@ pub enum Event<TId> {
 
     /// We closed a connection (e.g. due to inactivity, roaming, etc).
     ConnectionClosed(TId),
+
+    /// ICE nominated a different candidate pair for this connection.
+    ///
+    /// For example, because we upgraded from a relayed to a direct connection or switched to a different relay.
+    ConnectionPathChanged {
+        connection: TId,
+        /// `None` if this is the first nomination for this connection.
+        old: Option<CandidatePair>,
+        new: CandidatePair,
+    },
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Ord)]
@@ -1517,6 +1652,10 @@ pub struct Transmit {
     pub dst: SocketAddr,
     /// The data that should be sent.
     pub payload: Buffer<Vec<u8>>,
+    /// How the data should be sent.
+    ///
+    /// Only messages to relays that we fell back to a stream transport for use anything other than [`Transport::Udp`].
+    pub transport: Transport,
 }
 
 impl fmt::Debug for Transmit {
@@ -1525,6 +1664,7 @@ impl fmt::Debug for Transmit {
             .field("src", &self.src)
             .field("dst", &self.dst)
             .field("len", &self.payload.len())
+            .field("transport", &self.transport)
             .finish()
     }
 }
@@ -1582,6 +1722,12 @@ struct Connection<RId> {
     agent: IceAgent,
 
     tunnel: Tunn,
+    /// A tunnel using an upgraded preshared key, until its first handshake completes.
+    ///
+    /// See [`Node::upgrade_preshared_key`].
+    upgraded_tunnel: Option<Tunn>,
+    /// The preshared key this connection was created with.
+    preshared_key: [u8; 32],
     remote_pub_key: PublicKey,
     /// When to next update the [`Tunn`]'s timers.
     next_wg_timer_update: Instant,
@@ -1596,6 +1742,14 @@ struct Connection<RId> {
     possible_sockets: BTreeSet<SocketAddr>,
 
     stats: ConnectionStats,
+    /// The ICE binding requests we sent most recently, for measuring the RTT.
+    pending_binding_requests: AllocRingBuffer<([u8; 12], Instant)>,
+    /// When we last sent a WireGuard handshake initiation, for measuring the RTT.
+    handshake_initiated_at: Option<Instant>,
+    /// When we last responded to a WireGuard handshake, for measuring the RTT.
+    ///
+    /// The initiator confirms the session with a keepalive as soon as it receives our response.
+    handshake_responded_at: Option<Instant>,
     intent_sent_at: Instant,
     signalling_completed_at: Instant,
 
@@ -1850,6 +2004,38 @@ where
         now.duration_since(self.intent_sent_at)
     }
 
+    fn on_binding_response(&mut self, transaction_id: [u8; 12], now: Instant) {
+        // Retransmissions re-use the transaction ID so we measure from the most recent one.
+        let Some((_, sent_at)) = self
+            .pending_binding_requests
+            .iter()
+            .filter(|(id, _)| *id == transaction_id)
+            .last()
+        else {
+            return;
+        };
+
+        self.stats.rtt = Some(now.duration_since(*sent_at));
+    }
+
+    /// Tracks handshakes in the WireGuard packets we send to the remote.
+    fn on_wg_transmit(&mut self, packet: &[u8], now: Instant) {
+        if crate::is_handshake_initiation(packet) {
+            self.handshake_initiated_at = Some(now);
+        }
+
+        // Responding to a handshake completes it from our end.
+        if crate::is_handshake_response(packet) {
+            self.handshake_responded_at = Some(now);
+            self.on_handshake_completed(now);
+        }
+    }
+
+    fn on_handshake_completed(&mut self, now: Instant) {
+        self.stats.handshakes += 1;
+        self.stats.last_handshake_at = Some(now);
+    }
+
     #[must_use]
     fn poll_timeout(&mut self) -> Option<(Instant, &'static str)> {
         iter::empty()
@@ -1891,6 +2077,7 @@ where
         now: Instant,
         allocations: &mut BTreeMap<RId, Allocation>,
         transmits: &mut VecDeque<Transmit>,
+        events: &mut VecDeque<Event<TId>>,
     ) where
         TId: Copy + Ord + fmt::Display,
         RId: Copy + Ord + fmt::Display,
@@ -1941,6 +2128,7 @@ where
                     tracing::debug!(grace_period = ?DISCONNECT_TIMEOUT, "Received ICE disconnect");
 
                     self.disconnected_at = Some(now);
+                    events.push_back(Event::ConnectionDisconnected(cid));
                 }
                 IceAgentEvent::IceConnectionStateChange(
                     IceConnectionState::Checking | IceConnectionState::Connected,
@@ -1974,6 +2162,9 @@ where
                         .iter()
                         .any(|c| c.addr() == destination && c.kind() == CandidateKind::Relayed);
 
+                    let candidate_pair =
+                        self.candidate_pair(source, destination, source_relay.is_some());
+
                     let remote_socket = match (source_relay, dest_is_relay) {
                         (None, false) => PeerSocket::PeerToPeer {
                             source,
@@ -2073,6 +2264,16 @@ where
                         "Updating remote socket"
                     );
 
+                    let old_candidate_pair = self.stats.candidate_pair.replace(candidate_pair);
+
+                    if old_candidate_pair != Some(candidate_pair) {
+                        events.push_back(Event::ConnectionPathChanged {
+                            connection: cid,
+                            old: old_candidate_pair,
+                            new: candidate_pair,
+                        });
+                    }
+
                     if self.agent.controlling() {
                         self.force_handshake(allocations, transmits, now);
                     }
@@ -2086,6 +2287,10 @@ where
             let dst = transmit.destination;
             let stun_packet = transmit.contents;
 
+            if let Some(transaction_id) = binding_request_transaction_id(&stun_packet) {
+                self.pending_binding_requests.push((transaction_id, now));
+            }
+
             // Check if `str0m` wants us to send from a "remote" socket, i.e. one that we allocated with a relay.
             let allocation = allocations
                 .iter_mut()
@@ -2099,6 +2304,7 @@ where
                     src: Some(source),
                     dst,
                     payload: self.buffer_pool.pull_initialised(&Vec::from(stun_packet)),
+                    transport: Transport::Udp,
                 });
                 continue;
             };
@@ -2120,6 +2326,7 @@ where
                 src: None,
                 dst: encode_ok.socket,
                 payload: self.buffer_pool.pull_initialised(&data_channel_packet),
+                transport: encode_ok.transport,
             });
         }
     }
@@ -2152,6 +2359,40 @@ where
                 tracing::warn!("boringtun error: {e}");
             }
             TunnResult::WriteToNetwork(b) => {
+                self.on_wg_transmit(b, now);
+
+                transmits.extend(make_owned_transmit(
+                    self.relay,
+                    peer_socket,
+                    b,
+                    &self.buffer_pool,
+                    allocations,
+                    now,
+                ));
+            }
+            TunnResult::WriteToTunnelV4(..) | TunnResult::WriteToTunnelV6(..) => {
+                panic!("Unexpected result from update_timers")
+            }
+        };
+
+        let Some(upgraded) = self.upgraded_tunnel.as_mut() else {
+            return;
+        };
+
+        match upgraded.update_timers_at(&mut buf, now) {
+            TunnResult::Done => {}
+            TunnResult::Err(WireGuardError::ConnectionExpired) => {
+                tracing::info!(
+                    "Failed to handshake with upgraded preshared key; keeping current one"
+                );
+                self.upgraded_tunnel = None;
+            }
+            TunnResult::Err(e) => {
+                tracing::warn!("boringtun error: {e}");
+            }
+            TunnResult::WriteToNetwork(b) => {
+                self.on_wg_transmit(b, now);
+
                 transmits.extend(make_owned_transmit(
                     self.relay,
                     peer_socket,
@@ -2201,6 +2442,10 @@ where
         let packet_end = packet_start + len;
         buffer.truncate(packet_end);
 
+        self.on_wg_transmit(&buffer[packet_start..], now);
+        self.stats.bytes_encapsulated += packet.packet().len();
+        self.stats.packets_encapsulated += 1;
+
         match socket {
             PeerSocket::PeerToPeer {
                 source,
@@ -2213,6 +2458,7 @@ where
                 src: Some(source),
                 dst: remote,
                 payload: buffer,
+                transport: Transport::Udp,
             })),
             PeerSocket::RelayToPeer { dest: peer } | PeerSocket::RelayToRelay { dest: peer } => {
                 let Some(allocation) = allocations.get_mut(&self.relay) else {
@@ -2231,6 +2477,7 @@ where
                     src: None,
                     dst: encode_ok.socket,
                     payload: buffer,
+                    transport: encode_ok.transport,
                 }))
             }
         }
@@ -2245,6 +2492,61 @@ where
         transmits: &mut VecDeque<Transmit>,
         now: Instant,
     ) -> ControlFlow<Result<()>, IpPacket>
+    where
+        TId: fmt::Display,
+    {
+        let Some(mut upgraded) = self.upgraded_tunnel.take() else {
+            return self.decapsulate_with_tunnel(cid, src, packet, allocations, transmits, now);
+        };
+
+        // Once we have upgraded the preshared key, all handshakes use the new key.
+        // Data packets are for the current tunnel until the remote uses the new session.
+        let is_handshake = crate::is_handshake(packet);
+
+        if !is_handshake {
+            let control_flow =
+                self.decapsulate_with_tunnel(cid, src, packet, allocations, transmits, now);
+
+            if !matches!(control_flow, ControlFlow::Break(Err(_))) {
+                self.upgraded_tunnel = Some(upgraded);
+                return control_flow;
+            }
+        }
+
+        mem::swap(&mut self.tunnel, &mut upgraded);
+        let control_flow =
+            self.decapsulate_with_tunnel(cid, src, packet, allocations, transmits, now);
+
+        let is_success = !matches!(control_flow, ControlFlow::Break(Err(_)));
+
+        // Handshake initiations don't tell us whether the remote is using the new key, only a completed handshake or data does.
+        if is_success && !crate::is_handshake_initiation(packet) {
+            tracing::debug!(%cid, "Switched to upgraded preshared key");
+
+            self.stats.upgraded_preshared_key = true;
+
+            return control_flow;
+        }
+
+        mem::swap(&mut self.tunnel, &mut upgraded);
+        self.upgraded_tunnel = Some(upgraded);
+
+        if is_handshake && !is_success {
+            return self.decapsulate_with_tunnel(cid, src, packet, allocations, transmits, now);
+        }
+
+        control_flow
+    }
+
+    fn decapsulate_with_tunnel<TId>(
+        &mut self,
+        cid: TId,
+        src: IpAddr,
+        packet: &[u8],
+        allocations: &mut BTreeMap<RId, Allocation>,
+        transmits: &mut VecDeque<Transmit>,
+        now: Instant,
+    ) -> ControlFlow<Result<()>, IpPacket>
     where
         TId: fmt::Display,
     {
@@ -2294,6 +2596,8 @@ where
             // This should be fairly rare which is why we just allocate these and return them from `poll_transmit` instead.
             // Overall, this results in a much nicer API for our caller and should not affect performance.
             TunnResult::WriteToNetwork(bytes) => {
+                self.on_wg_transmit(bytes, now);
+
                 match &mut self.state {
                     ConnectionState::Connecting { wg_buffer, .. } => {
                         tracing::debug!(%cid, "No socket has been nominated yet, buffering WG packet");
@@ -2341,6 +2645,28 @@ where
 
         if let ControlFlow::Continue(packet) = &control_flow {
             self.state.on_incoming(cid, &mut self.agent, packet, now);
+
+            self.stats.bytes_decapsulated += packet.packet().len();
+            self.stats.packets_decapsulated += 1;
+        }
+
+        // Handshake responses never yield an IP packet, thus we only need to check them here.
+        if matches!(control_flow, ControlFlow::Break(Ok(())))
+            && crate::is_handshake_response(packet)
+        {
+            if let Some(initiated_at) = self.handshake_initiated_at.take() {
+                self.stats.rtt = Some(now.duration_since(initiated_at));
+            }
+
+            self.on_handshake_completed(now);
+        }
+
+        // The first data packet after our handshake response is the initiator's keepalive (or data that superseded it).
+        if !matches!(control_flow, ControlFlow::Break(Err(_)))
+            && crate::is_data(packet)
+            && let Some(responded_at) = self.handshake_responded_at.take()
+        {
+            self.stats.rtt = Some(now.duration_since(responded_at));
         }
 
         control_flow
@@ -2368,6 +2694,8 @@ where
             return;
         };
 
+        self.handshake_initiated_at = Some(now);
+
         let socket = self
             .socket()
             .expect("cannot force handshake while not connected");
@@ -2382,6 +2710,81 @@ where
         ));
     }
 
+    fn upgrade_tunnel(
+        &mut self,
+        mut tunnel: Tunn,
+        initiate_handshake: bool,
+        allocations: &mut BTreeMap<RId, Allocation>,
+        transmits: &mut VecDeque<Transmit>,
+        now: Instant,
+    ) where
+        RId: Copy,
+    {
+        /// [`boringtun`] requires us to pass buffers in where it can construct its packets.
+        ///
+        /// The largest packet that we may have to send here is `148` bytes as per `HANDSHAKE_INIT_SZ` constant in [`boringtun`].
+        const MAX_SCRATCH_SPACE: usize = 148;
+
+        let mut buf = [0u8; MAX_SCRATCH_SPACE];
+
+        if initiate_handshake
+            && let Some(socket) = self.socket()
+            && let TunnResult::WriteToNetwork(bytes) =
+                tunnel.format_handshake_initiation_at(&mut buf, false, now)
+        {
+            self.handshake_initiated_at = Some(now);
+
+            transmits.extend(make_owned_transmit(
+                self.relay,
+                socket,
+                bytes,
+                &self.buffer_pool,
+                allocations,
+                now,
+            ));
+        }
+
+        self.upgraded_tunnel = Some(tunnel);
+    }
+
+    fn candidate_pair(
+        &self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        source_is_relay: bool,
+    ) -> CandidatePair {
+        let remote = self
+            .agent
+            .remote_candidates()
+            .iter()
+            .find(|c| c.addr() == destination)
+            .map(|c| candidate_type(c.kind()))
+            .unwrap_or(CandidateType::ServerReflexive); // Candidates we did not get signalled are peer-reflexive.
+
+        // str0m sends from the base of server-reflexive candidates, thus `source` looks like a host candidate.
+        // Unless we talk to a host candidate, our traffic must be passing through the NAT though.
+        let local = if source_is_relay {
+            CandidateType::Relay
+        } else if remote != CandidateType::Host
+            && self
+                .agent
+                .local_candidates()
+                .iter()
+                .any(|c| c.kind() == CandidateKind::ServerReflexive && c.base() == source)
+        {
+            CandidateType::ServerReflexive
+        } else {
+            CandidateType::Host
+        };
+
+        CandidatePair {
+            local,
+            local_addr: source,
+            remote,
+            remote_addr: destination,
+        }
+    }
+
     fn socket(&self) -> Option<PeerSocket> {
         match self.state {
             ConnectionState::Connected { peer_socket, .. }
@@ -2423,6 +2826,7 @@ where
             src: Some(source),
             dst: remote,
             payload: buffer_pool.pull_initialised(message),
+            transport: Transport::Udp,
         },
         PeerSocket::RelayToPeer { dest: peer } | PeerSocket::RelayToRelay { dest: peer } => {
             let allocation = allocations.get_mut(&relay)?;
@@ -2434,6 +2838,7 @@ where
                 src: None,
                 dst: encode_ok.socket,
                 payload: buffer_pool.pull_initialised(&channel_data),
+                transport: encode_ok.transport,
             }
         }
     };
@@ -2461,6 +2866,45 @@ fn apply_idle_stun_timings(agent: &mut IceAgent) {
     agent.set_initial_stun_rto(Duration::from_secs(25));
 }
 
+fn candidate_type(kind: CandidateKind) -> CandidateType {
+    match kind {
+        CandidateKind::Host => CandidateType::Host,
+        CandidateKind::ServerReflexive | CandidateKind::PeerReflexive => {
+            CandidateType::ServerReflexive
+        }
+        CandidateKind::Relayed => CandidateType::Relay,
+    }
+}
+
+const STUN_BINDING_REQUEST: [u8; 2] = [0x00, 0x01];
+const STUN_BINDING_SUCCESS_RESPONSE: [u8; 2] = [0x01, 0x01];
+
+fn binding_request_transaction_id(packet: &[u8]) -> Option<[u8; 12]> {
+    stun_transaction_id(packet, STUN_BINDING_REQUEST)
+}
+
+fn binding_success_response_transaction_id(packet: &[u8]) -> Option<[u8; 12]> {
+    stun_transaction_id(packet, STUN_BINDING_SUCCESS_RESPONSE)
+}
+
+/// Returns the transaction ID of the STUN message if it has the given message type.
+fn stun_transaction_id(packet: &[u8], message_type: [u8; 2]) -> Option<[u8; 12]> {
+    if packet.get(..2)? != message_type {
+        return None;
+    }
+
+    packet.get(8..20)?.try_into().ok()
+}
+
+/// Derives the upgraded preshared key of a connection by hashing its original one and the negotiated secret with a domain-separator.
+fn upgraded_preshared_key(preshared_key: &[u8; 32], secret: &[u8; 32]) -> [u8; 32] {
+    sha2::Sha256::new_with_prefix(b"UPGRADED-PRESHARED-KEY")
+        .chain_update(preshared_key)
+        .chain_update(secret)
+        .finalize()
+        .into()
+}
+
 /// A session ID is constant for as long as a [`Node`] is operational.
 #[derive(Debug, Default, Clone)]
 pub(crate) struct SessionId([u8; 32]);
@@ -2527,4 +2971,21 @@ mod tests {
 
         assert!(agent.remote_candidates().contains(&expected_candidate))
     }
+
+    #[test]
+    fn extracts_transaction_id_of_binding_messages() {
+        let mut request = vec![0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xA4, 0x42];
+        request.extend_from_slice(&[0xAB; 12]);
+
+        let mut response = request.clone();
+        response[0] = 0x01;
+
+        assert_eq!(binding_request_transaction_id(&request), Some([0xAB; 12]));
+        assert_eq!(binding_success_response_transaction_id(&request), None);
+        assert_eq!(
+            binding_success_response_transaction_id(&response),
+            Some([0xAB; 12])
+        );
+        assert_eq!(binding_request_transaction_id(&request[..10]), None);
+    }
 }
@@ -1,9 +1,17 @@
-use std::ops::AddAssign;
+use crate::NatBehaviour;
+use std::{
+    net::SocketAddr,
+    ops::AddAssign,
+    time::{Duration, Instant},
+};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct NodeStats {
     /// How many bytes we sent as part of exchanging STUN messages with relays (control messages only).
     pub stun_bytes_to_relays: HumanBytes,
+
+    /// How our NAT behaves, as discovered against the first relay that supports RFC 5780.
+    pub nat_behaviour: Option<NatBehaviour>,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -12,6 +20,79 @@ pub struct ConnectionStats {
     pub stun_bytes_to_peer_direct: HumanBytes,
     /// How many bytes we sent as part of exchanging STUN messages to other peers via relays.
     pub stun_bytes_to_peer_relayed: HumanBytes,
+
+    /// The most recently measured round-trip time to the peer.
+    ///
+    /// Measured from whichever completed last of:
+    /// - the ICE binding requests that keep the nominated candidate pair alive,
+    /// - our WireGuard handshake initiations and their responses,
+    /// - our WireGuard handshake responses and the keepalives confirming them.
+    pub rtt: Option<Duration>,
+
+    /// How many bytes of IP packets we encrypted for the peer.
+    pub bytes_encapsulated: HumanBytes,
+    /// How many IP packets we encrypted for the peer.
+    pub packets_encapsulated: u64,
+    /// How many bytes of IP packets we decrypted from the peer.
+    pub bytes_decapsulated: HumanBytes,
+    /// How many IP packets we decrypted from the peer.
+    pub packets_decapsulated: u64,
+
+    /// The candidate pair ICE nominated for this connection, if any.
+    pub candidate_pair: Option<CandidatePair>,
+
+    /// How many WireGuard handshakes completed on this connection.
+    pub handshakes: u32,
+    /// When the most recent WireGuard handshake completed.
+    pub last_handshake_at: Option<Instant>,
+
+    /// Whether the connection switched to a preshared key that was upgraded with a secret negotiated with the peer.
+    pub upgraded_preshared_key: bool,
+}
+
+/// A nominated ICE candidate pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CandidatePair {
+    pub local: CandidateType,
+    /// The address we send from; for relay candidates, this is our allocation on the relay.
+    pub local_addr: SocketAddr,
+    pub remote: CandidateType,
+    pub remote_addr: SocketAddr,
+}
+
+impl CandidatePair {
+    /// Whether traffic on this pair passes through a relay on either side.
+    pub fn is_relayed(&self) -> bool {
+        self.local == CandidateType::Relay || self.remote == CandidateType::Relay
+    }
+}
+
+impl std::fmt::Display for CandidatePair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} -> {} {}",
+            self.local, self.local_addr, self.remote, self.remote_addr
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateType {
+    Host,
+    /// A server-reflexive or peer-reflexive candidate, i.e. an address of a NAT.
+    ServerReflexive,
+    Relay,
+}
+
+impl std::fmt::Display for CandidateType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CandidateType::Host => write!(f, "host"),
+            CandidateType::ServerReflexive => write!(f, "srflx"),
+            CandidateType::Relay => write!(f, "relay"),
+        }
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -0,0 +1,62 @@
+//! The transports we can use to talk to a relay.
+//!
+//! Over UDP, every datagram carries exactly one STUN or channel-data message.
+//! Networks that block outbound UDP can still reach a relay via TCP or TLS (see <https://www.rfc-editor.org/rfc/rfc8656#section-3.1>).
+//! The messages on such a stream are framed by the `turn-stream` crate.
+
+/// The port on which relays accept TURN over TLS.
+///
+/// Port 443 is almost never blocked because it is also used for HTTPS.
+pub const TURN_TLS_PORT: u16 = 443;
+
+/// How a [`Transmit`](crate::Transmit) should be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum Transport {
+    /// As a single UDP datagram.
+    #[default]
+    Udp,
+    /// As a message on a TCP stream to the relay's TURN port.
+    Tcp,
+    /// As a message on a TLS stream to [`TURN_TLS_PORT`] of the relay.
+    Tls,
+}
+
+impl Transport {
+    pub fn is_stream(&self) -> bool {
+        match self {
+            Transport::Udp => false,
+            Transport::Tcp | Transport::Tls => true,
+        }
+    }
+
+    /// The transport we try next if a relay never responded on this one.
+    pub(crate) fn fallback(&self) -> Option<Self> {
+        match self {
+            Transport::Udp => Some(Transport::Tcp),
+            Transport::Tcp => Some(Transport::Tls),
+            Transport::Tls => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "UDP"),
+            Transport::Tcp => write!(f, "TCP"),
+            Transport::Tls => write!(f, "TLS"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_from_udp_to_tcp_to_tls() {
+        assert_eq!(Transport::Udp.fallback(), Some(Transport::Tcp));
+        assert_eq!(Transport::Tcp.fallback(), Some(Transport::Tls));
+        assert_eq!(Transport::Tls.fallback(), None);
+    }
+}
@@ -109,6 +109,12 @@ pub struct TcpStream {
     _backpack: Option<Box<dyn Any + Send + Sync + Unpin + 'static>>,
 }
 
+impl TcpStream {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
 impl tokio::io::AsyncWrite for TcpStream {
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -0,0 +1,544 @@
+//! Opt-in packet capture in the [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-03.html) format.
+//!
+//! Unlike running `tcpdump` on the TUN device and the physical interface, a capture taken from within connlib
+//! can annotate each packet with what connlib decided about it, i.e. which resource and which peer it was routed to.
+//!
+//! The capture has two interfaces:
+//!
+//! - [`TUN_INTERFACE`]: IP packets as they are read from and written to the TUN device, i.e. after any NAT has been applied.
+//! - [`NETWORK_INTERFACE`]: UDP datagrams as they are sent and received on our sockets.
+//!   These are encrypted, thus we annotate them with the IP packet they carry.
+//!   The IP and UDP headers are synthesized from the socket addresses.
+//!
+//! Blocks are written to the output by a dedicated thread so a slow disk never stalls the tunnel.
+//! If the writer falls behind, packets are dropped from the capture rather than queued indefinitely.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::mpsc,
+    thread,
+};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use connlib_model::{ClientId, GatewayId, ResourceId};
+use ip_packet::IpPacket;
+
+const TUN_INTERFACE: u32 = 0;
+const NETWORK_INTERFACE: u32 = 1;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Raw IP packets without a link-layer header, see <https://www.tcpdump.org/linktypes.html>.
+const LINKTYPE_RAW: u16 = 101;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const EPB_FLAGS: u16 = 2;
+
+const SNAP_LEN: u32 = 65535;
+
+/// How many blocks may be waiting for the writer thread before we start dropping packets.
+const MAX_QUEUED_BLOCKS: usize = 1024;
+
+/// The direction of a captured packet, relative to the interface it was captured on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// What connlib knows about the packet at the time it is captured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Labels {
+    pub(crate) resource: Option<ResourceId>,
+    pub(crate) gateway: Option<GatewayId>,
+    pub(crate) client: Option<ClientId>,
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+
+        if let Some(resource) = self.resource {
+            write!(f, "{separator}resource={resource}")?;
+            separator = " ";
+        }
+
+        if let Some(gateway) = self.gateway {
+            write!(f, "{separator}gateway={gateway}")?;
+            separator = " ";
+        }
+
+        if let Some(client) = self.client {
+            write!(f, "{separator}client={client}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes captured packets to a pcapng stream.
+pub(crate) struct Capture {
+    blocks: mpsc::SyncSender<Vec<u8>>,
+    writer_thread: thread::JoinHandle<()>,
+    buffer: Vec<u8>,
+    num_dropped: u64,
+}
+
+impl Capture {
+    /// Starts a new capture, writing the section header and interface descriptions to `writer`.
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Result<Self> {
+        let (blocks, rx) = mpsc::sync_channel(MAX_QUEUED_BLOCKS);
+        let writer_thread = thread::Builder::new()
+            .name("packet capture".to_owned())
+            .spawn(move || write_blocks(writer, rx))
+            .context("Failed to spawn packet capture thread")?;
+
+        let mut capture = Self {
+            blocks,
+            writer_thread,
+            buffer: Vec::with_capacity(2048),
+            num_dropped: 0,
+        };
+
+        capture.write_section_header()?;
+        capture.write_interface_description("tun")?;
+        capture.write_interface_description("network")?;
+
+        Ok(capture)
+    }
+
+    /// Stops the capture.
+    ///
+    /// The writer thread keeps running until all queued blocks are written and flushed.
+    /// Join the returned handle to wait for it.
+    pub(crate) fn finish(self) -> thread::JoinHandle<()> {
+        if self.num_dropped > 0 {
+            tracing::warn!(
+                num_dropped = self.num_dropped,
+                "Dropped packets from capture because the writer could not keep up"
+            );
+        }
+
+        self.writer_thread
+    }
+
+    /// Records an IP packet read from or written to the TUN device.
+    pub(crate) fn record_tun(
+        &mut self,
+        now: DateTime<Utc>,
+        direction: Direction,
+        packet: &IpPacket,
+        labels: Labels,
+    ) -> Result<()> {
+        let comment = labels.to_string();
+
+        self.write_packet(
+            now,
+            TUN_INTERFACE,
+            direction,
+            &[packet.packet()],
+            packet.packet().len(),
+            &comment,
+        )
+    }
+
+    /// Records a UDP datagram sent or received on one of our sockets.
+    ///
+    /// `inner` is the (unencrypted) IP packet carried by the datagram, if any.
+    /// A `src` of [`None`] means the datagram is sent from whichever interface the OS picks.
+    #[expect(clippy::too_many_arguments)]
+    pub(crate) fn record_network(
+        &mut self,
+        now: DateTime<Utc>,
+        direction: Direction,
+        src: Option<SocketAddr>,
+        dst: SocketAddr,
+        payload: &[u8],
+        inner: Option<&IpPacket>,
+        labels: Labels,
+    ) -> Result<()> {
+        let src = src.unwrap_or_else(|| match dst {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        });
+        let headers = udp_headers(src, dst, payload.len())?;
+        let comment = match inner {
+            Some(inner) => format!("{labels} {inner:?}").trim_start().to_owned(),
+            None => labels.to_string(),
+        };
+
+        self.write_packet(
+            now,
+            NETWORK_INTERFACE,
+            direction,
+            &[headers.as_slice(), payload],
+            headers.len() + payload.len(),
+            &comment,
+        )
+    }
+
+    fn write_section_header(&mut self) -> Result<()> {
+        self.buffer.clear();
+        self.buffer
+            .extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        self.buffer.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        self.buffer.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        self.buffer.extend_from_slice(&(-1i64).to_le_bytes()); // Section length is unknown.
+
+        self.write_block(SECTION_HEADER_BLOCK)
+    }
+
+    fn write_interface_description(&mut self, name: &str) -> Result<()> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        self.buffer.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+        self.buffer.extend_from_slice(&SNAP_LEN.to_le_bytes());
+        push_option(&mut self.buffer, IF_NAME, name.as_bytes());
+        push_option(&mut self.buffer, OPT_END_OF_OPT, &[]);
+
+        self.write_block(INTERFACE_DESCRIPTION_BLOCK)
+    }
+
+    fn write_packet(
+        &mut self,
+        now: DateTime<Utc>,
+        interface: u32,
+        direction: Direction,
+        data: &[&[u8]],
+        len: usize,
+        comment: &str,
+    ) -> Result<()> {
+        // The default timestamp resolution of an interface is microseconds.
+        let timestamp = u64::try_from(now.timestamp_micros()).unwrap_or_default();
+        let len = u32::try_from(len).context("Packet too large")?;
+        let flags: u32 = match direction {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        };
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&interface.to_le_bytes());
+        self.buffer
+            .extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        self.buffer
+            .extend_from_slice(&(timestamp as u32).to_le_bytes());
+        self.buffer.extend_from_slice(&len.to_le_bytes()); // Captured length
+        self.buffer.extend_from_slice(&len.to_le_bytes()); // Original length
+        for chunk in data {
+            self.buffer.extend_from_slice(chunk);
+        }
+        pad_to_32_bits(&mut self.buffer);
+        if !comment.is_empty() {
+            push_option(&mut self.buffer, OPT_COMMENT, comment.as_bytes());
+        }
+        push_option(&mut self.buffer, EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut self.buffer, OPT_END_OF_OPT, &[]);
+
+        self.write_block(ENHANCED_PACKET_BLOCK)
+    }
+
+    /// Queues the block body in `self.buffer`, framed by the block type and (twice) the total length.
+    ///
+    /// Blocks are only ever dropped whole, so the output stays a valid pcapng stream.
+    fn write_block(&mut self, block_type: u32) -> Result<()> {
+        let total_len = u32::try_from(self.buffer.len() + 12).context("Block too large")?;
+
+        let mut block = Vec::with_capacity(total_len as usize);
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&total_len.to_le_bytes());
+        block.extend_from_slice(&self.buffer);
+        block.extend_from_slice(&total_len.to_le_bytes());
+
+        match self.blocks.try_send(block) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.num_dropped += 1;
+
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                anyhow::bail!("Packet capture writer has stopped")
+            }
+        }
+    }
+}
+
+/// Writes blocks to `writer` until the [`Capture`] is dropped, flushing whenever we run out of blocks.
+fn write_blocks(writer: Box<dyn Write + Send>, blocks: mpsc::Receiver<Vec<u8>>) {
+    let mut writer = io::BufWriter::new(writer);
+
+    loop {
+        let block = match blocks.try_recv() {
+            Ok(block) => block,
+            Err(mpsc::TryRecvError::Empty) => {
+                if let Err(e) = writer.flush() {
+                    tracing::warn!("Failed to flush packet capture: {e}");
+                    return;
+                }
+
+                match blocks.recv() {
+                    Ok(block) => block,
+                    Err(mpsc::RecvError) => break,
+                }
+            }
+            Err(mpsc::TryRecvError::Disconnected) => break,
+        };
+
+        if let Err(e) = writer.write_all(&block) {
+            tracing::warn!("Failed to write packet capture: {e}");
+            return;
+        }
+    }
+
+    if let Err(e) = writer.flush() {
+        tracing::warn!("Failed to flush packet capture: {e}");
+    }
+}
+
+fn push_option(buffer: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buffer.extend_from_slice(&code.to_le_bytes());
+    buffer.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(value);
+    pad_to_32_bits(buffer);
+}
+
+fn pad_to_32_bits(buffer: &mut Vec<u8>) {
+    let padding = (4 - buffer.len() % 4) % 4;
+
+    buffer.extend(std::iter::repeat_n(0, padding));
+}
+
+/// Synthesizes IP and UDP headers for a datagram between the given socket addresses.
+///
+/// The UDP checksum is left empty because we never compute it over the payload.
+fn udp_headers(src: SocketAddr, dst: SocketAddr, payload_len: usize) -> Result<Vec<u8>> {
+    const UDP: u8 = 17;
+
+    let udp_len = u16::try_from(payload_len + 8).context("Datagram too large")?;
+
+    let mut headers = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let total_len = udp_len.checked_add(20).context("Datagram too large")?;
+
+            let mut header = Vec::with_capacity(28);
+            header.extend_from_slice(&[0x45, 0]); // Version & IHL, DSCP & ECN
+            header.extend_from_slice(&total_len.to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0x40, 0]); // Identification, flags (DF) & fragment offset
+            header.extend_from_slice(&[64, UDP, 0, 0]); // TTL, protocol & checksum
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+
+            let checksum = ipv4_header_checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            header
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let mut header = Vec::with_capacity(48);
+            header.extend_from_slice(&[0x60, 0, 0, 0]); // Version, traffic class & flow label
+            header.extend_from_slice(&udp_len.to_be_bytes());
+            header.extend_from_slice(&[UDP, 64]); // Next header & hop limit
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+
+            header
+        }
+        (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => {
+            anyhow::bail!("Cannot capture datagram from {src} to {dst}")
+        }
+    };
+
+    headers.extend_from_slice(&src.port().to_be_bytes());
+    headers.extend_from_slice(&dst.port().to_be_bytes());
+    headers.extend_from_slice(&udp_len.to_be_bytes());
+    headers.extend_from_slice(&[0, 0]); // Checksum
+
+    Ok(headers)
+}
+
+fn ipv4_header_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks_exact(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    let folded = (sum & 0xFFFF) + (sum >> 16);
+    let folded = (folded & 0xFFFF) + (folded >> 16);
+
+    !(folded as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::SocketAddrV4,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn starts_with_section_header_and_two_interfaces() {
+        let (writer, output) = SharedBuffer::new();
+
+        Capture::new(Box::new(writer))
+            .unwrap()
+            .finish()
+            .join()
+            .unwrap();
+
+        let blocks = blocks(&output.lock().unwrap());
+
+        assert_eq!(
+            blocks.iter().map(|(ty, _)| *ty).collect::<Vec<_>>(),
+            vec![
+                SECTION_HEADER_BLOCK,
+                INTERFACE_DESCRIPTION_BLOCK,
+                INTERFACE_DESCRIPTION_BLOCK
+            ]
+        );
+    }
+
+    #[test]
+    fn network_datagram_has_synthesized_headers_and_labels() {
+        let (writer, output) = SharedBuffer::new();
+        let mut capture = Capture::new(Box::new(writer)).unwrap();
+
+        let src = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 52625));
+        let dst = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 3478));
+        let resource = ResourceId::from_u128(1);
+
+        capture
+            .record_network(
+                DateTime::UNIX_EPOCH,
+                Direction::Outbound,
+                Some(src),
+                dst,
+                b"hello",
+                None,
+                Labels {
+                    resource: Some(resource),
+                    ..Labels::default()
+                },
+            )
+            .unwrap();
+        capture.finish().join().unwrap();
+
+        let output = output.lock().unwrap();
+        let (ty, body) = blocks(&output).pop().unwrap();
+        assert_eq!(ty, ENHANCED_PACKET_BLOCK);
+
+        let interface = u32::from_le_bytes(body[0..4].try_into().unwrap());
+        let captured_len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+        let packet = &body[20..20 + captured_len];
+
+        assert_eq!(interface, NETWORK_INTERFACE);
+        assert_eq!(captured_len, 20 + 8 + 5);
+        assert_eq!(ipv4_header_checksum(&packet[..20]), 0);
+        assert_eq!(&packet[28..], b"hello");
+
+        let comment = format!("resource={resource}");
+        assert!(
+            body.windows(comment.len())
+                .any(|window| window == comment.as_bytes())
+        );
+    }
+
+    #[test]
+    fn drops_whole_packets_instead_of_blocking_on_a_slow_writer() {
+        let (writer, output) = SharedBuffer::new();
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let mut capture = Capture::new(Box::new(GatedWriter {
+            gate: gate.clone(),
+            inner: writer,
+        }))
+        .unwrap();
+
+        for _ in 0..(MAX_QUEUED_BLOCKS * 2) {
+            capture
+                .record_network(
+                    DateTime::UNIX_EPOCH,
+                    Direction::Inbound,
+                    None,
+                    SocketAddr::from((Ipv4Addr::LOCALHOST, 3478)),
+                    b"hello",
+                    None,
+                    Labels::default(),
+                )
+                .unwrap();
+        }
+        assert!(capture.num_dropped > 0);
+
+        drop(blocked);
+        capture.finish().join().unwrap();
+
+        let blocks = blocks(&output.lock().unwrap());
+        assert_eq!(blocks[0].0, SECTION_HEADER_BLOCK);
+        assert!(blocks.len() < 3 + MAX_QUEUED_BLOCKS * 2);
+    }
+
+    /// Parses the blocks of a pcapng stream into their type and body.
+    fn blocks(mut bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = Vec::new();
+
+        while !bytes.is_empty() {
+            let ty = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+            let trailing_len = u32::from_le_bytes(bytes[len - 4..len].try_into().unwrap()) as usize;
+
+            assert_eq!(len % 4, 0);
+            assert_eq!(len, trailing_len);
+
+            blocks.push((ty, bytes[8..len - 4].to_vec()));
+            bytes = &bytes[len..];
+        }
+
+        blocks
+    }
+
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn new() -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(Vec::new()));
+
+            (Self(output.clone()), output)
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Blocks every write until `gate` is unlocked.
+    struct GatedWriter {
+        gate: Arc<Mutex<()>>,
+        inner: SharedBuffer,
+    }
+
+    impl Write for GatedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _gate = self.gate.lock().unwrap();
+
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
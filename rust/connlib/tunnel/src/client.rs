@@ -1,24 +1,36 @@
+mod diagnosis;
+mod dns_cache;
 mod dns_resource_nat;
+mod post_quantum_psk;
 mod resource;
+mod standby_gateways;
+mod traffic;
 
+pub use diagnosis::{ConnectionState, Diagnosis, FlowFailure, Problem, ResourceDiagnosis};
+use dns_cache::DnsCache;
 use dns_resource_nat::DnsResourceNat;
 use dns_types::ResponseCode;
+use post_quantum_psk::PostQuantumPsk;
 pub(crate) use resource::{CidrResource, Resource};
 #[cfg(all(feature = "proptest", test))]
 pub(crate) use resource::{DnsResource, InternetResource};
 use ringbuffer::{AllocRingBuffer, RingBuffer};
+use standby_gateways::StandbyGateways;
+use traffic::TrafficCounters;
 
 use crate::dns::StubResolver;
 use crate::expiring_map::ExpiringMap;
+use crate::messages::client::{FailReason, ViolatedProperty};
 use crate::messages::{DnsServer, Interface as InterfaceConfig, IpDnsServer};
 use crate::messages::{IceCredentials, SecretKey};
 use crate::peer_store::PeerStore;
 use crate::unique_packet_buffer::UniquePacketBuffer;
-use crate::{IPV4_TUNNEL, IPV6_TUNNEL, IpConfig, TunConfig, dns, is_peer, p2p_control};
+use crate::{IPV4_TUNNEL, IPV6_TUNNEL, IpConfig, TunConfig, capture, dns, is_peer, p2p_control};
 use anyhow::Context;
 use bimap::BiMap;
 use connlib_model::{
-    GatewayId, IceCandidate, PublicKey, RelayId, ResourceId, ResourceStatus, ResourceView,
+    ConnectionPath, GatewayId, IceCandidate, PublicKey, RelayId, ResourceId, ResourceStatus,
+    ResourceView,
 };
 use connlib_model::{Site, SiteId};
 use firezone_logging::{err_with_src, unwrap_or_debug, unwrap_or_warn};
@@ -31,7 +43,7 @@ use crate::ClientEvent;
 use crate::peer::GatewayOnClient;
 use lru::LruCache;
 use secrecy::{ExposeSecret as _, Secret};
-use snownet::{ClientNode, NoTurnServers, RelaySocket, Transmit};
+use snownet::{CandidatePair, ClientNode, ConnectionStats, NoTurnServers, RelaySocket, Transmit};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroUsize;
@@ -97,12 +109,22 @@ pub struct ClientState {
     /// Tracks the flows to resources that we are currently trying to establish.
     pending_flows: HashMap<ResourceId, PendingFlow>,
     dns_resource_nat: DnsResourceNat,
+    /// Negotiates post-quantum preshared keys with gateways.
+    post_quantum_psk: PostQuantumPsk,
     /// Tracks which gateway to use for a particular Resource.
     resources_gateways: HashMap<ResourceId, GatewayId>,
+    /// Tracks which gateway to fail over to for a particular Resource.
+    standby_gateways: StandbyGateways,
+    /// Why the portal last refused to create a flow for a particular Resource.
+    flow_failures: HashMap<ResourceId, FlowFailure>,
     /// The site a gateway belongs to.
     gateways_site: HashMap<GatewayId, SiteId>,
+    /// How packets to a connected gateway travel.
+    gateways_path: HashMap<GatewayId, ConnectionPath>,
     /// The online/offline status of a site.
     sites_status: HashMap<SiteId, ResourceStatus>,
+    /// How much traffic we exchanged with each resource.
+    traffic: TrafficCounters,
 
     /// All CIDR resources we know about, indexed by the IP range they cover (like `1.1.0.0/8`).
     active_cidr_resources: IpNetworkTable<CidrResource>,
@@ -124,6 +146,8 @@ pub struct ClientState {
     udp_dns_sockets_by_upstream_and_query_id: ExpiringMap<(SocketAddr, u16), SocketAddr>,
     /// Manages internal dns records and emits forwarding event when not internally handled
     stub_resolver: StubResolver,
+    /// Caches responses to DNS queries that we forwarded to upstream resolvers via the host.
+    dns_cache: DnsCache,
 
     /// Configuration of the TUN device, when it is up.
     tun_config: Option<TunConfig>,
@@ -188,9 +212,14 @@ impl PendingFlow {
 }
 
 impl ClientState {
-    pub(crate) fn new(seed: [u8; 32], now: Instant) -> Self {
+    /// Creates a new [`ClientState`].
+    ///
+    /// `seed` determines our WireGuard private key, `post_quantum_psk_seed` must therefore be independent of it.
+    pub(crate) fn new(seed: [u8; 32], post_quantum_psk_seed: [u8; 32], now: Instant) -> Self {
         Self {
             resources_gateways: Default::default(),
+            standby_gateways: Default::default(),
+            flow_failures: Default::default(),
             active_cidr_resources: IpNetworkTable::new(),
             resources_by_id: Default::default(),
             peers: Default::default(),
@@ -201,9 +230,12 @@ impl ClientState {
             node: ClientNode::new(seed, now),
             system_resolvers: Default::default(),
             sites_status: Default::default(),
+            traffic: Default::default(),
             gateways_site: Default::default(),
+            gateways_path: Default::default(),
             udp_dns_sockets_by_upstream_and_query_id: Default::default(),
             stub_resolver: Default::default(),
+            dns_cache: Default::default(),
             disabled_resources: Default::default(),
             buffered_transmits: Default::default(),
             internet_resource: None,
@@ -215,6 +247,7 @@ impl ClientState {
             tcp_dns_streams_by_upstream_and_query_id: Default::default(),
             pending_flows: Default::default(),
             dns_resource_nat: Default::default(),
+            post_quantum_psk: PostQuantumPsk::new(post_quantum_psk_seed),
         }
     }
 
@@ -237,7 +270,14 @@ impl ClientState {
             .cloned()
             .map(|r| {
                 let status = self.resource_status(&r);
-                r.with_status(status)
+                let traffic = self.traffic.get(&r.id());
+                let path = self
+                    .resources_gateways
+                    .get(&r.id())
+                    .and_then(|gid| self.gateways_path.get(gid))
+                    .copied();
+
+                r.with_status(status).with_traffic(traffic).with_path(path)
             })
             .sorted()
             .collect_vec()
@@ -263,7 +303,38 @@ impl ClientState {
         ResourceStatus::Unknown
     }
 
+    /// Handles the portal refusing to create a flow to a resource.
+    pub fn on_flow_creation_failed(
+        &mut self,
+        id: ResourceId,
+        reason: FailReason,
+        violated_properties: Vec<ViolatedProperty>,
+    ) {
+        self.flow_failures.insert(
+            id,
+            FlowFailure {
+                reason: reason.clone(),
+                violated_properties,
+            },
+        );
+
+        match reason {
+            FailReason::Offline => self.set_resource_offline(id),
+            FailReason::NotFound | FailReason::Forbidden | FailReason::Unknown => {
+                tracing::debug!(%id, "Failed to create flow: {reason:?}")
+            }
+        }
+    }
+
     pub fn set_resource_offline(&mut self, id: ResourceId) {
+        let standby_requested = self.standby_gateways.on_flow_creation_failed(id);
+
+        // Whilst we are also waiting for the primary Gateway, we can't tell which request failed: assume the worst.
+        if standby_requested && !self.pending_flows.contains_key(&id) {
+            tracing::debug!(%id, "No standby gateway available for resource");
+            return;
+        }
+
         let Some(resource) = self.resources_by_id.get(&id).cloned() else {
             return;
         };
@@ -280,6 +351,22 @@ impl ClientState {
         self.node.public_key()
     }
 
+    /// Statistics about our connections to Gateways.
+    pub fn connection_stats(&self) -> impl Iterator<Item = (GatewayId, ConnectionStats)> + '_ {
+        let (_, connections) = self.node.stats();
+
+        connections
+    }
+
+    /// How our NAT behaves, as discovered against our relays.
+    ///
+    /// Helps explain why connections to Gateways are always relayed.
+    pub fn nat_behaviour(&self) -> Option<snownet::NatBehaviour> {
+        let (node_stats, _) = self.node.stats();
+
+        node_stats.nat_behaviour
+    }
+
     /// Updates the NAT for all domains resolved by the stub resolver on the corresponding gateway.
     ///
     /// In order to route traffic for DNS resources, the designated gateway needs to set up NAT from
@@ -424,6 +511,7 @@ impl ClientState {
                 gid,
                 fz_p2p_control,
                 &mut self.dns_resource_nat,
+                &mut self.post_quantum_psk,
                 &mut self.node,
                 &mut self.buffered_transmits,
                 now,
@@ -441,15 +529,30 @@ impl ClientState {
             .inspect_err(|e| tracing::debug!(%gid, %local, %from, "{e}"))
             .ok()?;
 
-        let packet = maybe_mangle_dns_response_from_upstream_dns_server(
-            packet,
-            &mut self.udp_dns_sockets_by_upstream_and_query_id,
-        );
+        let src = packet.source();
+
+        if !is_peer(src)
+            && let Some(rid) = self.get_resource_by_destination(src)
+        {
+            self.traffic.on_received(rid, packet.packet().len(), now);
+        }
+
+        let packet = self.maybe_mangle_dns_response_from_upstream_dns_server(packet, now);
 
         Some(packet)
     }
 
-    pub(crate) fn handle_dns_response(&mut self, response: dns::RecursiveResponse) {
+    /// Handles the response to a [`dns::RecursiveQuery`] emitted via [`ClientState::poll_dns_queries`].
+    pub(crate) fn handle_dns_response(&mut self, response: dns::RecursiveResponse, now: Instant) {
+        if let Ok(message) = &response.message {
+            self.dns_cache
+                .insert(response.server, &response.query, message, now);
+        }
+
+        self.send_dns_response(response);
+    }
+
+    fn send_dns_response(&mut self, response: dns::RecursiveResponse) {
         let qid = response.query.id();
         let server = response.server;
         let domain = response.query.domain();
@@ -499,6 +602,78 @@ impl ClientState {
         }
     }
 
+    fn maybe_mangle_dns_response_from_upstream_dns_server(
+        &mut self,
+        mut packet: IpPacket,
+        now: Instant,
+    ) -> IpPacket {
+        let src_ip = packet.source();
+
+        let Some(udp) = packet.as_udp() else {
+            return packet;
+        };
+
+        let src_port = udp.source_port();
+        let dst_port = udp.destination_port();
+        let src_socket = SocketAddr::new(src_ip, src_port);
+
+        let Ok(message) = dns_types::Response::parse(udp.payload()) else {
+            return packet;
+        };
+
+        let Some(original_dst) = self
+            .udp_dns_sockets_by_upstream_and_query_id
+            .remove(&(src_socket, message.id()))
+        else {
+            return packet;
+        };
+
+        tracing::trace!(server = %src_ip, query_id = %message.id(), domain = %message.domain(), "Received UDP DNS response via tunnel");
+
+        if let Some(response) = self.handle_site_dns_response(&message, now) {
+            match ip_packet::make::udp_packet(
+                original_dst.ip(),
+                packet.destination(),
+                original_dst.port(),
+                dst_port,
+                response.into_bytes(MAX_UDP_PAYLOAD),
+            ) {
+                Ok(packet) => return packet,
+                Err(e) => {
+                    tracing::debug!("Failed to create rewritten UDP DNS response: {e:#}");
+                }
+            }
+        }
+
+        if let Err(e) = packet.set_src(original_dst.ip()) {
+            tracing::warn!("Failed to set source IP for UDP DNS query: {e:#}");
+        }
+
+        packet
+            .as_udp_mut()
+            .expect("we parsed it as a UDP packet earlier")
+            .set_source_port(original_dst.port());
+
+        packet.update_checksum();
+
+        packet
+    }
+
+    /// Rewrites a DNS response from a site such that SRV targets and CNAMEs are resolved via the tunnel, see [`StubResolver::handle_site_response`].
+    ///
+    /// Returns `None` if the response doesn't need to be rewritten.
+    fn handle_site_dns_response(
+        &mut self,
+        response: &dns_types::Response,
+        now: Instant,
+    ) -> Option<dns_types::Response> {
+        let response = self.stub_resolver.handle_site_response(response, now)?;
+
+        self.update_dns_resource_nat(now, iter::empty());
+
+        Some(response)
+    }
+
     fn encapsulate(&mut self, mut packet: IpPacket, now: Instant) -> Option<snownet::Transmit> {
         let dst = packet.destination();
 
@@ -519,6 +694,9 @@ impl ClientState {
                 return None;
             };
 
+            // Count the packet as soon as it enters the tunnel, it may be buffered until the connection is ready.
+            self.traffic.on_sent(resource, packet.packet().len(), now);
+
             let Some(peer) =
                 peer_by_resource_mut(&self.resources_gateways, &mut self.peers, resource)
             else {
@@ -555,9 +733,10 @@ impl ClientState {
         dst: SocketAddr,
         message: dns_types::Response,
     ) -> anyhow::Result<()> {
-        let saddr = *self
+        let saddr = self
             .dns_mapping
-            .get_by_right(&DnsServer::from(from))
+            .iter()
+            .find_map(|(sentinel, server)| (server.address() == from).then_some(*sentinel))
             .context("Unknown DNS server")?;
 
         let ip_packet = ip_packet::make::udp_packet(
@@ -582,7 +761,7 @@ impl ClientState {
         self.node
             .add_remote_candidate(conn_id, ice_candidate.into(), now);
         self.node.handle_timeout(now);
-        self.drain_node_events();
+        self.drain_node_events(now);
     }
 
     pub fn remove_ice_candidate(
@@ -594,7 +773,7 @@ impl ClientState {
         self.node
             .remove_remote_candidate(conn_id, ice_candidate.into(), now);
         self.node.handle_timeout(now);
-        self.drain_node_events();
+        self.drain_node_events(now);
     }
 
     #[tracing::instrument(level = "debug", skip_all, fields(%rid))]
@@ -615,7 +794,23 @@ impl ClientState {
 
         let resource = self.resources_by_id.get(&rid).context("Unknown resource")?;
 
+        self.flow_failures.remove(&rid);
+
         let Some(pending_flow) = self.pending_flows.remove(&rid) else {
+            if self.standby_gateways.on_flow_created(rid, gid) {
+                return Ok(self.add_standby_gateway(
+                    rid,
+                    gid,
+                    gateway_key,
+                    gateway_tun,
+                    site_id,
+                    preshared_key,
+                    client_ice,
+                    gateway_ice,
+                    now,
+                ));
+            }
+
             tracing::debug!("No pending flow");
 
             return Ok(Ok(()));
@@ -718,9 +913,124 @@ impl ClientState {
             self.forward_tcp_dns_query_to_new_upstream_via_tunnel(server, query);
         }
 
+        self.request_standby_gateway(rid, gid);
+
         Ok(Ok(()))
     }
 
+    #[expect(clippy::too_many_arguments)]
+    fn add_standby_gateway(
+        &mut self,
+        rid: ResourceId,
+        gid: GatewayId,
+        gateway_key: PublicKey,
+        gateway_tun: IpConfig,
+        site_id: SiteId,
+        preshared_key: SecretKey,
+        client_ice: IceCredentials,
+        gateway_ice: IceCredentials,
+        now: Instant,
+    ) -> Result<(), NoTurnServers> {
+        if self.resources_gateways.get(&rid).is_some_and(|g| *g == gid) {
+            tracing::debug!(%gid, "Portal selected our primary gateway as standby");
+
+            self.standby_gateways.remove_resource(&rid);
+            return Ok(());
+        }
+
+        tracing::debug!(%gid, "Connecting to standby gateway");
+
+        self.node.upsert_connection(
+            gid,
+            gateway_key,
+            Secret::new(preshared_key.expose_secret().0),
+            snownet::Credentials {
+                username: client_ice.username,
+                password: client_ice.password,
+            },
+            snownet::Credentials {
+                username: gateway_ice.username,
+                password: gateway_ice.password,
+            },
+            now,
+        )?;
+        self.gateways_site.insert(gid, site_id);
+        self.recently_connected_gateways.put(gid, ());
+
+        if self.peers.get(&gid).is_none() {
+            self.peers
+                .insert(GatewayOnClient::new(gid, gateway_tun), &[]);
+        };
+
+        self.peers.add_ip(&gid, &gateway_tun.v4.into());
+        self.peers.add_ip(&gid, &gateway_tun.v6.into());
+
+        Ok(())
+    }
+
+    fn request_standby_gateway(&mut self, rid: ResourceId, primary: GatewayId) {
+        if !self.standby_gateways.on_primary(rid, primary) {
+            return;
+        }
+
+        tracing::debug!(%rid, %primary, "Requesting standby gateway");
+
+        self.buffered_events
+            .push_back(ClientEvent::ConnectionIntent {
+                resource: rid,
+                connected_gateway_ids: self.connected_gateway_ids(),
+                excluded_gateway_ids: BTreeSet::from([primary]),
+            })
+    }
+
+    /// Switches all resources of the given gateway over to their standby gateway.
+    ///
+    /// Resources whose standby is not connected stay with the current gateway.
+    fn fail_over_to_standby_gateways(&mut self, gid: GatewayId, now: Instant) {
+        let resources = self
+            .resources_gateways
+            .iter()
+            .filter_map(|(rid, g)| (*g == gid).then_some(*rid))
+            .collect::<Vec<_>>();
+        let mut dns_resources_failed_over = false;
+
+        for rid in resources {
+            let Some(standby) = self.standby_gateways.get(&rid) else {
+                continue;
+            };
+
+            if !self.node.is_connected(standby) {
+                tracing::debug!(%rid, %standby, "Standby gateway is not connected");
+                continue;
+            }
+
+            let Some(resource) = self.resources_by_id.get(&rid) else {
+                continue;
+            };
+
+            tracing::info!(%rid, from = %gid, to = %standby, "Failing over to standby gateway");
+
+            self.standby_gateways.fail_over(rid, gid);
+            self.resources_gateways.insert(rid, standby);
+            self.peers.remove_resource(&gid, &rid);
+
+            match resource {
+                Resource::Cidr(_) | Resource::Internet(_) => {
+                    self.peers.add_ips_with_resource(
+                        &standby,
+                        resource.addresses().into_iter(),
+                        &rid,
+                    );
+                }
+                Resource::Dns(_) => dns_resources_failed_over = true,
+            }
+        }
+
+        if dns_resources_failed_over {
+            self.update_dns_resource_nat(now, iter::empty());
+        }
+    }
+
     fn is_upstream_set_by_the_portal(&self) -> bool {
         !self.upstream_dns.is_empty()
     }
@@ -728,16 +1038,20 @@ impl ClientState {
     /// For DNS queries to IPs that are a CIDR resources we want to mangle and forward to the gateway that handles that resource.
     ///
     /// We only want to do this if the upstream DNS server is set by the portal, otherwise, the server might be a local IP.
-    fn should_forward_dns_query_to_gateway(&self, dns_server: IpAddr) -> bool {
+    /// Encrypted queries are always sent via the host because the gateway can only forward plaintext DNS.
+    fn should_forward_dns_query_to_gateway(&self, dns_server: &DnsServer) -> bool {
         if !self.is_upstream_set_by_the_portal() {
             return false;
         }
+        if dns_server.is_encrypted() {
+            return false;
+        }
         if self.internet_resource.is_some() {
             return true;
         }
 
         self.active_cidr_resources
-            .longest_match(dns_server)
+            .longest_match(dns_server.ip())
             .is_some()
     }
 
@@ -750,7 +1064,7 @@ impl ClientState {
             return ControlFlow::Break(());
         }
 
-        let Some(upstream) = self.dns_mapping.get_by_left(&dst).map(|s| s.address()) else {
+        let Some(upstream) = self.dns_mapping.get_by_left(&dst).cloned() else {
             return ControlFlow::Continue(packet); // Not for our DNS resolver.
         };
 
@@ -808,6 +1122,7 @@ impl ClientState {
             .push_back(ClientEvent::ConnectionIntent {
                 resource: rid,
                 connected_gateway_ids: self.connected_gateway_ids(),
+                excluded_gateway_ids: BTreeSet::new(),
             })
     }
 
@@ -824,6 +1139,153 @@ impl ClientState {
         self.resources_gateways.get(resource).copied()
     }
 
+    /// The resource and gateway that `packet` is routed to or from, for annotating packet captures.
+    ///
+    /// The resource is the destination of packets we read from the TUN device and the source of packets we write to it.
+    pub(crate) fn capture_labels(
+        &self,
+        packet: &IpPacket,
+        direction: capture::Direction,
+    ) -> capture::Labels {
+        let resource_ip = match direction {
+            capture::Direction::Outbound => packet.destination(),
+            capture::Direction::Inbound => packet.source(),
+        };
+        let resource = self.get_resource_by_destination(resource_ip);
+
+        capture::Labels {
+            resource,
+            gateway: resource.and_then(|r| self.gateway_by_resource(&r)),
+            client: None,
+        }
+    }
+
+    /// Explains whether and how traffic to `host` (a hostname or IP) is routed through the tunnel.
+    ///
+    /// Filters on ports and protocols are enforced by the Gateway and therefore not part of the [`Diagnosis`].
+    pub fn diagnose(&self, host: &str) -> Diagnosis {
+        let mut proxy_ips = Vec::new();
+        let mut domain = None;
+        let mut dns_upstream = None;
+        let mut is_dns_resource = false;
+        let mut hint = None;
+
+        let ip = host.parse::<IpAddr>().ok();
+
+        let rid = match ip {
+            Some(ip) => {
+                dns_upstream = self.dns_mapping.get_by_left(&ip).cloned();
+                domain = self
+                    .stub_resolver
+                    .resolve_resource_by_ip(&ip)
+                    .map(|(domain, _)| domain.to_string());
+
+                self.resource_by_ip_including_disabled(ip)
+            }
+            None => {
+                let rid = dns_types::DomainName::vec_from_str(host)
+                    .ok()
+                    .and_then(|domain| self.stub_resolver.resource_by_domain(&domain))
+                    .map(|(rid, ips)| {
+                        proxy_ips = ips;
+                        is_dns_resource = true;
+
+                        rid
+                    });
+
+                // Without resolving `host`, we can't tell whether it is covered by a CIDR or the Internet resource.
+                if rid.is_none() {
+                    hint = Some(format!(
+                        "{host} is not a DNS resource, diagnose the IP it resolves to instead"
+                    ));
+                }
+
+                rid
+            }
+        };
+
+        let resource = rid
+            .and_then(|rid| self.resources_by_id.get(&rid))
+            .map(|r| self.diagnose_resource(r));
+
+        let problem = if self.tun_config.is_none() {
+            Some(Problem::NoTunnel)
+        } else {
+            match &resource {
+                None => Some(Problem::NotAResource),
+                Some(r) if !r.enabled => Some(Problem::ResourceDisabled),
+                Some(r) if r.status == ResourceStatus::Offline => Some(Problem::SitesOffline),
+                Some(r)
+                    if r.last_failure.is_some() && r.connection != ConnectionState::Connected =>
+                {
+                    Some(Problem::FlowCreationFailed)
+                }
+                Some(_) if is_dns_resource && proxy_ips.is_empty() => {
+                    Some(Problem::NotResolvedViaFirezone)
+                }
+                Some(_) => None,
+            }
+        };
+
+        Diagnosis {
+            host: host.to_owned(),
+            proxy_ips,
+            domain,
+            dns_upstream,
+            resource,
+            problem,
+            hint,
+        }
+    }
+
+    /// Like [`ClientState::get_resource_by_destination`] but also considers disabled resources.
+    fn resource_by_ip_including_disabled(&self, ip: IpAddr) -> Option<ResourceId> {
+        if let Some(rid) = self.get_resource_by_destination(ip) {
+            return Some(rid);
+        }
+
+        if let Some((_, rid)) = self.stub_resolver.resolve_resource_by_ip(&ip) {
+            return Some(*rid);
+        }
+
+        self.resources_by_id
+            .values()
+            .filter_map(|r| match r {
+                Resource::Cidr(cidr) if cidr.address.contains(ip) => {
+                    Some((cidr.address.netmask(), cidr.id))
+                }
+                Resource::Cidr(_) | Resource::Dns(_) | Resource::Internet(_) => None,
+            })
+            .max_by_key(|(netmask, _)| *netmask)
+            .map(|(_, rid)| rid)
+    }
+
+    fn diagnose_resource(&self, resource: &Resource) -> ResourceDiagnosis {
+        let id = resource.id();
+        let gateway = self.resources_gateways.get(&id).copied();
+
+        let connection = if gateway.is_some_and(|gid| self.peers.get(&gid).is_some()) {
+            ConnectionState::Connected
+        } else if self.pending_flows.contains_key(&id) {
+            ConnectionState::Pending
+        } else {
+            ConnectionState::NotConnected
+        };
+
+        ResourceDiagnosis {
+            id,
+            name: resource.name().to_owned(),
+            address: resource.address_string(),
+            sites: resource.sites().into_iter().cloned().collect(),
+            status: self.resource_status(resource),
+            enabled: self.is_resource_enabled(&id),
+            gateway,
+            gateway_site: gateway.and_then(|gid| self.gateways_site.get(&gid).copied()),
+            connection,
+            last_failure: self.flow_failures.get(&id).cloned(),
+        }
+    }
+
     fn set_dns_mapping(&mut self, new_mapping: BiMap<IpAddr, DnsServer>) {
         self.dns_mapping = new_mapping;
     }
@@ -872,17 +1334,50 @@ impl ClientState {
         self.maybe_update_tun_routes()
     }
 
+    pub fn set_standby_gateways_enabled(&mut self, enabled: bool) {
+        self.standby_gateways.set_enabled(enabled);
+
+        if !enabled {
+            return;
+        }
+
+        for (rid, gid) in self.resources_gateways.clone() {
+            self.request_standby_gateway(rid, gid);
+        }
+    }
+
     pub fn dns_mapping(&self) -> BiMap<IpAddr, DnsServer> {
         self.dns_mapping.clone()
     }
 
     #[tracing::instrument(level = "debug", skip_all, fields(gateway = %disconnected_gateway))]
     fn cleanup_connected_gateway(&mut self, disconnected_gateway: &GatewayId) {
-        self.update_site_status_by_gateway(disconnected_gateway, ResourceStatus::Unknown);
         self.peers.remove(disconnected_gateway);
+        self.gateways_path.remove(disconnected_gateway);
         self.resources_gateways
             .retain(|_, g| g != disconnected_gateway);
         self.dns_resource_nat.clear_by_gateway(disconnected_gateway);
+        self.post_quantum_psk.remove_gateway(disconnected_gateway);
+
+        for rid in self.standby_gateways.remove_gateway(disconnected_gateway) {
+            let Some(primary) = self.resources_gateways.get(&rid).copied() else {
+                continue;
+            };
+
+            self.request_standby_gateway(rid, primary);
+        }
+
+        // The site is still reachable if we are connected to another one of its gateways, i.e. a standby.
+        let site = self.gateways_site.get(disconnected_gateway);
+        if self
+            .gateways_site
+            .iter()
+            .any(|(g, s)| Some(s) == site && self.peers.get(g).is_some())
+        {
+            return;
+        }
+
+        self.update_site_status_by_gateway(disconnected_gateway, ResourceStatus::Unknown);
     }
 
     fn routes(&self) -> impl Iterator<Item = IpNetwork> + '_ {
@@ -943,6 +1438,7 @@ impl ClientState {
         tracing::debug!(servers = ?new_dns, "Received system-defined DNS servers");
 
         self.system_resolvers = new_dns;
+        self.dns_cache.clear(); // We might be on a different network now, previous answers may no longer be valid.
 
         self.update_dns_mapping()
     }
@@ -1012,19 +1508,39 @@ impl ClientState {
                     .poll_timeout()
                     .map(|instant| (instant, "TCP DNS server")),
             )
+            .chain(
+                self.post_quantum_psk
+                    .poll_timeout()
+                    .map(|instant| (instant, "post-quantum PSK")),
+            )
+            .chain(
+                self.traffic
+                    .poll_timeout()
+                    .map(|instant| (instant, "resource traffic report")),
+            )
             .chain(self.node.poll_timeout())
             .min_by_key(|(instant, _)| *instant)
     }
 
     pub fn handle_timeout(&mut self, now: Instant) {
         self.node.handle_timeout(now);
-        self.drain_node_events();
+        self.drain_node_events(now);
 
         self.udp_dns_sockets_by_upstream_and_query_id
             .handle_timeout(now);
 
         self.advance_dns_tcp_sockets(now);
         self.send_dns_resource_nat_packets(now);
+
+        self.post_quantum_psk.handle_timeout(now);
+        self.send_post_quantum_psk_packets(now);
+
+        self.stub_resolver.handle_timeout(now);
+
+        if let Some(traffic) = self.traffic.handle_timeout(now) {
+            self.buffered_events
+                .push_back(ClientEvent::ResourceTrafficUpdated { traffic });
+        }
     }
 
     /// Advance the TCP DNS server and client state machines.
@@ -1065,12 +1581,15 @@ impl ClientState {
                     continue;
                 };
 
-                self.handle_dns_response(dns::RecursiveResponse {
+                let message = query_result.result.map(|message| {
+                    self.handle_site_dns_response(&message, now)
+                        .unwrap_or(message)
+                });
+
+                self.send_dns_response(dns::RecursiveResponse {
                     server,
                     query: query_result.query,
-                    message: query_result
-                        .result
-                        .map_err(|e| io::Error::other(format!("{e:#}"))),
+                    message: message.map_err(|e| io::Error::other(format!("{e:#}"))),
                     transport: dns::Transport::Tcp { local, remote },
                 });
                 continue;
@@ -1094,9 +1613,23 @@ impl ClientState {
         }
     }
 
+    fn send_post_quantum_psk_packets(&mut self, now: Instant) {
+        while let Some((gid, packet)) = self.post_quantum_psk.poll_packet() {
+            tracing::debug!(%gid, "Sending post-quantum encapsulation key");
+
+            encapsulate_and_buffer(
+                packet,
+                gid,
+                now,
+                &mut self.node,
+                &mut self.buffered_transmits,
+            );
+        }
+    }
+
     fn handle_udp_dns_query(
         &mut self,
-        upstream: SocketAddr,
+        upstream: DnsServer,
         packet: IpPacket,
         now: Instant,
     ) -> ControlFlow<(), IpPacket> {
@@ -1130,23 +1663,39 @@ impl ClientState {
                 self.update_dns_resource_nat(now, iter::empty());
 
                 unwrap_or_debug!(
-                    self.try_queue_udp_dns_response(upstream, source, response),
+                    self.try_queue_udp_dns_response(upstream.address(), source, response),
                     "Failed to queue UDP DNS response: {}"
                 );
             }
             dns::ResolveStrategy::RecurseLocal => {
-                if self.should_forward_dns_query_to_gateway(upstream.ip()) {
-                    let packet = self
-                        .mangle_udp_dns_query_to_new_upstream_through_tunnel(upstream, now, packet);
+                if self.should_forward_dns_query_to_gateway(&upstream) {
+                    let packet = self.mangle_udp_dns_query_to_new_upstream_through_tunnel(
+                        upstream.address(),
+                        now,
+                        packet,
+                    );
 
                     return ControlFlow::Continue(packet);
                 }
                 let query_id = message.id();
 
-                tracing::trace!(server = %upstream, %query_id, "Forwarding UDP DNS query directly via host");
+                if let Some(response) = self.dns_cache.get(upstream.address(), &message, now) {
+                    tracing::trace!(%query_id, "Answering UDP DNS query from cache");
+
+                    unwrap_or_debug!(
+                        self.try_queue_udp_dns_response(upstream.address(), source, response),
+                        "Failed to queue UDP DNS response: {}"
+                    );
+
+                    return ControlFlow::Break(());
+                }
+
+                tracing::trace!(server = ?upstream, %query_id, "Forwarding UDP DNS query directly via host");
 
-                self.buffered_dns_queries
-                    .push_back(dns::RecursiveQuery::via_udp(source, upstream, message));
+                self.buffered_dns_queries.push_back(
+                    dns::RecursiveQuery::via_udp(source, upstream.address(), message)
+                        .with_encryption(dns::Encryption::from(&upstream)),
+                );
             }
             dns::ResolveStrategy::RecurseSite(resource) => {
                 let Some(gateway) =
@@ -1289,21 +1838,31 @@ impl ClientState {
                 );
             }
             dns::ResolveStrategy::RecurseLocal => {
-                if self.should_forward_dns_query_to_gateway(server.ip()) {
+                if self.should_forward_dns_query_to_gateway(upstream) {
                     self.forward_tcp_dns_query_to_new_upstream_via_tunnel(server, query);
 
                     return;
                 }
 
+                if let Some(response) = self.dns_cache.get(server, &query.message, now) {
+                    tracing::trace!(%query_id, "Answering TCP DNS query from cache");
+
+                    unwrap_or_debug!(
+                        self.tcp_dns_server
+                            .send_message(query.local, query.remote, response),
+                        "Failed to send TCP DNS response: {}"
+                    );
+
+                    return;
+                }
+
                 tracing::trace!(%server, %query_id, "Forwarding TCP DNS query");
 
-                self.buffered_dns_queries
-                    .push_back(dns::RecursiveQuery::via_tcp(
-                        query.local,
-                        query.remote,
-                        server,
-                        query.message,
-                    ));
+                let encryption = dns::Encryption::from(upstream);
+                self.buffered_dns_queries.push_back(
+                    dns::RecursiveQuery::via_tcp(query.local, query.remote, server, query.message)
+                        .with_encryption(encryption),
+                );
             }
             dns::ResolveStrategy::RecurseSite(resource) => {
                 let Some(gateway) =
@@ -1427,7 +1986,7 @@ impl ClientState {
         self.initialise_tcp_dns_server();
     }
 
-    fn drain_node_events(&mut self) {
+    fn drain_node_events(&mut self, now: Instant) {
         let mut resources_changed = false; // Track this separately to batch together `ResourcesChanged` events.
         let mut added_ice_candidates = BTreeMap::<GatewayId, BTreeSet<IceCandidate>>::default();
         let mut removed_ice_candidates = BTreeMap::<GatewayId, BTreeSet<IceCandidate>>::default();
@@ -1435,9 +1994,13 @@ impl ClientState {
         while let Some(event) = self.node.poll_event() {
             match event {
                 snownet::Event::ConnectionFailed(id) | snownet::Event::ConnectionClosed(id) => {
+                    self.fail_over_to_standby_gateways(id, now);
                     self.cleanup_connected_gateway(&id);
                     resources_changed = true;
                 }
+                snownet::Event::ConnectionDisconnected(id) => {
+                    self.fail_over_to_standby_gateways(id, now);
+                }
                 snownet::Event::NewIceCandidate {
                     connection,
                     candidate,
@@ -1457,9 +2020,32 @@ impl ClientState {
                         .insert(candidate.into());
                 }
                 snownet::Event::ConnectionEstablished(id) => {
+                    if let Err(e) = self.post_quantum_psk.on_connection_established(id) {
+                        tracing::warn!(%id, "Failed to negotiate post-quantum preshared key: {e:#}");
+                    }
+
                     self.update_site_status_by_gateway(&id, ResourceStatus::Online);
                     resources_changed = true;
                 }
+                snownet::Event::ConnectionPathChanged {
+                    connection,
+                    old,
+                    new,
+                } => {
+                    let path = connection_path(&new);
+
+                    // Switching between two direct or two relayed pairs doesn't change what we show for the resources.
+                    if self.gateways_path.insert(connection, path) != Some(path) {
+                        resources_changed = true;
+                    }
+
+                    self.buffered_events
+                        .push_back(ClientEvent::ConnectionPathChanged {
+                            conn_id: connection,
+                            old,
+                            new,
+                        });
+                }
             }
         }
 
@@ -1504,12 +2090,15 @@ impl ClientState {
 
         self.node.reset(now); // Clear all network connections.
         self.peers.clear(); // Clear all state associated with Gateways.
+        self.gateways_path.clear(); // All connections are gone, so are their paths.
 
         self.resources_gateways.clear(); // Clear Resource <> Gateway mapping (we will re-create this as new flows are authorized).
+        self.standby_gateways.clear(); // Standby gateways are requested again together with the new flows.
 
         self.recently_connected_gateways.clear(); // Ensure we don't have sticky gateways when we roam.
         self.dns_resource_nat.clear(); // Clear all state related to DNS resource NATs.
-        self.drain_node_events();
+        self.dns_cache.clear(); // Don't serve answers from a network we may have roamed away from.
+        self.drain_node_events(now);
 
         // Resetting the client will trigger a failed `QueryResult` for each one that is in-progress.
         // Failed queries get translated into `SERVFAIL` responses to the client.
@@ -1641,6 +2230,8 @@ impl ClientState {
     #[tracing::instrument(level = "debug", skip_all, fields(?id))]
     pub fn remove_resource(&mut self, id: ResourceId) {
         self.disable_resource(id);
+        self.flow_failures.remove(&id);
+        self.traffic.remove(&id);
 
         if self
             .resources_by_id
@@ -1689,6 +2280,7 @@ impl ClientState {
         tracing::info!(%name, address, %sites, "Deactivating resource");
 
         self.pending_flows.remove(&id);
+        self.standby_gateways.remove_resource(&id);
 
         let Some(peer) = peer_by_resource_mut(&self.resources_gateways, &mut self.peers, id) else {
             return;
@@ -1741,6 +2333,8 @@ impl ClientState {
             return;
         }
 
+        self.dns_cache.clear();
+
         let dns_mapping = sentinel_dns_mapping(
             &effective_dns_servers,
             self.dns_mapping()
@@ -1777,7 +2371,7 @@ impl ClientState {
         now: Instant,
     ) {
         self.node.update_relays(to_remove, &to_add, now);
-        self.drain_node_events(); // Ensure all state changes are fully-propagated.
+        self.drain_node_events(now); // Ensure all state changes are fully-propagated.
     }
 }
 
@@ -1788,6 +2382,14 @@ fn is_llmnr(dst: IpAddr) -> bool {
     }
 }
 
+fn connection_path(candidate_pair: &CandidatePair) -> ConnectionPath {
+    if candidate_pair.is_relayed() {
+        ConnectionPath::Relayed
+    } else {
+        ConnectionPath::Direct
+    }
+}
+
 fn encapsulate_and_buffer(
     packet: IpPacket,
     gid: GatewayId,
@@ -1811,11 +2413,12 @@ fn handle_p2p_control_packet(
     gid: GatewayId,
     fz_p2p_control: ip_packet::FzP2pControlSlice,
     dns_resource_nat: &mut DnsResourceNat,
+    post_quantum_psk: &mut PostQuantumPsk,
     node: &mut ClientNode<GatewayId, RelayId>,
     buffered_transmits: &mut VecDeque<Transmit>,
     now: Instant,
 ) {
-    use p2p_control::dns_resource_nat;
+    use p2p_control::{dns_resource_nat, post_quantum_psk as pq};
 
     match fz_p2p_control.event_type() {
         p2p_control::DOMAIN_STATUS_EVENT => {
@@ -1831,6 +2434,21 @@ fn handle_p2p_control_packet(
                 encapsulate_and_buffer(packet, gid, now, node, buffered_transmits);
             }
         }
+        p2p_control::CIPHERTEXT_EVENT => {
+            let Ok(ciphertext) =
+                pq::decode_ciphertext(fz_p2p_control).inspect_err(|e| tracing::debug!("{e:#}"))
+            else {
+                return;
+            };
+
+            let Some(secret) = post_quantum_psk.on_ciphertext(gid, ciphertext) else {
+                return;
+            };
+
+            if let Err(e) = node.upgrade_preshared_key(gid, secret, now) {
+                tracing::warn!("Failed to upgrade preshared key: {e:#}");
+            }
+        }
         code => {
             tracing::debug!(code = %code.into_u8(), "Unknown control protocol");
         }
@@ -1927,45 +2545,6 @@ fn is_definitely_not_a_resource(ip: IpAddr) -> bool {
     false
 }
 
-fn maybe_mangle_dns_response_from_upstream_dns_server(
-    mut packet: IpPacket,
-    udp_dns_sockets_by_upstream_and_query_id: &mut ExpiringMap<(SocketAddr, u16), SocketAddr>,
-) -> IpPacket {
-    let src_ip = packet.source();
-
-    let Some(udp) = packet.as_udp() else {
-        return packet;
-    };
-
-    let src_port = udp.source_port();
-    let src_socket = SocketAddr::new(src_ip, src_port);
-
-    let Ok(message) = dns_types::Response::parse(udp.payload()) else {
-        return packet;
-    };
-
-    let Some(original_dst) =
-        udp_dns_sockets_by_upstream_and_query_id.remove(&(src_socket, message.id()))
-    else {
-        return packet;
-    };
-
-    tracing::trace!(server = %src_ip, query_id = %message.id(), domain = %message.domain(), "Received UDP DNS response via tunnel");
-
-    if let Err(e) = packet.set_src(original_dst.ip()) {
-        tracing::warn!("Failed to set source IP for UDP DNS query: {e:#}");
-    }
-
-    packet
-        .as_udp_mut()
-        .expect("we parsed it as a UDP packet earlier")
-        .set_source_port(original_dst.port());
-
-    packet.update_checksum();
-
-    packet
-}
-
 /// What triggered us to establish a connection to a Gateway.
 enum ConnectionTrigger {
     /// A packet received on the TUN device with a destination IP that maps to one of our resources.
@@ -2047,6 +2626,7 @@ impl IpProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::DoHDnsServer;
 
     #[test]
     fn ignores_ip4_igmp_multicast() {
@@ -2091,9 +2671,222 @@ mod tests {
         )
     }
 
+    #[test]
+    fn forwards_queries_for_encrypted_upstream_via_host() {
+        let mut client = ClientState::for_test();
+        let upstream = DnsServer::DoH(DoHDnsServer {
+            address: "1.1.1.1:443".parse().unwrap(),
+            url: "https://cloudflare-dns.com/dns-query".parse().unwrap(),
+        });
+
+        client.update_interface_config(InterfaceConfig {
+            ipv4: Ipv4Addr::new(100, 64, 0, 1),
+            ipv6: "fd00:2021:1111::1".parse().unwrap(),
+            upstream_dns: vec![upstream.clone()],
+            search_domain: None,
+        });
+        let sentinel = *client.dns_mapping().get_by_right(&upstream).unwrap();
+
+        let query = dns_types::Query::new(
+            dns_types::DomainName::vec_from_str("example.com").unwrap(),
+            dns_types::RecordType::A,
+        );
+        let packet = ip_packet::make::udp_packet(
+            ip("100.64.0.1"),
+            sentinel,
+            12345,
+            DNS_PORT,
+            query.into_bytes(),
+        )
+        .unwrap();
+
+        assert!(client.handle_tun_input(packet, Instant::now()).is_none());
+
+        let query = client.poll_dns_queries().unwrap();
+        assert_eq!(query.server, upstream.address());
+        assert!(matches!(
+            query.encryption,
+            dns::Encryption::Https { url } if url.as_str() == "https://cloudflare-dns.com/dns-query"
+        ));
+    }
+
+    #[test]
+    fn diagnoses_ip_outside_of_resources() {
+        let client = client_with_tun();
+
+        let diagnosis = client.diagnose("1.1.1.1");
+
+        assert!(diagnosis.resource.is_none());
+        assert_eq!(diagnosis.problem, Some(Problem::NotAResource));
+    }
+
+    #[test]
+    fn diagnoses_disabled_cidr_resource() {
+        let mut client = client_with_tun();
+        let resource = cidr_resource("10.0.0.0/24");
+        client.add_resource(Resource::Cidr(resource.clone()));
+        client.set_disabled_resources(BTreeSet::from([resource.id]));
+
+        let diagnosis = client.diagnose("10.0.0.5");
+
+        let diagnosed_resource = diagnosis.resource.unwrap();
+        assert_eq!(diagnosed_resource.id, resource.id);
+        assert!(!diagnosed_resource.enabled);
+        assert_eq!(diagnosis.problem, Some(Problem::ResourceDisabled));
+    }
+
+    #[test]
+    fn diagnoses_refused_flow() {
+        let mut client = client_with_tun();
+        let resource = cidr_resource("10.0.0.0/24");
+        client.add_resource(Resource::Cidr(resource.clone()));
+        client.on_flow_creation_failed(resource.id, FailReason::Forbidden, vec![]);
+
+        let diagnosis = client.diagnose("10.0.0.5");
+
+        let diagnosed_resource = diagnosis.resource.unwrap();
+        assert_eq!(diagnosed_resource.connection, ConnectionState::NotConnected);
+        assert!(matches!(
+            diagnosed_resource.last_failure,
+            Some(FlowFailure {
+                reason: FailReason::Forbidden,
+                ..
+            })
+        ));
+        assert_eq!(diagnosis.problem, Some(Problem::FlowCreationFailed));
+    }
+
+    #[test]
+    fn diagnoses_hostname_outside_of_dns_resources() {
+        let mut client = client_with_tun();
+        client.add_resource(Resource::Internet(InternetResource {
+            name: "Internet".to_owned(),
+            id: ResourceId::random(),
+            sites: vec![Site {
+                id: SiteId::from_u128(1),
+                name: "site".to_owned(),
+            }],
+        }));
+
+        let diagnosis = client.diagnose("example.com");
+
+        assert!(diagnosis.resource.is_none());
+        assert_eq!(diagnosis.problem, Some(Problem::NotAResource));
+        assert!(diagnosis.hint.is_some());
+    }
+
+    #[test]
+    fn resource_shows_path_of_its_gateway() {
+        let mut client = ClientState::for_test();
+        let routed = cidr_resource("10.0.0.0/24");
+        let not_routed = cidr_resource("10.0.1.0/24");
+        client.add_resource(Resource::Cidr(routed.clone()));
+        client.add_resource(Resource::Cidr(not_routed.clone()));
+
+        let gid = GatewayId::from_u128(1);
+        client.resources_gateways.insert(routed.id, gid);
+        client.gateways_path.insert(gid, ConnectionPath::Relayed);
+
+        let paths = client
+            .resources()
+            .into_iter()
+            .map(|r| (r.id(), r.path()))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(paths[&routed.id], Some(ConnectionPath::Relayed));
+        assert_eq!(paths[&not_routed.id], None);
+    }
+
+    #[test]
+    fn pending_standby_request_does_not_hide_offline_resource() {
+        let mut client = client_with_tun();
+        client.set_standby_gateways_enabled(true);
+        let resource = cidr_resource("10.0.0.0/24");
+        client.add_resource(Resource::Cidr(resource.clone()));
+
+        client.request_standby_gateway(resource.id, GatewayId::from_u128(1));
+        client.on_not_connected_resource(
+            resource.id,
+            ip_packet::make::udp_packet(
+                Ipv4Addr::new(100, 64, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 5),
+                1234,
+                53,
+                vec![],
+            )
+            .unwrap(),
+            Instant::now(),
+        );
+        client.on_flow_creation_failed(resource.id, FailReason::Offline, vec![]);
+
+        assert_eq!(
+            client.resource_status(&Resource::Cidr(resource)),
+            ResourceStatus::Offline
+        );
+    }
+
+    #[test]
+    fn capture_labels_use_the_resource_side_of_the_packet() {
+        let mut client = client_with_tun();
+        let resource = cidr_resource("10.0.0.0/24");
+        let gateway = GatewayId::from_u128(1);
+        client.add_resource(Resource::Cidr(resource.clone()));
+        client.resources_gateways.insert(resource.id, gateway);
+
+        let outbound =
+            ip_packet::make::udp_packet(ip("100.64.0.1"), ip("10.0.0.5"), 12345, 53, vec![])
+                .unwrap();
+        let inbound =
+            ip_packet::make::udp_packet(ip("10.0.0.5"), ip("100.64.0.1"), 53, 12345, vec![])
+                .unwrap();
+        let expected = capture::Labels {
+            resource: Some(resource.id),
+            gateway: Some(gateway),
+            client: None,
+        };
+
+        assert_eq!(
+            client.capture_labels(&outbound, capture::Direction::Outbound),
+            expected
+        );
+        assert_eq!(
+            client.capture_labels(&inbound, capture::Direction::Inbound),
+            expected
+        );
+        assert_eq!(
+            client.capture_labels(&outbound, capture::Direction::Inbound),
+            capture::Labels::default()
+        );
+    }
+
+    fn client_with_tun() -> ClientState {
+        let mut client = ClientState::for_test();
+        client.update_interface_config(InterfaceConfig {
+            ipv4: Ipv4Addr::new(100, 64, 0, 1),
+            ipv6: "fd00:2021:1111::1".parse().unwrap(),
+            upstream_dns: vec![],
+            search_domain: None,
+        });
+
+        client
+    }
+
+    fn cidr_resource(address: &str) -> CidrResource {
+        CidrResource {
+            id: ResourceId::random(),
+            address: address.parse().unwrap(),
+            name: address.to_owned(),
+            address_description: None,
+            sites: vec![Site {
+                id: SiteId::from_u128(1),
+                name: "site".to_owned(),
+            }],
+        }
+    }
+
     impl ClientState {
         pub fn for_test() -> ClientState {
-            ClientState::new(rand::random(), Instant::now())
+            ClientState::new(rand::random(), rand::random(), Instant::now())
         }
     }
 
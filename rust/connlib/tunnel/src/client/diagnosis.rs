@@ -0,0 +1,99 @@
+//! Explains why a host is or isn't reachable through the tunnel.
+
+use std::{fmt, net::IpAddr};
+
+use connlib_model::{GatewayId, ResourceId, ResourceStatus, Site, SiteId};
+use serde::Serialize;
+
+use crate::messages::DnsServer;
+use crate::messages::client::{FailReason, ViolatedProperty};
+
+/// What connlib knows about traffic to a particular host.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnosis {
+    /// The hostname or IP that was diagnosed.
+    pub host: String,
+    /// The IPs we handed out for `host` when it was resolved through our DNS.
+    ///
+    /// Empty if `host` is an IP or hasn't been resolved through our DNS yet.
+    pub proxy_ips: Vec<IpAddr>,
+    /// The domain we handed out `host` for, if `host` is one of our proxy IPs.
+    pub domain: Option<String>,
+    /// Where DNS queries to `host` are forwarded to, if `host` is one of our sentinel DNS servers.
+    pub dns_upstream: Option<DnsServer>,
+    /// The resource `host` belongs to.
+    pub resource: Option<ResourceDiagnosis>,
+    /// The most likely reason `host` is unreachable, [`None`] if nothing stands out.
+    pub problem: Option<Problem>,
+    /// What to try next if the diagnosis is inconclusive.
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceDiagnosis {
+    pub id: ResourceId,
+    pub name: String,
+    pub address: Option<String>,
+    pub sites: Vec<Site>,
+    pub status: ResourceStatus,
+    pub enabled: bool,
+    /// The gateway traffic for this resource is routed to.
+    pub gateway: Option<GatewayId>,
+    /// The site of [`ResourceDiagnosis::gateway`].
+    pub gateway_site: Option<SiteId>,
+    pub connection: ConnectionState,
+    /// Why the portal refused to create a flow to this resource the last time we asked.
+    pub last_failure: Option<FlowFailure>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    NotConnected,
+    /// We asked the portal for a gateway and are waiting for the connection to be set up.
+    Pending,
+    Connected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowFailure {
+    pub reason: FailReason,
+    pub violated_properties: Vec<ViolatedProperty>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// The TUN device has not been configured yet.
+    NoTunnel,
+    /// The host doesn't belong to any resource, traffic to it bypasses the tunnel.
+    NotAResource,
+    /// The host matches a DNS resource but hasn't been resolved through our DNS.
+    NotResolvedViaFirezone,
+    ResourceDisabled,
+    /// All sites of the resource are offline.
+    SitesOffline,
+    /// The portal refused to create a flow to the resource.
+    FlowCreationFailed,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoTunnel => write!(f, "The tunnel is not up yet"),
+            Problem::NotAResource => write!(
+                f,
+                "Host is not a resource, traffic to it is not routed through Firezone"
+            ),
+            Problem::NotResolvedViaFirezone => write!(
+                f,
+                "Host matches a DNS resource but was not resolved through Firezone's DNS"
+            ),
+            Problem::ResourceDisabled => write!(f, "Resource is disabled"),
+            Problem::SitesOffline => write!(f, "All sites of the resource are offline"),
+            Problem::FlowCreationFailed => {
+                write!(f, "The portal refused access to the resource")
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    net::SocketAddr,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use dns_types::{DomainName, Query, RecordType, Response, ResponseCode};
+use lru::LruCache;
+use opentelemetry::metrics::Counter;
+
+use crate::otel;
+
+const MAX_ENTRIES: NonZeroUsize = NonZeroUsize::new(1000).expect("1000 > 0");
+
+/// How long we cache positive responses at most, regardless of their TTL.
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long we cache negative responses at most.
+///
+/// RFC 2308 recommends 1-3 hours for resolvers but we are a stub and would rather re-query than serve stale NXDOMAINs.
+const MAX_NEGATIVE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Caches responses of upstream DNS servers for queries that are not for a resource.
+///
+/// Positive responses are cached for the smallest TTL of their records.
+/// Negative responses (NXDOMAIN and NODATA) are cached according to the SOA record in their authority section, see <https://www.rfc-editor.org/rfc/rfc2308#section-5>.
+pub(crate) struct DnsCache {
+    entries: LruCache<Key, Entry>,
+
+    hits: Counter<u64>,
+    misses: Counter<u64>,
+}
+
+/// Everything that may influence the response of an upstream server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    upstream: SocketAddr,
+    domain: DomainName,
+    qtype: RecordType,
+    /// Responses to queries with the DO bit include DNSSEC records.
+    dnssec_ok: bool,
+    /// Responses to queries with the CD bit may include data that failed DNSSEC validation.
+    checking_disabled: bool,
+}
+
+impl Key {
+    fn new(upstream: SocketAddr, query: &Query) -> Self {
+        Self {
+            upstream,
+            domain: query.domain(),
+            qtype: query.qtype(),
+            dnssec_ok: query.dnssec_ok(),
+            checking_disabled: query.checking_disabled(),
+        }
+    }
+}
+
+struct Entry {
+    response: Response,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self {
+            entries: LruCache::new(MAX_ENTRIES),
+            hits: otel::metrics::dns_cache_hits(),
+            misses: otel::metrics::dns_cache_misses(),
+        }
+    }
+}
+
+impl DnsCache {
+    /// Answers the given query from the cache, with the TTLs adjusted to how long the response has been cached for.
+    ///
+    /// Only responses from the same `upstream` server are considered.
+    pub(crate) fn get(
+        &mut self,
+        upstream: SocketAddr,
+        query: &Query,
+        now: Instant,
+    ) -> Option<Response> {
+        let key = Key::new(upstream, query);
+
+        match self.entries.get(&key) {
+            Some(entry) if entry.expires_at > now => {
+                let elapsed = now.duration_since(entry.inserted_at).as_secs();
+                let response = entry
+                    .response
+                    .aged(query, u32::try_from(elapsed).unwrap_or(u32::MAX));
+
+                self.hits.add(1, &[]);
+
+                Some(response)
+            }
+            Some(_) => {
+                self.entries.pop(&key);
+                self.misses.add(1, &[]);
+
+                None
+            }
+            None => {
+                self.misses.add(1, &[]);
+
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        upstream: SocketAddr,
+        query: &Query,
+        response: &Response,
+        now: Instant,
+    ) {
+        let Some(ttl) = ttl(response) else {
+            return;
+        };
+
+        self.entries.put(
+            Key::new(upstream, query),
+            Entry {
+                response: response.clone(),
+                inserted_at: now,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// For how long the given response may be cached, if at all.
+fn ttl(response: &Response) -> Option<Duration> {
+    if response.truncated() {
+        return None;
+    }
+
+    let rcode = response.response_code();
+
+    let ttl = match response.min_ttl() {
+        Some(ttl) if rcode == ResponseCode::NOERROR => Duration::from_secs(ttl.into()).min(MAX_TTL),
+        None if rcode == ResponseCode::NOERROR || rcode == ResponseCode::NXDOMAIN => {
+            Duration::from_secs(response.negative_ttl()?.into()).min(MAX_NEGATIVE_TTL)
+        }
+        Some(_) | None => return None,
+    };
+
+    (!ttl.is_zero()).then_some(ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use dns_types::{ResponseBuilder, records};
+
+    use super::*;
+
+    #[test]
+    fn answers_from_cache_with_new_id_and_reduced_ttl() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &response(&query(1), 300), now);
+        let cached = cache
+            .get(upstream(), &query(2), now + Duration::from_secs(100))
+            .unwrap();
+
+        assert_eq!(cached.id(), 2);
+        assert_eq!(cached.min_ttl(), Some(200));
+    }
+
+    #[test]
+    fn expires_after_ttl() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &response(&query(1), 60), now);
+
+        assert!(
+            cache
+                .get(upstream(), &query(2), now + Duration::from_secs(60))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn does_not_cache_negative_response_without_soa() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &Response::nxdomain(&query(1)), now);
+
+        assert!(cache.get(upstream(), &query(2), now).is_none());
+    }
+
+    #[test]
+    fn does_not_cache_servfail() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &Response::servfail(&query(1)), now);
+
+        assert!(cache.get(upstream(), &query(2), now).is_none());
+    }
+
+    #[test]
+    fn clear_removes_all_entries() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &response(&query(1), 300), now);
+        cache.clear();
+
+        assert!(cache.get(upstream(), &query(2), now).is_none());
+    }
+
+    #[test]
+    fn does_not_answer_from_other_upstream() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &response(&query(1), 300), now);
+
+        assert!(
+            cache
+                .get(
+                    SocketAddr::from((Ipv4Addr::new(1, 1, 1, 1), 53)),
+                    &query(2),
+                    now
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn does_not_answer_query_with_checking_disabled_from_validated_response() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+
+        cache.insert(upstream(), &query(1), &response(&query(1), 300), now);
+
+        let mut bytes = query(2).into_bytes();
+        bytes[3] |= 0b0001_0000; // Set the CD bit.
+        let query = Query::parse(&bytes).unwrap();
+
+        assert!(cache.get(upstream(), &query, now).is_none());
+    }
+
+    fn upstream() -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::new(8, 8, 8, 8), 53))
+    }
+
+    fn query(id: u16) -> Query {
+        Query::new(
+            DomainName::vec_from_str("example.com").unwrap(),
+            RecordType::A,
+        )
+        .with_id(id)
+    }
+
+    fn response(query: &Query, ttl: u32) -> Response {
+        ResponseBuilder::for_query(query, ResponseCode::NOERROR)
+            .with_records([(query.domain(), ttl, records::a(Ipv4Addr::LOCALHOST))])
+            .build()
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use connlib_model::GatewayId;
+use ip_packet::IpPacket;
+use ml_kem::{KemCore as _, MlKem768, kem::Decapsulate as _};
+use rand::{SeedableRng as _, rngs::StdRng};
+use secrecy::Secret;
+
+use crate::p2p_control::post_quantum_psk::{self, Ciphertext, DecapsulationKey};
+
+/// How long we wait for the gateway's ciphertext before sending our encapsulation key again.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often we send our encapsulation key before assuming that the gateway does not support post-quantum preshared keys.
+const MAX_ATTEMPTS: usize = 5;
+
+/// Negotiates a post-quantum secret with each gateway to upgrade the preshared key of our tunnel.
+///
+/// The preshared key handed to us by the portal is known to the portal and only protects against a classical adversary.
+/// Gateways that don't support this ignore our encapsulation key, we keep using the portal's preshared key for those.
+pub struct PostQuantumPsk {
+    inner: BTreeMap<GatewayId, State>,
+
+    encapsulation_key_packets: VecDeque<(GatewayId, IpPacket)>,
+
+    rng: StdRng,
+}
+
+enum State {
+    Pending {
+        decapsulation_key: Box<DecapsulationKey>,
+        packet: IpPacket,
+        sent_at: Option<Instant>,
+        attempts: usize,
+    },
+    Done,
+}
+
+impl PostQuantumPsk {
+    pub fn new(seed: [u8; 32]) -> Self {
+        Self {
+            inner: Default::default(),
+            encapsulation_key_packets: Default::default(),
+            rng: StdRng::from_seed(seed),
+        }
+    }
+
+    /// Starts a new negotiation with the given gateway, discarding any previous one.
+    pub fn on_connection_established(&mut self, gid: GatewayId) -> Result<()> {
+        let (decapsulation_key, encapsulation_key) = MlKem768::generate(&mut self.rng);
+        let packet = post_quantum_psk::encapsulation_key(&encapsulation_key)?;
+
+        self.inner.insert(
+            gid,
+            State::Pending {
+                decapsulation_key: Box::new(decapsulation_key),
+                packet,
+                sent_at: None,
+                attempts: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns the secret negotiated with the gateway if we are still waiting for it.
+    ///
+    /// Retransmitted ciphertexts are ignored.
+    pub fn on_ciphertext(
+        &mut self,
+        gid: GatewayId,
+        ciphertext: Ciphertext,
+    ) -> Option<Secret<[u8; 32]>> {
+        let state = self.inner.get_mut(&gid)?;
+
+        let State::Pending {
+            decapsulation_key, ..
+        } = state
+        else {
+            return None;
+        };
+
+        let Ok(shared_key) = decapsulation_key.decapsulate(&ciphertext);
+        *state = State::Done;
+
+        Some(Secret::new(shared_key.into()))
+    }
+
+    pub fn remove_gateway(&mut self, gid: &GatewayId) {
+        self.inner.remove(gid);
+        self.encapsulation_key_packets.retain(|(g, _)| g != gid);
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        for (gid, state) in self.inner.iter_mut() {
+            let State::Pending {
+                packet,
+                sent_at,
+                attempts,
+                ..
+            } = state
+            else {
+                continue;
+            };
+
+            if sent_at.is_some_and(|sent_at| now < sent_at + RETRANSMIT_INTERVAL) {
+                continue;
+            }
+
+            if *attempts >= MAX_ATTEMPTS {
+                tracing::debug!(%gid, "Gateway does not support post-quantum preshared keys");
+
+                *state = State::Done;
+                continue;
+            }
+
+            *sent_at = Some(now);
+            *attempts += 1;
+
+            self.encapsulation_key_packets
+                .push_back((*gid, packet.clone()));
+        }
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.inner
+            .values()
+            .filter_map(|state| match state {
+                State::Pending {
+                    sent_at: Some(sent_at),
+                    ..
+                } => Some(*sent_at + RETRANSMIT_INTERVAL),
+                State::Pending { sent_at: None, .. } | State::Done => None,
+            })
+            .min()
+    }
+
+    pub fn poll_packet(&mut self) -> Option<(GatewayId, IpPacket)> {
+        self.encapsulation_key_packets.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ml_kem::kem::Encapsulate as _;
+    use secrecy::ExposeSecret as _;
+
+    #[test]
+    fn retransmits_encapsulation_key_until_giving_up() {
+        let mut pq = PostQuantumPsk::new([0; 32]);
+        let mut now = Instant::now();
+
+        pq.on_connection_established(gid()).unwrap();
+
+        for _ in 0..MAX_ATTEMPTS {
+            pq.handle_timeout(now);
+            assert!(pq.poll_packet().is_some());
+            assert!(pq.poll_packet().is_none());
+
+            now = pq.poll_timeout().unwrap();
+        }
+
+        pq.handle_timeout(now);
+        assert!(pq.poll_packet().is_none());
+        assert!(pq.poll_timeout().is_none());
+    }
+
+    #[test]
+    fn derives_secret_from_first_ciphertext_only() {
+        let mut pq = PostQuantumPsk::new([0; 32]);
+        let now = Instant::now();
+
+        pq.on_connection_established(gid()).unwrap();
+        pq.handle_timeout(now);
+
+        let (_, packet) = pq.poll_packet().unwrap();
+        let key = post_quantum_psk::decode_encapsulation_key(packet.as_fz_p2p_control().unwrap())
+            .unwrap();
+        let Ok((ciphertext, gateway_secret)) = key.encapsulate(&mut StdRng::from_seed([1; 32]));
+
+        let secret = pq.on_ciphertext(gid(), ciphertext.clone()).unwrap();
+
+        assert_eq!(secret.expose_secret().as_slice(), gateway_secret.as_slice());
+        assert!(pq.on_ciphertext(gid(), ciphertext).is_none());
+        assert!(pq.poll_timeout().is_none());
+    }
+
+    fn gid() -> GatewayId {
+        GatewayId::from_u128(1)
+    }
+}
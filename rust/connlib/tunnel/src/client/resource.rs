@@ -8,21 +8,21 @@ use connlib_model::{
 };
 use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use itertools::Itertools as _;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::messages::client::{
     ResourceDescription, ResourceDescriptionCidr, ResourceDescriptionDns,
     ResourceDescriptionInternet,
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Resource {
     Dns(DnsResource),
     Cidr(CidrResource),
     Internet(InternetResource),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct DnsResource {
     /// Resource's id.
     pub id: ResourceId,
@@ -40,7 +40,7 @@ pub struct DnsResource {
 }
 
 /// Description of a resource that maps to a CIDR.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct CidrResource {
     /// Resource's id.
     pub id: ResourceId,
@@ -56,7 +56,7 @@ pub struct CidrResource {
 }
 
 /// Description of an internet resource.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct InternetResource {
     /// Name of the resource.
     ///
@@ -227,6 +227,8 @@ impl CidrResource {
             address_description: self.address_description,
             sites: self.sites,
             status,
+            traffic: Default::default(),
+            path: None,
         }
     }
 }
@@ -246,6 +248,8 @@ impl InternetResource {
             id: self.id,
             sites: self.sites,
             status,
+            traffic: Default::default(),
+            path: None,
         }
     }
 }
@@ -270,6 +274,8 @@ impl DnsResource {
             address_description: self.address_description,
             sites: self.sites,
             status,
+            traffic: Default::default(),
+            path: None,
         }
     }
 }
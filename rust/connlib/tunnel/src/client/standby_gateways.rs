@@ -0,0 +1,155 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
+
+use connlib_model::{GatewayId, ResourceId};
+
+/// Tracks a hot standby gateway for each resource we are connected to.
+///
+/// The portal picks the standby from the same site as the primary gateway, we just ask it to exclude the primary.
+/// Once ICE reports the connection to the primary gateway as disconnected, we switch the resource over to its standby.
+#[derive(Debug, Default)]
+pub struct StandbyGateways {
+    enabled: bool,
+
+    by_resource: HashMap<ResourceId, GatewayId>,
+    /// Resources for which we asked the portal for a standby gateway.
+    pending: HashSet<ResourceId>,
+}
+
+impl StandbyGateways {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    /// Called when a resource got a new primary gateway.
+    ///
+    /// Returns `true` if we should request a standby gateway for this resource.
+    pub fn on_primary(&mut self, rid: ResourceId, primary: GatewayId) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        if self.by_resource.get(&rid).is_some_and(|g| *g == primary) {
+            self.by_resource.remove(&rid);
+        }
+
+        if self.by_resource.contains_key(&rid) {
+            return false;
+        }
+
+        self.pending.insert(rid)
+    }
+
+    /// Returns `true` if we were waiting for a standby gateway for this resource.
+    pub fn on_flow_created(&mut self, rid: ResourceId, gid: GatewayId) -> bool {
+        if !self.pending.remove(&rid) {
+            return false;
+        }
+
+        self.by_resource.insert(rid, gid);
+
+        true
+    }
+
+    /// Returns `true` if we were waiting for a standby gateway for this resource.
+    pub fn on_flow_creation_failed(&mut self, rid: ResourceId) -> bool {
+        self.pending.remove(&rid)
+    }
+
+    pub fn get(&self, rid: &ResourceId) -> Option<GatewayId> {
+        self.by_resource.get(rid).copied()
+    }
+
+    /// Makes the current primary the standby of the resource, returning the previous standby.
+    pub fn fail_over(&mut self, rid: ResourceId, primary: GatewayId) -> Option<GatewayId> {
+        let standby = self.by_resource.get_mut(&rid)?;
+
+        Some(mem::replace(standby, primary))
+    }
+
+    /// Removes the gateway as standby, returning the resources that need a new one.
+    pub fn remove_gateway(&mut self, gid: &GatewayId) -> Vec<ResourceId> {
+        let mut resources = Vec::new();
+
+        self.by_resource.retain(|rid, standby| {
+            if standby != gid {
+                return true;
+            }
+
+            resources.push(*rid);
+            false
+        });
+
+        resources
+    }
+
+    pub fn remove_resource(&mut self, rid: &ResourceId) {
+        self.by_resource.remove(rid);
+        self.pending.remove(rid);
+    }
+
+    pub fn clear(&mut self) {
+        self.by_resource.clear();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_request_standby_when_disabled() {
+        let mut standby = StandbyGateways::default();
+
+        assert!(!standby.on_primary(rid(), gid(1)));
+    }
+
+    #[test]
+    fn swaps_primary_and_standby_on_fail_over() {
+        let mut standby = StandbyGateways::default();
+        standby.set_enabled(true);
+
+        assert!(standby.on_primary(rid(), gid(1)));
+        assert!(standby.on_flow_created(rid(), gid(2)));
+        assert!(!standby.on_primary(rid(), gid(1)));
+
+        assert_eq!(standby.fail_over(rid(), gid(1)), Some(gid(2)));
+        assert_eq!(standby.get(&rid()), Some(gid(1)));
+    }
+
+    #[test]
+    fn requests_new_standby_when_standby_becomes_primary() {
+        let mut standby = StandbyGateways::default();
+        standby.set_enabled(true);
+
+        standby.on_primary(rid(), gid(1));
+        standby.on_flow_created(rid(), gid(2));
+
+        assert!(standby.on_primary(rid(), gid(2)));
+        assert_eq!(standby.get(&rid()), None);
+    }
+
+    #[test]
+    fn ignores_flows_that_were_not_requested_as_standby() {
+        let mut standby = StandbyGateways::default();
+        standby.set_enabled(true);
+
+        assert!(!standby.on_flow_created(rid(), gid(2)));
+        assert!(!standby.on_flow_creation_failed(rid()));
+    }
+
+    fn rid() -> ResourceId {
+        ResourceId::from_u128(1)
+    }
+
+    fn gid(n: u128) -> GatewayId {
+        GatewayId::from_u128(n)
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use connlib_model::{ResourceId, ResourceTraffic};
+
+/// How often we at most report updated counters.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Counts the packets and bytes exchanged with each resource.
+///
+/// Updated counters are reported at most every [`REPORT_INTERVAL`].
+/// The timer is only armed once traffic has been counted so an idle Client doesn't wake up for nothing.
+#[derive(Default)]
+pub struct TrafficCounters {
+    by_resource: BTreeMap<ResourceId, ResourceTraffic>,
+
+    next_report: Option<Instant>,
+}
+
+impl TrafficCounters {
+    pub fn on_sent(&mut self, rid: ResourceId, num_bytes: usize, now: Instant) {
+        let traffic = self.by_resource.entry(rid).or_default();
+        traffic.bytes_sent += num_bytes as u64;
+        traffic.packets_sent += 1;
+
+        self.schedule_report(now);
+    }
+
+    pub fn on_received(&mut self, rid: ResourceId, num_bytes: usize, now: Instant) {
+        let traffic = self.by_resource.entry(rid).or_default();
+        traffic.bytes_received += num_bytes as u64;
+        traffic.packets_received += 1;
+
+        self.schedule_report(now);
+    }
+
+    pub fn get(&self, rid: &ResourceId) -> ResourceTraffic {
+        self.by_resource.get(rid).copied().unwrap_or_default()
+    }
+
+    pub fn remove(&mut self, rid: &ResourceId) {
+        self.by_resource.remove(rid);
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.next_report
+    }
+
+    /// Returns the counters of all resources if they are due to be reported.
+    pub fn handle_timeout(
+        &mut self,
+        now: Instant,
+    ) -> Option<BTreeMap<ResourceId, ResourceTraffic>> {
+        if self.next_report.is_none_or(|next_report| now < next_report) {
+            return None;
+        }
+
+        self.next_report = None;
+
+        Some(self.by_resource.clone())
+    }
+
+    fn schedule_report(&mut self, now: Instant) {
+        self.next_report.get_or_insert(now + REPORT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_counters_after_interval() {
+        let mut counters = TrafficCounters::default();
+        let now = Instant::now();
+        let rid = ResourceId::from_u128(1);
+
+        counters.on_sent(rid, 100, now);
+        counters.on_received(rid, 50, now + Duration::from_secs(1));
+
+        assert_eq!(counters.poll_timeout(), Some(now + REPORT_INTERVAL));
+        assert!(
+            counters
+                .handle_timeout(now + Duration::from_secs(1))
+                .is_none()
+        );
+
+        let report = counters.handle_timeout(now + REPORT_INTERVAL).unwrap();
+
+        assert_eq!(
+            report.get(&rid),
+            Some(&ResourceTraffic {
+                bytes_sent: 100,
+                bytes_received: 50,
+                packets_sent: 1,
+                packets_received: 1,
+            })
+        );
+        assert_eq!(counters.poll_timeout(), None);
+    }
+
+    #[test]
+    fn removed_resource_is_not_reported() {
+        let mut counters = TrafficCounters::default();
+        let now = Instant::now();
+        let rid = ResourceId::from_u128(1);
+
+        counters.on_sent(rid, 100, now);
+        counters.remove(&rid);
+
+        let report = counters.handle_timeout(now + REPORT_INTERVAL).unwrap();
+
+        assert!(report.is_empty());
+        assert_eq!(counters.get(&rid), ResourceTraffic::default());
+    }
+}
@@ -1,9 +1,11 @@
 use crate::client::IpProvider;
+use crate::messages::DnsServer;
 use anyhow::Result;
 use connlib_model::{IpStack, ResourceId};
+use dns_types::prelude::*;
 use dns_types::{
-    DomainName, DomainNameRef, OwnedRecordData, Query, RecordType, Response, ResponseBuilder,
-    ResponseCode,
+    DomainName, DomainNameRef, OwnedRecordData, Query, RecordData, RecordType, Response,
+    ResponseBuilder, ResponseCode,
 };
 use firezone_logging::err_with_src;
 use itertools::Itertools;
@@ -13,6 +15,7 @@ use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{
     collections::{BTreeMap, HashMap},
     net::SocketAddr,
+    time::{Duration, Instant},
 };
 
 const DNS_TTL: u32 = 1;
@@ -21,6 +24,11 @@ const REVERSE_DNS_ADDRESS_V4: &str = "in-addr";
 const REVERSE_DNS_ADDRESS_V6: &str = "ip6";
 pub(crate) const DNS_PORT: u16 = 53;
 
+/// For how long a domain stays linked to a DNS resource at least.
+///
+/// Without this, records with a TTL of 0 would unlink their targets before the client had a chance to query them.
+const MIN_LINKED_DOMAIN_TTL: Duration = Duration::from_secs(10);
+
 /// The DNS over HTTPS canary domain used by Firefox to check whether DoH can be enabled by default.
 ///
 /// Responding to queries for this domain with NXDOMAIN will disable DoH.
@@ -39,6 +47,10 @@ pub struct StubResolver {
     ip_provider: IpProvider,
     /// All DNS resources we know about, indexed by the glob pattern they match against.
     dns_resources: BTreeMap<Pattern, Resource>,
+    /// Domains that don't match any pattern but were returned as SRV targets or CNAMEs by a site for one of its DNS resources.
+    ///
+    /// Each link expires together with the record that established it.
+    linked_domains: HashMap<DomainName, (Resource, Instant)>,
     search_domain: Option<DomainName>,
 }
 
@@ -54,6 +66,7 @@ pub(crate) struct RecursiveQuery {
     pub server: SocketAddr,
     pub message: dns_types::Query,
     pub transport: Transport,
+    pub encryption: Encryption,
 }
 
 /// A response to a [`RecursiveQuery`].
@@ -75,6 +88,7 @@ impl RecursiveQuery {
             server,
             message,
             transport: Transport::Udp { source },
+            encryption: Encryption::None,
         }
     }
 
@@ -88,8 +102,15 @@ impl RecursiveQuery {
             server,
             message,
             transport: Transport::Tcp { local, remote },
+            encryption: Encryption::None,
         }
     }
+
+    pub(crate) fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = encryption;
+
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -104,6 +125,29 @@ pub(crate) enum Transport {
     },
 }
 
+/// How a [`RecursiveQuery`] is sent to the upstream server.
+#[derive(Debug, Clone)]
+pub(crate) enum Encryption {
+    /// Plaintext, using the same [`Transport`] as the original query.
+    None,
+    /// DNS over HTTPS (RFC 8484).
+    Https { url: url::Url },
+    /// DNS over TLS (RFC 7858).
+    Tls { server_name: String },
+}
+
+impl From<&DnsServer> for Encryption {
+    fn from(server: &DnsServer) -> Self {
+        match server {
+            DnsServer::IpPort(_) => Encryption::None,
+            DnsServer::DoH(s) => Encryption::Https { url: s.url.clone() },
+            DnsServer::DoT(s) => Encryption::Tls {
+                server_name: s.server_name.clone(),
+            },
+        }
+    }
+}
+
 /// Tells the Client how to reply to a single DNS query
 #[derive(Debug)]
 pub(crate) enum ResolveStrategy {
@@ -122,6 +166,7 @@ impl Default for StubResolver {
             ips_to_fqdn: Default::default(),
             ip_provider: IpProvider::for_resources(),
             dns_resources: Default::default(),
+            linked_domains: Default::default(),
             search_domain: Default::default(),
         }
     }
@@ -170,6 +215,25 @@ impl StubResolver {
 
     pub(crate) fn remove_resource(&mut self, id: ResourceId) {
         self.dns_resources.retain(|_, r| r.id != id);
+        self.linked_domains.retain(|_, (r, _)| r.id != id);
+    }
+
+    pub(crate) fn handle_timeout(&mut self, now: Instant) {
+        self.linked_domains.retain(|domain, (resource, expires_at)| {
+            if *expires_at > now {
+                return true;
+            }
+
+            tracing::debug!(%domain, rid = %resource.id, "Link of domain to DNS resource expired");
+
+            false
+        });
+    }
+
+    fn linked_resource(&self, domain: &DomainName) -> Option<Resource> {
+        self.linked_domains
+            .get(domain)
+            .map(|(resource, _)| *resource)
     }
 
     fn get_or_assign_a_records(
@@ -227,6 +291,23 @@ impl StubResolver {
         ips
     }
 
+    /// Matches the given domain against our DNS resources, returning the resource and the proxy IPs we handed out for the domain so far.
+    ///
+    /// This performs a linear search and **must not** be called in the hot-path of packet routing.
+    pub(crate) fn resource_by_domain(
+        &self,
+        domain: &dns_types::DomainName,
+    ) -> Option<(ResourceId, Vec<IpAddr>)> {
+        let resource = self.match_resource_linear(domain)?;
+        let ips = self
+            .fqdn_to_ips
+            .get(&(domain.clone(), resource.id))
+            .cloned()
+            .unwrap_or_default();
+
+        Some((resource.id, ips))
+    }
+
     /// Attempts to match the given domain against our list of possible patterns.
     ///
     /// This performs a linear search and is thus O(N) and **must not** be called in the hot-path of packet routing.
@@ -273,7 +354,9 @@ impl StubResolver {
         }
 
         // `match_resource` is `O(N)` which we deem fine for DNS queries.
-        let maybe_resource = self.match_resource_linear(&domain);
+        let maybe_resource = self
+            .match_resource_linear(&domain)
+            .or_else(|| self.linked_resource(&domain));
 
         let records = match (qtype, maybe_resource) {
             (RecordType::A, Some(resource)) => {
@@ -312,6 +395,57 @@ impl StubResolver {
         ResolveStrategy::LocalResponse(response)
     }
 
+    /// Processes the response to a query that we forwarded to a site via [`ResolveStrategy::RecurseSite`].
+    ///
+    /// SRV targets and CNAMEs in the answer may point to domains that aren't matched by any resource and would thus be resolved outside of the tunnel.
+    /// We link those domains to the resource of the original query so subsequent queries for them are handled like queries for the resource itself.
+    /// SRV targets are assigned proxy IPs right away, which we add as A / AAAA records to the additional section.
+    ///
+    /// Returns `None` if the response doesn't need to be rewritten.
+    pub(crate) fn handle_site_response(
+        &mut self,
+        response: &Response,
+        now: Instant,
+    ) -> Option<Response> {
+        if !matches!(response.qtype(), RecordType::SRV | RecordType::TXT) {
+            return None;
+        }
+
+        let domain = response.domain();
+        let resource = self
+            .match_resource_linear(&domain)
+            .or_else(|| self.linked_resource(&domain))?;
+
+        let mut additional_records = Vec::new();
+
+        for (rtype, target, ttl) in record_targets(response) {
+            if self.match_resource_linear(&target).is_some() {
+                continue;
+            }
+
+            tracing::debug!(%domain, %target, rid = %resource.id, ?ttl, "Linking domain to DNS resource");
+
+            self.linked_domains
+                .insert(target.clone(), (resource, now + ttl));
+
+            if rtype != RecordType::SRV {
+                continue;
+            }
+
+            additional_records.extend(
+                self.get_or_assign_ips(target.clone(), resource)
+                    .into_iter()
+                    .map(|ip| (target.clone(), DNS_TTL, dns_types::records::ip(ip))),
+            );
+        }
+
+        if additional_records.is_empty() {
+            return None;
+        }
+
+        Some(response.clone().with_additional_records(additional_records))
+    }
+
     pub(crate) fn set_search_domain(&mut self, new_search_domain: Option<DomainName>) {
         if self.search_domain == new_search_domain {
             return;
@@ -323,6 +457,26 @@ impl StubResolver {
     }
 }
 
+/// The SRV targets and CNAMEs in the answer of the given response, together with how long they are valid for.
+///
+/// The validity is at least [`MIN_LINKED_DOMAIN_TTL`], even if the record's TTL is shorter.
+pub(crate) fn record_targets(
+    response: &Response,
+) -> impl Iterator<Item = (RecordType, DomainName, Duration)> + '_ {
+    response.records().filter_map(|record| {
+        #[expect(clippy::wildcard_enum_match_arm)]
+        let target = match record.data() {
+            RecordData::Srv(srv) => srv.target().clone().flatten_into(),
+            RecordData::Cname(cname) => cname.cname().clone().flatten_into(),
+            _ => return None,
+        };
+
+        let ttl = Duration::from_secs(record.ttl().as_secs().into()).max(MIN_LINKED_DOMAIN_TTL);
+
+        Some((record.rtype(), target, ttl))
+    })
+}
+
 pub fn is_subdomain(name: &dns_types::DomainName, pattern: &str) -> bool {
     let pattern = match Pattern::new(pattern) {
         Ok(p) => p,
@@ -738,6 +892,173 @@ mod tests {
         assert_eq!(response.response_code(), ResponseCode::NOERROR);
         assert_eq!(response.records().count(), 0);
     }
+
+    #[test]
+    fn srv_target_of_resource_gets_proxy_ips() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(1),
+            "**.corp.example".to_owned(),
+            IpStack::Dual,
+        );
+
+        let query = Query::new("_ldap._tcp.corp.example".parse().unwrap(), RecordType::SRV);
+        let site_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                query.domain(),
+                300,
+                dns_types::records::srv(0, 0, 389, "dc1.ad.internal".parse().unwrap()),
+            )])
+            .build();
+
+        let response = resolver
+            .handle_site_response(&site_response, Instant::now())
+            .unwrap();
+
+        assert_eq!(response.records().count(), 1);
+        assert_eq!(response.additional_records().count(), 8);
+
+        let ResolveStrategy::LocalResponse(a_response) = resolver.handle(&Query::new(
+            "dc1.ad.internal".parse().unwrap(),
+            RecordType::A,
+        )) else {
+            panic!("Unexpected result")
+        };
+
+        assert_eq!(a_response.records().count(), 4);
+        assert!(
+            resolver
+                .resolved_resources()
+                .any(|(domain, rid, _)| domain.to_string() == "dc1.ad.internal"
+                    && *rid == ResourceId::from_u128(1))
+        );
+    }
+
+    #[test]
+    fn cname_target_of_resource_is_forwarded_to_site() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(1),
+            "**.corp.example".to_owned(),
+            IpStack::Dual,
+        );
+
+        let query = Query::new("_ldap._tcp.corp.example".parse().unwrap(), RecordType::SRV);
+        let site_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                query.domain(),
+                300,
+                dns_types::records::cname("_ldap._tcp.ad.internal".parse().unwrap()),
+            )])
+            .build();
+
+        assert!(
+            resolver
+                .handle_site_response(&site_response, Instant::now())
+                .is_none()
+        );
+
+        let strategy = resolver.handle(&Query::new(
+            "_ldap._tcp.ad.internal".parse().unwrap(),
+            RecordType::SRV,
+        ));
+
+        assert!(matches!(
+            strategy,
+            ResolveStrategy::RecurseSite(rid) if rid == ResourceId::from_u128(1)
+        ));
+    }
+
+    #[test]
+    fn srv_target_matching_a_resource_is_not_rewritten() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(1),
+            "**.corp.example".to_owned(),
+            IpStack::Dual,
+        );
+
+        let query = Query::new("_ldap._tcp.corp.example".parse().unwrap(), RecordType::SRV);
+        let site_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                query.domain(),
+                300,
+                dns_types::records::srv(0, 0, 389, "dc1.corp.example".parse().unwrap()),
+            )])
+            .build();
+
+        assert!(
+            resolver
+                .handle_site_response(&site_response, Instant::now())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn linked_domain_expires_with_its_record() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(1),
+            "**.corp.example".to_owned(),
+            IpStack::Dual,
+        );
+        let now = Instant::now();
+
+        let query = Query::new("_ldap._tcp.corp.example".parse().unwrap(), RecordType::SRV);
+        let site_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                query.domain(),
+                300,
+                dns_types::records::srv(0, 0, 389, "dc1.ad.internal".parse().unwrap()),
+            )])
+            .build();
+        resolver.handle_site_response(&site_response, now).unwrap();
+
+        let a_query = Query::new("dc1.ad.internal".parse().unwrap(), RecordType::A);
+
+        resolver.handle_timeout(now + Duration::from_secs(299));
+        assert!(matches!(
+            resolver.handle(&a_query),
+            ResolveStrategy::LocalResponse(_)
+        ));
+
+        resolver.handle_timeout(now + Duration::from_secs(300));
+        assert!(matches!(
+            resolver.handle(&a_query),
+            ResolveStrategy::RecurseLocal
+        ));
+    }
+
+    #[test]
+    fn removing_resource_unlinks_domains() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(1),
+            "**.corp.example".to_owned(),
+            IpStack::Dual,
+        );
+
+        let query = Query::new("_ldap._tcp.corp.example".parse().unwrap(), RecordType::SRV);
+        let site_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                query.domain(),
+                300,
+                dns_types::records::srv(0, 0, 389, "dc1.ad.internal".parse().unwrap()),
+            )])
+            .build();
+
+        resolver
+            .handle_site_response(&site_response, Instant::now())
+            .unwrap();
+        resolver.remove_resource(ResourceId::from_u128(1));
+
+        let strategy = resolver.handle(&Query::new(
+            "dc1.ad.internal".parse().unwrap(),
+            RecordType::A,
+        ));
+
+        assert!(matches!(strategy, ResolveStrategy::RecurseLocal));
+    }
 }
 
 #[cfg(feature = "divan")]
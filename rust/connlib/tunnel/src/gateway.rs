@@ -1,22 +1,27 @@
-use crate::messages::gateway::ResourceDescription;
+use crate::messages::gateway::{RateLimit, ResourceDescription};
 use crate::messages::{Answer, IceCredentials, ResolveRequest, SecretKey};
-use crate::peer::TranslateOutboundResult;
-use crate::{GatewayEvent, IpConfig, p2p_control};
+use crate::peer::{ExhaustedNat, TranslateOutboundResult};
+use crate::{GatewayEvent, IpConfig, capture, otel, p2p_control};
 use crate::{peer::ClientOnGateway, peer_store::PeerStore};
 use anyhow::{Context, Result};
 use boringtun::x25519::PublicKey;
 use chrono::{DateTime, Utc};
 use connlib_model::{ClientId, IceCandidate, RelayId, ResourceId};
 use dns_types::DomainName;
+use egress_pool::EgressPool;
 use ip_packet::{FzP2pControlSlice, IpPacket};
+use post_quantum_psk::PostQuantumPsk;
 use secrecy::{ExposeSecret as _, Secret};
-use snownet::{Credentials, NoTurnServers, RelaySocket, ServerNode, Transmit};
+use snownet::{ConnectionStats, Credentials, NoTurnServers, RelaySocket, ServerNode, Transmit};
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::iter;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+mod egress_pool;
+mod post_quantum_psk;
+
 pub const TUN_DNS_PORT: u16 = 53535;
 
 const EXPIRE_RESOURCES_INTERVAL: Duration = Duration::from_secs(1);
@@ -37,6 +42,17 @@ pub struct GatewayState {
 
     tun_ip_config: Option<IpConfig>,
 
+    /// Whether we should track and report the flows of each client.
+    flow_logs_enabled: bool,
+    /// The default rate limit applied to each client.
+    client_rate_limit: Option<RateLimit>,
+    /// Additional egress IPs for clients whose NAT is exhausted.
+    egress_pool: EgressPool,
+    /// Negotiates post-quantum preshared keys with clients.
+    post_quantum_psk: PostQuantumPsk,
+
+    num_nat_sessions: opentelemetry::metrics::Gauge<u64>,
+
     buffered_events: VecDeque<GatewayEvent>,
     buffered_transmits: VecDeque<Transmit>,
 }
@@ -59,7 +75,10 @@ impl DnsResourceNatEntry {
 }
 
 impl GatewayState {
-    pub(crate) fn new(seed: [u8; 32], now: Instant) -> Self {
+    /// Creates a new [`GatewayState`].
+    ///
+    /// `seed` determines our WireGuard private key, `post_quantum_psk_seed` must therefore be independent of it.
+    pub(crate) fn new(seed: [u8; 32], post_quantum_psk_seed: [u8; 32], now: Instant) -> Self {
         Self {
             peers: Default::default(),
             node: ServerNode::new(seed, now),
@@ -67,6 +86,37 @@ impl GatewayState {
             buffered_events: VecDeque::default(),
             buffered_transmits: VecDeque::default(),
             tun_ip_config: None,
+            flow_logs_enabled: false,
+            client_rate_limit: None,
+            egress_pool: EgressPool::default(),
+            post_quantum_psk: PostQuantumPsk::new(post_quantum_psk_seed),
+            num_nat_sessions: otel::metrics::network_nat_sessions(),
+        }
+    }
+
+    pub fn set_flow_logs_enabled(&mut self, enabled: bool) {
+        self.flow_logs_enabled = enabled;
+    }
+
+    /// Configures additional egress IPs to use for clients whose NAT is exhausted.
+    ///
+    /// Return traffic for these IPs must be routed to the TUN device.
+    pub fn set_egress_ips(&mut self, ips: impl IntoIterator<Item = IpAddr>) {
+        self.egress_pool.set_ips(ips);
+    }
+
+    /// Whether we answer clients that want to upgrade their preshared key with a post-quantum secret.
+    ///
+    /// Enabled by default.
+    pub fn set_post_quantum_psk_enabled(&mut self, enabled: bool) {
+        self.post_quantum_psk.set_enabled(enabled);
+    }
+
+    pub fn set_client_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.client_rate_limit = limit;
+
+        for peer in self.peers.iter_mut() {
+            peer.set_rate_limit(limit);
         }
     }
 
@@ -79,6 +129,37 @@ impl GatewayState {
         self.node.public_key()
     }
 
+    /// Statistics about our connections to Clients.
+    pub fn connection_stats(&self) -> impl Iterator<Item = (ClientId, ConnectionStats)> + '_ {
+        let (_, connections) = self.node.stats();
+
+        connections
+    }
+
+    /// The client and resource that `packet` is sent between, for annotating packet captures.
+    ///
+    /// Packets we read from the TUN device are on their way to a client, packets we write to it come from one.
+    pub(crate) fn capture_labels(
+        &self,
+        packet: &IpPacket,
+        direction: capture::Direction,
+    ) -> capture::Labels {
+        let (client_ip, resource_ip) = match direction {
+            capture::Direction::Outbound => (packet.destination(), packet.source()),
+            capture::Direction::Inbound => (packet.source(), packet.destination()),
+        };
+
+        let Some(peer) = self.peers.peer_by_ip(client_ip) else {
+            return capture::Labels::default();
+        };
+
+        capture::Labels {
+            resource: peer.resource_for_destination(resource_ip),
+            gateway: None,
+            client: Some(peer.id()),
+        }
+    }
+
     /// Handles packets received on the TUN device.
     pub(crate) fn handle_tun_input(
         &mut self,
@@ -87,7 +168,7 @@ impl GatewayState {
     ) -> Result<Option<snownet::Transmit>> {
         let dst = packet.destination();
 
-        if !crate::is_peer(dst) {
+        if !crate::is_peer(dst) && !self.egress_pool.is_leased(dst) {
             return Ok(None);
         }
 
@@ -142,9 +223,14 @@ impl GatewayState {
             .with_context(|| format!("No peer for connection {cid}"))?;
 
         if let Some(fz_p2p_control) = packet.as_fz_p2p_control() {
-            let Some(immediate_response) =
-                handle_p2p_control_packet(fz_p2p_control, peer, &mut self.buffered_events)
-            else {
+            let Some(immediate_response) = handle_p2p_control_packet(
+                fz_p2p_control,
+                peer,
+                &mut self.post_quantum_psk,
+                &mut self.node,
+                &mut self.buffered_events,
+                now,
+            ) else {
                 return Ok(None);
             };
 
@@ -158,10 +244,15 @@ impl GatewayState {
             return Ok(None);
         }
 
-        match peer
-            .translate_outbound(packet, now)
-            .context("Failed to translate outbound packet")?
+        let result = peer.translate_outbound(packet, now);
+
+        if let Err(e) = &result
+            && let Some(ExhaustedNat { dst }) = e.downcast_ref::<ExhaustedNat>()
         {
+            self.lease_egress_ip(cid, *dst);
+        }
+
+        match result.context("Failed to translate outbound packet")? {
             TranslateOutboundResult::Send(ip_packet) => Ok(Some(ip_packet)),
             TranslateOutboundResult::DestinationUnreachable(reply)
             | TranslateOutboundResult::Filtered(reply) => {
@@ -173,11 +264,31 @@ impl GatewayState {
 
                 Ok(None)
             }
+            TranslateOutboundResult::RateLimited => Ok(None),
         }
     }
 
+    /// Leases an additional egress IP to the client, allowing it to continue with new NAT sessions.
+    ///
+    /// The packet that exhausted the NAT is still dropped but subsequent ones will make it.
+    fn lease_egress_ip(&mut self, cid: ClientId, dst: IpAddr) {
+        let Some(ip) = self.egress_pool.lease(cid, dst) else {
+            tracing::debug!(%cid, %dst, "NAT is exhausted and no egress IPs are available");
+            return;
+        };
+
+        let Some(peer) = self.peers.add_ip(&cid, &ip.into()) else {
+            self.egress_pool.release(cid, ip);
+            return;
+        };
+
+        peer.add_egress_ip(ip);
+
+        tracing::info!(%cid, %dst, egress_ip = %ip, "NAT is exhausted; leased additional egress IP");
+    }
+
     pub fn cleanup_connection(&mut self, id: &ClientId) {
-        self.peers.remove(id);
+        self.remove_peer(id);
     }
 
     pub fn add_ice_candidate(
@@ -212,7 +323,7 @@ impl GatewayState {
 
         peer.remove_resource(rid);
         if peer.is_emptied() {
-            self.peers.remove(cid);
+            self.remove_peer(cid);
         }
 
         tracing::debug!("Access removed");
@@ -289,10 +400,16 @@ impl GatewayState {
     ) -> anyhow::Result<()> {
         let gateway_tun = self.tun_ip_config.context("TUN device not configured")?;
 
-        let peer = self
-            .peers
-            .entry(client)
-            .or_insert_with(|| ClientOnGateway::new(client, client_tun, gateway_tun));
+        let peer = self.peers.entry(client).or_insert_with(|| {
+            let mut peer = ClientOnGateway::new(client, client_tun, gateway_tun);
+            peer.set_rate_limit(self.client_rate_limit);
+
+            peer
+        });
+
+        if self.flow_logs_enabled {
+            peer.enable_flow_logs();
+        }
 
         peer.add_resource(resource.clone(), expires_at);
 
@@ -325,6 +442,35 @@ impl GatewayState {
         Ok(())
     }
 
+    /// The DNS server to forward a client's query to, in case it is for a DNS resource with a dedicated DNS server.
+    ///
+    /// Queries that the client forwards to us because they are for a DNS resource (e.g. SRV or TXT queries) must be resolved by the same server as the resource itself.
+    pub fn dns_resolver_for_query(
+        &self,
+        client: IpAddr,
+        query: &dns_types::Query,
+    ) -> Option<SocketAddr> {
+        self.peers
+            .peer_by_ip(client)?
+            .dns_resolver_for_domain(&query.domain())
+    }
+
+    /// Handles a response from our DNS server to a query of a client.
+    ///
+    /// SRV targets and CNAMEs returned for a DNS resource are linked to it such that the client can set up NAT for them.
+    pub fn handle_dns_response_for_client(
+        &mut self,
+        client: IpAddr,
+        response: &dns_types::Response,
+        now: Instant,
+    ) {
+        let Some(peer) = self.peers.peer_by_ip_mut(client) else {
+            return;
+        };
+
+        peer.link_domains(response, now);
+    }
+
     pub fn handle_domain_resolved(
         &mut self,
         req: ResolveDnsRequest,
@@ -381,11 +527,38 @@ impl GatewayState {
 
         match self.next_expiry_resources_check {
             Some(next_expiry_resources_check) if now >= next_expiry_resources_check => {
-                self.peers.iter_mut().for_each(|p| {
-                    p.expire_resources(utc_now);
-                    p.handle_timeout(now)
-                });
-                self.peers.retain(|_, p| !p.is_emptied());
+                let mut emptied = Vec::new();
+                let mut unused_egress_ips = Vec::new();
+                let mut num_nat_sessions = 0;
+
+                for peer in self.peers.iter_mut() {
+                    peer.expire_resources(utc_now);
+                    peer.handle_timeout(now);
+
+                    num_nat_sessions += peer.num_nat_sessions();
+                    unused_egress_ips.extend(
+                        peer.remove_unused_egress_ips()
+                            .into_iter()
+                            .map(|ip| (peer.id(), ip)),
+                    );
+
+                    if peer.is_emptied() {
+                        emptied.push(peer.id());
+                    }
+                }
+
+                for (cid, ip) in unused_egress_ips {
+                    tracing::debug!(%cid, egress_ip = %ip, "All NAT sessions via egress IP expired; releasing it");
+
+                    self.peers.remove_ip(&cid, &ip.into());
+                    self.egress_pool.release(cid, ip);
+                }
+
+                for cid in emptied {
+                    self.remove_peer(&cid);
+                }
+
+                self.num_nat_sessions.record(num_nat_sessions as u64, &[]);
 
                 self.next_expiry_resources_check = Some(now + EXPIRE_RESOURCES_INTERVAL);
             }
@@ -401,7 +574,7 @@ impl GatewayState {
         while let Some(event) = self.node.poll_event() {
             match event {
                 snownet::Event::ConnectionFailed(id) | snownet::Event::ConnectionClosed(id) => {
-                    self.peers.remove(&id);
+                    self.remove_peer(&id);
                 }
                 snownet::Event::NewIceCandidate {
                     connection,
@@ -421,7 +594,20 @@ impl GatewayState {
                         .or_default()
                         .insert(candidate.into());
                 }
-                snownet::Event::ConnectionEstablished(_) => {}
+                snownet::Event::ConnectionEstablished(_)
+                | snownet::Event::ConnectionDisconnected(_) => {}
+                snownet::Event::ConnectionPathChanged {
+                    connection,
+                    old,
+                    new,
+                } => {
+                    self.buffered_events
+                        .push_back(GatewayEvent::ConnectionPathChanged {
+                            conn_id: connection,
+                            old,
+                            new,
+                        });
+                }
             }
         }
 
@@ -457,11 +643,32 @@ impl GatewayState {
             if let Some(ev) = peer.poll_event() {
                 return Some(ev);
             }
+
+            if let Some(record) = peer.poll_flow_record() {
+                return Some(GatewayEvent::FlowCompleted(record));
+            }
         }
 
         None
     }
 
+    /// Removes a peer, reporting all of its flows as completed.
+    fn remove_peer(&mut self, cid: &ClientId) {
+        self.egress_pool.release_all(*cid);
+        self.post_quantum_psk.remove_client(cid);
+
+        let Some(mut peer) = self.peers.remove(cid) else {
+            return;
+        };
+
+        peer.close_all_flows();
+
+        while let Some(record) = peer.poll_flow_record() {
+            self.buffered_events
+                .push_back(GatewayEvent::FlowCompleted(record));
+        }
+    }
+
     pub fn update_relays(
         &mut self,
         to_remove: BTreeSet<RelayId>,
@@ -493,9 +700,12 @@ impl GatewayState {
 fn handle_p2p_control_packet(
     fz_p2p_control: FzP2pControlSlice,
     peer: &ClientOnGateway,
+    post_quantum_psk: &mut PostQuantumPsk,
+    node: &mut ServerNode<ClientId, RelayId>,
     buffered_events: &mut VecDeque<GatewayEvent>,
+    now: Instant,
 ) -> Option<IpPacket> {
-    use p2p_control::dns_resource_nat;
+    use p2p_control::{dns_resource_nat, post_quantum_psk as pq};
 
     match fz_p2p_control.event_type() {
         p2p_control::ASSIGNED_IPS_EVENT => {
@@ -526,8 +736,33 @@ fn handle_p2p_control_packet(
                 client: peer.id(),
                 resource: req.resource,
                 proxy_ips: req.proxy_ips,
+                resolver: peer.dns_resolver(req.resource),
             }));
         }
+        p2p_control::ENCAPSULATION_KEY_EVENT => {
+            let Ok(encapsulation_key) = pq::decode_encapsulation_key(fz_p2p_control)
+                .inspect_err(|e| tracing::debug!("{e:#}"))
+            else {
+                return None;
+            };
+
+            let Some((ciphertext, secret)) =
+                post_quantum_psk.on_encapsulation_key(peer.id(), encapsulation_key)
+            else {
+                tracing::debug!(cid = %peer.id(), "Ignoring post-quantum encapsulation key");
+                return None;
+            };
+
+            node.upgrade_preshared_key(peer.id(), secret, now)
+                .inspect_err(|e| tracing::warn!("Failed to upgrade preshared key: {e:#}"))
+                .ok()?;
+
+            let packet = pq::ciphertext(&ciphertext)
+                .inspect_err(|e| tracing::warn!("Failed to create `Ciphertext` packet: {e:#}"))
+                .ok()?;
+
+            return Some(packet);
+        }
         code => {
             tracing::debug!(code = %code.into_u8(), "Unknown control protocol event");
         }
@@ -556,10 +791,87 @@ pub struct ResolveDnsRequest {
     client: ClientId,
     resource: ResourceId,
     proxy_ips: Vec<IpAddr>,
+    resolver: Option<SocketAddr>,
 }
 
 impl ResolveDnsRequest {
     pub fn domain(&self) -> &DomainName {
         &self.domain
     }
+
+    /// The DNS server configured for the resource, if any.
+    pub fn resolver(&self) -> Option<SocketAddr> {
+        self.resolver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::gateway::ResourceDescriptionCidr;
+
+    #[test]
+    fn capture_labels_use_the_client_side_of_the_packet() {
+        let mut gateway = GatewayState::new([0; 32], [0; 32], Instant::now());
+        gateway.update_tun_device(IpConfig {
+            v4: "100.64.0.2".parse().unwrap(),
+            v6: "fd00:2021:1111::2".parse().unwrap(),
+        });
+
+        let client = ClientId::from_u128(1);
+        let resource = ResourceId::from_u128(2);
+        gateway
+            .allow_access(
+                client,
+                IpConfig {
+                    v4: "100.64.0.1".parse().unwrap(),
+                    v6: "fd00:2021:1111::1".parse().unwrap(),
+                },
+                None,
+                ResourceDescription::Cidr(ResourceDescriptionCidr {
+                    id: resource,
+                    address: "10.0.0.0/24".parse().unwrap(),
+                    name: "cidr".to_owned(),
+                    filters: vec![],
+                    rate_limit: None,
+                }),
+                None,
+            )
+            .unwrap();
+
+        let from_client = ip_packet::make::udp_packet(
+            "100.64.0.1".parse::<IpAddr>().unwrap(),
+            "10.0.0.5".parse::<IpAddr>().unwrap(),
+            12345,
+            53,
+            vec![],
+        )
+        .unwrap();
+        let to_client = ip_packet::make::udp_packet(
+            "10.0.0.5".parse::<IpAddr>().unwrap(),
+            "100.64.0.1".parse::<IpAddr>().unwrap(),
+            53,
+            12345,
+            vec![],
+        )
+        .unwrap();
+        let expected = capture::Labels {
+            resource: Some(resource),
+            gateway: None,
+            client: Some(client),
+        };
+
+        assert_eq!(
+            gateway.capture_labels(&from_client, capture::Direction::Inbound),
+            expected
+        );
+        assert_eq!(
+            gateway.capture_labels(&to_client, capture::Direction::Outbound),
+            expected
+        );
+        assert_eq!(
+            gateway.capture_labels(&to_client, capture::Direction::Inbound),
+            capture::Labels::default()
+        );
+    }
 }
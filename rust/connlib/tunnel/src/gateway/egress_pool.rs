@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+use connlib_model::ClientId;
+
+/// A pool of additional egress IPs for clients that exhausted their NAT.
+///
+/// Each IP is leased exclusively to a single client.
+/// This allows us to route return traffic to the correct client purely based on the destination IP.
+///
+/// A pool of N IPs can therefore serve N clients at a time.
+/// To not starve other clients, leases are returned as soon as all NAT sessions of a client through the IP expired.
+#[derive(Debug, Default)]
+pub(crate) struct EgressPool {
+    ips: BTreeSet<IpAddr>,
+    leased: BTreeMap<IpAddr, ClientId>,
+}
+
+impl EgressPool {
+    /// Sets the IPs in the pool.
+    ///
+    /// Existing leases remain untouched, even if their IP is no longer part of the pool.
+    /// Such IPs are not leased again once they are released.
+    pub(crate) fn set_ips(&mut self, ips: impl IntoIterator<Item = IpAddr>) {
+        self.ips = ips.into_iter().collect();
+    }
+
+    /// Leases an IP of the same family as `dst` to the given client.
+    pub(crate) fn lease(&mut self, client: ClientId, dst: IpAddr) -> Option<IpAddr> {
+        let ip = self
+            .ips
+            .iter()
+            .filter(|ip| !self.leased.contains_key(ip))
+            .find(|ip| ip.is_ipv4() == dst.is_ipv4())
+            .copied()?;
+
+        self.leased.insert(ip, client);
+
+        Some(ip)
+    }
+
+    /// Returns a single IP leased to the given client back to the pool.
+    pub(crate) fn release(&mut self, client: ClientId, ip: IpAddr) {
+        if self.leased.get(&ip) == Some(&client) {
+            self.leased.remove(&ip);
+        }
+    }
+
+    /// Returns all IPs leased to the given client back to the pool.
+    pub(crate) fn release_all(&mut self, client: ClientId) {
+        self.leased.retain(|_, c| *c != client);
+    }
+
+    pub(crate) fn is_leased(&self, ip: IpAddr) -> bool {
+        self.leased.contains_key(&ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_are_exclusive_until_released() {
+        let mut pool = EgressPool::default();
+        pool.set_ips([ip("192.0.2.1"), ip("2001:db8::1")]);
+
+        assert_eq!(
+            pool.lease(client_a(), ip("10.0.0.1")),
+            Some(ip("192.0.2.1"))
+        );
+        assert_eq!(pool.lease(client_b(), ip("10.0.0.1")), None);
+        assert_eq!(
+            pool.lease(client_b(), ip("2001:db8::2")),
+            Some(ip("2001:db8::1"))
+        );
+
+        pool.release_all(client_a());
+
+        assert!(!pool.is_leased(ip("192.0.2.1")));
+        assert_eq!(
+            pool.lease(client_b(), ip("10.0.0.1")),
+            Some(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    fn released_ip_can_be_leased_by_other_client() {
+        let mut pool = EgressPool::default();
+        pool.set_ips([ip("192.0.2.1")]);
+
+        assert_eq!(
+            pool.lease(client_a(), ip("10.0.0.1")),
+            Some(ip("192.0.2.1"))
+        );
+
+        pool.release(client_b(), ip("192.0.2.1"));
+        assert!(pool.is_leased(ip("192.0.2.1")));
+
+        pool.release(client_a(), ip("192.0.2.1"));
+        assert_eq!(
+            pool.lease(client_b(), ip("10.0.0.1")),
+            Some(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    fn released_ip_removed_from_pool_is_not_leased_again() {
+        let mut pool = EgressPool::default();
+        pool.set_ips([ip("192.0.2.1")]);
+        pool.lease(client_a(), ip("10.0.0.1"));
+
+        pool.set_ips([]);
+        assert!(pool.is_leased(ip("192.0.2.1")));
+
+        pool.release_all(client_a());
+        assert_eq!(pool.lease(client_b(), ip("10.0.0.1")), None);
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn client_a() -> ClientId {
+        ClientId::from_u128(1)
+    }
+
+    fn client_b() -> ClientId {
+        ClientId::from_u128(2)
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use connlib_model::ClientId;
+use ml_kem::kem::Encapsulate as _;
+use rand::{SeedableRng as _, rngs::StdRng};
+use secrecy::Secret;
+
+use crate::p2p_control::post_quantum_psk::{Ciphertext, EncapsulationKey};
+
+/// Negotiates a post-quantum secret with each client to upgrade the preshared key of our tunnel.
+///
+/// Clients retransmit their encapsulation key until they receive our ciphertext.
+/// We therefore remember the last negotiation per client and answer retransmissions with the same ciphertext.
+pub(crate) struct PostQuantumPsk {
+    enabled: bool,
+    negotiated: BTreeMap<ClientId, Negotiation>,
+    rng: StdRng,
+}
+
+struct Negotiation {
+    encapsulation_key: Box<EncapsulationKey>,
+    ciphertext: Ciphertext,
+    secret: Secret<[u8; 32]>,
+}
+
+impl PostQuantumPsk {
+    pub(crate) fn new(seed: [u8; 32]) -> Self {
+        Self {
+            enabled: true,
+            negotiated: Default::default(),
+            rng: StdRng::from_seed(seed),
+        }
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Encapsulates a new secret for the client's encapsulation key.
+    ///
+    /// Returns `None` if post-quantum preshared keys are disabled.
+    pub(crate) fn on_encapsulation_key(
+        &mut self,
+        cid: ClientId,
+        encapsulation_key: EncapsulationKey,
+    ) -> Option<(Ciphertext, Secret<[u8; 32]>)> {
+        if !self.enabled {
+            return None;
+        }
+
+        if let Some(negotiation) = self
+            .negotiated
+            .get(&cid)
+            .filter(|n| *n.encapsulation_key == encapsulation_key)
+        {
+            return Some((negotiation.ciphertext.clone(), negotiation.secret.clone()));
+        }
+
+        let Ok((ciphertext, shared_key)) = encapsulation_key.encapsulate(&mut self.rng);
+        let secret = Secret::new(shared_key.into());
+
+        self.negotiated.insert(
+            cid,
+            Negotiation {
+                encapsulation_key: Box::new(encapsulation_key),
+                ciphertext: ciphertext.clone(),
+                secret: secret.clone(),
+            },
+        );
+
+        Some((ciphertext, secret))
+    }
+
+    pub(crate) fn remove_client(&mut self, cid: &ClientId) {
+        self.negotiated.remove(cid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ml_kem::{KemCore as _, MlKem768};
+    use secrecy::ExposeSecret as _;
+
+    use super::*;
+
+    #[test]
+    fn answers_retransmitted_encapsulation_key_with_same_ciphertext() {
+        let mut pq = PostQuantumPsk::new([0; 32]);
+        let (_, key) = MlKem768::generate(&mut StdRng::from_seed([1; 32]));
+
+        let (ct1, secret1) = pq.on_encapsulation_key(cid(), key.clone()).unwrap();
+        let (ct2, secret2) = pq.on_encapsulation_key(cid(), key).unwrap();
+
+        assert_eq!(ct1, ct2);
+        assert_eq!(secret1.expose_secret(), secret2.expose_secret());
+    }
+
+    #[test]
+    fn ignores_encapsulation_key_when_disabled() {
+        let mut pq = PostQuantumPsk::new([0; 32]);
+        pq.set_enabled(false);
+        let (_, key) = MlKem768::generate(&mut StdRng::from_seed([1; 32]));
+
+        assert!(pq.on_encapsulation_key(cid(), key).is_none());
+    }
+
+    fn cid() -> ClientId {
+        ClientId::from_u128(1)
+    }
+}
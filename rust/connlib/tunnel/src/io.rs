@@ -1,6 +1,11 @@
+mod doh;
+mod dot;
 mod gso_queue;
 mod nameserver_set;
+mod relay_streams;
 mod tcp_dns;
+#[cfg(test)]
+mod testing;
 mod udp_dns;
 
 use crate::{device_channel::Device, dns, otel, sockets::Sockets};
@@ -11,6 +16,7 @@ use gat_lending_iterator::LendingIterator;
 use gso_queue::GsoQueue;
 use ip_packet::{Ecn, IpPacket, MAX_FZ_PAYLOAD};
 use nameserver_set::NameserverSet;
+use relay_streams::RelayStreams;
 use socket_factory::{DatagramIn, SocketFactory, TcpSocket, UdpSocket};
 use std::{
     collections::{BTreeSet, VecDeque},
@@ -44,6 +50,8 @@ pub struct Io {
     /// The UDP sockets used to send & receive packets from the network.
     sockets: Sockets,
     gso_queue: GsoQueue,
+    /// TCP and TLS connections to relays that we could not reach via UDP.
+    relay_streams: RelayStreams,
 
     nameservers: NameserverSet,
     reval_nameserver_interval: tokio::time::Interval,
@@ -54,6 +62,10 @@ pub struct Io {
     tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
     udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
 
+    /// The TLS config for encrypted DNS queries.
+    tls_config: Arc<rustls::ClientConfig>,
+    doh_client: doh::Client,
+
     dns_queries: FuturesTupleSet<io::Result<dns_types::Response>, DnsQueryMetaData>,
 
     timeout: Option<Pin<Box<tokio::time::Sleep>>>,
@@ -86,6 +98,7 @@ pub enum Input<D, I> {
     Timeout(Instant),
     Device(D),
     Network(I),
+    RelayStream(relay_streams::Received),
     TcpDnsQuery(l4_tcp_dns_server::Query),
     UdpDnsQuery(l4_udp_dns_server::Query),
     DnsResponse(dns::RecursiveResponse),
@@ -106,6 +119,8 @@ impl Io {
         let mut sockets = Sockets::default();
         sockets.rebind(udp_socket_factory.clone()); // Bind sockets on startup.
 
+        let tls_config = tls_config();
+
         Self {
             outbound_packet_buffer: VecDeque::default(),
             timeout: None,
@@ -116,8 +131,11 @@ impl Io {
                 udp_socket_factory.clone(),
             ),
             reval_nameserver_interval: tokio::time::interval(RE_EVALUATE_NAMESERVER_INTERVAL),
+            relay_streams: RelayStreams::new(tcp_socket_factory.clone(), tls_config.clone()),
+            doh_client: doh::Client::new(tcp_socket_factory.clone(), tls_config.clone()),
             tcp_socket_factory,
             udp_socket_factory,
+            tls_config,
             dns_queries: FuturesTupleSet::new(DNS_QUERY_TIMEOUT, 1000),
             gso_queue: GsoQueue::new(),
             tun: Device::new(),
@@ -181,6 +199,10 @@ impl Io {
             )));
         }
 
+        if let Poll::Ready(received) = self.relay_streams.poll_recv(cx) {
+            return Poll::Ready(Ok(Input::RelayStream(received)));
+        }
+
         if let Poll::Ready(num_packets) =
             self.tun
                 .poll_read_many(cx, &mut buffers.ip, MAX_INBOUND_PACKET_BATCH)
@@ -325,6 +347,8 @@ impl Io {
         self.udp_socket_factory.reset();
         self.sockets.rebind(self.udp_socket_factory.clone());
         self.gso_queue.clear();
+        self.relay_streams.reset();
+        self.doh_client.reset();
         self.dns_queries = FuturesTupleSet::new(DNS_QUERY_TIMEOUT, 1000);
         self.nameservers.evaluate();
     }
@@ -358,14 +382,23 @@ impl Io {
         dst: SocketAddr,
         payload: &[u8],
         ecn: Ecn,
+        transport: snownet::Transport,
     ) {
-        self.gso_queue.enqueue(src, dst, payload, ecn);
+        let network_transport = if transport.is_stream() {
+            self.relay_streams.send(dst, transport, payload);
+
+            otel::attr::network_transport_tcp()
+        } else {
+            self.gso_queue.enqueue(src, dst, payload, ecn);
+
+            otel::attr::network_transport_udp()
+        };
 
         self.packet_counter.add(
             1,
             &[
                 otel::attr::network_protocol_name(payload),
-                otel::attr::network_transport_udp(),
+                network_transport,
                 otel::attr::network_io_direction_transmit(),
             ],
         );
@@ -378,8 +411,35 @@ impl Io {
             transport: query.transport,
         };
 
-        match query.transport {
-            dns::Transport::Udp { .. } => {
+        match (query.encryption, query.transport) {
+            (dns::Encryption::Https { url }, _) => {
+                if self
+                    .dns_queries
+                    .try_push(self.doh_client.send(query.server, url, query.message), meta)
+                    .is_err()
+                {
+                    tracing::debug!("Failed to queue DoH query")
+                }
+            }
+            (dns::Encryption::Tls { server_name }, _) => {
+                if self
+                    .dns_queries
+                    .try_push(
+                        dot::send(
+                            self.tcp_socket_factory.clone(),
+                            self.tls_config.clone(),
+                            query.server,
+                            server_name,
+                            query.message,
+                        ),
+                        meta,
+                    )
+                    .is_err()
+                {
+                    tracing::debug!("Failed to queue DoT query")
+                }
+            }
+            (dns::Encryption::None, dns::Transport::Udp { .. }) => {
                 if self
                     .dns_queries
                     .try_push(
@@ -391,7 +451,7 @@ impl Io {
                     tracing::debug!("Failed to queue UDP DNS query")
                 }
             }
-            dns::Transport::Tcp { .. } => {
+            (dns::Encryption::None, dns::Transport::Tcp { .. }) => {
                 if self
                     .dns_queries
                     .try_push(
@@ -423,6 +483,41 @@ impl Io {
     }
 }
 
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("ring supports the default protocol versions")
+    .with_root_certificates(root_certificates())
+    .with_no_client_auth();
+
+    Arc::new(config)
+}
+
+#[cfg(not(system_certs))]
+fn root_certificates() -> rustls::RootCertStore {
+    rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    }
+}
+
+#[cfg(system_certs)]
+fn root_certificates() -> rustls::RootCertStore {
+    let certs = rustls_native_certs::load_native_certs();
+
+    for error in certs.errors {
+        tracing::debug!("Failed to load system certificate: {error}");
+    }
+
+    let mut roots = rustls::RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(certs.certs);
+
+    tracing::debug!(%added, %ignored, "Loaded system root certificates");
+
+    roots
+}
+
 fn is_max_wg_packet_size(d: &DatagramIn) -> bool {
     let len = d.packet.len();
     if len > MAX_FZ_PAYLOAD {
@@ -435,7 +530,7 @@ fn is_max_wg_packet_size(d: &DatagramIn) -> bool {
 #[cfg(test)]
 mod tests {
     use futures::task::noop_waker_ref;
-    use std::{future::poll_fn, ptr::addr_of_mut};
+    use std::{future::poll_fn, net::Ipv4Addr, ptr::addr_of_mut};
 
     use super::*;
 
@@ -472,6 +567,49 @@ mod tests {
         assert!(timeout >= now, "timeout = {timeout:?}, now = {now:?}");
     }
 
+    #[tokio::test]
+    async fn sends_dns_query_via_https() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = testing::doh_server(testing::http_ok).await;
+        let query = dns_types::Query::new(
+            dns_types::DomainName::vec_from_str("example.com").unwrap(),
+            dns_types::RecordType::A,
+        )
+        .with_id(42);
+        let source = SocketAddr::from((Ipv4Addr::LOCALHOST, 1234));
+
+        let mut io = Io::new(
+            Arc::new(socket_factory::tcp),
+            Arc::new(|_| Err(io::Error::other("not implemented"))),
+            BTreeSet::new(),
+        );
+        io.set_tun(Box::new(DummyTun));
+        io.doh_client =
+            doh::Client::new(Arc::new(socket_factory::tcp), testing::tls_client_config());
+
+        io.send_dns_query(
+            dns::RecursiveQuery::via_udp(source, server, query).with_encryption(
+                dns::Encryption::Https {
+                    url: format!("https://localhost:{}/dns-query", server.port())
+                        .parse()
+                        .unwrap(),
+                },
+            ),
+        );
+
+        let Input::DnsResponse(response) = io.next().await else {
+            panic!("Unexpected result");
+        };
+
+        assert_eq!(response.server, server);
+        assert!(matches!(response.transport, dns::Transport::Udp { source: s } if s == source));
+
+        let message = response.message.unwrap();
+        assert_eq!(message.id(), 42);
+        assert_eq!(message.response_code(), dns_types::ResponseCode::NXDOMAIN);
+    }
+
     static mut DUMMY_BUF: Buffers = Buffers { ip: Vec::new() };
 
     /// Helper functions to make the test more concise.
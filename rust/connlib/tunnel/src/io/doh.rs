@@ -0,0 +1,480 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rustls::pki_types::ServerName;
+use socket_factory::{SocketFactory, TcpSocket, TcpStream};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+use url::{Host, Position, Url};
+
+/// See <https://www.rfc-editor.org/rfc/rfc8484#section-6>.
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+/// DNS messages are at most 65k, leave some room for the HTTP headers.
+const MAX_RESPONSE_SIZE: usize = 70_000;
+
+/// How many idle connections we keep open per DoH server.
+const MAX_IDLE_CONNECTIONS_PER_SERVER: usize = 4;
+
+/// For how long we reuse an idle connection.
+///
+/// Servers typically close idle connections after a while, reusing them past that point just costs us a retry.
+const MAX_IDLE_TIME: Duration = Duration::from_secs(30);
+
+type Key = (SocketAddr, ServerName<'static>);
+
+/// A DoH client that keeps its HTTP/1.1 connections alive for subsequent queries.
+///
+/// Establishing a TCP connection and a TLS session costs several roundtrips, which would otherwise be paid on every single query.
+#[derive(Clone)]
+pub struct Client {
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    tls_config: Arc<rustls::ClientConfig>,
+
+    idle_connections: Arc<Mutex<HashMap<Key, Vec<(TlsStream<TcpStream>, Instant)>>>>,
+}
+
+impl Client {
+    pub fn new(
+        factory: Arc<dyn SocketFactory<TcpSocket>>,
+        tls_config: Arc<rustls::ClientConfig>,
+    ) -> Self {
+        Self {
+            factory,
+            tls_config,
+            idle_connections: Default::default(),
+        }
+    }
+
+    /// Drops all idle connections, e.g. because the network changed.
+    pub fn reset(&self) {
+        self.idle_connections().clear();
+    }
+
+    pub fn send(
+        &self,
+        server: SocketAddr,
+        url: Url,
+        query: dns_types::Query,
+    ) -> impl Future<Output = io::Result<dns_types::Response>> + Send + 'static {
+        let client = self.clone();
+
+        async move {
+            tracing::trace!(target: "wire::dns::recursive::https", %server, %url, domain = %query.domain());
+
+            if url.scheme() != "https" {
+                return Err(invalid_input(format!(
+                    "Unsupported scheme: {}",
+                    url.scheme()
+                )));
+            }
+
+            let server_name = match url.host() {
+                Some(Host::Domain(domain)) => {
+                    ServerName::try_from(domain.to_owned()).map_err(invalid_input)?
+                }
+                Some(Host::Ipv4(ip)) => ServerName::from(IpAddr::V4(ip)),
+                Some(Host::Ipv6(ip)) => ServerName::from(IpAddr::V6(ip)),
+                None => return Err(invalid_input("URL has no host")),
+            };
+            let key = (server, server_name);
+            let request = http_request(&url, &query.into_bytes());
+
+            // The server may have closed an idle connection in the meantime, in which case we retry on a new one.
+            if let Some(stream) = client.take_idle_connection(&key) {
+                match client.roundtrip(&key, stream, &request).await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        tracing::debug!(%server, "Failed to send query on idle DoH connection: {e}")
+                    }
+                }
+            }
+
+            let tcp_socket = client.factory.bind(server)?;
+            let tcp_stream = tcp_socket.connect(server).await?;
+            let tls_stream = TlsConnector::from(client.tls_config.clone())
+                .connect(key.1.clone(), tcp_stream)
+                .await?;
+
+            client.roundtrip(&key, tls_stream, &request).await
+        }
+    }
+
+    async fn roundtrip(
+        &self,
+        key: &Key,
+        mut tls_stream: TlsStream<TcpStream>,
+        request: &[u8],
+    ) -> io::Result<dns_types::Response> {
+        tls_stream.write_all(request).await?;
+        tls_stream.flush().await?;
+
+        let mut buf = Vec::new();
+
+        let response = loop {
+            if let Some(response) = parse_response(&buf)? {
+                break response;
+            }
+
+            if buf.len() > MAX_RESPONSE_SIZE {
+                return Err(invalid_data("Response is too large"));
+            }
+
+            if tls_stream.read_buf(&mut buf).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        };
+
+        // Only reuse the connection if the server wants to keep it and we know exactly where the response ended.
+        if response.keep_alive && response.len == Some(buf.len()) {
+            self.return_idle_connection(key, tls_stream);
+        }
+
+        let message = dns_types::Response::parse(&response.body).map_err(io::Error::other)?;
+
+        Ok(message)
+    }
+
+    fn take_idle_connection(&self, key: &Key) -> Option<TlsStream<TcpStream>> {
+        let mut idle_connections = self.idle_connections();
+        let connections = idle_connections.get_mut(key)?;
+
+        let now = Instant::now();
+
+        while let Some((stream, idle_since)) = connections.pop() {
+            if now.duration_since(idle_since) < MAX_IDLE_TIME {
+                return Some(stream);
+            }
+        }
+
+        None
+    }
+
+    fn return_idle_connection(&self, key: &Key, stream: TlsStream<TcpStream>) {
+        let mut idle_connections = self.idle_connections();
+        let connections = idle_connections.entry(key.clone()).or_default();
+
+        if connections.len() >= MAX_IDLE_CONNECTIONS_PER_SERVER {
+            return;
+        }
+
+        connections.push((stream, Instant::now()));
+    }
+
+    fn idle_connections(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<Key, Vec<(TlsStream<TcpStream>, Instant)>>> {
+        self.idle_connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn http_request(url: &Url, body: &[u8]) -> Vec<u8> {
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: {DNS_MESSAGE_CONTENT_TYPE}\r\n\
+         Accept: {DNS_MESSAGE_CONTENT_TYPE}\r\n\
+         Content-Length: {length}\r\n\
+         \r\n",
+        path = &url[Position::BeforePath..Position::AfterQuery],
+        host = &url[Position::BeforeHost..Position::AfterPort],
+        length = body.len(),
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+
+    request
+}
+
+struct HttpResponse {
+    body: Vec<u8>,
+    /// The length of the entire HTTP response, if known.
+    len: Option<usize>,
+    keep_alive: bool,
+}
+
+/// Attempts to parse a complete HTTP response from the buffer.
+///
+/// Returns `None` if we need to read more data.
+fn parse_response(buf: &[u8]) -> io::Result<Option<HttpResponse>> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut response = httparse::Response::new(&mut headers);
+
+    let httparse::Status::Complete(header_len) = response.parse(buf).map_err(invalid_data)? else {
+        return Ok(None);
+    };
+
+    let status = response.code.unwrap_or_default();
+
+    if status != 200 {
+        return Err(io::Error::other(format!(
+            "DoH server responded with status {status}"
+        )));
+    }
+
+    let content_type = header(response.headers, "Content-Type");
+
+    if content_type.is_none_or(|ct| !ct.eq_ignore_ascii_case(DNS_MESSAGE_CONTENT_TYPE)) {
+        return Err(invalid_data(format!(
+            "Unexpected content-type: {content_type:?}"
+        )));
+    }
+
+    // Connections are persistent by default in HTTP/1.1, see <https://www.rfc-editor.org/rfc/rfc9112#section-9.3>.
+    let keep_alive = response.version == Some(1)
+        && header(response.headers, "Connection").is_none_or(|c| !c.eq_ignore_ascii_case("close"));
+
+    let body = &buf[header_len..];
+
+    if header(response.headers, "Transfer-Encoding")
+        .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+    {
+        let Some((body, body_len)) = decode_chunked(body)? else {
+            return Ok(None);
+        };
+
+        return Ok(Some(HttpResponse {
+            body,
+            len: body_len.map(|l| header_len + l),
+            keep_alive,
+        }));
+    }
+
+    let content_length = header(response.headers, "Content-Length")
+        .ok_or_else(|| invalid_data("Missing content-length"))?
+        .parse::<usize>()
+        .map_err(invalid_data)?;
+
+    let Some(body) = body.get(..content_length) else {
+        return Ok(None);
+    };
+
+    Ok(Some(HttpResponse {
+        body: body.to_vec(),
+        len: Some(header_len + content_length),
+        keep_alive,
+    }))
+}
+
+/// Decodes a chunked body, returning it together with its encoded length, if known.
+fn decode_chunked(encoded: &[u8]) -> io::Result<Option<(Vec<u8>, Option<usize>)>> {
+    let mut body = encoded;
+    let mut decoded = Vec::new();
+
+    loop {
+        let httparse::Status::Complete((offset, size)) = httparse::parse_chunk_size(body)
+            .map_err(|httparse::InvalidChunkSize| invalid_data("Invalid chunk size"))?
+        else {
+            return Ok(None);
+        };
+        let size = usize::try_from(size).map_err(invalid_data)?;
+        body = &body[offset..];
+
+        // We don't care about trailers, the last chunk is all we need.
+        // Without trailers, we also know where the response ends.
+        if size == 0 {
+            let len = body
+                .starts_with(b"\r\n")
+                .then(|| encoded.len() - body.len() + 2);
+
+            return Ok(Some((decoded, len)));
+        }
+
+        let Some(chunk) = body.get(..size) else {
+            return Ok(None);
+        };
+        decoded.extend_from_slice(chunk);
+
+        match body.get(size..size + 2) {
+            Some(b"\r\n") => body = &body[size + 2..],
+            Some(_) => return Err(invalid_data("Chunk is not terminated by CRLF")),
+            None => return Ok(None),
+        }
+    }
+}
+
+fn header<'b>(headers: &[httparse::Header<'b>], name: &str) -> Option<&'b str> {
+    let header = headers.iter().find(|h| h.name.eq_ignore_ascii_case(name))?;
+
+    std::str::from_utf8(header.value).ok().map(str::trim)
+}
+
+fn invalid_input(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use dns_types::{DomainName, RecordType, ResponseCode};
+
+    use super::*;
+    use crate::io::testing;
+
+    #[tokio::test]
+    async fn can_resolve_via_https() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = testing::doh_server(testing::http_ok).await;
+
+        let response = client()
+            .send(
+                server,
+                format!("https://localhost:{}/dns-query", server.port())
+                    .parse()
+                    .unwrap(),
+                query(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.response_code(), ResponseCode::NXDOMAIN);
+    }
+
+    #[tokio::test]
+    async fn can_resolve_via_https_with_chunked_response() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = testing::doh_server(|response| {
+            let (first, second) = response.split_at(response.len() / 2);
+
+            let mut http = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            for chunk in [first, second] {
+                http.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                http.extend_from_slice(chunk);
+                http.extend_from_slice(b"\r\n");
+            }
+            http.extend_from_slice(b"0\r\n\r\n");
+
+            http
+        })
+        .await;
+
+        let response = client()
+            .send(
+                server,
+                format!("https://127.0.0.1:{}/dns-query", server.port())
+                    .parse()
+                    .unwrap(),
+                query(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.response_code(), ResponseCode::NXDOMAIN);
+    }
+
+    #[tokio::test]
+    async fn fails_on_http_error() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = testing::doh_server(|_| {
+            b"HTTP/1.1 415 Unsupported Media Type\r\nContent-Length: 0\r\n\r\n".to_vec()
+        })
+        .await;
+
+        let result = client()
+            .send(
+                server,
+                format!("https://localhost:{}/dns-query", server.port())
+                    .parse()
+                    .unwrap(),
+                query(),
+            )
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn waits_for_complete_body() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: 4\r\n\r\nab";
+
+        assert!(parse_response(response).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reuses_connection_for_subsequent_queries() {
+        let _guard = firezone_logging::test("debug");
+
+        let (server, connections) =
+            testing::doh_server_with_connection_count(testing::http_ok).await;
+        let url = format!("https://localhost:{}/dns-query", server.port())
+            .parse::<Url>()
+            .unwrap();
+        let client = client();
+
+        for _ in 0..3 {
+            let response = client.send(server, url.clone(), query()).await.unwrap();
+
+            assert_eq!(response.id(), 42);
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_reuse_connection_closed_by_server() {
+        let _guard = firezone_logging::test("debug");
+
+        let (server, connections) = testing::doh_server_with_connection_count(|response| {
+            let mut http = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response.len()
+            )
+            .into_bytes();
+            http.extend_from_slice(&response);
+
+            http
+        })
+        .await;
+        let url = format!("https://localhost:{}/dns-query", server.port())
+            .parse::<Url>()
+            .unwrap();
+        let client = client();
+
+        for _ in 0..2 {
+            let response = client.send(server, url.clone(), query()).await.unwrap();
+
+            assert_eq!(response.id(), 42);
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn knows_length_of_chunked_response_without_trailers() {
+        let http = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n0\r\n\r\n";
+
+        let response = parse_response(http).unwrap().unwrap();
+
+        assert_eq!(response.body, b"ab");
+        assert_eq!(response.len, Some(http.len()));
+    }
+
+    fn client() -> Client {
+        Client::new(Arc::new(socket_factory::tcp), testing::tls_client_config())
+    }
+
+    fn query() -> dns_types::Query {
+        dns_types::Query::new(
+            DomainName::vec_from_str("example.com").unwrap(),
+            RecordType::A,
+        )
+        .with_id(42)
+    }
+}
@@ -0,0 +1,132 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use rustls::pki_types::ServerName;
+use socket_factory::{SocketFactory, TcpSocket};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio_rustls::TlsConnector;
+
+pub async fn send(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    tls_config: Arc<rustls::ClientConfig>,
+    server: SocketAddr,
+    server_name: String,
+    query: dns_types::Query,
+) -> io::Result<dns_types::Response> {
+    tracing::trace!(target: "wire::dns::recursive::tls", %server, %server_name, domain = %query.domain());
+
+    let server_name = ServerName::try_from(server_name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let tcp_socket = factory.bind(server)?;
+    let tcp_stream = tcp_socket.connect(server).await?;
+    let mut tls_stream = TlsConnector::from(tls_config)
+        .connect(server_name, tcp_stream)
+        .await?;
+
+    // DNS over TLS uses the same framing as DNS over TCP, see <https://www.rfc-editor.org/rfc/rfc7858#section-3.3>.
+    let query = query.into_bytes();
+    let dns_message_length = (query.len() as u16).to_be_bytes();
+
+    tls_stream.write_all(&dns_message_length).await?;
+    tls_stream.write_all(&query).await?;
+    tls_stream.flush().await?;
+
+    let mut response_length = [0u8; 2];
+    tls_stream.read_exact(&mut response_length).await?;
+    let response_length = u16::from_be_bytes(response_length) as usize;
+
+    // A u16 is at most 65k, meaning we are okay to allocate here based on what the remote is sending.
+    let mut response = vec![0u8; response_length];
+    tls_stream.read_exact(&mut response).await?;
+
+    let message = dns_types::Response::parse(&response).map_err(io::Error::other)?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use dns_types::{DomainName, RecordType};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::io::testing;
+
+    #[tokio::test]
+    async fn can_resolve_via_tls() {
+        let _guard = firezone_logging::test("debug");
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = testing::tls_acceptor().accept(stream).await.unwrap();
+
+            let mut query_length = [0u8; 2];
+            stream.read_exact(&mut query_length).await.unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(query_length) as usize];
+            stream.read_exact(&mut query).await.unwrap();
+
+            let query = dns_types::Query::parse(&query).unwrap();
+            let response = dns_types::Response::nxdomain(&query).into_bytes(u16::MAX);
+
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&response).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let query = dns_types::Query::new(
+            DomainName::vec_from_str("example.com").unwrap(),
+            RecordType::A,
+        )
+        .with_id(42);
+
+        let response = send(
+            Arc::new(socket_factory::tcp),
+            testing::tls_client_config(),
+            server,
+            "localhost".to_owned(),
+            query,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.response_code(), dns_types::ResponseCode::NXDOMAIN);
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_server_name() {
+        let _guard = firezone_logging::test("debug");
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = testing::tls_acceptor().accept(stream).await;
+        });
+
+        let query = dns_types::Query::new(
+            DomainName::vec_from_str("example.com").unwrap(),
+            RecordType::A,
+        );
+
+        let result = send(
+            Arc::new(socket_factory::tcp),
+            testing::tls_client_config(),
+            server,
+            "dns.example.com".to_owned(),
+            query,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! TCP and TLS connections to relays that we cannot reach via UDP.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::BytesMut;
+use futures::future::Either;
+use rustls::pki_types::ServerName;
+use snownet::{TURN_TLS_PORT, Transport};
+use socket_factory::{SocketFactory, TcpSocket};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    sync::mpsc,
+};
+use tokio_rustls::TlsConnector;
+use turn_stream::{StreamDecoder, encode_stream_message};
+
+/// How many messages we buffer per relay before we start dropping them.
+///
+/// TURN is designed for unreliable transports, dropping a message here is no different from losing a UDP datagram.
+const MAX_BUFFERED_MESSAGES: usize = 1024;
+
+pub struct RelayStreams {
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    tls_config: Arc<rustls::ClientConfig>,
+
+    /// The sending half of each connection, indexed by the relay's TURN socket and the transport.
+    outbound: HashMap<(SocketAddr, Transport), mpsc::Sender<Vec<u8>>>,
+
+    inbound_tx: mpsc::Sender<Received>,
+    inbound_rx: mpsc::Receiver<Received>,
+}
+
+/// A message received from a relay.
+pub struct Received {
+    pub local: SocketAddr,
+    /// The relay's TURN socket, regardless of which port we are connected to.
+    pub from: SocketAddr,
+    pub message: BytesMut,
+}
+
+impl RelayStreams {
+    pub fn new(
+        tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+        tls_config: Arc<rustls::ClientConfig>,
+    ) -> Self {
+        let (inbound_tx, inbound_rx) = mpsc::channel(MAX_BUFFERED_MESSAGES);
+
+        Self {
+            tcp_socket_factory,
+            tls_config,
+            outbound: Default::default(),
+            inbound_tx,
+            inbound_rx,
+        }
+    }
+
+    /// Sends a message to the given relay, connecting to it first if necessary.
+    ///
+    /// Must be called within a Tokio runtime context.
+    pub fn send(&mut self, relay: SocketAddr, transport: Transport, message: &[u8]) {
+        debug_assert!(transport.is_stream());
+
+        if let Some(sender) = self.outbound.get(&(relay, transport)) {
+            match sender.try_send(message.to_vec()) {
+                Ok(()) => return,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::debug!(%relay, %transport, "Dropping message to relay; stream is congested");
+                    return;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    tracing::debug!(%relay, %transport, "Stream to relay closed; reconnecting");
+                }
+            }
+        }
+
+        let (outbound_tx, outbound_rx) = mpsc::channel(MAX_BUFFERED_MESSAGES);
+        outbound_tx
+            .try_send(message.to_vec())
+            .expect("new channel has capacity");
+
+        tokio::spawn(connect_and_run(
+            self.tcp_socket_factory.clone(),
+            self.tls_config.clone(),
+            relay,
+            transport,
+            outbound_rx,
+            self.inbound_tx.clone(),
+        ));
+
+        self.outbound.insert((relay, transport), outbound_tx);
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Received> {
+        self.inbound_rx
+            .poll_recv(cx)
+            .map(|r| r.expect("we hold a sender ourselves"))
+    }
+
+    /// Closes all connections to relays.
+    pub fn reset(&mut self) {
+        self.outbound.clear();
+    }
+}
+
+async fn connect_and_run(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    tls_config: Arc<rustls::ClientConfig>,
+    relay: SocketAddr,
+    transport: Transport,
+    outbound: mpsc::Receiver<Vec<u8>>,
+    inbound: mpsc::Sender<Received>,
+) {
+    match try_connect_and_run(factory, tls_config, relay, transport, outbound, inbound).await {
+        Ok(()) => tracing::debug!(%relay, %transport, "Closed stream to relay"),
+        Err(e) => tracing::debug!(%relay, %transport, "Stream to relay failed: {e}"),
+    }
+}
+
+async fn try_connect_and_run(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    tls_config: Arc<rustls::ClientConfig>,
+    relay: SocketAddr,
+    transport: Transport,
+    outbound: mpsc::Receiver<Vec<u8>>,
+    inbound: mpsc::Sender<Received>,
+) -> io::Result<()> {
+    let remote = match transport {
+        Transport::Tcp => relay,
+        Transport::Tls => SocketAddr::new(relay.ip(), TURN_TLS_PORT),
+        Transport::Udp => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "UDP is not a stream transport",
+            ));
+        }
+    };
+
+    let tcp_stream = factory.bind(remote)?.connect(remote).await?;
+    let local = tcp_stream.local_addr()?;
+
+    tracing::debug!(%relay, %transport, %local, "Connected to relay");
+
+    match transport {
+        Transport::Tcp => run(tcp_stream, relay, local, outbound, inbound).await,
+        Transport::Tls => {
+            // Relays are only known to us by their IP, thus their certificate must be valid for it.
+            let tls_stream = TlsConnector::from(tls_config)
+                .connect(ServerName::from(relay.ip()), tcp_stream)
+                .await?;
+
+            run(tls_stream, relay, local, outbound, inbound).await
+        }
+        Transport::Udp => unreachable!("checked above"),
+    }
+}
+
+async fn run(
+    stream: impl AsyncRead + AsyncWrite,
+    relay: SocketAddr,
+    local: SocketAddr,
+    outbound: mpsc::Receiver<Vec<u8>>,
+    inbound: mpsc::Sender<Received>,
+) -> io::Result<()> {
+    let (reader, writer) = tokio::io::split(stream);
+
+    match futures::future::select(
+        pin!(read_messages(reader, relay, local, inbound)),
+        pin!(write_messages(writer, outbound)),
+    )
+    .await
+    {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+async fn read_messages(
+    mut reader: impl AsyncRead + Unpin,
+    relay: SocketAddr,
+    local: SocketAddr,
+    inbound: mpsc::Sender<Received>,
+) -> io::Result<()> {
+    let mut decoder = StreamDecoder::default();
+    let mut buffer = vec![0u8; u16::MAX as usize];
+
+    loop {
+        let num_read = reader.read(&mut buffer).await?;
+
+        if num_read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+
+        decoder.push(&buffer[..num_read]);
+
+        while let Some(message) = decoder.next_message()? {
+            inbound
+                .send(Received {
+                    local,
+                    from: relay,
+                    message,
+                })
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+    }
+}
+
+async fn write_messages(
+    mut writer: impl AsyncWrite + Unpin,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+) -> io::Result<()> {
+    let mut buffer = BytesMut::new();
+
+    // The sender is dropped once we no longer want to talk to this relay.
+    while let Some(message) = outbound.recv().await {
+        buffer.clear();
+        encode_stream_message(&message, &mut buffer);
+
+        writer.write_all(&buffer).await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use futures::future::poll_fn;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::io::testing;
+
+    #[tokio::test]
+    async fn frames_messages_over_tcp() {
+        let _guard = firezone_logging::test("debug");
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let relay = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // Expect a padded channel-data message and echo it back.
+            let mut message = [0u8; 8];
+            stream.read_exact(&mut message).await.unwrap();
+            assert_eq!(message, [0x40, 0x00, 0x00, 0x03, 1, 2, 3, 0]);
+
+            stream.write_all(&message).await.unwrap();
+        });
+
+        let mut streams =
+            RelayStreams::new(Arc::new(socket_factory::tcp), testing::tls_client_config());
+        streams.send(relay, Transport::Tcp, &[0x40, 0x00, 0x00, 0x03, 1, 2, 3]);
+
+        let received =
+            tokio::time::timeout(Duration::from_secs(1), poll_fn(|cx| streams.poll_recv(cx)))
+                .await
+                .unwrap();
+
+        assert_eq!(received.from, relay);
+        assert_eq!(
+            received.message,
+            [0x40, 0x00, 0x00, 0x03, 1, 2, 3].as_slice()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Local TLS servers for testing encrypted DNS, using a self-signed certificate for `localhost`.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+
+const CERTIFICATE: &[u8] = include_bytes!("testdata/localhost.crt");
+const PRIVATE_KEY: &[u8] = include_bytes!("testdata/localhost.key");
+
+/// A client config that only trusts our test certificate.
+pub fn tls_client_config() -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(certificate()).unwrap();
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    Arc::new(config)
+}
+
+pub fn tls_acceptor() -> tokio_rustls::TlsAcceptor {
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(
+        vec![certificate()],
+        PrivateKeyDer::from_pem_slice(PRIVATE_KEY).unwrap(),
+    )
+    .unwrap();
+
+    tokio_rustls::TlsAcceptor::from(Arc::new(config))
+}
+
+/// Serves DoH requests, answering each with NXDOMAIN wrapped in the HTTP response created by `make_response`.
+pub async fn doh_server(
+    make_response: impl Fn(Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+) -> SocketAddr {
+    let (server, _) = doh_server_with_connection_count(make_response).await;
+
+    server
+}
+
+/// Like [`doh_server`] but also counts the accepted connections.
+pub async fn doh_server_with_connection_count(
+    make_response: impl Fn(Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let make_response = Arc::new(make_response);
+
+    tokio::spawn({
+        let connections = connections.clone();
+
+        async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                connections.fetch_add(1, Ordering::SeqCst);
+
+                tokio::spawn(serve_doh_connection(stream, make_response.clone()));
+            }
+        }
+    });
+
+    (server, connections)
+}
+
+/// Serves requests on a single connection until the client closes it or we answered with `Connection: close`.
+async fn serve_doh_connection(stream: TcpStream, make_response: Arc<impl Fn(Vec<u8>) -> Vec<u8>>) {
+    let mut stream = tls_acceptor().accept(stream).await.unwrap();
+    let mut request = Vec::new();
+
+    loop {
+        let (header_len, content_length) = loop {
+            let mut headers = [httparse::EMPTY_HEADER; 32];
+            let mut req = httparse::Request::new(&mut headers);
+
+            if let httparse::Status::Complete(header_len) = req.parse(&request).unwrap() {
+                assert_eq!(req.method, Some("POST"));
+                assert_eq!(req.path, Some("/dns-query"));
+
+                let header = |name: &str| {
+                    let header = req
+                        .headers
+                        .iter()
+                        .find(|h| h.name.eq_ignore_ascii_case(name))
+                        .unwrap();
+
+                    std::str::from_utf8(header.value).unwrap()
+                };
+
+                assert_eq!(header("Content-Type"), "application/dns-message");
+                let content_length = header("Content-Length").parse::<usize>().unwrap();
+
+                break (header_len, content_length);
+            }
+
+            if stream.read_buf(&mut request).await.unwrap() == 0 {
+                return;
+            }
+        };
+
+        while request.len() < header_len + content_length {
+            stream.read_buf(&mut request).await.unwrap();
+        }
+
+        let query =
+            dns_types::Query::parse(&request[header_len..header_len + content_length]).unwrap();
+        let response = dns_types::Response::nxdomain(&query).into_bytes(u16::MAX);
+        request.drain(..header_len + content_length);
+
+        let response = make_response(response);
+        stream.write_all(&response).await.unwrap();
+
+        if !keeps_connection_alive(&response) {
+            stream.shutdown().await.unwrap();
+            return;
+        }
+    }
+}
+
+fn keeps_connection_alive(response: &[u8]) -> bool {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut res = httparse::Response::new(&mut headers);
+    res.parse(response).unwrap();
+
+    res.code == Some(200)
+        && !res.headers.iter().any(|h| {
+            h.name.eq_ignore_ascii_case("Connection") && h.value.eq_ignore_ascii_case(b"close")
+        })
+}
+
+/// An HTTP response with the given DNS message as its body.
+pub fn http_ok(message: Vec<u8>) -> Vec<u8> {
+    let mut http = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        message.len()
+    )
+    .into_bytes();
+    http.extend_from_slice(&message);
+
+    http
+}
+
+fn certificate() -> CertificateDer<'static> {
+    CertificateDer::from_pem_slice(CERTIFICATE).unwrap()
+}
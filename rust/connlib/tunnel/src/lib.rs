@@ -7,8 +7,11 @@
 
 use anyhow::Result;
 use bimap::BiMap;
+use capture::{Direction, Labels};
 use chrono::Utc;
-use connlib_model::{ClientId, GatewayId, IceCandidate, PublicKey, ResourceId, ResourceView};
+use connlib_model::{
+    ClientId, GatewayId, IceCandidate, PublicKey, ResourceId, ResourceTraffic, ResourceView,
+};
 use dns_types::DomainName;
 use gat_lending_iterator::LendingIterator;
 use io::{Buffers, Io};
@@ -16,7 +19,7 @@ use ip_network::{Ipv4Network, Ipv6Network};
 use ip_packet::Ecn;
 use socket_factory::{SocketFactory, TcpSocket, UdpSocket};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     sync::Arc,
@@ -25,6 +28,7 @@ use std::{
 };
 use tun::Tun;
 
+mod capture;
 mod client;
 mod device_channel;
 mod dns;
@@ -68,8 +72,12 @@ pub const IPV6_TUNNEL: Ipv6Network =
 pub type GatewayTunnel = Tunnel<GatewayState>;
 pub type ClientTunnel = Tunnel<ClientState>;
 
-pub use client::ClientState;
+pub use client::{
+    ClientState, ConnectionState, Diagnosis, FlowFailure, Problem, ResourceDiagnosis,
+};
 pub use gateway::{DnsResourceNatEntry, GatewayState, ResolveDnsRequest};
+pub use peer::{FlowCounters, FlowProtocol, FlowRecord};
+pub use snownet::{CandidatePair, CandidateType, ConnectionStats};
 pub use sockets::UdpSocketThreadStopped;
 pub use utils::turn;
 
@@ -87,6 +95,9 @@ pub struct Tunnel<TRoleState> {
     io: Io,
     buffers: Buffers,
 
+    /// Only present while a packet capture is running.
+    capture: Option<capture::Capture>,
+
     packet_counter: opentelemetry::metrics::Counter<u64>,
 }
 
@@ -106,6 +117,29 @@ impl<TRoleState> Tunnel<TRoleState> {
     pub fn rebind_dns_ipv6(&mut self, socket: SocketAddrV6) -> Result<()> {
         self.io.rebind_dns_ipv6(socket)
     }
+
+    /// Starts capturing all packets passing through the tunnel to `writer` in the pcapng format.
+    ///
+    /// Replaces any previously running capture.
+    pub fn start_capture(&mut self, writer: Box<dyn std::io::Write + Send>) -> Result<()> {
+        self.stop_capture();
+        self.capture = Some(capture::Capture::new(writer)?);
+
+        tracing::info!("Started packet capture");
+
+        Ok(())
+    }
+
+    pub fn stop_capture(&mut self) {
+        let Some(capture) = self.capture.take() else {
+            return;
+        };
+
+        // Don't wait for the writer thread, it flushes the remaining packets on its own.
+        let _writer_thread = capture.finish();
+
+        tracing::info!("Stopped packet capture");
+    }
 }
 
 impl ClientTunnel {
@@ -119,8 +153,9 @@ impl ClientTunnel {
                 udp_socket_factory.clone(),
                 BTreeSet::default(),
             ),
-            role_state: ClientState::new(rand::random(), Instant::now()),
+            role_state: ClientState::new(rand::random(), rand::random(), Instant::now()),
             buffers: Buffers::default(),
+            capture: None,
             packet_counter: opentelemetry::global::meter("connlib")
                 .u64_counter("system.network.packets")
                 .with_description("The number of packets processed.")
@@ -146,13 +181,39 @@ impl ClientTunnel {
             }
 
             if let Some(packet) = self.role_state.poll_packets() {
+                record(&mut self.capture, |c| {
+                    c.record_tun(
+                        Utc::now(),
+                        Direction::Inbound,
+                        &packet,
+                        self.role_state.capture_labels(&packet, Direction::Inbound),
+                    )
+                });
+
                 self.io.send_tun(packet);
                 continue;
             }
 
             if let Some(trans) = self.role_state.poll_transmit() {
-                self.io
-                    .send_network(trans.src, trans.dst, &trans.payload, Ecn::NonEct);
+                record(&mut self.capture, |c| {
+                    c.record_network(
+                        Utc::now(),
+                        Direction::Outbound,
+                        trans.src,
+                        trans.dst,
+                        &trans.payload,
+                        None,
+                        Labels::default(),
+                    )
+                });
+
+                self.io.send_network(
+                    trans.src,
+                    trans.dst,
+                    &trans.payload,
+                    Ecn::NonEct,
+                    trans.transport,
+                );
                 continue;
             }
 
@@ -180,13 +241,44 @@ impl ClientTunnel {
 
                         let ecn = packet.ecn();
 
+                        let captured = self.capture.is_some().then(|| {
+                            let labels =
+                                self.role_state.capture_labels(&packet, Direction::Outbound);
+
+                            (packet.clone(), labels)
+                        });
+                        if let Some((packet, labels)) = &captured {
+                            record(&mut self.capture, |c| {
+                                c.record_tun(Utc::now(), Direction::Outbound, packet, *labels)
+                            });
+                        }
+
                         let Some(transmit) = self.role_state.handle_tun_input(packet, now) else {
                             self.role_state.handle_timeout(now);
                             continue;
                         };
 
-                        self.io
-                            .send_network(transmit.src, transmit.dst, &transmit.payload, ecn);
+                        if let Some((packet, labels)) = &captured {
+                            record(&mut self.capture, |c| {
+                                c.record_network(
+                                    Utc::now(),
+                                    Direction::Outbound,
+                                    transmit.src,
+                                    transmit.dst,
+                                    &transmit.payload,
+                                    Some(packet),
+                                    *labels,
+                                )
+                            });
+                        }
+
+                        self.io.send_network(
+                            transmit.src,
+                            transmit.dst,
+                            &transmit.payload,
+                            ecn,
+                            transmit.transport,
+                        );
                     }
 
                     continue;
@@ -204,12 +296,25 @@ impl ClientTunnel {
                             ],
                         );
 
-                        let Some(packet) = self.role_state.handle_network_input(
+                        let packet = self.role_state.handle_network_input(
                             received.local,
                             received.from,
                             received.packet,
                             now,
-                        ) else {
+                        );
+
+                        record(&mut self.capture, |c| {
+                            record_received(
+                                c,
+                                received.from,
+                                received.local,
+                                received.packet,
+                                packet.as_ref(),
+                                |p| self.role_state.capture_labels(p, Direction::Inbound),
+                            )
+                        });
+
+                        let Some(packet) = packet else {
                             self.role_state.handle_timeout(now);
                             continue;
                         };
@@ -220,8 +325,47 @@ impl ClientTunnel {
 
                     continue;
                 }
+                Poll::Ready(io::Input::RelayStream(received)) => {
+                    let now = Instant::now();
+
+                    self.packet_counter.add(
+                        1,
+                        &[
+                            otel::attr::network_protocol_name(&received.message),
+                            otel::attr::network_transport_tcp(),
+                            otel::attr::network_io_direction_receive(),
+                        ],
+                    );
+
+                    let packet = self.role_state.handle_network_input(
+                        received.local,
+                        received.from,
+                        &received.message,
+                        now,
+                    );
+
+                    record(&mut self.capture, |c| {
+                        record_received(
+                            c,
+                            received.from,
+                            received.local,
+                            &received.message,
+                            packet.as_ref(),
+                            |p| self.role_state.capture_labels(p, Direction::Inbound),
+                        )
+                    });
+
+                    let Some(packet) = packet else {
+                        self.role_state.handle_timeout(now);
+                        continue;
+                    };
+
+                    self.io.send_tun(packet);
+
+                    continue;
+                }
                 Poll::Ready(io::Input::DnsResponse(packet)) => {
-                    self.role_state.handle_dns_response(packet);
+                    self.role_state.handle_dns_response(packet, Instant::now());
                     self.role_state.handle_timeout(Instant::now());
                     continue;
                 }
@@ -252,8 +396,9 @@ impl GatewayTunnel {
     ) -> Self {
         Self {
             io: Io::new(tcp_socket_factory, udp_socket_factory.clone(), nameservers),
-            role_state: GatewayState::new(rand::random(), Instant::now()),
+            role_state: GatewayState::new(rand::random(), rand::random(), Instant::now()),
             buffers: Buffers::default(),
+            capture: None,
             packet_counter: opentelemetry::global::meter("connlib")
                 .u64_counter("system.network.packets")
                 .with_description("The number of packets processed.")
@@ -274,8 +419,25 @@ impl GatewayTunnel {
             }
 
             if let Some(trans) = self.role_state.poll_transmit() {
-                self.io
-                    .send_network(trans.src, trans.dst, &trans.payload, Ecn::NonEct);
+                record(&mut self.capture, |c| {
+                    c.record_network(
+                        Utc::now(),
+                        Direction::Outbound,
+                        trans.src,
+                        trans.dst,
+                        &trans.payload,
+                        None,
+                        Labels::default(),
+                    )
+                });
+
+                self.io.send_network(
+                    trans.src,
+                    trans.dst,
+                    &trans.payload,
+                    Ecn::NonEct,
+                    trans.transport,
+                );
                 continue;
             }
 
@@ -293,9 +455,19 @@ impl GatewayTunnel {
 
                     match response.transport {
                         dns::Transport::Udp { source } => {
+                            self.role_state.handle_dns_response_for_client(
+                                source.ip(),
+                                &message,
+                                Instant::now(),
+                            );
                             self.io.send_udp_dns_response(source, message)?;
                         }
                         dns::Transport::Tcp { remote, .. } => {
+                            self.role_state.handle_dns_response_for_client(
+                                remote.ip(),
+                                &message,
+                                Instant::now(),
+                            );
                             self.io.send_tcp_dns_response(remote, message)?;
                         }
                     }
@@ -316,13 +488,44 @@ impl GatewayTunnel {
 
                         let ecn = packet.ecn();
 
+                        let captured = self.capture.is_some().then(|| {
+                            let labels =
+                                self.role_state.capture_labels(&packet, Direction::Outbound);
+
+                            (packet.clone(), labels)
+                        });
+                        if let Some((packet, labels)) = &captured {
+                            record(&mut self.capture, |c| {
+                                c.record_tun(Utc::now(), Direction::Outbound, packet, *labels)
+                            });
+                        }
+
                         let Some(transmit) = self.role_state.handle_tun_input(packet, now)? else {
                             self.role_state.handle_timeout(now, Utc::now());
                             continue;
                         };
 
-                        self.io
-                            .send_network(transmit.src, transmit.dst, &transmit.payload, ecn);
+                        if let Some((packet, labels)) = &captured {
+                            record(&mut self.capture, |c| {
+                                c.record_network(
+                                    Utc::now(),
+                                    Direction::Outbound,
+                                    transmit.src,
+                                    transmit.dst,
+                                    &transmit.payload,
+                                    Some(packet),
+                                    *labels,
+                                )
+                            });
+                        }
+
+                        self.io.send_network(
+                            transmit.src,
+                            transmit.dst,
+                            &transmit.payload,
+                            ecn,
+                            transmit.transport,
+                        );
                     }
 
                     continue;
@@ -341,13 +544,25 @@ impl GatewayTunnel {
                             ],
                         );
 
-                        let Some(packet) = self.role_state.handle_network_input(
+                        let packet = self.role_state.handle_network_input(
                             received.local,
                             received.from,
                             received.packet,
                             now,
-                        )?
-                        else {
+                        )?;
+
+                        record(&mut self.capture, |c| {
+                            record_received(
+                                c,
+                                received.from,
+                                received.local,
+                                received.packet,
+                                packet.as_ref(),
+                                |p| self.role_state.capture_labels(p, Direction::Inbound),
+                            )
+                        });
+
+                        let Some(packet) = packet else {
                             self.role_state.handle_timeout(now, utc_now);
                             continue;
                         };
@@ -358,8 +573,14 @@ impl GatewayTunnel {
 
                     continue;
                 }
+                Poll::Ready(io::Input::RelayStream(received)) => {
+                    // Gateways never connect to relays via a stream, so this can only be a stray message.
+                    tracing::debug!(from = %received.from, "Dropping message from relay stream");
+                    continue;
+                }
                 Poll::Ready(io::Input::UdpDnsQuery(query)) => {
-                    let Some(nameserver) = self.io.fastest_nameserver() else {
+                    let Some(server) = self.dns_server_for_query(query.source.ip(), &query.message)
+                    else {
                         tracing::warn!(query = ?query.message, "No nameserver available to handle UDP DNS query");
 
                         self.io.send_udp_dns_response(
@@ -372,12 +593,13 @@ impl GatewayTunnel {
 
                     self.io.send_dns_query(dns::RecursiveQuery::via_udp(
                         query.source,
-                        SocketAddr::new(nameserver, dns::DNS_PORT),
+                        server,
                         query.message,
                     ));
                 }
                 Poll::Ready(io::Input::TcpDnsQuery(query)) => {
-                    let Some(nameserver) = self.io.fastest_nameserver() else {
+                    let Some(server) = self.dns_server_for_query(query.remote.ip(), &query.message)
+                    else {
                         tracing::warn!(query = ?query.message, "No nameserver available to handle TCP DNS query");
 
                         self.io.send_tcp_dns_response(
@@ -391,7 +613,7 @@ impl GatewayTunnel {
                     self.io.send_dns_query(dns::RecursiveQuery::via_tcp(
                         query.local,
                         query.remote,
-                        SocketAddr::new(nameserver, dns::DNS_PORT),
+                        server,
                         query.message,
                     ));
                 }
@@ -405,6 +627,19 @@ impl GatewayTunnel {
         cx.waker().wake_by_ref(); // Schedule another wake-up with the runtime to avoid getting suspended forever.
         Poll::Pending
     }
+
+    /// The DNS server to forward a client's query to.
+    ///
+    /// Queries for DNS resources with a dedicated DNS server go there, all others to the fastest system nameserver.
+    fn dns_server_for_query(&self, client: IpAddr, query: &dns_types::Query) -> Option<SocketAddr> {
+        self.role_state
+            .dns_resolver_for_query(client, query)
+            .or_else(|| {
+                self.io
+                    .fastest_nameserver()
+                    .map(|nameserver| SocketAddr::new(nameserver, dns::DNS_PORT))
+            })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -420,12 +655,28 @@ pub enum ClientEvent {
     ConnectionIntent {
         resource: ResourceId,
         connected_gateway_ids: BTreeSet<GatewayId>,
+        /// Gateways that must not be selected for this flow.
+        ///
+        /// Non-empty if we are asking for a standby gateway.
+        excluded_gateway_ids: BTreeSet<GatewayId>,
     },
     /// The list of resources has changed and UI clients may have to be updated.
     ResourcesChanged {
         resources: Vec<ResourceView>,
     },
+    /// Counters of the traffic exchanged with resources have changed.
+    ///
+    /// Contains the counters of all resources we have exchanged traffic with.
+    ResourceTrafficUpdated {
+        traffic: BTreeMap<ResourceId, ResourceTraffic>,
+    },
     TunInterfaceUpdated(TunConfig),
+    /// Our connection to a Gateway now uses a different ICE candidate pair.
+    ConnectionPathChanged {
+        conn_id: GatewayId,
+        old: Option<CandidatePair>,
+        new: CandidatePair,
+    },
 }
 
 #[derive(Clone, derive_more::Debug, PartialEq, Eq)]
@@ -472,6 +723,16 @@ pub enum GatewayEvent {
         candidates: BTreeSet<IceCandidate>,
     },
     ResolveDns(ResolveDnsRequest),
+    /// A flow between a client and a resource has completed.
+    ///
+    /// Only emitted if flow logs are enabled via [`GatewayState::set_flow_logs_enabled`].
+    FlowCompleted(FlowRecord),
+    /// Our connection to a Client now uses a different ICE candidate pair.
+    ConnectionPathChanged {
+        conn_id: ClientId,
+        old: Option<CandidatePair>,
+        new: CandidatePair,
+    },
 }
 
 /// Adapter-struct to [`fmt::Display`] a [`BTreeSet`].
@@ -493,6 +754,52 @@ where
     }
 }
 
+/// Records a packet to the capture, if one is running.
+///
+/// A capture that fails to write is stopped, we don't want to fail the tunnel because of it.
+fn record(
+    capture: &mut Option<capture::Capture>,
+    f: impl FnOnce(&mut capture::Capture) -> Result<()>,
+) {
+    let Some(c) = capture.as_mut() else {
+        return;
+    };
+
+    if let Err(e) = f(c) {
+        tracing::warn!("Failed to write packet capture, stopping it: {e:#}");
+        *capture = None;
+    }
+}
+
+/// Records a datagram received on the network and the IP packet it carried, if any.
+fn record_received(
+    capture: &mut capture::Capture,
+    from: SocketAddr,
+    local: SocketAddr,
+    datagram: &[u8],
+    packet: Option<&ip_packet::IpPacket>,
+    labels: impl Fn(&ip_packet::IpPacket) -> Labels,
+) -> Result<()> {
+    let now = Utc::now();
+    let labels = packet.map(&labels).unwrap_or_default();
+
+    capture.record_network(
+        now,
+        Direction::Inbound,
+        Some(from),
+        local,
+        datagram,
+        packet,
+        labels,
+    )?;
+
+    if let Some(packet) = packet {
+        capture.record_tun(now, Direction::Inbound, packet, labels)?;
+    }
+
+    Ok(())
+}
+
 pub fn is_peer(dst: IpAddr) -> bool {
     match dst {
         IpAddr::V4(v4) => IPV4_TUNNEL.contains(v4),
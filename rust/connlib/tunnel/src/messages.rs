@@ -111,30 +111,49 @@ pub struct IceCredentials {
     pub password: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "protocol", rename_all = "snake_case")]
 pub enum DnsServer {
     IpPort(IpDnsServer),
+    /// DNS over HTTPS, see <https://www.rfc-editor.org/rfc/rfc8484>.
+    #[serde(rename = "doh")]
+    DoH(DoHDnsServer),
+    /// DNS over TLS, see <https://www.rfc-editor.org/rfc/rfc7858>.
+    #[serde(rename = "dot")]
+    DoT(DoTDnsServer),
 }
 
 impl fmt::Debug for DnsServer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::IpPort(IpDnsServer { address }) => address.fmt(f),
+            Self::DoH(DoHDnsServer { address, url }) => write!(f, "{url} ({address})"),
+            Self::DoT(DoTDnsServer {
+                address,
+                server_name,
+            }) => write!(f, "tls://{server_name} ({address})"),
         }
     }
 }
 
 impl DnsServer {
     pub fn ip(&self) -> IpAddr {
-        match self {
-            DnsServer::IpPort(s) => s.address.ip(),
-        }
+        self.address().ip()
     }
 
     pub fn address(&self) -> SocketAddr {
         match self {
             DnsServer::IpPort(s) => s.address,
+            DnsServer::DoH(s) => s.address,
+            DnsServer::DoT(s) => s.address,
+        }
+    }
+
+    /// Whether queries to this server are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            DnsServer::IpPort(_) => false,
+            DnsServer::DoH(_) | DnsServer::DoT(_) => true,
         }
     }
 }
@@ -155,6 +174,24 @@ pub struct IpDnsServer {
     pub address: SocketAddr,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct DoHDnsServer {
+    /// The address of the server.
+    ///
+    /// Resolving the host of `url` would itself require DNS, hence the portal sends it alongside.
+    pub address: SocketAddr,
+    /// The URL to send queries to, e.g. `https://dns.example.com/dns-query`.
+    pub url: url::Url,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct DoTDnsServer {
+    /// The address of the server, typically on port 853.
+    pub address: SocketAddr,
+    /// The name we expect in the server's certificate.
+    pub server_name: String,
+}
+
 /// Represents a wireguard interface configuration.
 ///
 /// Note that the ips are /32 for ipv4 and /128 for ipv6.
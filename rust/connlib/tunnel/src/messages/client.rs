@@ -112,7 +112,7 @@ pub struct FlowCreationFailed {
     pub violated_properties: Vec<ViolatedProperty>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum FailReason {
     NotFound,
@@ -122,7 +122,7 @@ pub enum FailReason {
     Unknown,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ViolatedProperty {
     RemoteIpLocationRegion,
@@ -182,6 +182,9 @@ pub enum EgressMessages {
     CreateFlow {
         resource_id: ResourceId,
         connected_gateway_ids: BTreeSet<GatewayId>,
+        /// Gateways the portal must not select, i.e. because we are requesting a standby for a gateway we are already connected to.
+        #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+        excluded_gateway_ids: BTreeSet<GatewayId>,
     },
     /// Candidates that can be used by the addressed gateways.
     BroadcastIceCandidates(GatewaysIceCandidates),
@@ -192,6 +195,7 @@ pub enum EgressMessages {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::{DnsServer, DoHDnsServer, DoTDnsServer};
 
     #[test]
     fn can_deserialize_internet_resource() {
@@ -321,6 +325,54 @@ mod tests {
         assert!(matches!(message, IngressMessages::ConfigChanged(_)))
     }
 
+    #[test]
+    fn can_deserialize_encrypted_upstream_dns() {
+        let json = r#"
+        {
+            "event": "config_changed",
+            "ref": null,
+            "topic": "client",
+            "payload": {
+              "interface": {
+                "ipv6": "fd00:2021:1111::e:65ea",
+                "upstream_dns": [
+                  {
+                    "protocol": "doh",
+                    "address": "1.1.1.1:443",
+                    "url": "https://cloudflare-dns.com/dns-query"
+                  },
+                  {
+                    "protocol": "dot",
+                    "address": "9.9.9.9:853",
+                    "server_name": "dns.quad9.net"
+                  }
+                ],
+                "ipv4": "100.67.138.25"
+              }
+            }
+          }
+        "#;
+
+        let message = serde_json::from_str::<IngressMessages>(json).unwrap();
+        let IngressMessages::ConfigChanged(config) = message else {
+            panic!("Unexpected message: {message:?}")
+        };
+
+        assert_eq!(
+            config.interface.upstream_dns,
+            vec![
+                DnsServer::DoH(DoHDnsServer {
+                    address: "1.1.1.1:443".parse().unwrap(),
+                    url: "https://cloudflare-dns.com/dns-query".parse().unwrap(),
+                }),
+                DnsServer::DoT(DoTDnsServer {
+                    address: "9.9.9.9:853".parse().unwrap(),
+                    server_name: "dns.quad9.net".to_owned(),
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn can_deserialize_init_message() {
         let json = r#"{
@@ -484,6 +536,7 @@ mod tests {
         let message = EgressMessages::CreateFlow {
             resource_id: "f16ecfa0-a94f-4bfd-a2ef-1cc1f2ef3da3".parse().unwrap(),
             connected_gateway_ids: BTreeSet::new(),
+            excluded_gateway_ids: BTreeSet::new(),
         };
         let expected_json = r#"{"event":"create_flow","payload":{"resource_id":"f16ecfa0-a94f-4bfd-a2ef-1cc1f2ef3da3","connected_gateway_ids":[]}}"#;
         let actual_json = serde_json::to_string(&message).unwrap();
@@ -491,6 +544,21 @@ mod tests {
         assert_eq!(actual_json, expected_json);
     }
 
+    #[test]
+    fn serialize_create_flow_message_with_excluded_gateways() {
+        let message = EgressMessages::CreateFlow {
+            resource_id: "f16ecfa0-a94f-4bfd-a2ef-1cc1f2ef3da3".parse().unwrap(),
+            connected_gateway_ids: BTreeSet::new(),
+            excluded_gateway_ids: BTreeSet::from(["b3d34a15-55ab-40df-994b-a838e75d65d7"
+                .parse()
+                .unwrap()]),
+        };
+        let expected_json = r#"{"event":"create_flow","payload":{"resource_id":"f16ecfa0-a94f-4bfd-a2ef-1cc1f2ef3da3","connected_gateway_ids":[],"excluded_gateway_ids":["b3d34a15-55ab-40df-994b-a838e75d65d7"]}}"#;
+        let actual_json = serde_json::to_string(&message).unwrap();
+
+        assert_eq!(actual_json, expected_json);
+    }
+
     #[test]
     fn faulty_candidate_get_skipped() {
         let bad_candidates = serde_json::json!({ "gateway_id": "f16ecfa0-a94f-4bfd-a2ef-1cc1f2ef3da3", "candidates": ["foo", "bar", "baz", "candidate:fffeff6435be70ddbf995982 1 udp 1694498559 87.121.72.60 57114 typ srflx raddr 0.0.0.0 rport 0"] });
@@ -10,10 +10,12 @@ use chrono::{
 };
 use connlib_model::{ClientId, IceCandidate, ResourceId};
 use ip_network::IpNetwork;
+use ip_packet::MAX_IP_SIZE;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeSet,
-    net::{Ipv4Addr, Ipv6Addr},
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
 };
 
 use super::Offer;
@@ -33,6 +35,13 @@ pub struct ResourceDescriptionDns {
     pub name: String,
 
     pub filters: Filters,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// DNS server to resolve the resource's domains with.
+    ///
+    /// If not set, we use the gateway's system resolver.
+    #[serde(default)]
+    pub resolver: Option<SocketAddr>,
 }
 
 /// Description of a resource that maps to a CIDR.
@@ -48,14 +57,40 @@ pub struct ResourceDescriptionCidr {
     pub name: String,
 
     pub filters: Filters,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 /// Description of an Internet resource.
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResourceDescriptionInternet {
     pub id: ResourceId,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// A token-bucket rate limit for traffic between a client and a resource.
+///
+/// Applies to the sum of traffic in both directions.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "RateLimitParams")]
+pub struct RateLimit {
+    bytes_per_second: u64,
+    burst_bytes: u64,
+}
+
+#[derive(Deserialize)]
+struct RateLimitParams {
+    bytes_per_second: u64,
+    #[serde(default)]
+    burst_bytes: Option<u64>,
 }
 
+/// A burst that is smaller than a single packet would drop all traffic.
+#[derive(Debug, thiserror::Error)]
+#[error("Burst of {0} bytes is smaller than the maximum packet size of {MAX_IP_SIZE} bytes")]
+pub struct BurstTooSmall(u64);
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ResourceDescription {
@@ -64,15 +99,61 @@ pub enum ResourceDescription {
     Internet(ResourceDescriptionInternet),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(tag = "protocol", rename_all = "snake_case")]
 pub enum Filter {
-    Udp(PortRange),
-    Tcp(PortRange),
-    Icmp,
+    Udp(PortFilter),
+    Tcp(PortFilter),
+    Icmp(IcmpFilter),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+/// A TCP or UDP filter.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortFilter {
+    /// Whether matching packets are allowed or denied.
+    #[serde(default)]
+    pub action: FilterAction,
+    /// The destination ports on the resource this filter matches.
+    #[serde(flatten)]
+    pub ports: PortRange,
+    /// The client's source ports this filter matches.
+    ///
+    /// `None` means any source port is allowed.
+    #[serde(default)]
+    pub source_ports: Option<PortRange>,
+    /// Sub-networks of the resource this filter applies to.
+    ///
+    /// An empty list means the filter applies to the entire resource.
+    /// For DNS resources, these are matched against the resolved IPs.
+    #[serde(default)]
+    pub destinations: Vec<IpNetwork>,
+}
+
+/// An ICMP filter.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IcmpFilter {
+    /// Whether matching packets are allowed or denied.
+    #[serde(default)]
+    pub action: FilterAction,
+    /// Sub-networks of the resource this filter applies to.
+    ///
+    /// An empty list means the filter applies to the entire resource.
+    #[serde(default)]
+    pub destinations: Vec<IpNetwork>,
+}
+
+/// What to do with packets that match a filter.
+///
+/// The filters of a resource are evaluated in order, the first matching filter decides.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PortRange {
     // TODO: we can use a custom deserializer
     // or maybe change the control plane to use start and end would suffice
@@ -92,6 +173,122 @@ fn max_port() -> u16 {
     u16::MAX
 }
 
+impl Filter {
+    /// Sub-networks of the resource this filter applies to.
+    pub fn destinations(&self) -> &[IpNetwork] {
+        match self {
+            Filter::Udp(f) | Filter::Tcp(f) => &f.destinations,
+            Filter::Icmp(f) => &f.destinations,
+        }
+    }
+
+    pub fn action(&self) -> FilterAction {
+        match self {
+            Filter::Udp(f) | Filter::Tcp(f) => f.action,
+            Filter::Icmp(f) => f.action,
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Udp(p) => write!(f, "{} udp {p}", p.action)?,
+            Filter::Tcp(p) => write!(f, "{} tcp {p}", p.action)?,
+            Filter::Icmp(i) => write!(f, "{} icmp", i.action)?,
+        }
+
+        let mut destinations = self.destinations().iter();
+
+        if let Some(first) = destinations.next() {
+            write!(f, " to {first}")?;
+        }
+
+        for destination in destinations {
+            write!(f, ", {destination}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for PortFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "port {}", self.ports)?;
+
+        if let Some(source_ports) = self.source_ports {
+            write!(f, " from port {source_ports}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterAction::Allow => write!(f, "allow"),
+            FilterAction::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.port_range_start == self.port_range_end {
+            return write!(f, "{}", self.port_range_start);
+        }
+
+        write!(f, "{}-{}", self.port_range_start, self.port_range_end)
+    }
+}
+
+impl From<PortRange> for PortFilter {
+    fn from(ports: PortRange) -> Self {
+        Self {
+            action: FilterAction::Allow,
+            ports,
+            source_ports: None,
+            destinations: Vec::new(),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Creates a new rate limit.
+    ///
+    /// - `bytes_per_second` is the sustained rate.
+    /// - `burst_bytes` is how many bytes may be sent in a single burst, defaults to one second worth of traffic.
+    pub fn new(bytes_per_second: u64, burst_bytes: Option<u64>) -> Result<Self, BurstTooSmall> {
+        let burst_bytes = burst_bytes.unwrap_or(bytes_per_second);
+
+        if burst_bytes < MAX_IP_SIZE as u64 {
+            return Err(BurstTooSmall(burst_bytes));
+        }
+
+        Ok(Self {
+            bytes_per_second,
+            burst_bytes,
+        })
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    pub fn burst_bytes(&self) -> u64 {
+        self.burst_bytes
+    }
+}
+
+impl TryFrom<RateLimitParams> for RateLimit {
+    type Error = BurstTooSmall;
+
+    fn try_from(params: RateLimitParams) -> Result<Self, Self::Error> {
+        Self::new(params.bytes_per_second, params.burst_bytes)
+    }
+}
+
 impl ResourceDescription {
     pub fn id(&self) -> ResourceId {
         match self {
@@ -108,6 +305,21 @@ impl ResourceDescription {
             ResourceDescription::Internet(_) => Vec::default(),
         }
     }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        match self {
+            ResourceDescription::Dns(r) => r.rate_limit,
+            ResourceDescription::Cidr(r) => r.rate_limit,
+            ResourceDescription::Internet(r) => r.rate_limit,
+        }
+    }
+
+    pub fn dns_resolver(&self) -> Option<SocketAddr> {
+        match self {
+            ResourceDescription::Dns(r) => r.resolver,
+            ResourceDescription::Cidr(_) | ResourceDescription::Internet(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -282,10 +494,13 @@ mod tests {
     #[test]
     fn can_deserialize_udp_filter() {
         let msg = r#"{ "protocol": "udp", "port_range_start": 10, "port_range_end": 20 }"#;
-        let expected_filter = Filter::Udp(PortRange {
-            port_range_start: 10,
-            port_range_end: 20,
-        });
+        let expected_filter = Filter::Udp(
+            PortRange {
+                port_range_start: 10,
+                port_range_end: 20,
+            }
+            .into(),
+        );
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
@@ -295,10 +510,13 @@ mod tests {
     #[test]
     fn can_deserialize_empty_udp_filter() {
         let msg = r#"{ "protocol": "udp" }"#;
-        let expected_filter = Filter::Udp(PortRange {
-            port_range_start: 0,
-            port_range_end: u16::MAX,
-        });
+        let expected_filter = Filter::Udp(
+            PortRange {
+                port_range_start: 0,
+                port_range_end: u16::MAX,
+            }
+            .into(),
+        );
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
@@ -308,10 +526,13 @@ mod tests {
     #[test]
     fn can_deserialize_tcp_filter() {
         let msg = r#"{ "protocol": "tcp", "port_range_start": 10, "port_range_end": 20 }"#;
-        let expected_filter = Filter::Tcp(PortRange {
-            port_range_start: 10,
-            port_range_end: 20,
-        });
+        let expected_filter = Filter::Tcp(
+            PortRange {
+                port_range_start: 10,
+                port_range_end: 20,
+            }
+            .into(),
+        );
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
@@ -321,10 +542,13 @@ mod tests {
     #[test]
     fn can_deserialize_empty_tcp_filter() {
         let msg = r#"{ "protocol": "tcp" }"#;
-        let expected_filter = Filter::Tcp(PortRange {
-            port_range_start: 0,
-            port_range_end: u16::MAX,
-        });
+        let expected_filter = Filter::Tcp(
+            PortRange {
+                port_range_start: 0,
+                port_range_end: u16::MAX,
+            }
+            .into(),
+        );
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
@@ -334,13 +558,102 @@ mod tests {
     #[test]
     fn can_deserialize_icmp_filter() {
         let msg = r#"{ "protocol": "icmp" }"#;
-        let expected_filter = Filter::Icmp;
+        let expected_filter = Filter::Icmp(IcmpFilter::default());
+
+        let actual_filter = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(expected_filter, actual_filter);
+    }
+
+    #[test]
+    fn can_deserialize_tcp_filter_with_destinations_and_source_ports() {
+        let msg = r#"{
+            "protocol": "tcp",
+            "port_range_start": 443,
+            "port_range_end": 443,
+            "source_ports": { "port_range_start": 1024 },
+            "destinations": ["10.0.1.5/32", "10.0.2.0/24"]
+        }"#;
+        let expected_filter = Filter::Tcp(PortFilter {
+            action: FilterAction::Allow,
+            ports: PortRange {
+                port_range_start: 443,
+                port_range_end: 443,
+            },
+            source_ports: Some(PortRange {
+                port_range_start: 1024,
+                port_range_end: u16::MAX,
+            }),
+            destinations: vec![
+                "10.0.1.5/32".parse().unwrap(),
+                "10.0.2.0/24".parse().unwrap(),
+            ],
+        });
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
         assert_eq!(expected_filter, actual_filter);
     }
 
+    #[test]
+    fn can_deserialize_icmp_filter_with_destinations() {
+        let msg = r#"{ "protocol": "icmp", "destinations": ["fd00::/64"] }"#;
+        let expected_filter = Filter::Icmp(IcmpFilter {
+            action: FilterAction::Allow,
+            destinations: vec!["fd00::/64".parse().unwrap()],
+        });
+
+        let actual_filter = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(expected_filter, actual_filter);
+    }
+
+    #[test]
+    fn can_deserialize_deny_filter() {
+        let msg = r#"{ "protocol": "tcp", "action": "deny", "port_range_start": 22, "port_range_end": 22 }"#;
+        let expected_filter = Filter::Tcp(PortFilter {
+            action: FilterAction::Deny,
+            ports: PortRange {
+                port_range_start: 22,
+                port_range_end: 22,
+            },
+            source_ports: None,
+            destinations: vec![],
+        });
+
+        let actual_filter = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(expected_filter, actual_filter);
+    }
+
+    #[test]
+    fn display_filter() {
+        let filter = Filter::Udp(PortFilter {
+            action: FilterAction::Deny,
+            ports: PortRange {
+                port_range_start: 5000,
+                port_range_end: 5100,
+            },
+            source_ports: Some(PortRange {
+                port_range_start: 123,
+                port_range_end: 123,
+            }),
+            destinations: vec![
+                "10.0.1.0/24".parse().unwrap(),
+                "10.0.2.5/32".parse().unwrap(),
+            ],
+        });
+
+        assert_eq!(
+            filter.to_string(),
+            "deny udp port 5000-5100 from port 123 to 10.0.1.0/24, 10.0.2.5/32"
+        );
+        assert_eq!(
+            Filter::Icmp(IcmpFilter::default()).to_string(),
+            "allow icmp"
+        );
+    }
+
     #[test]
     fn can_deserialize_internet_resource() {
         let resources = r#"[
@@ -371,6 +684,54 @@ mod tests {
         serde_json::from_str::<Vec<ResourceDescription>>(resources).unwrap();
     }
 
+    #[test]
+    fn can_deserialize_resource_with_rate_limit() {
+        let resource = r#"{
+            "id": "73037362-715d-4a83-a749-f18eadd970e6",
+            "type": "cidr",
+            "address": "172.172.0.0/16",
+            "name": "172.172.0.0/16",
+            "filters": [],
+            "rate_limit": { "bytes_per_second": 1000000 }
+        }"#;
+
+        let resource = serde_json::from_str::<ResourceDescription>(resource).unwrap();
+
+        assert_eq!(
+            resource.rate_limit(),
+            Some(RateLimit::new(1_000_000, None).unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_rate_limit_with_burst_smaller_than_a_packet() {
+        let rate_limit = r#"{ "bytes_per_second": 1000000, "burst_bytes": 1000 }"#;
+
+        let result = serde_json::from_str::<RateLimit>(rate_limit);
+
+        assert!(result.is_err());
+        assert!(RateLimit::new(1000, None).is_err());
+    }
+
+    #[test]
+    fn can_deserialize_dns_resource_with_resolver() {
+        let resource = r#"{
+            "id": "73037362-715d-4a83-a749-f18eadd970e6",
+            "type": "dns",
+            "address": "**.corp.example",
+            "name": "Corp",
+            "filters": [],
+            "resolver": "10.0.0.53:53"
+        }"#;
+
+        let resource = serde_json::from_str::<ResourceDescription>(resource).unwrap();
+
+        assert_eq!(
+            resource.dns_resolver(),
+            Some("10.0.0.53:53".parse().unwrap())
+        );
+    }
+
     #[test]
     fn can_deserialize_request_connection_messages() {
         let json = r#"{
@@ -14,6 +14,8 @@ use ip_packet::FzP2pEventType;
 
 pub const ASSIGNED_IPS_EVENT: FzP2pEventType = FzP2pEventType::new(0);
 pub const DOMAIN_STATUS_EVENT: FzP2pEventType = FzP2pEventType::new(1);
+pub const ENCAPSULATION_KEY_EVENT: FzP2pEventType = FzP2pEventType::new(2);
+pub const CIPHERTEXT_EVENT: FzP2pEventType = FzP2pEventType::new(3);
 
 pub mod dns_resource_nat {
     use super::*;
@@ -214,3 +216,123 @@ pub mod dns_resource_nat {
         }
     }
 }
+
+/// Negotiates a secret between client and gateway with a post-quantum key encapsulation mechanism (ML-KEM-768).
+///
+/// The client sends its encapsulation key, the gateway replies with the ciphertext of a shared secret encapsulated with it.
+/// Both sides then upgrade the preshared key of their WireGuard tunnel with the shared secret.
+///
+/// The payloads are the raw encoded keys and ciphertexts.
+pub mod post_quantum_psk {
+    use super::*;
+    use anyhow::{Context as _, Result};
+    use ip_packet::{FzP2pControlSlice, IpPacket};
+    use ml_kem::{EncodedSizeUser as _, KemCore, MlKem768};
+
+    pub type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+    pub type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+    pub type Ciphertext = ml_kem::Ciphertext<MlKem768>;
+
+    /// Construct a new [`ENCAPSULATION_KEY_EVENT`].
+    pub fn encapsulation_key(key: &EncapsulationKey) -> Result<IpPacket> {
+        let ip_packet = ip_packet::make::fz_p2p_control(
+            [ENCAPSULATION_KEY_EVENT.into_u8(), 0, 0, 0, 0, 0, 0, 0],
+            &key.as_bytes(),
+        )
+        .context("Failed to create p2p control protocol packet")?;
+
+        Ok(ip_packet)
+    }
+
+    /// Construct a new [`CIPHERTEXT_EVENT`].
+    pub fn ciphertext(ciphertext: &Ciphertext) -> Result<IpPacket> {
+        let ip_packet = ip_packet::make::fz_p2p_control(
+            [CIPHERTEXT_EVENT.into_u8(), 0, 0, 0, 0, 0, 0, 0],
+            ciphertext,
+        )
+        .context("Failed to create p2p control protocol packet")?;
+
+        Ok(ip_packet)
+    }
+
+    pub fn decode_encapsulation_key(packet: FzP2pControlSlice) -> Result<EncapsulationKey> {
+        anyhow::ensure!(
+            packet.event_type() == ENCAPSULATION_KEY_EVENT,
+            "Control protocol packet is not a `post_quantum_psk::EncapsulationKey` event"
+        );
+
+        let encoded = packet
+            .payload()
+            .try_into()
+            .context("Failed to deserialize `post_quantum_psk::EncapsulationKey`")?;
+
+        Ok(EncapsulationKey::from_bytes(&encoded))
+    }
+
+    pub fn decode_ciphertext(packet: FzP2pControlSlice) -> Result<Ciphertext> {
+        anyhow::ensure!(
+            packet.event_type() == CIPHERTEXT_EVENT,
+            "Control protocol packet is not a `post_quantum_psk::Ciphertext` event"
+        );
+
+        let ciphertext = packet
+            .payload()
+            .try_into()
+            .context("Failed to deserialize `post_quantum_psk::Ciphertext`")?;
+
+        Ok(ciphertext)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ml_kem::kem::{Decapsulate as _, Encapsulate as _};
+        use rand::{SeedableRng as _, rngs::StdRng};
+
+        #[test]
+        fn encapsulation_key_roundtrip() {
+            let (_, key) = MlKem768::generate(&mut StdRng::seed_from_u64(0));
+
+            let packet = encapsulation_key(&key).unwrap();
+
+            let slice = packet.as_fz_p2p_control().unwrap();
+            let decoded = decode_encapsulation_key(slice).unwrap();
+
+            assert_eq!(decoded, key);
+        }
+
+        #[test]
+        fn negotiates_shared_secret() {
+            let mut rng = StdRng::seed_from_u64(0);
+            let (decapsulation_key, key) = MlKem768::generate(&mut rng);
+
+            let key = decode_encapsulation_key(
+                encapsulation_key(&key)
+                    .unwrap()
+                    .as_fz_p2p_control()
+                    .unwrap(),
+            )
+            .unwrap();
+            let Ok((ct, gateway_secret)) = key.encapsulate(&mut rng);
+
+            let ct =
+                decode_ciphertext(ciphertext(&ct).unwrap().as_fz_p2p_control().unwrap()).unwrap();
+            let Ok(client_secret) = decapsulation_key.decapsulate(&ct);
+
+            assert_eq!(client_secret, gateway_secret);
+        }
+
+        #[test]
+        fn rejects_truncated_encapsulation_key() {
+            let packet = ip_packet::make::fz_p2p_control(
+                [ENCAPSULATION_KEY_EVENT.into_u8(), 0, 0, 0, 0, 0, 0, 0],
+                &[0u8; 1000],
+            )
+            .unwrap();
+
+            let result = decode_encapsulation_key(packet.as_fz_p2p_control().unwrap());
+
+            assert!(result.is_err());
+        }
+    }
+}
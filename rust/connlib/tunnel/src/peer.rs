@@ -5,11 +5,13 @@ use std::time::Instant;
 
 use crate::client::{IPV4_RESOURCES, IPV6_RESOURCES};
 use crate::messages::gateway::Filters;
+use crate::messages::gateway::RateLimit;
 use crate::messages::gateway::ResourceDescription;
 use chrono::{DateTime, Utc};
 use connlib_model::{ClientId, GatewayId, ResourceId};
 use dns_types::DomainName;
 use filter_engine::FilterEngine;
+use flow_tracker::{FlowKey, FlowTracker};
 use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use ip_network_table::IpNetworkTable;
 use ip_packet::{IpPacket, Protocol, UnsupportedProtocol};
@@ -19,9 +21,15 @@ use crate::{GatewayEvent, IpConfig, otel};
 
 use anyhow::{Context, Result, bail};
 use nat_table::{NatTable, TranslateIncomingResult};
+use rate_limiter::TokenBucket;
 
 mod filter_engine;
+mod flow_tracker;
 mod nat_table;
+mod rate_limiter;
+
+pub use flow_tracker::{FlowCounters, FlowProtocol, FlowRecord};
+pub(crate) use nat_table::ExhaustedNat;
 
 /// The state of one gateway on a client.
 pub(crate) struct GatewayOnClient {
@@ -39,6 +47,26 @@ impl GatewayOnClient {
         }
     }
 
+    /// Removes the resource from all allowed IPs.
+    ///
+    /// Returns the networks that are no longer allowed for any resource.
+    pub(crate) fn remove_id(&mut self, id: &ResourceId) -> Vec<IpNetwork> {
+        let mut removed = Vec::new();
+
+        self.allowed_ips.retain(|ip, resources| {
+            resources.remove(id);
+
+            if resources.is_empty() {
+                removed.push(ip);
+                return false;
+            }
+
+            true
+        });
+
+        removed
+    }
+
     /// For a given destination IP, return the endpoint to which the DNS query should be sent.
     pub(crate) fn tun_dns_server_endpoint(&self, dst: IpAddr) -> SocketAddr {
         let new_dst_ip = match dst {
@@ -71,9 +99,17 @@ pub struct ClientOnGateway {
     resources: HashMap<ResourceId, ResourceOnGateway>,
     /// Caches the existence of internet resource
     internet_resource_enabled: bool,
+    /// Caches whether any resource has a rate limit.
+    resource_rate_limits_enabled: bool,
     filters: IpNetworkTable<FilterEngine>,
+    /// The CIDR and Internet resources by their network, used to attribute traffic to a resource.
+    resources_by_network: IpNetworkTable<ResourceId>,
     permanent_translations: BTreeMap<IpAddr, TranslationState>,
     nat_table: NatTable,
+    /// Only present if flow logging is enabled.
+    flow_tracker: Option<FlowTracker>,
+    /// The rate limit for all traffic of this client, regardless of the resource.
+    rate_limit: Option<TokenBucket>,
     buffered_events: VecDeque<GatewayEvent>,
 
     num_dropped_packets: opentelemetry::metrics::Counter<u64>,
@@ -91,10 +127,14 @@ impl ClientOnGateway {
             gateway_tun,
             resources: HashMap::new(),
             filters: IpNetworkTable::new(),
+            resources_by_network: IpNetworkTable::new(),
             permanent_translations: Default::default(),
             nat_table: Default::default(),
+            flow_tracker: None,
+            rate_limit: None,
             buffered_events: Default::default(),
             internet_resource_enabled: false,
+            resource_rate_limits_enabled: false,
             num_dropped_packets: otel::metrics::network_packet_dropped(),
         }
     }
@@ -124,13 +164,19 @@ impl ClientOnGateway {
             .context("Unknown resource")?;
 
         let ResourceOnGateway::Dns {
-            address, domains, ..
+            address,
+            domains,
+            linked_domains,
+            ..
         } = resource
         else {
             bail!("Cannot setup NAT for non-DNS resource")
         };
 
-        anyhow::ensure!(crate::dns::is_subdomain(&name, address));
+        anyhow::ensure!(
+            crate::dns::is_subdomain(&name, address) || linked_domains.contains_key(&name),
+            "Domain is not part of the resource"
+        );
 
         let mapped_ipv4 = mapped_ipv4(&resolved_ips);
         let mapped_ipv6 = mapped_ipv6(&resolved_ips);
@@ -167,6 +213,70 @@ impl ClientOnGateway {
         Ok(())
     }
 
+    /// Links the SRV targets and CNAMEs of a DNS response to all DNS resources that match the queried domain.
+    ///
+    /// This allows the client to set up NAT for these domains even though they don't match the resource's address.
+    /// The links expire together with the records that established them.
+    pub(crate) fn link_domains(&mut self, response: &dns_types::Response, now: Instant) {
+        let domain = response.domain();
+        let targets = crate::dns::record_targets(response)
+            .map(|(_, target, ttl)| (target, now + ttl))
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return;
+        }
+
+        for (rid, resource) in self.resources.iter_mut() {
+            let ResourceOnGateway::Dns {
+                address,
+                linked_domains,
+                ..
+            } = resource
+            else {
+                continue;
+            };
+
+            if !crate::dns::is_subdomain(&domain, address) && !linked_domains.contains_key(&domain)
+            {
+                continue;
+            }
+
+            tracing::debug!(cid = %self.id, %rid, %domain, ?targets, "Linking domains to DNS resource");
+
+            linked_domains.extend(targets.iter().cloned());
+        }
+    }
+
+    /// The DNS server to resolve domains of the given DNS resource with, if any.
+    pub(crate) fn dns_resolver(&self, rid: ResourceId) -> Option<SocketAddr> {
+        match self.resources.get(&rid)? {
+            ResourceOnGateway::Dns { resolver, .. } => *resolver,
+            ResourceOnGateway::Cidr { .. } | ResourceOnGateway::Internet { .. } => None,
+        }
+    }
+
+    /// The DNS server to resolve the given domain with, if it belongs to a DNS resource with a dedicated DNS server.
+    ///
+    /// This covers domains that were linked to a DNS resource via SRV or CNAME records.
+    pub(crate) fn dns_resolver_for_domain(&self, domain: &DomainName) -> Option<SocketAddr> {
+        self.resources.values().find_map(|resource| match resource {
+            ResourceOnGateway::Dns {
+                address,
+                linked_domains,
+                resolver,
+                ..
+            } if crate::dns::is_subdomain(domain, address)
+                || linked_domains.contains_key(domain) =>
+            {
+                *resolver
+            }
+            ResourceOnGateway::Dns { .. }
+            | ResourceOnGateway::Cidr { .. }
+            | ResourceOnGateway::Internet { .. } => None,
+        })
+    }
+
     pub(crate) fn is_emptied(&self) -> bool {
         self.resources.is_empty()
     }
@@ -191,6 +301,55 @@ impl ClientOnGateway {
 
     pub(crate) fn handle_timeout(&mut self, now: Instant) {
         self.nat_table.handle_timeout(now);
+
+        for resource in self.resources.values_mut() {
+            let ResourceOnGateway::Dns { linked_domains, .. } = resource else {
+                continue;
+            };
+
+            linked_domains.retain(|_, expires_at| *expires_at > now);
+        }
+
+        if let Some(tracker) = self.flow_tracker.as_mut() {
+            tracker.handle_timeout(now);
+        }
+    }
+
+    pub(crate) fn enable_flow_logs(&mut self) {
+        if self.flow_tracker.is_some() {
+            return;
+        }
+
+        self.flow_tracker = Some(FlowTracker::new(self.id));
+    }
+
+    /// Allows the NAT to send from `ip` once all ports of the client's tunnel IP are in use.
+    pub(crate) fn add_egress_ip(&mut self, ip: IpAddr) {
+        self.nat_table.add_egress_ip(ip);
+    }
+
+    /// Stops using egress IPs whose NAT sessions have all expired, returning them.
+    pub(crate) fn remove_unused_egress_ips(&mut self) -> Vec<IpAddr> {
+        self.nat_table.remove_unused_egress_ips()
+    }
+
+    pub(crate) fn num_nat_sessions(&self) -> usize {
+        self.nat_table.num_sessions()
+    }
+
+    pub(crate) fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        update_rate_limit(&mut self.rate_limit, limit);
+    }
+
+    /// Completes all active flows, e.g. because the client disconnected.
+    pub(crate) fn close_all_flows(&mut self) {
+        if let Some(tracker) = self.flow_tracker.as_mut() {
+            tracker.close_all();
+        }
+    }
+
+    pub(crate) fn poll_flow_record(&mut self) -> Option<FlowRecord> {
+        self.flow_tracker.as_mut()?.poll_completed()
     }
 
     pub(crate) fn remove_resource(&mut self, resource: &ResourceId) {
@@ -239,7 +398,7 @@ impl ClientOnGateway {
         let old_expiry = match resource {
             ResourceOnGateway::Cidr { expires_at, .. } => expires_at.replace(new_expiry),
             ResourceOnGateway::Dns { expires_at, .. } => expires_at.replace(new_expiry),
-            ResourceOnGateway::Internet { expires_at } => expires_at.replace(new_expiry),
+            ResourceOnGateway::Internet { expires_at, .. } => expires_at.replace(new_expiry),
         };
 
         tracing::info!(old = ?old_expiry.map(|e| e.to_rfc3339()), new = %new_expiry_rfc3339, "Updated resource expiry");
@@ -264,8 +423,11 @@ impl ClientOnGateway {
         self.filters = IpNetworkTable::new();
         self.recalculate_cidr_filters();
         self.recalculate_dns_filters();
+        self.recalculate_resources_by_network();
 
         self.internet_resource_enabled = self.resources.values().any(|r| r.is_internet_resource());
+        self.resource_rate_limits_enabled =
+            self.resources.values().any(|r| r.rate_limit().is_some());
     }
 
     fn recalculate_cidr_filters(&mut self) {
@@ -283,6 +445,28 @@ impl ClientOnGateway {
         }
     }
 
+    fn recalculate_resources_by_network(&mut self) {
+        self.resources_by_network = IpNetworkTable::new();
+
+        for (rid, resource) in &self.resources {
+            match resource {
+                ResourceOnGateway::Cidr { network, .. } => {
+                    self.resources_by_network.insert(*network, *rid);
+                }
+                ResourceOnGateway::Internet { .. } => {
+                    // Longest-prefix matching lets more specific CIDR resources take precedence.
+                    for network in [
+                        IpNetwork::from(Ipv4Network::DEFAULT_ROUTE),
+                        IpNetwork::from(Ipv6Network::DEFAULT_ROUTE),
+                    ] {
+                        self.resources_by_network.insert(network, *rid);
+                    }
+                }
+                ResourceOnGateway::Dns { .. } => {}
+            }
+        }
+    }
+
     fn recalculate_dns_filters(&mut self) {
         for (addr, TranslationState { resource_id, .. }) in &self.permanent_translations {
             let Some(resource) = self.resources.get(resource_id) else {
@@ -316,12 +500,99 @@ impl ClientOnGateway {
             ));
         }
 
+        if let Err(e) = self.consume_rate_limits(packet.destination(), packet.packet().len(), now) {
+            tracing::trace!(?packet, "{e}");
+
+            self.num_dropped_packets.add(
+                1,
+                &[
+                    otel::attr::network_type_for_packet(&packet),
+                    otel::attr::network_io_direction_transmit(),
+                    otel::attr::error_type(e.error_type()),
+                ],
+            );
+
+            return Ok(TranslateOutboundResult::RateLimited);
+        }
+
+        let flow = self
+            .flow_tracker
+            .as_ref()
+            .and_then(|_| FlowKey::for_outbound(&packet))
+            .and_then(|key| Some((key, self.resource_for_destination(key.resource_ip())?)));
+
         // Failing to transform is an error we want to know about further up.
         let result = self.transform_network_to_tun(packet, now)?;
 
+        if let (Some((key, rid)), Some(tracker), TranslateOutboundResult::Send(packet)) =
+            (flow, self.flow_tracker.as_mut(), &result)
+        {
+            tracker.record_outbound(rid, key, packet, now);
+        }
+
         Ok(result)
     }
 
+    /// Consumes `bytes` from the client's rate limit and the one of the resource `ip` belongs to.
+    ///
+    /// Nothing is consumed unless all applicable rate limits have enough capacity.
+    fn consume_rate_limits(
+        &mut self,
+        ip: IpAddr,
+        bytes: usize,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let resource = self
+            .resource_rate_limits_enabled
+            .then(|| self.resource_for_destination(ip))
+            .flatten();
+
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.refill(now);
+
+            if !bucket.has_capacity(bytes) {
+                return Err(RateLimited::Client);
+            }
+        }
+
+        if let Some((rid, bucket)) = resource.and_then(|rid| {
+            let bucket = self.resources.get_mut(&rid)?.rate_limit_mut()?;
+
+            Some((rid, bucket))
+        }) {
+            bucket.refill(now);
+
+            if !bucket.has_capacity(bytes) {
+                return Err(RateLimited::Resource(rid));
+            }
+
+            bucket.consume(bytes);
+        }
+
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.consume(bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Which resource traffic to the given (inside) destination belongs to.
+    ///
+    /// Traffic to the gateway itself doesn't belong to any resource.
+    pub(crate) fn resource_for_destination(&self, dst: IpAddr) -> Option<ResourceId> {
+        if self.gateway_tun.is_ip(dst) {
+            return None;
+        }
+
+        if let Some(state) = self.permanent_translations.get(&dst) {
+            return Some(state.resource_id);
+        }
+
+        self.resources_by_network
+            .longest_match(dst)
+            .map(|(_, rid)| *rid)
+    }
+
     pub fn translate_inbound(
         &mut self,
         packet: IpPacket,
@@ -345,7 +616,7 @@ impl ClientOnGateway {
             return Ok(Some(packet));
         }
 
-        if let Err(e) = self.ensure_allowed_resource(packet.source(), packet.source_protocol()) {
+        if let Err(e) = self.ensure_allowed_inbound(&packet) {
             tracing::debug!(
                 "Inbound packet is not allowed, perhaps from an old client session? error = {e:#}"
             );
@@ -362,6 +633,25 @@ impl ClientOnGateway {
             return Ok(None);
         }
 
+        if let Err(e) = self.consume_rate_limits(packet.source(), packet.packet().len(), now) {
+            tracing::trace!(?packet, "{e}");
+
+            self.num_dropped_packets.add(
+                1,
+                &[
+                    otel::attr::network_type_for_packet(&packet),
+                    otel::attr::network_io_direction_receive(),
+                    otel::attr::error_type(e.error_type()),
+                ],
+            );
+
+            return Ok(None);
+        }
+
+        if let Some(tracker) = self.flow_tracker.as_mut() {
+            tracker.record_inbound(&packet, now);
+        }
+
         Ok(Some(packet))
     }
 
@@ -410,13 +700,18 @@ impl ClientOnGateway {
             ));
         }
 
-        let (source_protocol, real_ip) =
-            self.nat_table
-                .translate_outgoing(&packet, state.resolved_ip, now)?;
+        let outside = self
+            .nat_table
+            .translate_outgoing(&packet, state.resolved_ip, now)?;
 
         packet
-            .translate_destination(source_protocol, real_ip)
+            .translate_destination(outside.proto, outside.dst)
             .context("Failed to translate packet to new destination")?;
+        if let Some(src) = outside.src {
+            packet
+                .set_src(src)
+                .context("Failed to translate packet to egress IP")?;
+        }
         packet.update_checksum();
 
         Ok(TranslateOutboundResult::Send(packet))
@@ -465,6 +760,16 @@ impl ClientOnGateway {
         packet
             .translate_source(proto, ip)
             .context("Failed to translate packet to new source")?;
+        if self.nat_table.is_egress_ip(packet.destination()) {
+            let client_ip = match packet.destination() {
+                IpAddr::V4(_) => IpAddr::V4(self.client_tun.v4),
+                IpAddr::V6(_) => IpAddr::V6(self.client_tun.v6),
+            };
+
+            packet
+                .set_dst(client_ip)
+                .context("Failed to translate packet from egress IP")?;
+        }
         packet.update_checksum();
 
         Ok(Some(packet))
@@ -482,11 +787,29 @@ impl ClientOnGateway {
             return Ok(());
         }
 
-        self.ensure_allowed_resource(packet.destination(), packet.destination_protocol())?;
+        self.ensure_allowed_outbound(packet)?;
 
         Ok(())
     }
 
+    /// Checks whether a packet from the client to a resource is allowed by the filters.
+    fn ensure_allowed_outbound(&self, packet: &IpPacket) -> anyhow::Result<()> {
+        self.ensure_allowed_resource(
+            packet.destination(),
+            packet.destination_protocol(),
+            packet.source_protocol(),
+        )
+    }
+
+    /// Checks whether a packet from a resource to the client is allowed by the filters.
+    fn ensure_allowed_inbound(&self, packet: &IpPacket) -> anyhow::Result<()> {
+        self.ensure_allowed_resource(
+            packet.source(),
+            packet.source_protocol(),
+            packet.destination_protocol(),
+        )
+    }
+
     fn ensure_client_ip(&self, ip: IpAddr) -> anyhow::Result<()> {
         if !self.allowed_ips().contains(&ip) {
             return Err(anyhow::Error::new(NotClientIp(ip)));
@@ -499,6 +822,7 @@ impl ClientOnGateway {
         &self,
         ip: IpAddr,
         protocol: Result<Protocol, UnsupportedProtocol>,
+        client_protocol: Result<Protocol, UnsupportedProtocol>,
     ) -> anyhow::Result<()> {
         // Note a Gateway with Internet resource should never get packets for other resources
         if self.internet_resource_enabled && !is_dns_addr(ip) {
@@ -511,7 +835,20 @@ impl ClientOnGateway {
             .context("No filter")
             .context(NotAllowedResource(ip))?;
 
-        filter.apply(protocol).context(NotAllowedResource(ip))?;
+        // Filters of DNS resources are installed for the proxy IP but their destinations refer to the resolved IP.
+        let dst = self
+            .permanent_translations
+            .get(&ip)
+            .map(|state| state.resolved_ip)
+            .unwrap_or(ip);
+        let client_port = match client_protocol {
+            Ok(Protocol::Tcp(port) | Protocol::Udp(port)) => Some(port),
+            Ok(Protocol::Icmp(_)) | Err(_) => None,
+        };
+
+        filter
+            .apply(dst, protocol, client_port)
+            .context(NotAllowedResource(ip))?;
 
         Ok(())
     }
@@ -526,6 +863,8 @@ pub enum TranslateOutboundResult {
     Send(IpPacket),
     DestinationUnreachable(IpPacket),
     Filtered(IpPacket),
+    /// The packet exceeded a rate limit and should be dropped.
+    RateLimited,
 }
 
 impl GatewayOnClient {
@@ -556,21 +895,44 @@ pub(crate) struct NotClientIp(IpAddr);
 #[error("Traffic to/from this resource IP is not allowed: {0}")]
 pub(crate) struct NotAllowedResource(IpAddr);
 
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RateLimited {
+    #[error("Client exceeded its rate limit")]
+    Client,
+    #[error("Client exceeded the rate limit of resource {0}")]
+    Resource(ResourceId),
+}
+
+impl RateLimited {
+    fn error_type(&self) -> &'static str {
+        match self {
+            RateLimited::Client => "ClientRateLimited",
+            RateLimited::Resource(_) => "ResourceRateLimited",
+        }
+    }
+}
+
 #[derive(Debug)]
 enum ResourceOnGateway {
     Cidr {
         network: IpNetwork,
         filters: Filters,
         expires_at: Option<DateTime<Utc>>,
+        rate_limit: Option<TokenBucket>,
     },
     Dns {
         address: String,
         domains: HashMap<DomainName, BTreeSet<IpAddr>>,
+        /// Domains outside of `address` that the site's DNS server pointed us to via SRV or CNAME records, together with when the link expires.
+        linked_domains: HashMap<DomainName, Instant>,
+        resolver: Option<SocketAddr>,
         filters: Filters,
         expires_at: Option<DateTime<Utc>>,
+        rate_limit: Option<TokenBucket>,
     },
     Internet {
         expires_at: Option<DateTime<Utc>>,
+        rate_limit: Option<TokenBucket>,
     },
 }
 
@@ -579,29 +941,57 @@ impl ResourceOnGateway {
         match resource {
             ResourceDescription::Dns(r) => ResourceOnGateway::Dns {
                 domains: HashMap::default(),
+                linked_domains: HashMap::default(),
+                resolver: r.resolver,
                 filters: r.filters,
                 address: r.address,
                 expires_at,
+                rate_limit: r.rate_limit.map(TokenBucket::new),
             },
             ResourceDescription::Cidr(r) => ResourceOnGateway::Cidr {
                 network: r.address,
                 filters: r.filters,
                 expires_at,
+                rate_limit: r.rate_limit.map(TokenBucket::new),
+            },
+            ResourceDescription::Internet(r) => ResourceOnGateway::Internet {
+                expires_at,
+                rate_limit: r.rate_limit.map(TokenBucket::new),
             },
-            ResourceDescription::Internet(_) => ResourceOnGateway::Internet { expires_at },
         }
     }
 
     fn update(&mut self, resource: &ResourceDescription) {
         match (self, resource) {
-            (ResourceOnGateway::Cidr { filters, .. }, ResourceDescription::Cidr(new)) => {
+            (
+                ResourceOnGateway::Cidr {
+                    filters,
+                    rate_limit,
+                    ..
+                },
+                ResourceDescription::Cidr(new),
+            ) => {
                 *filters = new.filters.clone();
+                update_rate_limit(rate_limit, new.rate_limit);
             }
-            (ResourceOnGateway::Dns { filters, .. }, ResourceDescription::Dns(new)) => {
+            (
+                ResourceOnGateway::Dns {
+                    filters,
+                    rate_limit,
+                    resolver,
+                    ..
+                },
+                ResourceDescription::Dns(new),
+            ) => {
                 *filters = new.filters.clone();
+                *resolver = new.resolver;
+                update_rate_limit(rate_limit, new.rate_limit);
             }
-            (ResourceOnGateway::Internet { .. }, ResourceDescription::Internet(_)) => {
-                // No-op.
+            (
+                ResourceOnGateway::Internet { rate_limit, .. },
+                ResourceDescription::Internet(new),
+            ) => {
+                update_rate_limit(rate_limit, new.rate_limit);
             }
             (current, new) => {
                 tracing::error!(?current, ?new, "Resources cannot change type");
@@ -648,7 +1038,23 @@ impl ResourceOnGateway {
         match self {
             ResourceOnGateway::Cidr { expires_at, .. } => expires_at.as_ref(),
             ResourceOnGateway::Dns { expires_at, .. } => expires_at.as_ref(),
-            ResourceOnGateway::Internet { expires_at } => expires_at.as_ref(),
+            ResourceOnGateway::Internet { expires_at, .. } => expires_at.as_ref(),
+        }
+    }
+
+    fn rate_limit(&self) -> Option<&TokenBucket> {
+        match self {
+            ResourceOnGateway::Cidr { rate_limit, .. } => rate_limit.as_ref(),
+            ResourceOnGateway::Dns { rate_limit, .. } => rate_limit.as_ref(),
+            ResourceOnGateway::Internet { rate_limit, .. } => rate_limit.as_ref(),
+        }
+    }
+
+    fn rate_limit_mut(&mut self) -> Option<&mut TokenBucket> {
+        match self {
+            ResourceOnGateway::Cidr { rate_limit, .. } => rate_limit.as_mut(),
+            ResourceOnGateway::Dns { rate_limit, .. } => rate_limit.as_mut(),
+            ResourceOnGateway::Internet { rate_limit, .. } => rate_limit.as_mut(),
         }
     }
 
@@ -691,6 +1097,14 @@ fn ipv6_addresses(ip: &BTreeSet<IpAddr>) -> BTreeSet<IpAddr> {
     ip.iter().filter(|ip| ip.is_ipv6()).copied().collect()
 }
 
+fn update_rate_limit(bucket: &mut Option<TokenBucket>, limit: Option<RateLimit>) {
+    match (bucket.as_mut(), limit) {
+        (Some(bucket), Some(limit)) => bucket.set_limit(limit),
+        (None, Some(limit)) => *bucket = Some(TokenBucket::new(limit)),
+        (_, None) => *bucket = None,
+    }
+}
+
 fn mapped_ipv4(ips: &BTreeSet<IpAddr>) -> BTreeSet<IpAddr> {
     if !ipv4_addresses(ips).is_empty() {
         ipv4_addresses(ips)
@@ -732,7 +1146,10 @@ mod tests {
 
     use crate::{
         IpConfig,
-        messages::gateway::{Filter, PortRange, ResourceDescription, ResourceDescriptionCidr},
+        messages::gateway::{
+            Filter, FilterAction, PortFilter, PortRange, RateLimit, ResourceDescription,
+            ResourceDescriptionCidr,
+        },
         peer::{TranslateOutboundResult, nat_table},
     };
     use chrono::Utc;
@@ -753,10 +1170,14 @@ mod tests {
                 id: resource_id(),
                 address: cidr_v4_resource().into(),
                 name: "cidr1".to_owned(),
-                filters: vec![Filter::Tcp(PortRange {
-                    port_range_start: 20,
-                    port_range_end: 100,
-                })],
+                filters: vec![Filter::Tcp(
+                    PortRange {
+                        port_range_start: 20,
+                        port_range_end: 100,
+                    }
+                    .into(),
+                )],
+                rate_limit: None,
             }),
             Some(then),
         );
@@ -765,10 +1186,14 @@ mod tests {
                 id: resource2_id(),
                 address: cidr_v4_resource().into(),
                 name: "cidr2".to_owned(),
-                filters: vec![Filter::Udp(PortRange {
-                    port_range_start: 20,
-                    port_range_end: 100,
-                })],
+                filters: vec![Filter::Udp(
+                    PortRange {
+                        port_range_start: 20,
+                        port_range_end: 100,
+                    }
+                    .into(),
+                )],
+                rate_limit: None,
             }),
             Some(after_then),
         );
@@ -794,53 +1219,162 @@ mod tests {
 
         peer.expire_resources(now);
 
-        assert!(
-            peer.ensure_allowed_resource(
-                tcp_packet.destination(),
-                tcp_packet.destination_protocol()
-            )
-            .is_ok()
-        );
-        assert!(
-            peer.ensure_allowed_resource(
-                udp_packet.destination(),
-                udp_packet.destination_protocol()
-            )
-            .is_ok()
-        );
+        assert!(peer.ensure_allowed_outbound(&tcp_packet).is_ok());
+        assert!(peer.ensure_allowed_outbound(&udp_packet).is_ok());
 
         peer.expire_resources(then);
 
-        assert!(
-            peer.ensure_allowed_resource(
-                tcp_packet.destination(),
-                tcp_packet.destination_protocol()
-            )
-            .is_err()
-        );
-        assert!(
-            peer.ensure_allowed_resource(
-                udp_packet.destination(),
-                udp_packet.destination_protocol()
-            )
-            .is_ok()
-        );
+        assert!(peer.ensure_allowed_outbound(&tcp_packet).is_err());
+        assert!(peer.ensure_allowed_outbound(&udp_packet).is_ok());
 
         peer.expire_resources(after_then);
 
-        assert!(
-            peer.ensure_allowed_resource(
-                tcp_packet.destination(),
-                tcp_packet.destination_protocol()
-            )
-            .is_err()
+        assert!(peer.ensure_allowed_outbound(&tcp_packet).is_err());
+        assert!(peer.ensure_allowed_outbound(&udp_packet).is_err());
+    }
+
+    #[test]
+    fn deny_filter_carves_out_ports_from_allowed_range() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![
+                    Filter::Tcp(PortFilter {
+                        action: FilterAction::Deny,
+                        ports: PortRange {
+                            port_range_start: 22,
+                            port_range_end: 22,
+                        },
+                        source_ports: None,
+                        destinations: vec![],
+                    }),
+                    Filter::Tcp(
+                        PortRange {
+                            port_range_start: 0,
+                            port_range_end: u16::MAX,
+                        }
+                        .into(),
+                    ),
+                ],
+                rate_limit: None,
+            }),
+            None,
         );
-        assert!(
-            peer.ensure_allowed_resource(
-                udp_packet.destination(),
-                udp_packet.destination_protocol()
+
+        let https = ip_packet::make::tcp_packet(
+            client_tun_ipv4(),
+            cidr_v4_resource().hosts().next().unwrap(),
+            5401,
+            443,
+            TcpFlags::default(),
+            vec![0; 100],
+        )
+        .unwrap();
+        let ssh = ip_packet::make::tcp_packet(
+            client_tun_ipv4(),
+            cidr_v4_resource().hosts().next().unwrap(),
+            5401,
+            22,
+            TcpFlags::default(),
+            vec![0; 100],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            peer.translate_outbound(https, Instant::now()).unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert!(matches!(
+            peer.translate_outbound(ssh, Instant::now()).unwrap(),
+            TranslateOutboundResult::Filtered(_)
+        ));
+    }
+
+    #[test]
+    fn resource_rate_limit_drops_excess_packets() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![],
+                rate_limit: Some(RateLimit::new(2000, Some(1500)).unwrap()),
+            }),
+            None,
+        );
+        let now = Instant::now();
+
+        let packet = || {
+            ip_packet::make::udp_packet(
+                client_tun_ipv4(),
+                cidr_v4_resource().hosts().next().unwrap(),
+                5401,
+                53,
+                vec![0; 1000],
             )
-            .is_err()
+            .unwrap()
+        };
+
+        assert!(matches!(
+            peer.translate_outbound(packet(), now).unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert_eq!(
+            peer.translate_outbound(packet(), now).unwrap(),
+            TranslateOutboundResult::RateLimited
+        );
+        assert!(matches!(
+            peer.translate_outbound(packet(), now + Duration::from_secs(1))
+                .unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+    }
+
+    #[test]
+    fn client_rate_limit_applies_across_resources() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.set_rate_limit(Some(RateLimit::new(2000, Some(1500)).unwrap()));
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![],
+                rate_limit: None,
+            }),
+            None,
+        );
+        peer.add_resource(internet_resource(), None);
+        let now = Instant::now();
+
+        let to_cidr = ip_packet::make::udp_packet(
+            client_tun_ipv4(),
+            cidr_v4_resource().hosts().next().unwrap(),
+            5401,
+            53,
+            vec![0; 1000],
+        )
+        .unwrap();
+        let to_internet = ip_packet::make::udp_packet(
+            client_tun_ipv4(),
+            Ipv4Addr::new(1, 1, 1, 1),
+            5401,
+            53,
+            vec![0; 1000],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            peer.translate_outbound(to_cidr, now).unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert_eq!(
+            peer.translate_outbound(to_internet, now).unwrap(),
+            TranslateOutboundResult::RateLimited
         );
     }
 
@@ -945,6 +1479,103 @@ mod tests {
         assert!(peer.translate_outbound(pkt, Instant::now()).is_ok());
     }
 
+    #[test]
+    fn can_only_setup_nat_for_linked_domains() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(foo_dns_resource(), None);
+
+        let target = "dc1.ad.internal".parse::<DomainName>().unwrap();
+
+        assert!(
+            peer.setup_nat(
+                target.clone(),
+                resource_id(),
+                BTreeSet::from([foo_real_ip1().into()]),
+                BTreeSet::from([foo_proxy_ip1().into()]),
+            )
+            .is_err()
+        );
+
+        let query = dns_types::Query::new(foo_name().parse().unwrap(), dns_types::RecordType::SRV);
+        let response =
+            dns_types::ResponseBuilder::for_query(&query, dns_types::ResponseCode::NOERROR)
+                .with_records([(
+                    query.domain(),
+                    300,
+                    dns_types::records::srv(0, 0, 389, target.clone()),
+                )])
+                .build();
+        peer.link_domains(&response, Instant::now());
+
+        peer.setup_nat(
+            target,
+            resource_id(),
+            BTreeSet::from([foo_real_ip1().into()]),
+            BTreeSet::from([foo_proxy_ip1().into()]),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn linked_domains_expire_with_their_records() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(foo_dns_resource(), None);
+        let now = Instant::now();
+
+        let target = "dc1.ad.internal".parse::<DomainName>().unwrap();
+        let query = dns_types::Query::new(foo_name().parse().unwrap(), dns_types::RecordType::SRV);
+        let response =
+            dns_types::ResponseBuilder::for_query(&query, dns_types::ResponseCode::NOERROR)
+                .with_records([(
+                    query.domain(),
+                    300,
+                    dns_types::records::srv(0, 0, 389, target.clone()),
+                )])
+                .build();
+        peer.link_domains(&response, now);
+
+        peer.handle_timeout(now + Duration::from_secs(300));
+
+        assert!(
+            peer.setup_nat(
+                target,
+                resource_id(),
+                BTreeSet::from([foo_real_ip1().into()]),
+                BTreeSet::from([foo_proxy_ip1().into()]),
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn queries_for_dns_resource_use_its_resolver() {
+        let resolver = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 53), 53));
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(foo_dns_resource_with_resolver(resolver), None);
+        let now = Instant::now();
+
+        let srv_domain = format!("_ldap._tcp.{}", foo_name())
+            .parse::<DomainName>()
+            .unwrap();
+        let target = "dc1.ad.internal".parse::<DomainName>().unwrap();
+
+        assert_eq!(peer.dns_resolver_for_domain(&srv_domain), Some(resolver));
+        assert_eq!(peer.dns_resolver_for_domain(&target), None);
+
+        let query = dns_types::Query::new(srv_domain, dns_types::RecordType::SRV);
+        let response =
+            dns_types::ResponseBuilder::for_query(&query, dns_types::ResponseCode::NOERROR)
+                .with_records([(
+                    query.domain(),
+                    300,
+                    dns_types::records::srv(0, 0, 389, target.clone()),
+                )])
+                .build();
+        peer.link_domains(&response, now);
+
+        assert_eq!(peer.dns_resolver_for_domain(&target), Some(resolver));
+    }
+
     #[test]
     fn internet_resource_doesnt_allow_all_traffic_for_dns_resources() {
         let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
@@ -1115,30 +1746,74 @@ mod tests {
         assert!(response.is_some());
     }
 
+    #[test]
+    fn attributes_traffic_to_most_specific_resource() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(internet_resource(), None);
+        peer.add_resource(bar_cidr_resource(), None);
+
+        assert_eq!(
+            peer.resource_for_destination(bar_contained_ip().into()),
+            Some(resource2_id())
+        );
+        assert_eq!(
+            peer.resource_for_destination("1.1.1.1".parse().unwrap()),
+            Some(internet_resource().id())
+        );
+
+        peer.remove_resource(&internet_resource().id());
+
+        assert_eq!(
+            peer.resource_for_destination("1.1.1.1".parse().unwrap()),
+            None
+        );
+    }
+
     fn foo_dns_resource() -> crate::messages::gateway::ResourceDescription {
         crate::messages::gateway::ResourceDescription::Dns(
             crate::messages::gateway::ResourceDescriptionDns {
                 id: resource_id(),
                 address: foo_name(),
                 name: "foo".to_string(),
-                filters: vec![Filter::Udp(PortRange {
-                    port_range_end: foo_allowed_port(),
-                    port_range_start: foo_allowed_port(),
-                })],
+                filters: vec![Filter::Udp(
+                    PortRange {
+                        port_range_end: foo_allowed_port(),
+                        port_range_start: foo_allowed_port(),
+                    }
+                    .into(),
+                )],
+                rate_limit: None,
+                resolver: None,
             },
         )
     }
 
+    fn foo_dns_resource_with_resolver(
+        resolver: SocketAddr,
+    ) -> crate::messages::gateway::ResourceDescription {
+        let crate::messages::gateway::ResourceDescription::Dns(mut resource) = foo_dns_resource()
+        else {
+            unreachable!()
+        };
+        resource.resolver = Some(resolver);
+
+        crate::messages::gateway::ResourceDescription::Dns(resource)
+    }
+
     fn bar_cidr_resource() -> crate::messages::gateway::ResourceDescription {
         crate::messages::gateway::ResourceDescription::Cidr(
             crate::messages::gateway::ResourceDescriptionCidr {
                 id: resource2_id(),
                 address: bar_address(),
                 name: "foo".to_string(),
-                filters: vec![Filter::Udp(PortRange {
-                    port_range_end: bar_allowed_port(),
-                    port_range_start: bar_allowed_port(),
-                })],
+                filters: vec![Filter::Udp(
+                    PortRange {
+                        port_range_end: bar_allowed_port(),
+                        port_range_start: bar_allowed_port(),
+                    }
+                    .into(),
+                )],
+                rate_limit: None,
             },
         )
     }
@@ -1147,6 +1822,7 @@ mod tests {
         crate::messages::gateway::ResourceDescription::Internet(
             crate::messages::gateway::ResourceDescriptionInternet {
                 id: "ed29c148-2acf-4ceb-8db5-d796c267163a".parse().unwrap(),
+                rate_limit: None,
             },
         )
     }
@@ -1239,7 +1915,7 @@ mod proptests {
     use super::tests::*;
     use super::*;
     use crate::messages::gateway::{
-        Filter, PortRange, ResourceDescription, ResourceDescriptionCidr,
+        Filter, IcmpFilter, PortFilter, PortRange, ResourceDescription, ResourceDescriptionCidr,
     };
     use crate::proptest::*;
     use ip_packet::make::{TcpFlags, icmp_request_packet, tcp_packet, udp_packet};
@@ -1301,10 +1977,7 @@ mod proptests {
                 Protocol::Icmp => icmp_request_packet(src, *dest, 1, 0, &[]),
             }
             .unwrap();
-            assert!(
-                peer.ensure_allowed_resource(packet.destination(), packet.destination_protocol())
-                    .is_ok()
-            );
+            assert!(peer.ensure_allowed_outbound(&packet).is_ok());
         }
     }
 
@@ -1344,6 +2017,7 @@ mod proptests {
                     address: resource_addr,
                     name: String::new(),
                     filters: filters.clone(),
+                    rate_limit: None,
                 }),
                 None,
             );
@@ -1364,10 +2038,7 @@ mod proptests {
             }
             .unwrap();
 
-            assert!(
-                peer.ensure_allowed_resource(packet.destination(), packet.destination_protocol())
-                    .is_ok()
-            );
+            assert!(peer.ensure_allowed_outbound(&packet).is_ok());
         }
     }
 
@@ -1413,14 +2084,12 @@ mod proptests {
                 address: resource_addr,
                 name: String::new(),
                 filters,
+                rate_limit: None,
             }),
             None,
         );
 
-        assert!(
-            peer.ensure_allowed_resource(packet.destination(), packet.destination_protocol())
-                .is_err()
-        );
+        assert!(peer.ensure_allowed_outbound(&packet).is_err());
     }
 
     #[test_strategy::proptest()]
@@ -1485,6 +2154,7 @@ mod proptests {
                 address: supernet(resource_addr).unwrap_or(resource_addr),
                 name: String::new(),
                 filters: filters_allowed,
+                rate_limit: None,
             }),
             None,
         );
@@ -1495,25 +2165,107 @@ mod proptests {
                 address: resource_addr,
                 name: String::new(),
                 filters: filters_removed,
+                rate_limit: None,
             }),
             None,
         );
         peer.remove_resource(&resource_id_removed);
 
-        assert!(
-            peer.ensure_allowed_resource(
-                packet_allowed.destination(),
-                packet_allowed.destination_protocol()
-            )
-            .is_ok()
+        assert!(peer.ensure_allowed_outbound(&packet_allowed).is_ok());
+        assert!(peer.ensure_allowed_outbound(&packet_rejected).is_err());
+    }
+
+    #[test_strategy::proptest()]
+    fn gateway_only_applies_filters_to_their_destinations(
+        #[strategy(client_id())] client_id: ClientId,
+        #[strategy(resource_id())] resource_id: ResourceId,
+        #[strategy(any::<Ipv4Addr>())] client_v4: Ipv4Addr,
+        #[strategy(any::<Ipv6Addr>())] client_v6: Ipv6Addr,
+        #[strategy(cidr_with_two_hosts())] config: (IpNetwork, IpAddr, IpAddr),
+        #[strategy(filters_with_allowed_protocol())]
+        #[filter(!#protocol_config.0.is_empty())]
+        protocol_config: (Filters, Protocol),
+        #[strategy(any::<u16>())] sport: u16,
+        #[strategy(any::<Vec<u8>>())] payload: Vec<u8>,
+    ) {
+        let (resource_addr, dest, other_dest) = config;
+        let src = if dest.is_ipv4() {
+            client_v4.into()
+        } else {
+            client_v6.into()
+        };
+        let (filters, protocol) = protocol_config;
+        let mut peer = ClientOnGateway::new(
+            client_id,
+            IpConfig {
+                v4: client_v4,
+                v6: client_v6,
+            },
+            gateway_tun(),
         );
-        assert!(
-            peer.ensure_allowed_resource(
-                packet_rejected.destination(),
-                packet_rejected.destination_protocol()
-            )
-            .is_err()
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id,
+                address: resource_addr,
+                name: String::new(),
+                filters: with_destination(filters, IpNetwork::from(dest)),
+                rate_limit: None,
+            }),
+            None,
         );
+
+        let packet_allowed = make_packet(src, dest, sport, protocol, payload.clone());
+        let packet_rejected = make_packet(src, other_dest, sport, protocol, payload);
+
+        assert!(peer.ensure_allowed_outbound(&packet_allowed).is_ok());
+        assert!(peer.ensure_allowed_outbound(&packet_rejected).is_err());
+    }
+
+    fn make_packet(
+        src: IpAddr,
+        dest: IpAddr,
+        sport: u16,
+        protocol: Protocol,
+        payload: Vec<u8>,
+    ) -> IpPacket {
+        match protocol {
+            Protocol::Tcp { dport } => {
+                tcp_packet(src, dest, sport, dport, TcpFlags::default(), payload)
+            }
+            Protocol::Udp { dport } => udp_packet(src, dest, sport, dport, payload),
+            Protocol::Icmp => icmp_request_packet(src, dest, 1, 0, &[]),
+        }
+        .unwrap()
+    }
+
+    fn with_destination(filters: Filters, destination: IpNetwork) -> Filters {
+        filters
+            .into_iter()
+            .map(|mut f| {
+                match &mut f {
+                    Filter::Udp(f) | Filter::Tcp(f) => f.destinations = vec![destination],
+                    Filter::Icmp(f) => f.destinations = vec![destination],
+                }
+
+                f
+            })
+            .collect()
+    }
+
+    fn cidr_with_two_hosts() -> impl Strategy<Value = (IpNetwork, IpAddr, IpAddr)> {
+        any_ip_network(8)
+            .prop_filter("network needs at least two hosts", |net| {
+                net.netmask() < max_netmask(net)
+            })
+            .prop_flat_map(|net| (Just(net), host(net), host(net)))
+            .prop_filter("hosts must be different", |(_, a, b)| a != b)
+    }
+
+    fn max_netmask(net: &IpNetwork) -> u8 {
+        match net {
+            IpNetwork::V4(_) => 32,
+            IpNetwork::V6(_) => 128,
+        }
     }
 
     fn cidr_resources(
@@ -1533,6 +2285,7 @@ mod proptests {
                             address,
                             name: String::new(),
                             filters,
+                            rate_limit: None,
                         }),
                         protocol,
                         host,
@@ -1592,7 +2345,9 @@ mod proptests {
                         let f = f.clone();
 
                         move |p| {
-                            (p != ProtocolKind::Icmp || !f.contains(&Filter::Icmp)).then_some(p)
+                            (p != ProtocolKind::Icmp
+                                || !f.iter().any(|f| matches!(f, Filter::Icmp(_))))
+                            .then_some(p)
                         }
                     })
                     .prop_filter("no gaps in port ranges", {
@@ -1627,10 +2382,10 @@ mod proptests {
             .into_iter()
             .filter_map(|f| match (f, protocol) {
                 (Filter::Udp(inner), ProtocolKind::Udp) => {
-                    Some(inner.port_range_start..=inner.port_range_end)
+                    Some(inner.ports.port_range_start..=inner.ports.port_range_end)
                 }
                 (Filter::Tcp(inner), ProtocolKind::Tcp) => {
-                    Some(inner.port_range_start..=inner.port_range_end)
+                    Some(inner.ports.port_range_start..=inner.ports.port_range_end)
                 }
                 (_, _) => None,
             })
@@ -1641,24 +2396,32 @@ mod proptests {
 
     fn protocol_from_filter(f: Filter) -> impl Strategy<Value = Protocol> {
         match f {
-            Filter::Udp(PortRange {
-                port_range_end,
-                port_range_start,
+            Filter::Udp(PortFilter {
+                ports:
+                    PortRange {
+                        port_range_end,
+                        port_range_start,
+                    },
+                ..
             }) => (port_range_start..=port_range_end)
                 .prop_map(|dport| Protocol::Udp { dport })
                 .boxed(),
-            Filter::Tcp(PortRange {
-                port_range_end,
-                port_range_start,
+            Filter::Tcp(PortFilter {
+                ports:
+                    PortRange {
+                        port_range_end,
+                        port_range_start,
+                    },
+                ..
             }) => (port_range_start..=port_range_end)
                 .prop_map(|dport| Protocol::Tcp { dport })
                 .boxed(),
-            Filter::Icmp => Just(Protocol::Icmp).boxed(),
+            Filter::Icmp(_) => Just(Protocol::Icmp).boxed(),
         }
     }
 
     fn filters_in_gaps(filters: Filters) -> impl Strategy<Value = Filters> {
-        let contains_icmp_filter = filters.contains(&Filter::Icmp);
+        let contains_icmp_filter = filters.iter().any(|f| matches!(f, Filter::Icmp(_)));
 
         let ranges_without_tcp_filter = gaps(filters.clone(), ProtocolKind::Tcp);
         let tcp_filters = filter_from_vec(ranges_without_tcp_filter, ProtocolKind::Tcp);
@@ -1669,7 +2432,7 @@ mod proptests {
         let icmp_filter = if contains_icmp_filter {
             Just(vec![])
         } else {
-            Just(vec![Filter::Icmp])
+            Just(vec![Filter::Icmp(IcmpFilter::default())])
         };
 
         (tcp_filters, udp_filters, icmp_filter)
@@ -1699,9 +2462,9 @@ mod proptests {
     fn filters() -> impl Strategy<Value = Filters> {
         collection::vec(
             prop_oneof![
-                Just(Filter::Icmp),
-                port_range().prop_map(Filter::Udp),
-                port_range().prop_map(Filter::Tcp),
+                Just(Filter::Icmp(IcmpFilter::default())),
+                port_range().prop_map(|r| Filter::Udp(r.into())),
+                port_range().prop_map(|r| Filter::Tcp(r.into())),
             ],
             0..=100,
         )
@@ -1735,7 +2498,7 @@ mod proptests {
             match value {
                 Filter::Udp(_) => ProtocolKind::Udp,
                 Filter::Tcp(_) => ProtocolKind::Tcp,
-                Filter::Icmp => ProtocolKind::Icmp,
+                Filter::Icmp(_) => ProtocolKind::Icmp,
             }
         }
     }
@@ -1758,15 +2521,21 @@ mod proptests {
 
         fn into_filter(self, range: RangeInclusive<u16>) -> Filter {
             match self {
-                ProtocolKind::Tcp => Filter::Tcp(PortRange {
-                    port_range_start: *range.start(),
-                    port_range_end: *range.end(),
-                }),
-                ProtocolKind::Udp => Filter::Udp(PortRange {
-                    port_range_start: *range.start(),
-                    port_range_end: *range.end(),
-                }),
-                ProtocolKind::Icmp => Filter::Icmp,
+                ProtocolKind::Tcp => Filter::Tcp(
+                    PortRange {
+                        port_range_start: *range.start(),
+                        port_range_end: *range.end(),
+                    }
+                    .into(),
+                ),
+                ProtocolKind::Udp => Filter::Udp(
+                    PortRange {
+                        port_range_start: *range.start(),
+                        port_range_end: *range.end(),
+                    }
+                    .into(),
+                ),
+                ProtocolKind::Icmp => Filter::Icmp(IcmpFilter::default()),
             }
         }
     }
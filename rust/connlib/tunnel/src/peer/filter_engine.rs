@@ -1,23 +1,70 @@
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
+use ip_network_table::IpNetworkTable;
 use ip_packet::{Protocol, UnsupportedProtocol};
-use rangemap::RangeInclusiveSet;
+use rangemap::RangeInclusiveMap;
+
+use crate::messages::gateway::{Filter, FilterAction, Filters, PortFilter, PortRange};
+use crate::utils::network_contains_network;
 
-use crate::messages::gateway::{Filter, Filters};
+/// What happens to a packet that doesn't match any of the filters of a resource.
+///
+/// Resources without any filters allow all traffic.
+const DEFAULT_ACTION: FilterAction = FilterAction::Deny;
 
+/// Evaluates the filters of all resources that cover a particular network.
+///
+/// The filters of each resource are evaluated in order and the first matching filter decides what happens to a packet.
+/// Packets that don't match any filter of a resource are handled according to [`DEFAULT_ACTION`].
+///
+/// A packet is allowed if any of the resources allows it.
 #[derive(Debug)]
 pub(crate) enum FilterEngine {
     PermitAll,
-    PermitSome(AllowRules),
+    PermitSome(RuleTable),
 }
 
+/// A table of rules, keyed by the destination network they apply to.
+///
+/// Each entry contains the rules of all networks that contain it, separately for each resource.
+/// Thus, a longest-prefix match for a destination IP yields all rules that apply to it.
 #[derive(Debug)]
-pub(crate) struct AllowRules {
-    udp: RangeInclusiveSet<u16>,
-    tcp: RangeInclusiveSet<u16>,
-    icmp: bool,
+pub(crate) struct RuleTable {
+    by_destination: IpNetworkTable<Vec<ResourceRules>>,
+}
+
+/// The filters of a single resource that apply to a destination network, indexed by protocol and port.
+#[derive(Debug, Default)]
+struct ResourceRules {
+    udp: PortRules,
+    tcp: PortRules,
+    /// ICMP filters match all ICMP packets, thus only the first one can ever match.
+    icmp: Option<Rule>,
+}
+
+#[derive(Debug, Default)]
+struct PortRules {
+    /// The first rule matching each destination port, for rules that apply to any source port.
+    any_source: RangeInclusiveMap<u16, Rule>,
+    /// Rules that only apply to specific source ports, in order.
+    by_source: Vec<(RangeInclusive<u16>, RangeInclusive<u16>, Rule)>,
+}
+
+/// A filter and its position within the filters of its resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    position: usize,
+    filter: Filter,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Filtered {
+    #[error("Denied by filter `{0}`")]
+    Denied(Filter),
+    #[error("Destination is not covered by any filter")]
+    Destination,
     #[error("TCP port is not in allowed range")]
     Tcp,
     #[error("UDP port is not in allowed range")]
@@ -29,13 +76,20 @@ pub(crate) enum Filtered {
 }
 
 impl FilterEngine {
+    /// Evaluates the filters for a packet to / from a resource.
+    ///
+    /// - `dst` is the IP of the resource.
+    /// - `protocol` is the protocol of the resource, i.e. contains the port on the resource.
+    /// - `client_port` is the port on the client, if any.
     pub(crate) fn apply(
         &self,
+        dst: IpAddr,
         protocol: Result<Protocol, UnsupportedProtocol>,
+        client_port: Option<u16>,
     ) -> Result<(), Filtered> {
         match self {
             FilterEngine::PermitAll => Ok(()),
-            FilterEngine::PermitSome(filter_engine) => filter_engine.apply(protocol),
+            FilterEngine::PermitSome(rules) => rules.apply(dst, protocol, client_port),
         }
     }
 
@@ -47,110 +101,488 @@ impl FilterEngine {
             return Self::PermitAll;
         }
 
-        let mut allow_rules = AllowRules::new();
-        allow_rules.add_filters(filters.flatten());
-
-        Self::PermitSome(allow_rules)
+        Self::PermitSome(RuleTable::new(filters))
     }
 }
 
-impl AllowRules {
-    fn new() -> AllowRules {
-        AllowRules {
-            udp: RangeInclusiveSet::new(),
-            tcp: RangeInclusiveSet::new(),
-            icmp: false,
+impl RuleTable {
+    fn new<'a>(resources: impl Iterator<Item = &'a Filters> + Clone) -> RuleTable {
+        let mut networks = Vec::<IpNetwork>::new();
+
+        for destination in resources.clone().flatten().flat_map(destinations) {
+            if !networks.contains(&destination) {
+                networks.push(destination);
+            }
         }
+
+        let mut by_destination = IpNetworkTable::new();
+
+        // Fold the rules of all containing networks into each entry so a longest-prefix match is sufficient.
+        for network in networks {
+            let rules = resources
+                .clone()
+                .filter_map(|filters| {
+                    ResourceRules::new(filters.iter().enumerate().filter(|(_, f)| {
+                        destinations(f)
+                            .into_iter()
+                            .any(|d| network_contains_network(d, network))
+                    }))
+                })
+                .collect();
+
+            by_destination.insert(network, rules);
+        }
+
+        RuleTable { by_destination }
     }
 
-    fn apply(&self, protocol: Result<Protocol, UnsupportedProtocol>) -> Result<(), Filtered> {
-        match protocol {
-            Ok(Protocol::Tcp(port)) if self.tcp.contains(&port) => Ok(()),
-            Ok(Protocol::Udp(port)) if self.udp.contains(&port) => Ok(()),
-            Ok(Protocol::Icmp(_)) if self.icmp => Ok(()),
+    fn apply(
+        &self,
+        dst: IpAddr,
+        protocol: Result<Protocol, UnsupportedProtocol>,
+        client_port: Option<u16>,
+    ) -> Result<(), Filtered> {
+        let (_, resources) = self
+            .by_destination
+            .longest_match(dst)
+            .ok_or(Filtered::Destination)?;
 
-            // If ICMP is allowed, we don't care about the specific ICMP type.
-            // i.e. it doesn't have to be an echo request / reply.
-            Err(
-                UnsupportedProtocol::UnsupportedIcmpv4Type(_)
-                | UnsupportedProtocol::UnsupportedIcmpv6Type(_),
-            ) if self.icmp => Ok(()),
+        let mut denied_by = None::<&Filter>;
 
+        for rules in resources {
+            let matching = rules.matching(&protocol, client_port);
+
+            match matching.map_or(DEFAULT_ACTION, |r| r.filter.action()) {
+                FilterAction::Allow => return Ok(()),
+                FilterAction::Deny => {
+                    // Resources are not ordered, pick the smallest filter so we always report the same one.
+                    if let Some(rule) = matching
+                        && denied_by.is_none_or(|d| rule.filter < *d)
+                    {
+                        denied_by = Some(&rule.filter);
+                    }
+                }
+            }
+        }
+
+        if let Some(filter) = denied_by {
+            return Err(Filtered::Denied(filter.clone()));
+        }
+
+        match protocol {
             Ok(Protocol::Tcp(_)) => Err(Filtered::Tcp),
             Ok(Protocol::Udp(_)) => Err(Filtered::Udp),
             Ok(Protocol::Icmp(_)) => Err(Filtered::Icmp),
-
             Err(e) => Err(Filtered::UnsupportedProtocol(e)),
         }
     }
+}
+
+impl ResourceRules {
+    /// Indexes the given filters, returns `None` if there aren't any.
+    fn new<'a>(filters: impl Iterator<Item = (usize, &'a Filter)>) -> Option<ResourceRules> {
+        let mut rules = ResourceRules::default();
+        let mut is_empty = true;
+
+        for (position, filter) in filters {
+            let rule = Rule {
+                position,
+                filter: filter.clone(),
+            };
 
-    fn add_filters<'a>(&mut self, filters: impl IntoIterator<Item = &'a Filter>) {
-        for filter in filters {
             match filter {
-                Filter::Udp(range) => {
-                    self.udp
-                        .insert(range.port_range_start..=range.port_range_end);
-                }
-                Filter::Tcp(range) => {
-                    self.tcp
-                        .insert(range.port_range_start..=range.port_range_end);
-                }
-                Filter::Icmp => {
-                    self.icmp = true;
+                Filter::Udp(f) => rules.udp.add(f, rule),
+                Filter::Tcp(f) => rules.tcp.add(f, rule),
+                Filter::Icmp(_) => {
+                    rules.icmp.get_or_insert(rule);
                 }
             }
+
+            is_empty = false;
+        }
+
+        (!is_empty).then_some(rules)
+    }
+
+    /// The first rule matching the packet.
+    fn matching(
+        &self,
+        protocol: &Result<Protocol, UnsupportedProtocol>,
+        client_port: Option<u16>,
+    ) -> Option<&Rule> {
+        match protocol {
+            Ok(Protocol::Tcp(port)) => self.tcp.matching(*port, client_port),
+            Ok(Protocol::Udp(port)) => self.udp.matching(*port, client_port),
+
+            // ICMP filters match all ICMP types.
+            // i.e. it doesn't have to be an echo request / reply.
+            Ok(Protocol::Icmp(_))
+            | Err(
+                UnsupportedProtocol::UnsupportedIcmpv4Type(_)
+                | UnsupportedProtocol::UnsupportedIcmpv6Type(_),
+            ) => self.icmp.as_ref(),
+
+            Err(_) => None,
         }
     }
 }
 
+impl PortRules {
+    fn add(&mut self, filter: &PortFilter, rule: Rule) {
+        let destination = range(&filter.ports);
+
+        let Some(source) = filter.source_ports.as_ref() else {
+            // Earlier rules take precedence, only fill the ports that aren't covered yet.
+            let gaps = self.any_source.gaps(&destination).collect::<Vec<_>>();
+
+            for gap in gaps {
+                self.any_source.insert(gap, rule.clone());
+            }
+
+            return;
+        };
+
+        self.by_source.push((range(source), destination, rule));
+    }
+
+    fn matching(&self, port: u16, client_port: Option<u16>) -> Option<&Rule> {
+        let any_source = self.any_source.get(&port);
+        let by_source = client_port.and_then(|client_port| {
+            self.by_source
+                .iter()
+                .find(|(source, destination, _)| {
+                    source.contains(&client_port) && destination.contains(&port)
+                })
+                .map(|(_, _, rule)| rule)
+        });
+
+        any_source
+            .into_iter()
+            .chain(by_source)
+            .min_by_key(|rule| rule.position)
+    }
+}
+
+fn range(ports: &PortRange) -> RangeInclusive<u16> {
+    ports.port_range_start..=ports.port_range_end
+}
+
+/// The networks a filter applies to; filters without explicit destinations apply to everything.
+fn destinations(filter: &Filter) -> Vec<IpNetwork> {
+    let destinations = filter.destinations();
+
+    if destinations.is_empty() {
+        return vec![
+            Ipv4Network::DEFAULT_ROUTE.into(),
+            Ipv6Network::DEFAULT_ROUTE.into(),
+        ];
+    }
+
+    destinations.to_vec()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::iter;
+
     use ip_packet::{Icmpv4Type, Icmpv6Type, icmpv4, icmpv6};
 
     use super::*;
+    use crate::messages::gateway::{IcmpFilter, PortFilter};
+    use ip_network::IpNetwork;
 
     #[test]
     fn allows_icmpv4_destination_unreachable() {
-        let filter = FilterEngine::PermitSome(AllowRules {
-            udp: RangeInclusiveSet::default(),
-            tcp: RangeInclusiveSet::default(),
-            icmp: true,
-        });
+        let filter = FilterEngine::with_filters(iter::once(&vec![icmp(&[])]));
 
-        let result = filter.apply(Err(UnsupportedProtocol::UnsupportedIcmpv4Type(
-            Icmpv4Type::DestinationUnreachable(icmpv4::DestUnreachableHeader::Host),
-        )));
+        let result = filter.apply(
+            any_ip(),
+            Err(UnsupportedProtocol::UnsupportedIcmpv4Type(
+                Icmpv4Type::DestinationUnreachable(icmpv4::DestUnreachableHeader::Host),
+            )),
+            None,
+        );
 
         assert!(result.is_ok())
     }
 
     #[test]
     fn allows_icmpv6_destination_unreachable() {
-        let filter = FilterEngine::PermitSome(AllowRules {
-            udp: RangeInclusiveSet::default(),
-            tcp: RangeInclusiveSet::default(),
-            icmp: true,
-        });
+        let filter = FilterEngine::with_filters(iter::once(&vec![icmp(&[])]));
 
-        let result = filter.apply(Err(UnsupportedProtocol::UnsupportedIcmpv6Type(
-            Icmpv6Type::DestinationUnreachable(icmpv6::DestUnreachableCode::Address),
-        )));
+        let result = filter.apply(
+            any_ip(),
+            Err(UnsupportedProtocol::UnsupportedIcmpv6Type(
+                Icmpv6Type::DestinationUnreachable(icmpv6::DestUnreachableCode::Address),
+            )),
+            None,
+        );
 
         assert!(result.is_ok())
     }
 
     #[test]
     fn icmp_false_blocks_other_icmp_messages() {
-        let filter = FilterEngine::PermitSome(AllowRules {
-            udp: RangeInclusiveSet::default(),
-            tcp: RangeInclusiveSet::default(),
-            icmp: false,
-        });
+        let filter = FilterEngine::with_filters(iter::once(&vec![tcp(80, None, &[])]));
 
-        let result = filter.apply(Err(UnsupportedProtocol::UnsupportedIcmpv4Type(
-            Icmpv4Type::TimestampRequest(icmpv4::TimestampMessage::from_bytes([0u8; 16])),
-        )));
+        let result = filter.apply(
+            any_ip(),
+            Err(UnsupportedProtocol::UnsupportedIcmpv4Type(
+                Icmpv4Type::TimestampRequest(icmpv4::TimestampMessage::from_bytes([0u8; 16])),
+            )),
+            None,
+        );
 
         assert!(result.is_err())
     }
+
+    #[test]
+    fn destination_specific_rules_only_apply_to_their_network() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![
+            tcp(443, None, &["10.0.1.5/32"]),
+            udp(53, None, &["10.0.1.0/24"]),
+        ]));
+
+        let host = "10.0.1.5".parse().unwrap();
+        let other = "10.0.1.6".parse().unwrap();
+        let outside = "10.0.2.1".parse().unwrap();
+
+        assert!(filter.apply(host, Ok(Protocol::Tcp(443)), None).is_ok());
+        assert!(filter.apply(host, Ok(Protocol::Udp(53)), None).is_ok());
+        assert!(matches!(
+            filter.apply(other, Ok(Protocol::Tcp(443)), None),
+            Err(Filtered::Tcp)
+        ));
+        assert!(filter.apply(other, Ok(Protocol::Udp(53)), None).is_ok());
+        assert!(matches!(
+            filter.apply(outside, Ok(Protocol::Udp(53)), None),
+            Err(Filtered::Destination)
+        ));
+    }
+
+    #[test]
+    fn rules_without_destination_apply_to_all_networks() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![
+            tcp(22, None, &[]),
+            tcp(443, None, &["10.0.1.0/24"]),
+        ]));
+
+        let host = "10.0.1.5".parse().unwrap();
+        let other = "10.0.2.5".parse().unwrap();
+
+        assert!(filter.apply(host, Ok(Protocol::Tcp(22)), None).is_ok());
+        assert!(filter.apply(host, Ok(Protocol::Tcp(443)), None).is_ok());
+        assert!(filter.apply(other, Ok(Protocol::Tcp(22)), None).is_ok());
+        assert!(matches!(
+            filter.apply(other, Ok(Protocol::Tcp(443)), None),
+            Err(Filtered::Tcp)
+        ));
+    }
+
+    #[test]
+    fn source_port_constraint_requires_matching_client_port() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![udp(123, Some(123..=123), &[])]));
+
+        assert!(
+            filter
+                .apply(any_ip(), Ok(Protocol::Udp(123)), Some(123))
+                .is_ok()
+        );
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Udp(123)), Some(50000)),
+            Err(Filtered::Udp)
+        ));
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Udp(123)), None),
+            Err(Filtered::Udp)
+        ));
+    }
+
+    #[test]
+    fn first_matching_filter_decides() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![
+            deny(tcp(22, None, &[])),
+            deny(tcp(3389, None, &[])),
+            tcp_all(),
+        ]));
+
+        assert!(filter.apply(any_ip(), Ok(Protocol::Tcp(443)), None).is_ok());
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Tcp(22)), None),
+            Err(Filtered::Denied(f)) if f == deny(tcp(22, None, &[]))
+        ));
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Tcp(3389)), None),
+            Err(Filtered::Denied(f)) if f == deny(tcp(3389, None, &[]))
+        ));
+    }
+
+    #[test]
+    fn deny_after_matching_allow_has_no_effect() {
+        let filter =
+            FilterEngine::with_filters(iter::once(&vec![tcp_all(), deny(tcp(22, None, &[]))]));
+
+        assert!(filter.apply(any_ip(), Ok(Protocol::Tcp(22)), None).is_ok());
+    }
+
+    #[test]
+    fn order_applies_across_nested_destinations() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![
+            deny(tcp(22, None, &["10.0.0.0/8"])),
+            tcp_all_to(&["10.0.1.0/24"]),
+            deny(tcp(443, None, &["10.0.1.5/32"])),
+        ]));
+
+        let host = "10.0.1.5".parse().unwrap();
+        let other = "10.0.1.6".parse().unwrap();
+
+        assert!(matches!(
+            filter.apply(host, Ok(Protocol::Tcp(22)), None),
+            Err(Filtered::Denied(f)) if f == deny(tcp(22, None, &["10.0.0.0/8"]))
+        ));
+        assert!(filter.apply(host, Ok(Protocol::Tcp(443)), None).is_ok());
+        assert!(filter.apply(other, Ok(Protocol::Tcp(443)), None).is_ok());
+    }
+
+    #[test]
+    fn source_port_rule_precedes_later_rule_for_any_source() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![
+            deny(udp(123, Some(1..=1023), &[])),
+            udp(123, None, &[]),
+        ]));
+
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Udp(123)), Some(123)),
+            Err(Filtered::Denied(_))
+        ));
+        assert!(
+            filter
+                .apply(any_ip(), Ok(Protocol::Udp(123)), Some(50000))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn deny_only_filters_deny_everything() {
+        let filter = FilterEngine::with_filters(iter::once(&vec![deny(icmp(&[]))]));
+
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Icmp(1)), None),
+            Err(Filtered::Denied(_))
+        ));
+        assert!(matches!(
+            filter.apply(any_ip(), Ok(Protocol::Udp(53)), None),
+            Err(Filtered::Udp)
+        ));
+    }
+
+    #[test]
+    fn deny_of_one_resource_does_not_restrict_overlapping_resource() {
+        let allow_ssh = vec![tcp(22, None, &[])];
+        let deny_ssh = vec![deny(tcp(22, None, &["10.0.1.0/24"])), tcp_all()];
+
+        let filter = FilterEngine::with_filters([&allow_ssh, &deny_ssh].into_iter());
+
+        assert!(
+            filter
+                .apply("10.0.1.1".parse().unwrap(), Ok(Protocol::Tcp(22)), None)
+                .is_ok()
+        );
+        assert!(
+            filter
+                .apply("10.0.1.1".parse().unwrap(), Ok(Protocol::Tcp(443)), None)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn reports_same_deny_filter_regardless_of_resource_order() {
+        let deny_ssh = vec![deny(tcp(22, None, &[]))];
+        let deny_all = vec![deny(Filter::Tcp(
+            PortRange {
+                port_range_start: 0,
+                port_range_end: u16::MAX,
+            }
+            .into(),
+        ))];
+
+        let forward = FilterEngine::with_filters([&deny_ssh, &deny_all].into_iter());
+        let backward = FilterEngine::with_filters([&deny_all, &deny_ssh].into_iter());
+
+        let Err(Filtered::Denied(forward)) = forward.apply(any_ip(), Ok(Protocol::Tcp(22)), None)
+        else {
+            panic!("Expected packet to be denied")
+        };
+        let Err(Filtered::Denied(backward)) = backward.apply(any_ip(), Ok(Protocol::Tcp(22)), None)
+        else {
+            panic!("Expected packet to be denied")
+        };
+
+        assert_eq!(forward, backward);
+    }
+
+    fn deny(mut filter: Filter) -> Filter {
+        match &mut filter {
+            Filter::Udp(f) | Filter::Tcp(f) => f.action = FilterAction::Deny,
+            Filter::Icmp(f) => f.action = FilterAction::Deny,
+        }
+
+        filter
+    }
+
+    fn tcp_all() -> Filter {
+        tcp_all_to(&[])
+    }
+
+    fn tcp_all_to(destinations: &[&str]) -> Filter {
+        Filter::Tcp(PortFilter {
+            destinations: networks(destinations),
+            ..PortFilter::from(PortRange {
+                port_range_start: 0,
+                port_range_end: u16::MAX,
+            })
+        })
+    }
+
+    fn tcp(port: u16, source: Option<RangeInclusive<u16>>, destinations: &[&str]) -> Filter {
+        Filter::Tcp(port_filter(port, source, destinations))
+    }
+
+    fn udp(port: u16, source: Option<RangeInclusive<u16>>, destinations: &[&str]) -> Filter {
+        Filter::Udp(port_filter(port, source, destinations))
+    }
+
+    fn icmp(destinations: &[&str]) -> Filter {
+        Filter::Icmp(IcmpFilter {
+            action: FilterAction::Allow,
+            destinations: networks(destinations),
+        })
+    }
+
+    fn port_filter(
+        port: u16,
+        source: Option<RangeInclusive<u16>>,
+        destinations: &[&str],
+    ) -> PortFilter {
+        PortFilter {
+            action: FilterAction::Allow,
+            ports: PortRange {
+                port_range_start: port,
+                port_range_end: port,
+            },
+            source_ports: source.map(|r| PortRange {
+                port_range_start: *r.start(),
+                port_range_end: *r.end(),
+            }),
+            destinations: networks(destinations),
+        }
+    }
+
+    fn networks(networks: &[&str]) -> Vec<IpNetwork> {
+        networks.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    fn any_ip() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
 }
@@ -0,0 +1,482 @@
+//! Tracks the flows of a single client through the gateway for the purposes of flow logging.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::time::Instant;
+
+use connlib_model::{ClientId, ResourceId};
+use ip_packet::{IpPacket, Protocol};
+use lru::LruCache;
+
+use super::nat_table::{ICMP_TTL, TCP_TTL, UDP_TTL};
+
+/// How many flows we track per client at most.
+///
+/// Once reached, the least recently active flow is completed to make room for a new one.
+const MAX_FLOWS: NonZeroUsize = NonZeroUsize::new(10_000).expect("10_000 > 0");
+
+/// A completed flow between a client and a resource.
+///
+/// The "inside" tuple is what the client sees, i.e. for DNS resources it contains the proxy IP.
+/// The "outside" tuple is what the resource sees (prior to any masquerading by the gateway's OS).
+/// For ICMP, the "port" is the ICMP identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowRecord {
+    pub client: ClientId,
+    pub resource: ResourceId,
+    pub protocol: FlowProtocol,
+
+    pub inside_src: SocketAddr,
+    pub inside_dst: SocketAddr,
+    pub outside_src: SocketAddr,
+    pub outside_dst: SocketAddr,
+
+    pub start: Instant,
+    pub end: Instant,
+
+    /// Traffic from the client to the resource.
+    pub tx: FlowCounters,
+    /// Traffic from the resource to the client.
+    pub rx: FlowCounters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl FlowProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlowProtocol::Tcp => "tcp",
+            FlowProtocol::Udp => "udp",
+            FlowProtocol::Icmp => "icmp",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowCounters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl FlowCounters {
+    fn add(&mut self, packet: &IpPacket) {
+        self.packets += 1;
+        self.bytes += packet.packet().len() as u64;
+    }
+}
+
+/// Identifies a flow by its "inside" tuple: client IP + port and resource IP + port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FlowKey {
+    client: (Protocol, IpAddr),
+    resource: (Protocol, IpAddr),
+}
+
+impl FlowKey {
+    /// Computes the key of a packet sent by the client, prior to any translation.
+    pub(crate) fn for_outbound(packet: &IpPacket) -> Option<Self> {
+        Some(Self {
+            client: (packet.source_protocol().ok()?, packet.source()),
+            resource: (packet.destination_protocol().ok()?, packet.destination()),
+        })
+    }
+
+    /// Computes the key of a packet sent to the client, after it has been translated back.
+    fn for_inbound(packet: &IpPacket) -> Option<Self> {
+        Some(Self {
+            client: (packet.destination_protocol().ok()?, packet.destination()),
+            resource: (packet.source_protocol().ok()?, packet.source()),
+        })
+    }
+
+    pub(crate) fn resource_ip(&self) -> IpAddr {
+        self.resource.1
+    }
+}
+
+#[derive(Debug)]
+struct Flow {
+    resource: ResourceId,
+    outside_src: (Protocol, IpAddr),
+    outside_dst: (Protocol, IpAddr),
+
+    start: Instant,
+    last_seen: Instant,
+
+    tx: FlowCounters,
+    rx: FlowCounters,
+
+    fin_tx: bool,
+    fin_rx: bool,
+    rst: bool,
+}
+
+impl Flow {
+    fn is_closed(&self) -> bool {
+        self.rst || (self.fin_tx && self.fin_rx)
+    }
+
+    fn is_idle(&self, protocol: Protocol, now: Instant) -> bool {
+        let ttl = match protocol {
+            Protocol::Tcp(_) => TCP_TTL,
+            Protocol::Udp(_) => UDP_TTL,
+            Protocol::Icmp(_) => ICMP_TTL,
+        };
+
+        now.duration_since(self.last_seen) >= ttl
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FlowTracker {
+    client: ClientId,
+    flows: LruCache<FlowKey, Flow>,
+    completed: VecDeque<FlowRecord>,
+}
+
+impl FlowTracker {
+    pub(crate) fn new(client: ClientId) -> Self {
+        Self::with_capacity(client, MAX_FLOWS)
+    }
+
+    fn with_capacity(client: ClientId, capacity: NonZeroUsize) -> Self {
+        Self {
+            client,
+            flows: LruCache::new(capacity),
+            completed: VecDeque::default(),
+        }
+    }
+
+    /// Records a packet from the client to a resource.
+    ///
+    /// - `key` identifies the packet as it was sent by the client.
+    /// - `outside` is the packet after it has been translated by the gateway.
+    pub(crate) fn record_outbound(
+        &mut self,
+        resource: ResourceId,
+        key: FlowKey,
+        outside: &IpPacket,
+        now: Instant,
+    ) {
+        let (Ok(outside_src_proto), Ok(outside_dst_proto)) =
+            (outside.source_protocol(), outside.destination_protocol())
+        else {
+            return;
+        };
+
+        if !self.flows.contains(&key) {
+            // Only a SYN starts a TCP flow; anything else belongs to a flow we already completed, e.g. the final ACK after both FINs.
+            if outside.as_tcp().is_some_and(|tcp| !tcp.syn() || tcp.ack()) {
+                return;
+            }
+
+            tracing::trace!(?key, %resource, "New flow");
+
+            let flow = Flow {
+                resource,
+                outside_src: (outside_src_proto, outside.source()),
+                outside_dst: (outside_dst_proto, outside.destination()),
+                start: now,
+                last_seen: now,
+                tx: FlowCounters::default(),
+                rx: FlowCounters::default(),
+                fin_tx: false,
+                fin_rx: false,
+                rst: false,
+            };
+
+            if let Some((evicted_key, evicted)) = self.flows.push(key, flow) {
+                tracing::debug!(key = ?evicted_key, "Too many flows; completing least recently active flow");
+
+                self.completed
+                    .push_back(make_record(self.client, evicted_key, evicted));
+            }
+        }
+
+        let Some(flow) = self.flows.get_mut(&key) else {
+            return;
+        };
+
+        // Translation doesn't change the length or the TCP flags of a packet.
+        flow.tx.add(outside);
+        flow.last_seen = now;
+
+        if let Some(tcp) = outside.as_tcp() {
+            flow.fin_tx |= tcp.fin();
+            flow.rst |= tcp.rst();
+        }
+    }
+
+    /// Records a packet from a resource to the client.
+    ///
+    /// `inside` is the packet after it has been translated back, i.e. as the client will see it.
+    pub(crate) fn record_inbound(&mut self, inside: &IpPacket, now: Instant) {
+        let Some(key) = FlowKey::for_inbound(inside) else {
+            return;
+        };
+
+        let Some(flow) = self.flows.get_mut(&key) else {
+            return;
+        };
+
+        flow.rx.add(inside);
+        flow.last_seen = now;
+
+        if let Some(tcp) = inside.as_tcp() {
+            flow.fin_rx |= tcp.fin();
+            flow.rst |= tcp.rst();
+        }
+    }
+
+    pub(crate) fn handle_timeout(&mut self, now: Instant) {
+        let expired = self
+            .flows
+            .iter()
+            .filter(|(key, flow)| flow.is_closed() || flow.is_idle(key.client.0, now))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in expired {
+            let Some(flow) = self.flows.pop(&key) else {
+                continue;
+            };
+
+            self.completed
+                .push_back(make_record(self.client, key, flow));
+        }
+    }
+
+    /// Completes all flows, regardless of their state.
+    ///
+    /// Used when the client disconnects.
+    pub(crate) fn close_all(&mut self) {
+        while let Some((key, flow)) = self.flows.pop_lru() {
+            self.completed
+                .push_back(make_record(self.client, key, flow));
+        }
+    }
+
+    pub(crate) fn poll_completed(&mut self) -> Option<FlowRecord> {
+        self.completed.pop_front()
+    }
+}
+
+fn make_record(client: ClientId, key: FlowKey, flow: Flow) -> FlowRecord {
+    FlowRecord {
+        client,
+        resource: flow.resource,
+        protocol: match key.client.0 {
+            Protocol::Tcp(_) => FlowProtocol::Tcp,
+            Protocol::Udp(_) => FlowProtocol::Udp,
+            Protocol::Icmp(_) => FlowProtocol::Icmp,
+        },
+        inside_src: socket(key.client),
+        inside_dst: socket(key.resource),
+        outside_src: socket(flow.outside_src),
+        outside_dst: socket(flow.outside_dst),
+        start: flow.start,
+        end: flow.last_seen,
+        tx: flow.tx,
+        rx: flow.rx,
+    }
+}
+
+fn socket((proto, ip): (Protocol, IpAddr)) -> SocketAddr {
+    SocketAddr::new(ip, proto.value())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ip_packet::make::TcpFlags;
+
+    use super::*;
+
+    #[test]
+    fn udp_flow_completes_after_idle_timeout() {
+        let mut tracker = FlowTracker::new(client_id());
+        let now = Instant::now();
+
+        let request = udp(client_ip(), 5000, proxy_ip(), 53);
+        let translated = udp(client_ip(), 5000, real_ip(), 53);
+        let response = udp(proxy_ip(), 53, client_ip(), 5000);
+
+        tracker.record_outbound(
+            resource_id(),
+            FlowKey::for_outbound(&request).unwrap(),
+            &translated,
+            now,
+        );
+        tracker.record_inbound(&response, now + Duration::from_secs(1));
+        tracker.handle_timeout(now + Duration::from_secs(2));
+
+        assert_eq!(tracker.poll_completed(), None);
+
+        tracker.handle_timeout(now + Duration::from_secs(1) + UDP_TTL);
+
+        let record = tracker.poll_completed().unwrap();
+
+        assert_eq!(record.protocol, FlowProtocol::Udp);
+        assert_eq!(record.resource, resource_id());
+        assert_eq!(record.inside_dst, SocketAddr::new(proxy_ip(), 53));
+        assert_eq!(record.outside_dst, SocketAddr::new(real_ip(), 53));
+        assert_eq!(record.tx.packets, 1);
+        assert_eq!(record.rx.packets, 1);
+        assert_eq!(record.tx.bytes, request.packet().len() as u64);
+        assert_eq!(record.end, now + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn tcp_flow_completes_after_rst() {
+        let mut tracker = FlowTracker::new(client_id());
+        let now = Instant::now();
+
+        let syn = tcp(client_ip(), 5000, real_ip(), 443, syn_flags());
+        let rst = tcp(
+            real_ip(),
+            443,
+            client_ip(),
+            5000,
+            TcpFlags {
+                rst: true,
+                ..Default::default()
+            },
+        );
+
+        tracker.record_outbound(
+            resource_id(),
+            FlowKey::for_outbound(&syn).unwrap(),
+            &syn,
+            now,
+        );
+        tracker.record_inbound(&rst, now);
+        tracker.handle_timeout(now);
+
+        let record = tracker.poll_completed().unwrap();
+
+        assert_eq!(record.protocol, FlowProtocol::Tcp);
+        assert_eq!(record.rx.packets, 1);
+    }
+
+    #[test]
+    fn tcp_segments_after_completion_do_not_start_new_flow() {
+        let mut tracker = FlowTracker::new(client_id());
+        let now = Instant::now();
+
+        let syn = tcp(client_ip(), 5000, real_ip(), 443, syn_flags());
+        let fin = TcpFlags {
+            fin: true,
+            ack: true,
+            ..Default::default()
+        };
+        let ack = TcpFlags {
+            ack: true,
+            ..Default::default()
+        };
+        let key = FlowKey::for_outbound(&syn).unwrap();
+
+        tracker.record_outbound(resource_id(), key, &syn, now);
+        tracker.record_outbound(
+            resource_id(),
+            key,
+            &tcp(client_ip(), 5000, real_ip(), 443, fin),
+            now,
+        );
+        tracker.record_inbound(&tcp(real_ip(), 443, client_ip(), 5000, fin), now);
+        tracker.handle_timeout(now);
+
+        assert!(tracker.poll_completed().is_some());
+
+        let final_ack = tcp(client_ip(), 5000, real_ip(), 443, ack);
+        tracker.record_outbound(resource_id(), key, &final_ack, now);
+        tracker.close_all();
+
+        assert_eq!(tracker.poll_completed(), None);
+    }
+
+    #[test]
+    fn completes_least_recently_active_flow_when_full() {
+        let mut tracker = FlowTracker::with_capacity(client_id(), NonZeroUsize::new(2).unwrap());
+        let now = Instant::now();
+
+        for port in [5000, 5001] {
+            let packet = udp(client_ip(), port, real_ip(), 53);
+
+            tracker.record_outbound(
+                resource_id(),
+                FlowKey::for_outbound(&packet).unwrap(),
+                &packet,
+                now,
+            );
+        }
+
+        // Activity on the first flow makes the second one the least recently active.
+        tracker.record_inbound(&udp(real_ip(), 53, client_ip(), 5000), now);
+
+        let third = udp(client_ip(), 5002, real_ip(), 53);
+        tracker.record_outbound(
+            resource_id(),
+            FlowKey::for_outbound(&third).unwrap(),
+            &third,
+            now,
+        );
+
+        let record = tracker.poll_completed().unwrap();
+
+        assert_eq!(record.inside_src, SocketAddr::new(client_ip(), 5001));
+        assert_eq!(tracker.poll_completed(), None);
+    }
+
+    #[test]
+    fn inbound_packet_without_flow_is_ignored() {
+        let mut tracker = FlowTracker::new(client_id());
+        let now = Instant::now();
+
+        tracker.record_inbound(&udp(proxy_ip(), 53, client_ip(), 5000), now);
+        tracker.close_all();
+
+        assert_eq!(tracker.poll_completed(), None);
+    }
+
+    fn udp(src: IpAddr, sport: u16, dst: IpAddr, dport: u16) -> IpPacket {
+        ip_packet::make::udp_packet(src, dst, sport, dport, vec![0; 8]).unwrap()
+    }
+
+    fn tcp(src: IpAddr, sport: u16, dst: IpAddr, dport: u16, flags: TcpFlags) -> IpPacket {
+        ip_packet::make::tcp_packet(src, dst, sport, dport, flags, vec![0; 8]).unwrap()
+    }
+
+    fn syn_flags() -> TcpFlags {
+        TcpFlags {
+            syn: true,
+            ..Default::default()
+        }
+    }
+
+    fn client_ip() -> IpAddr {
+        "100.64.0.1".parse().unwrap()
+    }
+
+    fn proxy_ip() -> IpAddr {
+        "100.96.0.1".parse().unwrap()
+    }
+
+    fn real_ip() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
+
+    fn client_id() -> ClientId {
+        "9d4b79f6-1db7-4cb3-a077-712102204d73".parse().unwrap()
+    }
+
+    fn resource_id() -> ResourceId {
+        "ed29c148-2acf-4ceb-8db5-d796c2671631".parse().unwrap()
+    }
+}
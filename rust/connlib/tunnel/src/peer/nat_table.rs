@@ -2,7 +2,7 @@
 use anyhow::{Context, Result};
 use bimap::BiMap;
 use ip_packet::{FailedPacket, IcmpError, IpPacket, PacketBuilder, Protocol};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::time::{Duration, Instant};
 
@@ -14,37 +14,156 @@ use std::time::{Duration, Instant};
 ///
 /// We need to include the L4 component because multiple DNS resources could resolve to the same IP on the Internet.
 /// Thus, purely an L3 NAT would not be sufficient as it would be impossible to map back to the proxy IP.
+///
+/// Once all ports towards a particular IP are in use, the NAT spreads new sessions across additional egress IPs, if any.
 #[derive(Default, Debug)]
 pub(crate) struct NatTable {
-    pub(crate) table: BiMap<(Protocol, IpAddr), (Protocol, IpAddr)>,
-    pub(crate) last_seen: BTreeMap<(Protocol, IpAddr), Instant>,
+    pub(crate) table: BiMap<(Protocol, IpAddr), Outside>,
+    pub(crate) last_seen: BTreeMap<Outside, Instant>,
+    /// The state of each TCP session, indexed by the "outside" tuple.
+    tcp_states: HashMap<Outside, TcpState>,
+
+    /// Additional source IPs we may send from, on top of the client's tunnel IP.
+    egress_ips: Vec<IpAddr>,
 
     // We don't bother with proactively freeing this because a single entry is only ~20 bytes and it gets cleanup once the connection to the client goes away.
-    expired: HashSet<(Protocol, IpAddr)>,
+    expired: HashSet<Outside>,
+}
+
+/// The "outside" of a NAT session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct Outside {
+    /// The egress IP we send from.
+    ///
+    /// `None` means we send from the client's tunnel IP.
+    pub(crate) src: Option<IpAddr>,
+    pub(crate) proto: Protocol,
+    pub(crate) dst: IpAddr,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Exhausted NAT for {dst}")]
+pub(crate) struct ExhaustedNat {
+    pub(crate) dst: IpAddr,
 }
 
+/// The TTL of an established TCP session.
 pub(crate) const TCP_TTL: Duration = Duration::from_secs(60 * 60 * 2);
+/// The TTL of a TCP session for which we have only seen the client's SYN.
+pub(crate) const TCP_SYN_SENT_TTL: Duration = Duration::from_secs(30);
+/// The TTL of a TCP session where one side has sent a FIN.
+pub(crate) const TCP_FIN_WAIT_TTL: Duration = Duration::from_secs(60 * 2);
+/// The TTL of a TCP session where both sides have sent a FIN.
+pub(crate) const TCP_TIME_WAIT_TTL: Duration = Duration::from_secs(30);
 pub(crate) const UDP_TTL: Duration = Duration::from_secs(60 * 2);
 pub(crate) const ICMP_TTL: Duration = Duration::from_secs(60 * 2);
 
 impl NatTable {
     pub(crate) fn handle_timeout(&mut self, now: Instant) {
-        for (outside, e) in self.last_seen.iter() {
-            let ttl = match outside.0 {
-                Protocol::Tcp(_) => TCP_TTL,
-                Protocol::Udp(_) => UDP_TTL,
-                Protocol::Icmp(_) => ICMP_TTL,
-            };
+        let expired = self
+            .last_seen
+            .iter()
+            .filter(|(outside, last_seen)| now.duration_since(**last_seen) >= self.ttl(outside))
+            .map(|(outside, _)| *outside)
+            .collect::<Vec<_>>();
+
+        for outside in expired {
+            let ttl = self.ttl(&outside);
 
-            if now.duration_since(*e) >= ttl
-                && let Some((inside, _)) = self.table.remove_by_right(outside)
-            {
+            if let Some(inside) = self.remove_session(&outside) {
                 tracing::debug!(?inside, ?outside, ?ttl, "NAT session expired");
-                self.expired.insert(*outside);
             }
         }
     }
 
+    fn ttl(&self, outside: &Outside) -> Duration {
+        match outside.proto {
+            Protocol::Tcp(_) => self
+                .tcp_states
+                .get(outside)
+                .map(|state| state.ttl())
+                .unwrap_or(TCP_TTL),
+            Protocol::Udp(_) => UDP_TTL,
+            Protocol::Icmp(_) => ICMP_TTL,
+        }
+    }
+
+    /// Removes the NAT session for the given "outside" tuple, remembering it as expired.
+    fn remove_session(&mut self, outside: &Outside) -> Option<(Protocol, IpAddr)> {
+        self.last_seen.remove(outside);
+        self.tcp_states.remove(outside);
+
+        let (inside, _) = self.table.remove_by_right(outside)?;
+        self.expired.insert(*outside);
+
+        Some(inside)
+    }
+
+    /// Advances the state of a TCP session, removing it if it was reset.
+    fn handle_tcp_packet(&mut self, packet: &IpPacket, outside: Outside, direction: Direction) {
+        let Some(tcp) = packet.as_tcp() else {
+            return;
+        };
+
+        if tcp.rst() {
+            tracing::debug!(
+                ?outside,
+                ?direction,
+                "Witnessed TCP RST, removing NAT session"
+            );
+
+            self.remove_session(&outside);
+            return;
+        }
+
+        let state = self
+            .tcp_states
+            .entry(outside)
+            .or_insert_with(|| TcpState::new(tcp.syn(), tcp.ack()));
+        let new_state = state.next(direction, tcp.syn(), tcp.ack(), tcp.fin());
+
+        if *state != new_state {
+            tracing::debug!(?outside, old = ?*state, new = ?new_state, "TCP session changed state");
+        }
+
+        *state = new_state;
+    }
+
+    /// Adds an additional egress IP to send from once all ports of the client's tunnel IP are in use.
+    pub(crate) fn add_egress_ip(&mut self, ip: IpAddr) {
+        if self.egress_ips.contains(&ip) {
+            return;
+        }
+
+        self.egress_ips.push(ip);
+    }
+
+    pub(crate) fn is_egress_ip(&self, ip: IpAddr) -> bool {
+        self.egress_ips.contains(&ip)
+    }
+
+    /// Stops using all egress IPs that no longer have any NAT sessions, returning them.
+    pub(crate) fn remove_unused_egress_ips(&mut self) -> Vec<IpAddr> {
+        let (used, unused) = std::mem::take(&mut self.egress_ips)
+            .into_iter()
+            .partition(|ip| self.table.right_values().any(|o| o.src == Some(*ip)));
+
+        self.egress_ips = used;
+        self.expired
+            .retain(|o| o.src.is_none_or(|src| self.egress_ips.contains(&src)));
+
+        unused
+    }
+
+    /// The number of active NAT sessions.
+    pub(crate) fn num_sessions(&self) -> usize {
+        self.table.len()
+    }
+
+    fn egress_src(&self, ip: IpAddr) -> Option<IpAddr> {
+        self.is_egress_ip(ip).then_some(ip)
+    }
+
     /// Returns true if the NAT table has any entries with the given "inside" IP address.
     pub(crate) fn has_entry_for_inside(&self, ip: IpAddr) -> bool {
         self.table.left_values().any(|(_, c)| c == &ip)
@@ -55,28 +174,19 @@ impl NatTable {
         packet: &IpPacket,
         outside_dst: IpAddr,
         now: Instant,
-    ) -> Result<(Protocol, IpAddr)> {
+    ) -> Result<Outside> {
         let src = packet.source_protocol()?;
         let dst = packet.destination();
 
         let inside = (src, dst);
 
         if let Some(outside) = self.table.get_by_left(&inside).copied() {
-            if outside.1 == outside_dst {
+            if outside.dst == outside_dst {
                 tracing::trace!(?inside, ?outside, "Translating outgoing packet");
 
-                if packet.as_tcp().is_some_and(|tcp| tcp.rst()) {
-                    tracing::debug!(
-                        ?inside,
-                        ?outside,
-                        "Witnessed outgoing TCP RST, removing NAT session"
-                    );
-
-                    self.table.remove_by_left(&inside);
-                    self.expired.insert(outside);
-                }
-
                 self.last_seen.insert(outside, now);
+                self.handle_tcp_packet(packet, outside, Direction::Outgoing);
+
                 return Ok(outside);
             }
 
@@ -85,20 +195,37 @@ impl NatTable {
 
         // Find the first available public port, starting from the port of the to-be-mapped packet.
         // This will re-assign the same port in most cases, even after the mapping expires.
-        let outside = (src.value()..=u16::MAX)
-            .chain(1..src.value())
-            .map(|p| (src.with_value(p), outside_dst))
+        // Only once all ports of the client's tunnel IP are in use, do we move on to the egress IPs.
+        let outside = std::iter::once(None)
+            .chain(
+                self.egress_ips
+                    .iter()
+                    .filter(|ip| ip.is_ipv4() == outside_dst.is_ipv4())
+                    .map(|ip| Some(*ip)),
+            )
+            .flat_map(|egress| {
+                (src.value()..=u16::MAX)
+                    .chain(1..src.value())
+                    .map(move |p| Outside {
+                        src: egress,
+                        proto: src.with_value(p),
+                        dst: outside_dst,
+                    })
+            })
             .find(|outside| !self.table.contains_right(outside))
-            .context("Exhausted NAT")?;
+            .ok_or(ExhaustedNat { dst: outside_dst })?;
 
         let inside = (src, dst);
 
         self.table.insert(inside, outside);
         self.last_seen.insert(outside, now);
+        self.tcp_states.remove(&outside);
         self.expired.remove(&outside);
 
         tracing::debug!(?inside, ?outside, "New NAT session");
 
+        self.handle_tcp_packet(packet, outside, Direction::Outgoing);
+
         Ok(outside)
     }
 
@@ -108,7 +235,11 @@ impl NatTable {
         now: Instant,
     ) -> Result<TranslateIncomingResult> {
         if let Some((failed_packet, icmp_error)) = packet.icmp_error()? {
-            let outside = (failed_packet.src_proto(), failed_packet.dst());
+            let outside = Outside {
+                src: self.egress_src(failed_packet.src()),
+                proto: failed_packet.src_proto(),
+                dst: failed_packet.dst(),
+            };
 
             if let Some((inside_proto, inside_dst)) = self.translate_incoming_inner(&outside, now) {
                 return Ok(TranslateIncomingResult::IcmpError(IcmpErrorPrototype {
@@ -126,19 +257,14 @@ impl NatTable {
             return Ok(TranslateIncomingResult::NoNatSession);
         }
 
-        let outside = (packet.destination_protocol()?, packet.source());
+        let outside = Outside {
+            src: self.egress_src(packet.destination()),
+            proto: packet.destination_protocol()?,
+            dst: packet.source(),
+        };
 
         if let Some(inside) = self.translate_incoming_inner(&outside, now) {
-            if packet.as_tcp().is_some_and(|tcp| tcp.rst()) {
-                tracing::debug!(
-                    ?inside,
-                    ?outside,
-                    "Witnessed incoming TCP RST, removing NAT session"
-                );
-
-                self.table.remove_by_right(&outside);
-                self.expired.insert(outside);
-            }
+            self.handle_tcp_packet(packet, outside, Direction::Incoming);
 
             let (proto, src) = inside;
 
@@ -154,18 +280,84 @@ impl NatTable {
 
     fn translate_incoming_inner(
         &mut self,
-        outside: &(Protocol, IpAddr),
+        outside: &Outside,
         now: Instant,
     ) -> Option<(Protocol, IpAddr)> {
         let inside = self.table.get_by_right(outside)?;
 
         tracing::trace!(?inside, ?outside, "Translating incoming packet");
-        self.last_seen.insert(*inside, now);
+
+        // `last_seen` is keyed by the "outside" tuple, like in `translate_outgoing`.
+        // Incoming traffic must refresh the same entry, otherwise sessions that mostly receive data expire.
+        self.last_seen.insert(*outside, now);
 
         Some(*inside)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// From the client to the resource.
+    Outgoing,
+    /// From the resource to the client.
+    Incoming,
+}
+
+/// A simplified TCP state machine, as seen from the NAT.
+///
+/// We only track enough state to know when a session can be reclaimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpState {
+    /// The client sent a SYN but the resource hasn't responded yet.
+    SynSent,
+    Established,
+    /// One side has sent a FIN.
+    FinWait(Direction),
+    /// Both sides have sent a FIN.
+    TimeWait,
+}
+
+impl TcpState {
+    fn new(syn: bool, ack: bool) -> Self {
+        // A session may also be picked up mid-stream, e.g. after the NAT session expired.
+        if syn && !ack {
+            return TcpState::SynSent;
+        }
+
+        TcpState::Established
+    }
+
+    fn next(self, direction: Direction, syn: bool, ack: bool, fin: bool) -> Self {
+        let state = match (self, direction) {
+            (TcpState::SynSent, Direction::Incoming) => TcpState::Established,
+            // A new SYN for a closing session means the client is re-using the port.
+            (TcpState::FinWait(_) | TcpState::TimeWait, Direction::Outgoing) if syn && !ack => {
+                return TcpState::SynSent;
+            }
+            (state, _) => state,
+        };
+
+        if !fin {
+            return state;
+        }
+
+        match state {
+            TcpState::SynSent | TcpState::Established => TcpState::FinWait(direction),
+            TcpState::FinWait(closed_by) if closed_by != direction => TcpState::TimeWait,
+            TcpState::FinWait(_) | TcpState::TimeWait => state,
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        match self {
+            TcpState::SynSent => TCP_SYN_SENT_TTL,
+            TcpState::Established => TCP_TTL,
+            TcpState::FinWait(_) => TCP_FIN_WAIT_TTL,
+            TcpState::TimeWait => TCP_TIME_WAIT_TTL,
+        }
+    }
+}
+
 /// A prototype for an ICMP error packet.
 ///
 /// A packet coming in from the "outside" of the NAT may be an ICMP error.
@@ -258,14 +450,14 @@ mod tests {
         let dst = packet.destination();
 
         // Translate out
-        let (new_source_protocol, new_dst_ip) = table
+        let outside = table
             .translate_outgoing(&packet, outside_dst, sent_at)
             .unwrap();
 
         // Pretend we are getting a response.
         let mut response = packet.clone();
-        response.set_destination_protocol(new_source_protocol.value());
-        response.set_src(new_dst_ip).unwrap();
+        response.set_destination_protocol(outside.proto.value());
+        response.set_src(outside.dst).unwrap();
 
         // Update time.
         table.handle_timeout(sent_at + response_delay);
@@ -321,14 +513,14 @@ mod tests {
             .map(|(p, _)| (p.source_protocol().unwrap(), p.destination()));
 
         // Translate out
-        let new_src_p_and_dst = packets
+        let outsides = packets
             .clone()
             .map(|(p, d)| table.translate_outgoing(&p, d, Instant::now()).unwrap());
 
         // Pretend we are getting a response.
-        for ((p, _), (new_src_p, new_d)) in packets.iter_mut().zip(new_src_p_and_dst) {
-            p.set_destination_protocol(new_src_p.value());
-            p.set_src(new_d).unwrap();
+        for ((p, _), outside) in packets.iter_mut().zip(outsides) {
+            p.set_destination_protocol(outside.proto.value());
+            p.set_src(outside.dst).unwrap();
         }
 
         // Translate in
@@ -349,7 +541,8 @@ mod tests {
     #[test_strategy::proptest]
     fn outgoing_tcp_rst_removes_nat_mapping(
         #[strategy(tcp_packet(Just(TcpFlags::default())))] req: IpPacket,
-        #[strategy(tcp_packet(Just(TcpFlags { rst: true })))] mut rst: IpPacket,
+        #[strategy(tcp_packet(Just(TcpFlags { rst: true, ..Default::default() })))]
+        mut rst: IpPacket,
         #[strategy(any::<IpAddr>())] outside_dst: IpAddr,
     ) {
         let _guard = firezone_logging::test("trace");
@@ -367,8 +560,8 @@ mod tests {
             .unwrap();
 
         let mut response = req.clone();
-        response.set_destination_protocol(outside.0.value());
-        response.set_src(outside.1).unwrap();
+        response.set_destination_protocol(outside.proto.value());
+        response.set_src(outside.dst).unwrap();
 
         match table.translate_incoming(&response, Instant::now()).unwrap() {
             TranslateIncomingResult::Ok { .. } => {}
@@ -392,4 +585,291 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn unanswered_tcp_syn_is_reclaimed_quickly() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+
+        table.handle_timeout(now + TCP_SYN_SENT_TTL);
+
+        assert!(!table.table.contains_right(&outside));
+        assert_eq!(
+            table
+                .translate_incoming(&incoming(outside, syn_ack()), now + TCP_SYN_SENT_TTL)
+                .unwrap(),
+            TranslateIncomingResult::ExpiredNatSession
+        );
+    }
+
+    #[test]
+    fn established_tcp_session_is_not_reclaimed_after_syn_timeout() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, syn_ack()), now)
+            .unwrap();
+
+        table.handle_timeout(now + TCP_SYN_SENT_TTL);
+
+        assert!(table.table.contains_right(&outside));
+    }
+
+    #[test]
+    fn closed_tcp_session_is_reclaimed_after_time_wait() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, syn_ack()), now)
+            .unwrap();
+        table
+            .translate_outgoing(&outgoing(fin()), outside_dst(), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, fin()), now)
+            .unwrap();
+
+        table.handle_timeout(now + TCP_TIME_WAIT_TTL);
+
+        assert!(!table.table.contains_right(&outside));
+    }
+
+    #[test]
+    fn half_closed_tcp_session_is_reclaimed_after_fin_wait() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, syn_ack()), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, fin()), now)
+            .unwrap();
+
+        table.handle_timeout(now + TCP_TIME_WAIT_TTL);
+        assert!(table.table.contains_right(&outside));
+
+        table.handle_timeout(now + TCP_FIN_WAIT_TTL);
+        assert!(!table.table.contains_right(&outside));
+    }
+
+    #[test]
+    fn incoming_tcp_rst_removes_nat_mapping() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+
+        let result = table
+            .translate_incoming(&incoming(outside, rst()), now)
+            .unwrap();
+
+        assert!(matches!(result, TranslateIncomingResult::Ok { .. }));
+        assert!(!table.table.contains_right(&outside));
+        assert_eq!(
+            table
+                .translate_incoming(&incoming(outside, syn_ack()), now)
+                .unwrap(),
+            TranslateIncomingResult::ExpiredNatSession
+        );
+    }
+
+    #[test]
+    fn incoming_traffic_keeps_session_alive() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+        table
+            .translate_incoming(&incoming(outside, syn_ack()), now)
+            .unwrap();
+
+        // The resource keeps sending data, the client only sent its SYN.
+        table
+            .translate_incoming(&incoming(outside, TcpFlags::default()), now + TCP_TTL / 2)
+            .unwrap();
+        table.handle_timeout(now + TCP_TTL);
+
+        assert!(table.table.contains_right(&outside));
+        assert_eq!(table.last_seen.len(), 1);
+    }
+
+    #[test]
+    fn reclaimed_slot_can_be_reused() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        let outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now)
+            .unwrap();
+        table.handle_timeout(now + TCP_SYN_SENT_TTL);
+
+        let new_outside = table
+            .translate_outgoing(&outgoing(syn()), outside_dst(), now + TCP_SYN_SENT_TTL)
+            .unwrap();
+
+        assert_eq!(outside, new_outside);
+        assert!(matches!(
+            table
+                .translate_incoming(&incoming(new_outside, syn_ack()), now + TCP_SYN_SENT_TTL)
+                .unwrap(),
+            TranslateIncomingResult::Ok { .. }
+        ));
+    }
+
+    #[test]
+    fn uses_egress_ip_once_nat_is_exhausted() {
+        let mut table = NatTable::default();
+        let now = Instant::now();
+
+        for port in 1..=u16::MAX {
+            table
+                .translate_outgoing(&udp(port, proxy_ip()), outside_dst(), now)
+                .unwrap();
+        }
+
+        let packet = udp(5000, other_proxy_ip());
+
+        let error = table
+            .translate_outgoing(&packet, outside_dst(), now)
+            .unwrap_err();
+        assert!(error.is::<ExhaustedNat>());
+
+        table.add_egress_ip(egress_ip());
+
+        let outside = table
+            .translate_outgoing(&packet, outside_dst(), now)
+            .unwrap();
+        assert_eq!(outside.src, Some(egress_ip()));
+
+        let response = ip_packet::make::udp_packet(
+            outside_dst(),
+            egress_ip(),
+            53,
+            outside.proto.value(),
+            vec![],
+        )
+        .unwrap();
+
+        assert_eq!(
+            table.translate_incoming(&response, now).unwrap(),
+            TranslateIncomingResult::Ok {
+                proto: Protocol::Udp(5000),
+                src: other_proxy_ip()
+            }
+        );
+    }
+
+    #[test]
+    fn removes_egress_ip_once_its_sessions_expired() {
+        let mut table = NatTable::default();
+        let mut now = Instant::now();
+
+        for port in 1..=u16::MAX {
+            table
+                .translate_outgoing(&udp(port, proxy_ip()), outside_dst(), now)
+                .unwrap();
+        }
+        table.add_egress_ip(egress_ip());
+        table
+            .translate_outgoing(&udp(5000, other_proxy_ip()), outside_dst(), now)
+            .unwrap();
+
+        assert!(table.remove_unused_egress_ips().is_empty());
+
+        now += UDP_TTL;
+        table.handle_timeout(now);
+
+        assert_eq!(table.remove_unused_egress_ips(), vec![egress_ip()]);
+        assert!(!table.is_egress_ip(egress_ip()));
+    }
+
+    fn outgoing(flags: TcpFlags) -> IpPacket {
+        ip_packet::make::tcp_packet(client_ip(), proxy_ip(), 5000, 443, flags, vec![]).unwrap()
+    }
+
+    fn incoming(outside: Outside, flags: TcpFlags) -> IpPacket {
+        ip_packet::make::tcp_packet(
+            outside.dst,
+            outside.src.unwrap_or(client_ip()),
+            443,
+            outside.proto.value(),
+            flags,
+            vec![],
+        )
+        .unwrap()
+    }
+
+    fn udp(sport: u16, dst: IpAddr) -> IpPacket {
+        ip_packet::make::udp_packet(client_ip(), dst, sport, 53, vec![]).unwrap()
+    }
+
+    fn syn() -> TcpFlags {
+        TcpFlags {
+            syn: true,
+            ..Default::default()
+        }
+    }
+
+    fn syn_ack() -> TcpFlags {
+        TcpFlags {
+            syn: true,
+            ack: true,
+            ..Default::default()
+        }
+    }
+
+    fn fin() -> TcpFlags {
+        TcpFlags {
+            fin: true,
+            ack: true,
+            ..Default::default()
+        }
+    }
+
+    fn rst() -> TcpFlags {
+        TcpFlags {
+            rst: true,
+            ..Default::default()
+        }
+    }
+
+    fn client_ip() -> IpAddr {
+        "100.64.0.1".parse().unwrap()
+    }
+
+    fn proxy_ip() -> IpAddr {
+        "100.96.0.1".parse().unwrap()
+    }
+
+    fn other_proxy_ip() -> IpAddr {
+        "100.96.0.2".parse().unwrap()
+    }
+
+    fn egress_ip() -> IpAddr {
+        "192.0.2.1".parse().unwrap()
+    }
+
+    fn outside_dst() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
 }
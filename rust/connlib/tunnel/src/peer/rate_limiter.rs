@@ -0,0 +1,95 @@
+use std::time::Instant;
+
+use crate::messages::gateway::RateLimit;
+
+/// A token bucket, measured in bytes.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    /// When we last refilled the bucket.
+    ///
+    /// `None` until the first packet passes through the bucket; until then, it is full.
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: capacity(limit),
+            last_refill: None,
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: RateLimit) {
+        self.limit = limit;
+        self.tokens = self.tokens.min(capacity(limit));
+    }
+
+    pub(crate) fn refill(&mut self, now: Instant) {
+        let Some(last_refill) = self.last_refill.replace(now) else {
+            return;
+        };
+
+        let elapsed = now.saturating_duration_since(last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.limit.bytes_per_second() as f64)
+            .min(capacity(self.limit));
+    }
+
+    pub(crate) fn has_capacity(&self, bytes: usize) -> bool {
+        self.tokens >= bytes as f64
+    }
+
+    pub(crate) fn consume(&mut self, bytes: usize) {
+        self.tokens = (self.tokens - bytes as f64).max(0.0);
+    }
+}
+
+fn capacity(limit: RateLimit) -> f64 {
+    limit.burst_bytes() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn allows_burst_then_refills_at_rate() {
+        let mut bucket = TokenBucket::new(RateLimit::new(2000, Some(3000)).unwrap());
+        let now = Instant::now();
+
+        bucket.refill(now);
+        assert!(bucket.has_capacity(3000));
+        bucket.consume(3000);
+        assert!(!bucket.has_capacity(1));
+
+        bucket.refill(now + Duration::from_millis(500));
+        assert!(bucket.has_capacity(1000));
+        assert!(!bucket.has_capacity(1001));
+    }
+
+    #[test]
+    fn never_exceeds_burst() {
+        let mut bucket = TokenBucket::new(RateLimit::new(2000, None).unwrap());
+        let now = Instant::now();
+
+        bucket.refill(now);
+        bucket.refill(now + Duration::from_secs(60));
+
+        assert!(bucket.has_capacity(2000));
+        assert!(!bucket.has_capacity(2001));
+    }
+
+    #[test]
+    fn lowering_limit_caps_available_tokens() {
+        let mut bucket = TokenBucket::new(RateLimit::new(10_000, None).unwrap());
+
+        bucket.set_limit(RateLimit::new(2000, None).unwrap());
+
+        assert!(!bucket.has_capacity(2001));
+    }
+}
@@ -38,6 +38,21 @@ impl PeerStore<GatewayId, GatewayOnClient> {
             peer.insert_id(&ip, resource);
         }
     }
+
+    /// Stops routing traffic for the given resource to this gateway.
+    ///
+    /// IPs shared with other resources remain routed to the gateway.
+    pub(crate) fn remove_resource(&mut self, id: &GatewayId, resource: &ResourceId) {
+        let Some(peer) = self.peer_by_id.get_mut(id) else {
+            return;
+        };
+
+        for ip in peer.remove_id(resource) {
+            if self.id_by_ip.exact_match(ip).is_some_and(|i| i == id) {
+                self.id_by_ip.remove(ip);
+            }
+        }
+    }
 }
 
 impl<TId, P> PeerStore<TId, P>
@@ -45,12 +60,6 @@ where
     TId: Hash + Eq + Copy + fmt::Debug + fmt::Display,
     P: Peer<Id = TId>,
 {
-    pub(crate) fn retain(&mut self, f: impl Fn(&TId, &mut P) -> bool) {
-        self.peer_by_id.retain(f);
-        self.id_by_ip
-            .retain(|_, id| self.peer_by_id.contains_key(id));
-    }
-
     pub(crate) fn add_ip(&mut self, id: &TId, ip: &IpNetwork) -> Option<&mut P> {
         let peer = self.peer_by_id.get_mut(id)?;
         let previous = self.id_by_ip.insert(*ip, *id);
@@ -62,6 +71,13 @@ where
         Some(peer)
     }
 
+    /// Stops routing traffic for `ip` to the given peer.
+    pub(crate) fn remove_ip(&mut self, id: &TId, ip: &IpNetwork) {
+        if self.id_by_ip.exact_match(*ip).is_some_and(|i| i == id) {
+            self.id_by_ip.remove(*ip);
+        }
+    }
+
     pub(crate) fn insert(&mut self, peer: P, ips: &[IpNetwork]) -> Option<P> {
         self.id_by_ip.retain(|_, &mut r_id| r_id != peer.id());
 
@@ -92,7 +108,6 @@ where
         self.peer_by_id.get_mut(id)
     }
 
-    #[cfg(test)]
     pub(crate) fn peer_by_ip(&self, ip: IpAddr) -> Option<&P> {
         let (_, id) = self.id_by_ip.longest_match(ip)?;
         self.peer_by_id.get(id)
@@ -192,6 +207,51 @@ mod tests {
         )
     }
 
+    #[test]
+    fn removing_resource_keeps_ips_shared_with_other_resources() {
+        let mut peer_storage = PeerStore::<GatewayId, GatewayOnClient>::default();
+        let gid = GatewayId::from_u128(1);
+        let r1 = ResourceId::from_u128(1);
+        let r2 = ResourceId::from_u128(2);
+        peer_storage.insert(
+            GatewayOnClient::new(
+                gid,
+                crate::IpConfig {
+                    v4: "100.64.0.1".parse().unwrap(),
+                    v6: "fd00:2021:1111::1".parse().unwrap(),
+                },
+            ),
+            &[],
+        );
+
+        peer_storage.add_ips_with_resource(
+            &gid,
+            ["10.0.0.0/24".parse::<IpNetwork>().unwrap()].into_iter(),
+            &r1,
+        );
+        peer_storage.add_ips_with_resource(
+            &gid,
+            [
+                "10.0.0.0/24".parse::<IpNetwork>().unwrap(),
+                "10.0.1.0/24".parse::<IpNetwork>().unwrap(),
+            ]
+            .into_iter(),
+            &r2,
+        );
+        peer_storage.remove_resource(&gid, &r2);
+
+        assert!(
+            peer_storage
+                .peer_by_ip("10.0.0.1".parse().unwrap())
+                .is_some()
+        );
+        assert!(
+            peer_storage
+                .peer_by_ip("10.0.1.1".parse().unwrap())
+                .is_none()
+        );
+    }
+
     #[test]
     fn inserting_peer_removes_previous_instances_of_same_id() {
         let mut peer_storage = PeerStore::<u64, DummyPeer>::default();
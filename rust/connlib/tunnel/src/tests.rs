@@ -8,10 +8,13 @@ use proptest::{
 };
 use proptest_state_machine::Sequential;
 use reference::ReferenceState;
+use std::path::Path;
 use std::sync::atomic::{self, AtomicU32};
+use trace::{Recorder, Trace, WriteOnDrop};
 use tracing_subscriber::{
     EnvFilter, Layer, layer::SubscriberExt as _, util::SubscriberInitExt as _,
 };
+use transition::Transition;
 
 mod assertions;
 mod buffered_transmits;
@@ -21,6 +24,7 @@ mod dns_server_resource;
 mod flux_capacitor;
 mod icmp_error_hosts;
 mod reference;
+mod selector;
 mod sim_client;
 mod sim_gateway;
 mod sim_net;
@@ -29,6 +33,7 @@ mod strategies;
 mod stub_portal;
 mod sut;
 mod tcp;
+mod trace;
 mod transition;
 
 type QueryId = u16;
@@ -55,35 +60,42 @@ fn tunnel_test() {
         ReferenceState::apply,
     );
 
-    let result = test_runner.run(
-        &strategy,
-        |(mut ref_state, transitions, mut seen_counter)| {
-            let test_index = test_index.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            let flux_capacitor = FluxCapacitor::default();
+    let result = test_runner.run(&strategy, |(ref_state, transitions, mut seen_counter)| {
+        let test_index = test_index.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let flux_capacitor = FluxCapacitor::default();
+        let recorder = Recorder::default();
 
-            let _guard = init_logging(flux_capacitor.clone(), test_index);
+        let _guard = init_logging(flux_capacitor.clone(), test_index);
 
-            std::fs::write(
-                format!("testcases/{test_index}.state"),
-                format!("{ref_state:#?}"),
-            )
-            .unwrap();
-            std::fs::write(
-                format!("testcases/{test_index}.transitions"),
-                format!("{transitions:#?}"),
-            )
-            .unwrap();
-
-            let num_transitions = transitions.len();
+        std::fs::write(
+            format!("testcases/{test_index}.state"),
+            format!("{ref_state:#?}"),
+        )
+        .unwrap();
+        std::fs::write(
+            format!("testcases/{test_index}.transitions"),
+            format!("{transitions:#?}"),
+        )
+        .unwrap();
 
-            println!("Running test case {test_index:04} with {num_transitions:02} transitions");
+        let trace_path = format!("testcases/{test_index}.json");
+        let _trace = WriteOnDrop {
+            path: Path::new(&trace_path),
+            initial_state: &ref_state,
+            transitions: &transitions,
+            recorder: recorder.clone(),
+        };
 
-            let mut sut = TunnelTest::init_test(&ref_state, flux_capacitor);
+        let num_transitions = transitions.len();
 
-            // Check the invariants on the initial state
-            TunnelTest::check_invariants(&sut, &ref_state);
+        println!("Running test case {test_index:04} with {num_transitions:02} transitions");
 
-            for (ix, transition) in transitions.iter().enumerate() {
+        run_test_case(
+            ref_state.clone(),
+            &transitions,
+            flux_capacitor,
+            recorder,
+            || {
                 // The counter is `Some` only before shrinking. When it's `Some` it
                 // must be incremented before every transition that's being applied
                 // to inform the strategy that the transition has been applied for
@@ -92,23 +104,11 @@ fn tunnel_test() {
                 if let Some(seen_counter) = seen_counter.as_mut() {
                     seen_counter.fetch_add(1, atomic::Ordering::SeqCst);
                 }
+            },
+        );
 
-                tracing::info!(
-                    "\n\nApplying transition {}/{num_transitions}: {transition:?}\n",
-                    ix + 1,
-                );
-
-                // Apply the transition on the states
-                ref_state = ReferenceState::apply(ref_state, transition);
-                sut = TunnelTest::apply(sut, &ref_state, transition.clone());
-
-                // Check the invariants after the transition is applied
-                TunnelTest::check_invariants(&sut, &ref_state);
-            }
-
-            Ok(())
-        },
-    );
+        Ok(())
+    });
 
     println!("TestRunner stats: \n\n{test_runner}");
 
@@ -122,11 +122,151 @@ fn tunnel_test() {
             eprintln!("{ref_state:#?}");
             eprintln!("{transitions:#?}");
 
+            let trace = Trace {
+                initial_state: ref_state,
+                transitions,
+                recording: None,
+            };
+            let path = Path::new("testcases/failure.json");
+
+            match trace.write(path) {
+                Ok(()) => eprintln!("Wrote trace of failing test case to `{}`", path.display()),
+                Err(e) => eprintln!("{e:#}"),
+            }
+
             panic!("{msg}")
         }
     }
 }
 
+/// Replays all traces in the `traces/` directory, see [`trace`].
+#[test]
+#[expect(clippy::print_stdout)]
+fn replay_traces() {
+    let Ok(dir) = std::fs::read_dir("traces") else {
+        return;
+    };
+
+    let mut paths = dir
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    for path in paths {
+        println!("Replaying {}", path.display());
+
+        let trace = Trace::read(&path).unwrap();
+        let flux_capacitor = FluxCapacitor::default();
+        let recorder = Recorder::default();
+
+        let _guard = init_replay_logging(flux_capacitor.clone());
+
+        run_test_case(
+            trace.initial_state,
+            &trace.transitions,
+            flux_capacitor,
+            recorder.clone(),
+            || {},
+        );
+
+        if let Some(expected) = &trace.recording {
+            trace::assert_same_recording(expected, &recorder.recording());
+        }
+    }
+}
+
+/// Replays generated test cases after serialising them to JSON and back.
+///
+/// Catches (de)serialisation changes that would silently alter the traces in `traces/`.
+#[test]
+fn traces_round_trip_through_json() {
+    for n in 0..10 {
+        let strategy = Sequential::new(
+            SizeRange::new(5..=15),
+            ReferenceState::initial_state,
+            ReferenceState::is_valid_transition,
+            ReferenceState::transitions,
+            ReferenceState::apply,
+        );
+        let (initial_state, transitions, _) = sample_from_strategy(n, strategy);
+
+        let flux_capacitor = FluxCapacitor::default();
+        let recorder = Recorder::default();
+        let guard = init_replay_logging(flux_capacitor.clone());
+        run_test_case(
+            initial_state.clone(),
+            &transitions,
+            flux_capacitor,
+            recorder.clone(),
+            || {},
+        );
+        drop(guard);
+
+        let json = Trace {
+            initial_state,
+            transitions,
+            recording: Some(recorder.recording()),
+        }
+        .to_json()
+        .unwrap();
+        let trace = Trace::from_json(&json).unwrap();
+
+        let flux_capacitor = FluxCapacitor::default();
+        let recorder = Recorder::default();
+        let _guard = init_replay_logging(flux_capacitor.clone());
+        run_test_case(
+            trace.initial_state,
+            &trace.transitions,
+            flux_capacitor,
+            recorder.clone(),
+            || {},
+        );
+
+        trace::assert_same_recording(&trace.recording.unwrap(), &recorder.recording());
+    }
+}
+
+/// Applies the given transitions to the [`ReferenceState`] and [`TunnelTest`], checking the invariants after each.
+///
+/// `on_transition` is invoked before each transition is applied.
+fn run_test_case(
+    mut ref_state: ReferenceState,
+    transitions: &[Transition],
+    flux_capacitor: FluxCapacitor,
+    recorder: Recorder,
+    mut on_transition: impl FnMut(),
+) {
+    let num_transitions = transitions.len();
+
+    let mut sut = TunnelTest::init_test(&ref_state, flux_capacitor, recorder);
+
+    // Check the invariants on the initial state
+    TunnelTest::check_invariants(&sut, &ref_state);
+
+    for (ix, transition) in transitions.iter().enumerate() {
+        on_transition();
+
+        assert!(
+            ReferenceState::is_valid_transition(&ref_state, transition),
+            "Transition {} is not valid in the current state: {transition:?}",
+            ix + 1
+        );
+
+        tracing::info!(
+            "\n\nApplying transition {}/{num_transitions}: {transition:?}\n",
+            ix + 1,
+        );
+
+        // Apply the transition on the states
+        ref_state = ReferenceState::apply(ref_state, transition);
+        sut = TunnelTest::apply(sut, &ref_state, transition.clone());
+
+        // Check the invariants after the transition is applied
+        TunnelTest::check_invariants(&sut, &ref_state);
+    }
+}
+
 #[test]
 fn reference_state_is_deterministic() {
     for n in 0..1000 {
@@ -196,6 +336,19 @@ fn init_logging(
         .set_default()
 }
 
+/// Like [`init_logging`] but only logs to stdout.
+fn init_replay_logging(flux_capacitor: FluxCapacitor) -> tracing::subscriber::DefaultGuard {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_test_writer()
+                .with_timer(flux_capacitor)
+                .with_filter(EnvFilter::from_default_env()),
+        )
+        .with(PanicOnErrorEvents::new(0))
+        .set_default()
+}
+
 fn log_file_filter() -> EnvFilter {
     let default_filter =
         "debug,firezone_tunnel=trace,firezone_tunnel::tests=debug,tunnel_test_coverage=trace,ip_packet=trace".to_owned();
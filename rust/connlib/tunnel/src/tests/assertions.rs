@@ -4,7 +4,7 @@ use super::{
     sim_gateway::SimGateway,
     transition::{Destination, ReplyTo},
 };
-use connlib_model::GatewayId;
+use connlib_model::{GatewayId, ResourceId};
 use ip_packet::IpPacket;
 use itertools::Itertools;
 use std::{
@@ -17,6 +17,9 @@ use std::{
 use tracing::{Level, Span, Subscriber};
 use tracing_subscriber::Layer;
 
+/// The size of an IPv4 header without options, the smallest IP header there is.
+const MIN_IP_HEADER_LEN: usize = 20;
+
 /// Asserts the following properties for all ICMP handshakes:
 /// 1. An ICMP request on the client MUST result in an ICMP response using the same sequence, identifier and flipped src & dst IP.
 /// 2. An ICMP request on the gateway MUST target the intended resource:
@@ -260,6 +263,179 @@ fn assert_packets_properties<T, U>(
     }
 }
 
+/// Asserts that the traffic counters of every resource are consistent with the packets we exchanged with it:
+/// 1. Every answered ICMP and UDP packet MUST be counted as sent to and received from the resource it was sent to.
+/// 2. The byte counters MUST account for at least a minimal IP header per packet.
+pub(crate) fn assert_resource_traffic(ref_client: &RefClient, sim_client: &SimClient) {
+    let traffic = sim_client
+        .sut
+        .resources()
+        .into_iter()
+        .map(|r| (r.id(), r.traffic()))
+        .collect::<BTreeMap<_, _>>();
+
+    let answered_icmp = ref_client
+        .expected_icmp_handshakes
+        .values()
+        .flatten()
+        .filter(|(_, (_, seq, identifier))| {
+            sim_client
+                .received_icmp_replies
+                .contains_key(&(*seq, *identifier))
+        })
+        .filter_map(|(payload, _)| ref_client.icmp_packet_resources.get(payload));
+    let answered_udp = ref_client
+        .expected_udp_handshakes
+        .values()
+        .flatten()
+        .filter(|(_, (_, sport, dport))| {
+            sim_client
+                .received_udp_replies
+                .contains_key(&(*sport, *dport))
+        })
+        .filter_map(|(payload, _)| ref_client.udp_packet_resources.get(payload));
+
+    let num_answered_by_resource = answered_icmp.chain(answered_udp).fold(
+        BTreeMap::<ResourceId, u64>::new(),
+        |mut num_answered, resource| {
+            *num_answered.entry(*resource).or_default() += 1;
+
+            num_answered
+        },
+    );
+
+    for (resource, num_answered) in num_answered_by_resource {
+        let _guard =
+            tracing::info_span!(target: "assertions", "resource_traffic", %resource).entered();
+
+        let traffic = traffic.get(&resource).copied().unwrap_or_default();
+
+        if traffic.packets_sent < num_answered || traffic.packets_received < num_answered {
+            tracing::error!(target: "assertions", ?traffic, %num_answered, "❌ Traffic counters miss answered packets");
+        }
+    }
+
+    for (resource, traffic) in traffic {
+        let _guard =
+            tracing::info_span!(target: "assertions", "resource_traffic", %resource).entered();
+
+        if traffic.bytes_sent < traffic.packets_sent * MIN_IP_HEADER_LEN as u64
+            || traffic.bytes_received < traffic.packets_received * MIN_IP_HEADER_LEN as u64
+        {
+            tracing::error!(target: "assertions", ?traffic, "❌ Byte counters don't match packet counters");
+        }
+    }
+}
+
+/// Asserts that the statistics of every connection are consistent with the traffic that went through it:
+/// 1. A connection that decrypted packets MUST have completed a handshake and measured an RTT.
+/// 2. The handshake counter and the time of the last handshake MUST agree.
+/// 3. The byte counters MUST account for at least a minimal IP header per packet.
+pub(crate) fn assert_connection_stats(
+    sim_client: &SimClient,
+    sim_gateways: &BTreeMap<GatewayId, &SimGateway>,
+) {
+    let client_connections = sim_client
+        .sut
+        .connection_stats()
+        .map(|(gid, stats)| (gid.to_string(), stats));
+    let gateway_connections = sim_gateways.values().flat_map(|g| {
+        g.sut
+            .connection_stats()
+            .map(|(cid, stats)| (cid.to_string(), stats))
+    });
+
+    for (peer, stats) in client_connections.chain(gateway_connections) {
+        let _guard = tracing::info_span!(target: "assertions", "connection_stats", %peer).entered();
+
+        if stats.packets_decapsulated > 0 && stats.handshakes == 0 {
+            tracing::error!(target: "assertions", ?stats, "❌ Decrypted packets without a handshake");
+        }
+
+        if stats.packets_decapsulated > 0 && stats.rtt.is_none() {
+            tracing::error!(target: "assertions", ?stats, "❌ Decrypted packets without measuring the RTT");
+        }
+
+        if (stats.handshakes > 0) != stats.last_handshake_at.is_some() {
+            tracing::error!(target: "assertions", ?stats, "❌ Handshake counter and time of last handshake disagree");
+        }
+
+        if stats.bytes_encapsulated.0 < stats.packets_encapsulated as usize * MIN_IP_HEADER_LEN
+            || stats.bytes_decapsulated.0 < stats.packets_decapsulated as usize * MIN_IP_HEADER_LEN
+        {
+            tracing::error!(target: "assertions", ?stats, "❌ Byte counters don't match packet counters");
+        }
+    }
+}
+
+/// Asserts that connlib reported the candidate pair that is currently nominated for each connection to a Gateway.
+pub(crate) fn assert_connection_paths(sim_client: &SimClient) {
+    for (gid, stats) in sim_client.sut.connection_stats() {
+        let Some(nominated) = stats.candidate_pair else {
+            continue;
+        };
+        let reported = sim_client.connection_paths.get(&gid).copied();
+
+        if reported != Some(nominated) {
+            tracing::error!(target: "assertions", %gid, %nominated, ?reported, "❌ Nominated candidate pair was not reported");
+        }
+    }
+}
+
+/// Asserts that the client and each Gateway use the same preshared key and that it got upgraded if the Gateway supports it.
+///
+/// Toggling a Gateway's support only affects new connections so we can't tell what to expect for Gateways that were toggled.
+pub(crate) fn assert_post_quantum_psk(
+    sim_client: &SimClient,
+    sim_gateways: &BTreeMap<GatewayId, &SimGateway>,
+) {
+    let client_connections = sim_client
+        .sut
+        .connection_stats()
+        .collect::<BTreeMap<_, _>>();
+
+    for (gid, gateway) in sim_gateways {
+        let Some(client_stats) = client_connections.get(gid) else {
+            continue;
+        };
+        let Some(gateway_stats) = gateway
+            .sut
+            .connection_stats()
+            .find_map(|(cid, stats)| (cid == sim_client.id).then_some(stats))
+        else {
+            continue;
+        };
+
+        // Without a handshake, neither side knows which preshared key the other one uses.
+        if client_stats.handshakes == 0 || gateway_stats.handshakes == 0 {
+            continue;
+        }
+
+        let _guard = tracing::info_span!(target: "assertions", "post_quantum_psk", %gid).entered();
+
+        let client_upgraded = client_stats.upgraded_preshared_key;
+        let gateway_upgraded = gateway_stats.upgraded_preshared_key;
+
+        if client_upgraded != gateway_upgraded {
+            tracing::error!(target: "assertions", %client_upgraded, %gateway_upgraded, "❌ Client and Gateway use different preshared keys");
+        }
+
+        if gateway.post_quantum_psk_toggled {
+            continue;
+        }
+
+        match (gateway.post_quantum_psk, client_upgraded) {
+            (true, false) => {
+                tracing::error!(target: "assertions", "❌ Preshared key was not upgraded");
+            }
+            (false, true) => {
+                tracing::error!(target: "assertions", "❌ Upgraded preshared key with Gateway that doesn't support it");
+            }
+            (true, true) | (false, false) => {}
+        }
+    }
+}
+
 pub(crate) fn assert_dns_servers_are_valid(ref_client: &RefClient, sim_client: &SimClient) {
     let expected = ref_client.expected_dns_servers();
     let actual = sim_client.effective_dns_servers();
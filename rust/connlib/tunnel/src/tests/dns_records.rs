@@ -6,8 +6,9 @@ use std::{
 use dns_types::prelude::*;
 use dns_types::{DomainName, OwnedRecordData, RecordType};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct DnsRecords {
     inner: BTreeMap<DomainName, BTreeSet<OwnedRecordData>>,
 }
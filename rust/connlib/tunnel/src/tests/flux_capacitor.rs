@@ -33,8 +33,8 @@ impl Default for FluxCapacitor {
 }
 
 impl FluxCapacitor {
-    const SMALL_TICK: Duration = Duration::from_millis(10);
-    const LARGE_TICK: Duration = Duration::from_millis(100);
+    pub(crate) const SMALL_TICK: Duration = Duration::from_millis(10);
+    pub(crate) const LARGE_TICK: Duration = Duration::from_millis(100);
 
     #[expect(private_bounds)]
     pub(crate) fn now<T>(&self) -> T
@@ -46,14 +46,6 @@ impl FluxCapacitor {
         T::pick_now(now, utc_now)
     }
 
-    pub(crate) fn small_tick(&self) {
-        self.tick(Self::SMALL_TICK);
-    }
-
-    pub(crate) fn large_tick(&self) {
-        self.tick(Self::LARGE_TICK);
-    }
-
     pub(crate) fn tick(&self, tick: Duration) {
         {
             let mut guard = self.now.lock().unwrap();
@@ -68,7 +60,11 @@ impl FluxCapacitor {
     }
 
     fn elapsed(&self) -> Duration {
-        self.now::<Instant>().duration_since(self.start)
+        self.since_start(self.now())
+    }
+
+    pub(crate) fn since_start(&self, instant: Instant) -> Duration {
+        instant.duration_since(self.start)
     }
 }
 
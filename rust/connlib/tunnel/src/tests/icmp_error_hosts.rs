@@ -4,10 +4,11 @@ use std::{
 };
 
 use proptest::{prelude::*, sample};
+use serde::{Deserialize, Serialize};
 
 use super::dns_records::DnsRecords;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct IcmpErrorHosts {
     inner: BTreeMap<IpAddr, IcmpError>,
 }
@@ -53,7 +54,7 @@ fn icmp_error() -> impl Strategy<Value = IcmpError> {
 }
 
 /// Enumerates all possible ICMP errors we may generate for IPs on a particular domain.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum IcmpError {
     Network,
     Host,
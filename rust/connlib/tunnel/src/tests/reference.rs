@@ -12,6 +12,7 @@ use ip_network::{Ipv4Network, Ipv6Network};
 use itertools::Itertools;
 use prop::sample::select;
 use proptest::{prelude::*, sample};
+use serde::{Deserialize, Serialize};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::{
     collections::{BTreeMap, BTreeSet, HashSet},
@@ -22,7 +23,7 @@ use std::{
 /// The reference state machine of the tunnel.
 ///
 /// This is the "expected" part of our test.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct ReferenceState {
     pub(crate) client: Host<RefClient>,
     pub(crate) gateways: BTreeMap<GatewayId, Host<RefGateway>>,
@@ -43,6 +44,8 @@ pub(crate) struct ReferenceState {
     /// A subset of all DNS resource records that have been selected to produce an ICMP error.
     pub(crate) icmp_error_hosts: IcmpErrorHosts,
 
+    /// Derived from the IPs of all hosts, see [`routing_table`].
+    #[serde(skip)]
     pub(crate) network: RoutingTable,
 }
 
@@ -131,22 +134,7 @@ impl ReferenceState {
                     mut global_dns,
                     drop_direct_client_traffic,
                 )| {
-                    let mut routing_table = RoutingTable::default();
-
-                    if !routing_table.add_host(c.inner().id, &c) {
-                        return None;
-                    }
-                    for (id, gateway) in &gateways {
-                        if !routing_table.add_host(*id, gateway) {
-                            return None;
-                        };
-                    }
-
-                    for (id, relay) in &relays {
-                        if !routing_table.add_host(*id, relay) {
-                            return None;
-                        };
-                    }
+                    let routing_table = routing_table(&c, &gateways, &relays)?;
 
                     // Merge all DNS records into `global_dns`.
                     global_dns.merge(records);
@@ -245,6 +233,7 @@ impl ReferenceState {
             )
             .with(1, Just(Transition::ReconnectPortal))
             .with(1, Just(Transition::Idle))
+            .with(1, Just(Transition::IdleUntilRekey))
             .with_if_not_empty(1, state.client.inner().all_resource_ids(), |resources_id| {
                 sample::subsequence(resources_id.clone(), resources_id.len()).prop_map(
                     |resources_id| Transition::DisableResources(BTreeSet::from_iter(resources_id)),
@@ -254,6 +243,16 @@ impl ReferenceState {
                 sample::select(resources_id)
                     .prop_map(Transition::DeauthorizeWhileGatewayIsPartitioned)
             })
+            .with_if_not_empty(
+                1,
+                state.portal.gateways_with_online_peer_in_site(),
+                |gateways| sample::select(gateways).prop_map(Transition::FailGateway),
+            )
+            .with_if_not_empty(
+                1,
+                state.gateways.keys().copied().collect::<Vec<_>>(),
+                |gateways| sample::select(gateways).prop_map(Transition::TogglePostQuantumPsk),
+            )
             .with_if_not_empty(
                 10,
                 state.client.inner().ipv4_cidr_resource_dsts(),
@@ -420,6 +419,7 @@ impl ReferenceState {
             }
             Transition::DisableResources(resources) => state.client.exec_mut(|client| {
                 client.disabled_resources.clone_from(resources);
+                client.forget_packet_resources();
 
                 for id in resources {
                     client.disconnect_resource(id)
@@ -514,7 +514,7 @@ impl ReferenceState {
             Transition::RebootRelaysWhilePartitioned(new_relays) => {
                 state.deploy_new_relays(new_relays)
             }
-            Transition::Idle => {}
+            Transition::Idle | Transition::IdleUntilRekey => {}
             Transition::PartitionRelaysFromPortal => {
                 if state.drop_direct_client_traffic || state.client.port == 3478 {
                     state.client.exec_mut(|client| client.reset_connections());
@@ -523,6 +523,36 @@ impl ReferenceState {
             Transition::DeauthorizeWhileGatewayIsPartitioned(resource) => state
                 .client
                 .exec_mut(|client| client.remove_resource(resource)),
+            Transition::FailGateway(gid) => {
+                let resources_on_gateway = state
+                    .client
+                    .inner()
+                    .all_resource_ids()
+                    .into_iter()
+                    .filter(|r| state.portal.gateway_for_resource(*r) == Some(gid))
+                    .collect::<Vec<_>>();
+
+                let site = state.portal.site_of_gateway(*gid);
+
+                // From now on, the portal will only hand out the remaining Gateways of the site.
+                state.portal.set_gateway_offline(*gid);
+
+                // With a standby Gateway, our connections to these resources are retained.
+                if !state.client.inner().standby_gateways {
+                    state.client.exec_mut(|client| {
+                        for resource in resources_on_gateway {
+                            client.disconnect_resource_from_gateway(resource);
+                        }
+
+                        if let Some(site) = site {
+                            client.set_site_unknown(site);
+                        }
+                    });
+                }
+            }
+            Transition::TogglePostQuantumPsk(_) => {
+                // Whether or not the preshared key gets upgraded has no impact on the data plane.
+            }
         };
 
         state
@@ -708,7 +738,7 @@ impl ReferenceState {
 
                 !route_overlap
             }
-            Transition::Idle => true,
+            Transition::Idle | Transition::IdleUntilRekey => true,
             Transition::PartitionRelaysFromPortal => true,
             Transition::DeauthorizeWhileGatewayIsPartitioned(r) => {
                 let has_resource = state.client.inner().has_resource(*r);
@@ -726,6 +756,22 @@ impl ReferenceState {
                 // Also don't deactivate resources where we have TCP connections as those would get interrupted.
                 has_resource && has_gateway_for_resource && !has_tcp_connection
             }
+            Transition::FailGateway(gid) => {
+                let client = state.client.inner();
+                let has_tcp_connection = client.all_resource_ids().into_iter().any(|r| {
+                    state.portal.gateway_for_resource(r) == Some(gid)
+                        && client.tcp_connection_tuple_to_resource(r).is_some()
+                });
+
+                // The site must keep at least one Gateway, otherwise its resources would become unreachable.
+                // Failing over TCP connections would reset them because the new Gateway doesn't know about them.
+                state
+                    .portal
+                    .gateways_with_online_peer_in_site()
+                    .contains(gid)
+                    && !has_tcp_connection
+            }
+            Transition::TogglePostQuantumPsk(gid) => state.gateways.contains_key(gid),
         }
     }
 
@@ -885,7 +931,35 @@ pub(crate) fn private_key() -> impl Strategy<Value = PrivateKey> {
     any::<[u8; 32]>().prop_map(PrivateKey).no_shrink()
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// Routes packets to the given hosts.
+///
+/// Returns `None` if any of the hosts share an IP.
+pub(crate) fn routing_table(
+    client: &Host<RefClient>,
+    gateways: &BTreeMap<GatewayId, Host<RefGateway>>,
+    relays: &BTreeMap<RelayId, Host<u64>>,
+) -> Option<RoutingTable> {
+    let mut routing_table = RoutingTable::default();
+
+    if !routing_table.add_host(client.inner().id, client) {
+        return None;
+    }
+    for (id, gateway) in gateways {
+        if !routing_table.add_host(*id, gateway) {
+            return None;
+        };
+    }
+
+    for (id, relay) in relays {
+        if !routing_table.add_host(*id, relay) {
+            return None;
+        };
+    }
+
+    Some(routing_table)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct PrivateKey(pub [u8; 32]);
 
 impl From<PrivateKey> for StaticSecret {
@@ -0,0 +1,37 @@
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Selects an element from a collection that is only known once a test is running.
+///
+/// Unlike [`proptest::sample::Selector`], this can be serialised as part of a [`Trace`](super::trace::Trace).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct Selector(u64);
+
+impl Selector {
+    /// Selects an element from `items`.
+    ///
+    /// # Panics
+    ///
+    /// If `items` is empty.
+    pub(crate) fn select<T>(&self, items: impl IntoIterator<Item = T>) -> T {
+        self.try_select(items)
+            .expect("cannot select from an empty collection")
+    }
+
+    /// Selects an element from `items`, returns `None` if `items` is empty.
+    pub(crate) fn try_select<T>(&self, items: impl IntoIterator<Item = T>) -> Option<T> {
+        let items = Vec::from_iter(items);
+        let index = self.0 % u64::try_from(items.len()).ok()?.max(1);
+
+        items.into_iter().nth(usize::try_from(index).ok()?)
+    }
+}
+
+impl Arbitrary for Selector {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<u64>().prop_map(Selector).boxed()
+    }
+}
@@ -20,7 +20,9 @@ use ip_network_table::IpNetworkTable;
 use ip_packet::{Icmpv4Type, Icmpv6Type, IpPacket, Layer4Protocol};
 use itertools::Itertools as _;
 use proptest::prelude::*;
-use snownet::Transmit;
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
+use snownet::{CandidatePair, Transmit};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     mem,
@@ -68,6 +70,9 @@ pub(crate) struct SimClient {
     /// TCP connections to resources.
     pub(crate) tcp_client: crate::tests::tcp::Client,
     pub(crate) failed_tcp_packets: BTreeMap<(SPort, DPort), IpPacket>,
+
+    /// The candidate pair of each connection to a Gateway, as last reported by connlib.
+    pub(crate) connection_paths: BTreeMap<GatewayId, CandidatePair>,
 }
 
 impl SimClient {
@@ -95,6 +100,7 @@ impl SimClient {
             tcp_dns_client,
             tcp_client: crate::tests::tcp::Client::new(now),
             failed_tcp_packets: Default::default(),
+            connection_paths: Default::default(),
         }
     }
 
@@ -387,7 +393,9 @@ impl SimClient {
 ///
 /// The reference state machine is designed to be as abstract as possible over connlib's functionality.
 /// For example, we try to model connectivity to _resources_ and don't really care, which gateway is being used to route us there.
-#[derive(Clone, derive_more::Debug)]
+///
+/// Only the configuration of the client is serialised, everything else is populated by [`Transition`](super::transition::Transition)s.
+#[derive(Clone, derive_more::Debug, Serialize, Deserialize)]
 pub struct RefClient {
     pub(crate) id: ClientId,
     pub(crate) key: PrivateKey,
@@ -403,20 +411,25 @@ pub struct RefClient {
     /// The search-domain configured in the portal.
     pub(crate) search_domain: Option<DomainName>,
 
+    #[serde(skip)]
     ipv4_routes: BTreeMap<ResourceId, Ipv4Network>,
+    #[serde(skip)]
     ipv6_routes: BTreeMap<ResourceId, Ipv6Network>,
 
     /// Tracks all resources in the order they have been added in.
     ///
     /// When reconnecting to the portal, we simulate them being re-added in the same order.
     #[debug(skip)]
+    #[serde(skip)]
     resources: Vec<Resource>,
 
     #[debug(skip)]
+    #[serde(skip)]
     internet_resource: Option<ResourceId>,
 
     /// The CIDR resources the client is aware of.
     #[debug(skip)]
+    #[serde(skip, default = "IpNetworkTable::new")]
     cidr_resources: IpNetworkTable<ResourceId>,
 
     /// The client's DNS records.
@@ -424,43 +437,70 @@ pub struct RefClient {
     /// The IPs assigned to a domain by connlib are an implementation detail that we don't want to model in these tests.
     /// Instead, we just remember what _kind_ of records we resolved to be able to sample a matching src IP.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) dns_records: BTreeMap<DomainName, BTreeSet<RecordType>>,
 
     /// Whether we are connected to the gateway serving the Internet resource.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) connected_internet_resource: bool,
 
     /// The CIDR resources the client is connected to.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) connected_cidr_resources: BTreeSet<ResourceId>,
 
     /// Actively disabled resources by the UI
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) disabled_resources: BTreeSet<ResourceId>,
 
+    /// Whether we keep a standby connection to a second Gateway of each site.
+    pub(crate) standby_gateways: bool,
+
     /// The [`ResourceStatus`] of each site.
     #[debug(skip)]
+    #[serde(skip)]
     site_status: BTreeMap<SiteId, ResourceStatus>,
 
     /// The expected ICMP handshakes.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) expected_icmp_handshakes:
         BTreeMap<GatewayId, BTreeMap<u64, (Destination, Seq, Identifier)>>,
 
     /// The expected UDP handshakes.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) expected_udp_handshakes:
         BTreeMap<GatewayId, BTreeMap<u64, (Destination, SPort, DPort)>>,
 
+    /// The resource each ICMP packet was sent to, by payload.
+    ///
+    /// Forgotten whenever resources change because a reply in flight may then be counted for a different resource.
+    #[debug(skip)]
+    #[serde(skip)]
+    pub(crate) icmp_packet_resources: BTreeMap<u64, ResourceId>,
+
+    /// The resource each UDP packet was sent to, by payload.
+    ///
+    /// Forgotten whenever resources change because a reply in flight may then be counted for a different resource.
+    #[debug(skip)]
+    #[serde(skip)]
+    pub(crate) udp_packet_resources: BTreeMap<u64, ResourceId>,
+
     /// The expected TCP connections.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) expected_tcp_connections: HashMap<(IpAddr, Destination, SPort, DPort), ResourceId>,
 
     /// The expected UDP DNS handshakes.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) expected_udp_dns_handshakes: VecDeque<(SocketAddr, QueryId)>,
     /// The expected TCP DNS handshakes.
     #[debug(skip)]
+    #[serde(skip)]
     pub(crate) expected_tcp_dns_handshakes: VecDeque<(SocketAddr, QueryId)>,
 }
 
@@ -469,7 +509,11 @@ impl RefClient {
     ///
     /// This simulates receiving the `init` message from the portal.
     pub(crate) fn init(self, now: Instant) -> SimClient {
-        let mut client_state = ClientState::new(self.key.0, now); // Cheating a bit here by reusing the key as seed.
+        let mut client_state = ClientState::new(
+            self.key.0,                              // Cheating a bit here by reusing the key as seed.
+            sha2::Sha256::digest(self.key.0).into(), // Must not be the key but needs to be deterministic.
+            now,
+        );
         client_state.update_interface_config(Interface {
             ipv4: self.tunnel_ip4,
             ipv6: self.tunnel_ip6,
@@ -477,6 +521,7 @@ impl RefClient {
             search_domain: self.search_domain.clone(),
         });
         client_state.update_system_resolvers(self.system_dns_resolvers.clone());
+        client_state.set_standby_gateways_enabled(self.standby_gateways);
 
         SimClient::new(self.id, client_state, now)
     }
@@ -486,12 +531,29 @@ impl RefClient {
         self.ipv6_routes.remove(resource);
 
         self.connected_cidr_resources.remove(resource);
+        self.forget_packet_resources();
 
         if self.internet_resource.is_some_and(|r| &r == resource) {
             self.connected_internet_resource = false;
         }
     }
 
+    /// The connection to the Gateway serving this resource failed.
+    pub(crate) fn disconnect_resource_from_gateway(&mut self, resource: ResourceId) {
+        self.connected_cidr_resources.remove(&resource);
+
+        if self.internet_resource.is_some_and(|r| r == resource) {
+            self.connected_internet_resource = false;
+        }
+    }
+
+    /// We are no longer connected to any Gateway of this site.
+    pub(crate) fn set_site_unknown(&mut self, site: SiteId) {
+        if let Some(status) = self.site_status.get_mut(&site) {
+            *status = ResourceStatus::Unknown;
+        }
+    }
+
     pub(crate) fn remove_resource(&mut self, resource: &ResourceId) {
         self.disconnect_resource(resource);
 
@@ -548,6 +610,7 @@ impl RefClient {
     }
 
     pub(crate) fn add_internet_resource(&mut self, r: InternetResource) {
+        self.forget_packet_resources();
         self.internet_resource = Some(r.id);
         self.resources.push(Resource::Internet(r.clone()));
 
@@ -560,6 +623,7 @@ impl RefClient {
     }
 
     pub(crate) fn add_cidr_resource(&mut self, r: CidrResource) {
+        self.forget_packet_resources();
         self.resources.push(Resource::Cidr(r.clone()));
         self.cidr_resources = self.recalculate_cidr_routes();
 
@@ -578,9 +642,15 @@ impl RefClient {
     }
 
     pub(crate) fn add_dns_resource(&mut self, r: DnsResource) {
+        self.forget_packet_resources();
         self.resources.push(Resource::Dns(r));
     }
 
+    pub(crate) fn forget_packet_resources(&mut self) {
+        self.icmp_packet_resources.clear();
+        self.udp_packet_resources.clear();
+    }
+
     /// Re-adds all resources in the order they have been initially added.
     pub(crate) fn readd_all_resources(&mut self) {
         self.cidr_resources = IpNetworkTable::new();
@@ -648,7 +718,7 @@ impl RefClient {
         gateway_by_resource: impl Fn(ResourceId) -> Option<GatewayId>,
         gateway_by_ip: impl Fn(IpAddr) -> Option<GatewayId>,
     ) {
-        self.on_packet(
+        let resource = self.on_packet(
             dst.clone(),
             (dst, seq, identifier),
             |ref_client| &mut ref_client.expected_icmp_handshakes,
@@ -656,6 +726,10 @@ impl RefClient {
             gateway_by_resource,
             gateway_by_ip,
         );
+
+        if let Some(resource) = resource {
+            self.icmp_packet_resources.insert(payload, resource);
+        }
     }
 
     pub(crate) fn on_udp_packet(
@@ -667,7 +741,7 @@ impl RefClient {
         gateway_by_resource: impl Fn(ResourceId) -> Option<GatewayId>,
         gateway_by_ip: impl Fn(IpAddr) -> Option<GatewayId>,
     ) {
-        self.on_packet(
+        let resource = self.on_packet(
             dst.clone(),
             (dst, sport, dport),
             |ref_client| &mut ref_client.expected_udp_handshakes,
@@ -675,8 +749,13 @@ impl RefClient {
             gateway_by_resource,
             gateway_by_ip,
         );
+
+        if let Some(resource) = resource {
+            self.udp_packet_resources.insert(payload, resource);
+        }
     }
 
+    /// Returns the resource the packet is sent to, if any.
     #[tracing::instrument(level = "debug", skip_all, fields(dst, resource, gateway))]
     fn on_packet<E>(
         &mut self,
@@ -686,26 +765,26 @@ impl RefClient {
         payload: u64,
         gateway_by_resource: impl Fn(ResourceId) -> Option<GatewayId>,
         gateway_by_ip: impl Fn(IpAddr) -> Option<GatewayId>,
-    ) {
-        let gateway = if dst.ip_addr().is_some_and(crate::is_peer) {
+    ) -> Option<ResourceId> {
+        let (gateway, resource) = if dst.ip_addr().is_some_and(crate::is_peer) {
             let Some(gateway) = gateway_by_ip(dst.ip_addr().unwrap()) else {
                 tracing::error!("Unknown gateway");
-                return;
+                return None;
             };
             tracing::Span::current().record("gateway", tracing::field::display(gateway));
 
-            gateway
+            (gateway, None)
         } else {
             let Some(resource) = self.resource_by_dst(&dst) else {
                 tracing::warn!("Unknown resource");
-                return;
+                return None;
             };
 
             tracing::Span::current().record("resource", tracing::field::display(resource));
 
             let Some(gateway) = gateway_by_resource(resource) else {
                 tracing::error!("No gateway for resource");
-                return;
+                return None;
             };
 
             tracing::Span::current().record("gateway", tracing::field::display(gateway));
@@ -713,7 +792,7 @@ impl RefClient {
             self.connect_to_resource(resource, dst);
             self.set_resource_online(resource);
 
-            gateway
+            (gateway, Some(resource))
         };
 
         tracing::debug!(%payload, "Sending packet");
@@ -722,6 +801,8 @@ impl RefClient {
             .entry(gateway)
             .or_default()
             .insert(payload, packet_id);
+
+        resource
     }
 
     pub(crate) fn on_connect_tcp(
@@ -1169,6 +1250,7 @@ fn ref_client(
         search_domain,
         client_id(),
         private_key(),
+        any::<bool>(),
     )
         .prop_map(
             move |(
@@ -1179,6 +1261,7 @@ fn ref_client(
                 search_domain,
                 id,
                 key,
+                standby_gateways,
             )| {
                 RefClient {
                     id,
@@ -1195,6 +1278,8 @@ fn ref_client(
                     connected_internet_resource: Default::default(),
                     expected_icmp_handshakes: Default::default(),
                     expected_udp_handshakes: Default::default(),
+                    icmp_packet_resources: Default::default(),
+                    udp_packet_resources: Default::default(),
                     expected_tcp_connections: Default::default(),
                     expected_udp_dns_handshakes: Default::default(),
                     expected_tcp_dns_handshakes: Default::default(),
@@ -1203,6 +1288,7 @@ fn ref_client(
                     ipv4_routes: Default::default(),
                     ipv6_routes: Default::default(),
                     site_status: Default::default(),
+                    standby_gateways,
                 }
             },
         )
@@ -13,6 +13,8 @@ use chrono::{DateTime, Utc};
 use connlib_model::{GatewayId, RelayId};
 use ip_packet::{IcmpEchoHeader, Icmpv4Type, Icmpv6Type, IpPacket};
 use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
 use snownet::Transmit;
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -37,19 +39,29 @@ pub(crate) struct SimGateway {
     tcp_dns_server_resources: BTreeMap<SocketAddr, TcpDnsServerResource>,
 
     tcp_resources: BTreeMap<SocketAddr, crate::tests::tcp::Server>,
+
+    /// Whether we negotiate post-quantum preshared keys with clients.
+    pub(crate) post_quantum_psk: bool,
+    /// Whether [`SimGateway::post_quantum_psk`] was toggled since we started, i.e. connections may have been negotiated with either setting.
+    pub(crate) post_quantum_psk_toggled: bool,
 }
 
 impl SimGateway {
     pub(crate) fn new(
         id: GatewayId,
-        sut: GatewayState,
+        mut sut: GatewayState,
         tcp_resources: BTreeSet<SocketAddr>,
         site_specific_dns_records: DnsRecords,
+        post_quantum_psk: bool,
         now: Instant,
     ) -> Self {
+        sut.set_post_quantum_psk_enabled(post_quantum_psk);
+
         Self {
             id,
             sut,
+            post_quantum_psk,
+            post_quantum_psk_toggled: false,
             site_specific_dns_records,
             received_icmp_requests: Default::default(),
             udp_dns_server_resources: Default::default(),
@@ -90,6 +102,13 @@ impl SimGateway {
         self.on_received_packet(packet, icmp_error_hosts, now)
     }
 
+    pub(crate) fn toggle_post_quantum_psk(&mut self) {
+        self.post_quantum_psk = !self.post_quantum_psk;
+        self.post_quantum_psk_toggled = true;
+
+        self.sut.set_post_quantum_psk_enabled(self.post_quantum_psk);
+    }
+
     pub(crate) fn advance_resources(
         &mut self,
         global_dns_records: &DnsRecords,
@@ -290,12 +309,15 @@ impl SimGateway {
 }
 
 /// Reference state for a particular gateway.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RefGateway {
     pub(crate) key: PrivateKey,
     pub(crate) tunnel_ip4: Ipv4Addr,
     pub(crate) tunnel_ip6: Ipv6Addr,
 
+    /// Whether the gateway negotiates post-quantum preshared keys with clients.
+    post_quantum_psk: bool,
+
     site_specific_dns_records: DnsRecords,
 }
 
@@ -309,13 +331,24 @@ impl RefGateway {
         tcp_resources: BTreeSet<SocketAddr>,
         now: Instant,
     ) -> SimGateway {
-        let mut sut = GatewayState::new(self.key.0, now); // Cheating a bit here by reusing the key as seed.
+        let mut sut = GatewayState::new(
+            self.key.0,                              // Cheating a bit here by reusing the key as seed.
+            sha2::Sha256::digest(self.key.0).into(), // Must not be the key but needs to be deterministic.
+            now,
+        );
         sut.update_tun_device(IpConfig {
             v4: self.tunnel_ip4,
             v6: self.tunnel_ip6,
         });
 
-        SimGateway::new(id, sut, tcp_resources, self.site_specific_dns_records, now)
+        SimGateway::new(
+            id,
+            sut,
+            tcp_resources,
+            self.site_specific_dns_records,
+            self.post_quantum_psk,
+            now,
+        )
     }
 
     pub fn dns_records(&self) -> &DnsRecords {
@@ -345,14 +378,18 @@ fn ref_gateway(
         private_key(),
         tunnel_ip4s,
         tunnel_ip6s,
+        any::<bool>(),
         site_specific_dns_records,
     )
         .prop_map(
-            move |(key, tunnel_ip4, tunnel_ip6, site_specific_dns_records)| RefGateway {
-                key,
-                tunnel_ip4,
-                tunnel_ip6,
-                site_specific_dns_records,
+            move |(key, tunnel_ip4, tunnel_ip6, post_quantum_psk, site_specific_dns_records)| {
+                RefGateway {
+                    key,
+                    tunnel_ip4,
+                    tunnel_ip6,
+                    post_quantum_psk,
+                    site_specific_dns_records,
+                }
             },
         )
 }
@@ -5,6 +5,7 @@ use firezone_relay::{AddressFamily, IpStack};
 use ip_network::IpNetwork;
 use ip_network_table::IpNetworkTable;
 use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
 use snownet::Transmit;
 use std::{
     collections::HashSet,
@@ -18,7 +19,7 @@ use super::sim_client::SimClient;
 use super::sim_gateway::SimGateway;
 use super::sim_relay::SimRelay;
 
-#[derive(Clone, derive_more::Debug)]
+#[derive(Clone, derive_more::Debug, Serialize, Deserialize)]
 pub(crate) struct Host<T> {
     inner: T,
 
@@ -27,18 +28,21 @@ pub(crate) struct Host<T> {
     pub(crate) port: u16,
 
     #[debug(skip)]
+    #[serde(skip)]
     allocated_ports: HashSet<(u16, AddressFamily)>,
 
     // The latency of incoming and outgoing packets.
     latency: Duration,
 
     #[debug(skip)]
+    #[serde(skip, default = "Span::none")]
     span: Span,
 
     /// Messages that have "arrived" and are waiting to be dispatched.
     ///
     /// We buffer them here because we need also apply our latency on inbound packets.
     #[debug(skip)]
+    #[serde(skip)]
     inbox: BufferedTransmits,
 }
 
@@ -8,7 +8,7 @@ use firezone_relay::{AddressFamily, AllocationPort, ClientSocket, IpStack, PeerS
 use proptest::prelude::*;
 use rand::{SeedableRng as _, rngs::StdRng};
 use secrecy::SecretString;
-use snownet::{RelaySocket, Transmit};
+use snownet::{RelaySocket, Transmit, Transport};
 use std::{
     collections::HashSet,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
@@ -104,6 +104,7 @@ impl SimRelay {
             payload,
             PeerSocket::new(sender),
             AllocationPort::new(dst.port()),
+            now,
         )
     }
 
@@ -151,6 +152,7 @@ impl SimRelay {
             src: Some(src),
             dst,
             payload,
+            transport: Transport::Udp,
         })
     }
 
@@ -159,8 +161,9 @@ impl SimRelay {
         mut payload: Buffer<Vec<u8>>,
         peer: PeerSocket,
         port: AllocationPort,
+        now: Instant,
     ) -> Option<Transmit> {
-        let (client, channel) = self.sut.handle_peer_traffic(&payload, peer, port)?;
+        let (client, channel) = self.sut.handle_peer_traffic(&payload, peer, port, now)?;
 
         let data_len = payload.len() as u16;
         let header = payload.shift_start_left(4);
@@ -176,6 +179,7 @@ impl SimRelay {
             src: Some(sending_socket),
             dst: receiving_socket,
             payload,
+            transport: Transport::Udp,
         })
     }
 
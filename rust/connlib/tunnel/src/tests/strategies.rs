@@ -1,6 +1,8 @@
 use super::dns_records::DnsRecords;
 use super::icmp_error_hosts::IcmpErrorHosts;
-use super::{sim_net::Host, sim_relay::ref_relay_host, stub_portal::StubPortal};
+use super::{
+    selector::Selector, sim_net::Host, sim_relay::ref_relay_host, stub_portal::StubPortal,
+};
 use crate::client::{
     CidrResource, DNS_SENTINELS_V4, DNS_SENTINELS_V6, DnsResource, IPV4_RESOURCES, IPV6_RESOURCES,
     InternetResource,
@@ -121,7 +123,7 @@ pub(crate) fn stub_portal() -> impl Strategy<Value = StubPortal> {
                 .collect::<Vec<_>>()
                 .prop_map(BTreeMap::from_iter);
 
-            let gateway_selector = any::<sample::Selector>();
+            let gateway_selector = any::<Selector>();
 
             (
                 gateways_by_site,
@@ -1,5 +1,6 @@
 use super::{
     dns_records::DnsRecords,
+    selector::Selector,
     sim_client::{RefClient, ref_client_host},
     sim_gateway::{RefGateway, ref_gateway_host},
     sim_net::Host,
@@ -15,10 +16,10 @@ use connlib_model::{ResourceId, SiteId};
 use dns_types::DomainName;
 use itertools::Itertools;
 use proptest::{
-    collection,
-    sample::{self, Selector},
+    collection, sample,
     strategy::{Just, Strategy},
 };
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, BTreeSet},
     iter,
@@ -26,12 +27,14 @@ use std::{
 };
 
 /// Stub implementation of the portal.
-#[derive(Clone, derive_more::Debug)]
+#[derive(Clone, derive_more::Debug, Serialize, Deserialize)]
 pub(crate) struct StubPortal {
     client_tunnel_ipv4: Ipv4Addr,
     client_tunnel_ipv6: Ipv6Addr,
 
     gateways_by_site: BTreeMap<SiteId, BTreeSet<(GatewayId, Ipv4Addr, Ipv6Addr)>>,
+    /// Gateways that went offline and will never be selected again.
+    offline_gateways: BTreeSet<GatewayId>,
 
     #[debug(skip)]
     sites_by_resource: BTreeMap<ResourceId, SiteId>,
@@ -119,6 +122,7 @@ impl StubPortal {
             client_tunnel_ipv4,
             client_tunnel_ipv6,
             gateways_by_site,
+            offline_gateways: BTreeSet::default(),
             gateway_selector,
             sites_by_resource: BTreeMap::from_iter(
                 cidr_sites.chain(dns_sites).chain(internet_site),
@@ -147,20 +151,60 @@ impl StubPortal {
     }
 
     /// Picks, which gateway and site we should connect to for the given resource.
+    ///
+    /// Returns `None` if all gateways of the site are either offline or excluded.
     pub(crate) fn handle_connection_intent(
         &self,
         resource: ResourceId,
         _connected_gateway_ids: BTreeSet<GatewayId>,
-    ) -> (GatewayId, SiteId) {
+        excluded_gateway_ids: BTreeSet<GatewayId>,
+    ) -> Option<(GatewayId, SiteId)> {
         let site_id = self
             .sites_by_resource
             .get(&resource)
             .expect("resource to be known");
 
-        let gateways = self.gateways_by_site.get(site_id).unwrap();
-        let (gateway, _, _) = self.gateway_selector.select(gateways);
+        let gateway = self.select_gateway(*site_id, &excluded_gateway_ids)?;
 
-        (*gateway, *site_id)
+        Some((*gateway, *site_id))
+    }
+
+    /// Selects the preferred gateway of a site, falling back to the remaining ones in a stable order.
+    ///
+    /// Using a stable order ensures the standby gateway we hand out is the same one that we pick once the primary gateway goes offline.
+    fn select_gateway(&self, site: SiteId, excluded: &BTreeSet<GatewayId>) -> Option<&GatewayId> {
+        let gateways = self.gateways_by_site.get(&site)?;
+        let (preferred, _, _) = self.gateway_selector.try_select(gateways)?;
+
+        iter::once(preferred)
+            .chain(gateways.iter().map(|(gid, _, _)| gid))
+            .find(|gid| !self.offline_gateways.contains(*gid) && !excluded.contains(*gid))
+    }
+
+    pub(crate) fn site_of_gateway(&self, gid: GatewayId) -> Option<SiteId> {
+        self.gateways_by_site.iter().find_map(|(site, gateways)| {
+            gateways.iter().any(|(g, _, _)| *g == gid).then_some(*site)
+        })
+    }
+
+    pub(crate) fn set_gateway_offline(&mut self, gid: GatewayId) {
+        self.offline_gateways.insert(gid);
+    }
+
+    /// All online gateways that share their site with at least one other online gateway.
+    pub(crate) fn gateways_with_online_peer_in_site(&self) -> Vec<GatewayId> {
+        self.gateways_by_site
+            .values()
+            .map(|gateways| {
+                gateways
+                    .iter()
+                    .map(|(gid, _, _)| *gid)
+                    .filter(|gid| !self.offline_gateways.contains(gid))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|online| online.len() > 1)
+            .flatten()
+            .collect()
     }
 
     pub(crate) fn map_client_resource_to_gateway_resource(
@@ -174,6 +218,7 @@ impl StubPortal {
                     address: r.address,
                     name: r.name.clone(),
                     filters: Vec::new(),
+                    rate_limit: None,
                 },
             ))
         });
@@ -183,11 +228,14 @@ impl StubPortal {
                 name: r.name.clone(),
                 filters: Vec::new(),
                 address: r.address.clone(),
+                rate_limit: None,
+                resolver: None,
             })
         });
         let internet_resource = Some(gateway::ResourceDescription::Internet(
             gateway::ResourceDescriptionInternet {
                 id: self.internet_resource.id,
+                rate_limit: None,
             },
         ));
 
@@ -213,10 +261,8 @@ impl StubPortal {
             .flatten();
 
         let sid = cidr_site.or(dns_site).or(internet_site)?;
-        let gateways = self.gateways_by_site.get(&sid)?;
-        let (gid, _, _) = self.gateway_selector.try_select(gateways)?;
 
-        Some(gid)
+        self.select_gateway(sid, &BTreeSet::new())
     }
 
     pub(crate) fn gateway_by_ip(&self, ip: IpAddr) -> Option<GatewayId> {
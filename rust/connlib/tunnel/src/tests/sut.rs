@@ -13,6 +13,7 @@ use crate::dns::is_subdomain;
 use crate::messages::{IceCredentials, Key, SecretKey};
 use crate::tests::assertions::*;
 use crate::tests::flux_capacitor::FluxCapacitor;
+use crate::tests::trace::{Delivery, Recorder};
 use crate::tests::transition::Transition;
 use crate::{ClientEvent, GatewayEvent, dns, messages::Interface};
 use bufferpool::BufferPool;
@@ -22,7 +23,7 @@ use dns_types::prelude::*;
 use rand::SeedableRng;
 use rand::distributions::DistString;
 use sha2::Digest;
-use snownet::{NoTurnServers, Transmit};
+use snownet::{NoTurnServers, Transmit, Transport};
 use std::collections::BTreeSet;
 use std::iter;
 use std::{
@@ -37,6 +38,7 @@ use tracing::debug_span;
 /// [`proptest`] manipulates this using [`Transition`]s and we assert it against [`ReferenceState`].
 pub(crate) struct TunnelTest {
     flux_capacitor: FluxCapacitor,
+    recorder: Recorder,
 
     client: Host<SimClient>,
     gateways: BTreeMap<GatewayId, Host<SimGateway>>,
@@ -45,12 +47,18 @@ pub(crate) struct TunnelTest {
     buffer_pool: BufferPool<Vec<u8>>,
 
     drop_direct_client_traffic: bool,
+    /// Gateways that can no longer send or receive any packets.
+    offline_gateways: BTreeSet<GatewayId>,
     network: RoutingTable,
 }
 
 impl TunnelTest {
     // Initialize the system under test from our reference state.
-    pub(crate) fn init_test(ref_state: &ReferenceState, flux_capacitor: FluxCapacitor) -> Self {
+    pub(crate) fn init_test(
+        ref_state: &ReferenceState,
+        flux_capacitor: FluxCapacitor,
+        recorder: Recorder,
+    ) -> Self {
         // Construct client, gateway and relay from the initial state.
         let mut client = ref_state.client.map(
             |ref_client, _, _| ref_client.init(flux_capacitor.now()),
@@ -100,8 +108,10 @@ impl TunnelTest {
 
         let mut this = Self {
             flux_capacitor: flux_capacitor.clone(),
+            recorder,
             network: ref_state.network.clone(),
             drop_direct_client_traffic: ref_state.drop_direct_client_traffic,
+            offline_gateways: BTreeSet::default(),
             client,
             gateways,
             relays,
@@ -296,7 +306,17 @@ impl TunnelTest {
                 let cut_off = state.flux_capacitor.now::<Instant>() + IDLE_DURATION;
 
                 while state.flux_capacitor.now::<Instant>() <= cut_off {
-                    state.flux_capacitor.tick(Duration::from_secs(5));
+                    state.tick(Duration::from_secs(5));
+                    state.advance(ref_state, &mut buffered_transmits);
+                }
+            }
+            Transition::IdleUntilRekey => {
+                // WireGuard rekeys a session on the next packet after `REKEY_AFTER_TIME` (2 minutes) and rejects it after `REJECT_AFTER_TIME` (3 minutes).
+                const IDLE_DURATION: Duration = Duration::from_secs(150);
+                let cut_off = state.flux_capacitor.now::<Instant>() + IDLE_DURATION;
+
+                while state.flux_capacitor.now::<Instant>() <= cut_off {
+                    state.tick(Duration::from_secs(5));
                     state.advance(ref_state, &mut buffered_transmits);
                 }
             }
@@ -357,6 +377,25 @@ impl TunnelTest {
                     tracing::error!(%rid, "No gateway for resource");
                 }
             }
+            Transition::FailGateway(gid) => {
+                const ICE_TIMEOUT: Duration = Duration::from_secs(3 * 60); // Idle connections only send a STUN binding every 25s.
+
+                state.offline_gateways.insert(gid);
+
+                let cut_off = state.flux_capacitor.now::<Instant>() + ICE_TIMEOUT;
+
+                while state.flux_capacitor.now::<Instant>() <= cut_off {
+                    state.tick(Duration::from_secs(5));
+                    state.advance(ref_state, &mut buffered_transmits);
+                }
+            }
+            Transition::TogglePostQuantumPsk(gid) => {
+                if let Some(gateway) = state.gateways.get_mut(&gid) {
+                    gateway.exec_mut(|g| g.toggle_post_quantum_psk());
+                } else {
+                    tracing::error!(%gid, "Unknown gateway");
+                }
+            }
         };
         state.advance(ref_state, &mut buffered_transmits);
 
@@ -393,6 +432,10 @@ impl TunnelTest {
         assert_search_domain_is_valid(ref_client, sim_client);
         assert_routes_are_valid(ref_client, sim_client);
         assert_resource_status(ref_client, sim_client);
+        assert_resource_traffic(ref_client, sim_client);
+        assert_connection_stats(sim_client, &sim_gateways);
+        assert_connection_paths(sim_client);
+        assert_post_quantum_psk(sim_client, &sim_gateways);
     }
 }
 
@@ -467,12 +510,15 @@ impl TunnelTest {
                 let response =
                     self.on_recursive_dns_query(&query.message, &ref_state.global_dns_records);
                 self.client.exec_mut(|c| {
-                    c.sut.handle_dns_response(dns::RecursiveResponse {
-                        server,
-                        query: query.message,
-                        message: Ok(response), // TODO: Vary this?
-                        transport,
-                    })
+                    c.sut.handle_dns_response(
+                        dns::RecursiveResponse {
+                            server,
+                            query: query.message,
+                            message: Ok(response), // TODO: Vary this?
+                            transport,
+                        },
+                        now,
+                    )
                 });
 
                 continue;
@@ -495,6 +541,7 @@ impl TunnelTest {
                                 src: Some(src),
                                 dst,
                                 payload: self.buffer_pool.pull_initialised(&payload),
+                                transport: Transport::Udp,
                             },
                             relay,
                             now,
@@ -509,6 +556,7 @@ impl TunnelTest {
                         relay.deallocate_port(port.value(), family);
                         relay.exec_mut(|r| r.allocations.remove(&(family, port)));
                     }
+                    firezone_relay::Command::SendMessageFrom { .. } => {} // Our simulated relays don't enable NAT behaviour discovery.
                     firezone_relay::Command::CreateChannelBinding { .. } => {}
                     firezone_relay::Command::DeleteChannelBinding { .. } => {}
                 }
@@ -542,7 +590,7 @@ impl TunnelTest {
             }
 
             if !buffered_transmits.is_empty() {
-                self.flux_capacitor.small_tick(); // Small tick to get to the next transmit.
+                self.tick(FluxCapacitor::SMALL_TICK); // Small tick to get to the next transmit.
                 continue;
             }
 
@@ -554,7 +602,7 @@ impl TunnelTest {
                 break; // Nothing to do before cut-off.
             }
 
-            self.flux_capacitor.large_tick(); // Large tick to more quickly advance to potential next timeout.
+            self.tick(FluxCapacitor::LARGE_TICK); // Large tick to more quickly advance to potential next timeout.
         }
 
         for (transmit, at) in buffered_transmits.drain() {
@@ -640,6 +688,11 @@ impl TunnelTest {
         }
     }
 
+    fn tick(&mut self, tick: Duration) {
+        self.flux_capacitor.tick(tick);
+        self.recorder.record_tick(tick);
+    }
+
     fn poll_timeout(&mut self) -> Option<(Instant, &'static str)> {
         iter::empty()
             .chain(self.client.poll_timeout())
@@ -660,11 +713,29 @@ impl TunnelTest {
             .expect("`src` should always be set in these tests");
         let dst = transmit.dst;
 
+        self.recorder.record_delivery(Delivery {
+            at: self.flux_capacitor.since_start(at),
+            src,
+            dst,
+            num_bytes: transmit.payload.len(),
+        });
+
         let Some(host) = self.network.host_by_ip(dst.ip()) else {
             tracing::error!("Unhandled packet: {src} -> {dst}");
             return;
         };
 
+        if self
+            .gateways
+            .iter()
+            .filter(|(id, _)| self.offline_gateways.contains(id))
+            .any(|(id, g)| g.is_sender(src.ip()) || host == HostId::Gateway(*id))
+        {
+            tracing::trace!(%src, %dst, "Dropping traffic of offline gateway");
+
+            return;
+        }
+
         match host {
             HostId::Client(_) => {
                 if self.drop_direct_client_traffic
@@ -741,9 +812,18 @@ impl TunnelTest {
             ClientEvent::ConnectionIntent {
                 resource: resource_id,
                 connected_gateway_ids,
+                excluded_gateway_ids,
             } => {
-                let (gateway_id, site_id) =
-                    portal.handle_connection_intent(resource_id, connected_gateway_ids);
+                let Some((gateway_id, site_id)) = portal.handle_connection_intent(
+                    resource_id,
+                    connected_gateway_ids,
+                    excluded_gateway_ids,
+                ) else {
+                    self.client
+                        .exec_mut(|c| c.sut.set_resource_offline(resource_id));
+
+                    return Ok(());
+                };
                 let gateway = self.gateways.get_mut(&gateway_id).expect("unknown gateway");
                 let resource = portal.map_client_resource_to_gateway_resource(resource_id);
 
@@ -801,6 +881,33 @@ impl TunnelTest {
 
                 Ok(())
             }
+            ClientEvent::ResourceTrafficUpdated { traffic } => {
+                let current = self
+                    .client
+                    .inner()
+                    .sut
+                    .resources()
+                    .into_iter()
+                    .map(|r| (r.id(), r.traffic()))
+                    .collect::<BTreeMap<_, _>>();
+
+                // Counters only ever go up, so a report can't be ahead of the current ones.
+                for (resource, reported) in traffic {
+                    let Some(current) = current.get(&resource) else {
+                        continue;
+                    };
+
+                    if reported.bytes_sent > current.bytes_sent
+                        || reported.bytes_received > current.bytes_received
+                        || reported.packets_sent > current.packets_sent
+                        || reported.packets_received > current.packets_received
+                    {
+                        tracing::error!(%resource, ?reported, ?current, "Reported traffic is ahead of the current counters");
+                    }
+                }
+
+                Ok(())
+            }
             ClientEvent::TunInterfaceUpdated(config) => {
                 if self.client.inner().dns_mapping() == &config.dns_by_sentinel
                     && self.client.inner().ipv4_routes == config.ipv4_routes
@@ -828,6 +935,22 @@ impl TunnelTest {
                     c.search_domain = config.search_domain
                 });
 
+                Ok(())
+            }
+            ClientEvent::ConnectionPathChanged { conn_id, old, new } => {
+                self.client.exec_mut(|c| {
+                    let reported = c.connection_paths.insert(conn_id, new);
+
+                    // A new connection starts without a path, regardless of what we reported for the previous one.
+                    if old.is_some() && old != reported {
+                        tracing::error!(target: "assertions", %conn_id, ?old, ?reported, "❌ Old path does not match the previously reported one");
+                    }
+
+                    if old == Some(new) {
+                        tracing::error!(target: "assertions", %conn_id, %new, "❌ Reported a path change without a change");
+                    }
+                });
+
                 Ok(())
             }
         }
@@ -967,5 +1090,7 @@ fn on_gateway_event(
                     .unwrap()
             })
         }
+        GatewayEvent::FlowCompleted(_) => {}
+        GatewayEvent::ConnectionPathChanged { .. } => {}
     }
 }
@@ -0,0 +1,171 @@
+//! Recorded test cases that can be replayed without proptest.
+//!
+//! Every test case of [`tunnel_test`](super::tunnel_test) is written to `testcases/<index>.json`.
+//! To turn one into a regression test, copy it into the `traces/` directory of this crate.
+
+use std::{
+    fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    reference::{ReferenceState, routing_table},
+    transition::Transition,
+};
+
+/// A test case for [`TunnelTest`](super::sut::TunnelTest).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Trace {
+    /// The state before the first transition.
+    pub(crate) initial_state: ReferenceState,
+    pub(crate) transitions: Vec<Transition>,
+    /// What happened while the transitions were applied.
+    ///
+    /// A replay fails if it doesn't reproduce this exactly.
+    /// `None` for hand-written traces.
+    #[serde(default)]
+    pub(crate) recording: Option<Recording>,
+}
+
+impl Trace {
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+        Self::from_json(&content).with_context(|| format!("Failed to parse `{}`", path.display()))
+    }
+
+    pub(crate) fn from_json(json: &str) -> Result<Self> {
+        let mut trace = serde_json::from_str::<Self>(json)?;
+
+        let state = &mut trace.initial_state;
+        state.network = routing_table(&state.client, &state.gateways, &state.relays)
+            .context("Network IPs must be unique")?;
+
+        Ok(trace)
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?)
+            .with_context(|| format!("Failed to write `{}`", path.display()))?;
+
+        Ok(())
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Time advancing and packets being delivered between hosts while a test case runs.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Recording {
+    pub(crate) ticks: Vec<Tick>,
+    pub(crate) deliveries: Vec<Delivery>,
+}
+
+/// The [`FluxCapacitor`](super::flux_capacitor::FluxCapacitor) advanced by `duration`, `count` times in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Tick {
+    pub(crate) duration: Duration,
+    pub(crate) count: u32,
+}
+
+/// A packet was handed to the host owning `dst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Delivery {
+    /// Since the start of the test case.
+    pub(crate) at: Duration,
+    pub(crate) src: SocketAddr,
+    pub(crate) dst: SocketAddr,
+    pub(crate) num_bytes: usize,
+}
+
+/// Records a [`Recording`] while a test case runs.
+///
+/// Clones share the same [`Recording`] so it survives the test case panicking.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Recorder {
+    inner: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    pub(crate) fn record_tick(&self, duration: Duration) {
+        let mut recording = self.inner.lock().unwrap();
+
+        match recording.ticks.last_mut() {
+            Some(last) if last.duration == duration => last.count += 1,
+            Some(_) | None => recording.ticks.push(Tick { duration, count: 1 }),
+        }
+    }
+
+    pub(crate) fn record_delivery(&self, delivery: Delivery) {
+        self.inner.lock().unwrap().deliveries.push(delivery);
+    }
+
+    pub(crate) fn recording(&self) -> Recording {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+/// Panics if `actual` diverges from `expected`, pointing at the first difference.
+pub(crate) fn assert_same_recording(expected: &Recording, actual: &Recording) {
+    if let Some((ix, (expected, actual))) = expected
+        .ticks
+        .iter()
+        .zip(&actual.ticks)
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!("Replay diverged at tick {ix}: expected {expected:?} but got {actual:?}");
+    }
+
+    if let Some((ix, (expected, actual))) = expected
+        .deliveries
+        .iter()
+        .zip(&actual.deliveries)
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!("Replay diverged at delivery {ix}: expected {expected:?} but got {actual:?}");
+    }
+
+    assert_eq!(
+        expected.ticks.len(),
+        actual.ticks.len(),
+        "Replay advanced time a different number of times"
+    );
+    assert_eq!(
+        expected.deliveries.len(),
+        actual.deliveries.len(),
+        "Replay delivered a different number of packets"
+    );
+}
+
+/// Writes a [`Trace`] of a test case once dropped, even if the test case panicked.
+pub(crate) struct WriteOnDrop<'a> {
+    pub(crate) path: &'a Path,
+    pub(crate) initial_state: &'a ReferenceState,
+    pub(crate) transitions: &'a [Transition],
+    pub(crate) recorder: Recorder,
+}
+
+impl Drop for WriteOnDrop<'_> {
+    fn drop(&mut self) {
+        let trace = Trace {
+            initial_state: self.initial_state.clone(),
+            transitions: self.transitions.to_vec(),
+            recording: Some(self.recorder.recording()),
+        };
+
+        if let Err(e) = trace.write(self.path) {
+            tracing::warn!("Failed to write trace: {e:#}");
+        }
+    }
+}
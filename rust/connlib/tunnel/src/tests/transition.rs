@@ -2,13 +2,15 @@ use crate::{
     client::{IPV4_RESOURCES, IPV6_RESOURCES, Resource},
     proptest::{host_v4, host_v6},
 };
-use connlib_model::{RelayId, ResourceId};
+use connlib_model::{GatewayId, RelayId, ResourceId};
 use dns_types::{DomainName, RecordType};
 
+use super::selector::Selector;
 use super::sim_net::{Host, any_ip_stack};
 use crate::messages::DnsServer;
 use prop::collection;
 use proptest::{prelude::*, sample};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, BTreeSet},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
@@ -16,7 +18,7 @@ use std::{
 };
 
 /// The possible transitions of the state machine.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) enum Transition {
     /// Activate a resource on the client.
     ActivateResource(Resource),
@@ -79,6 +81,11 @@ pub(crate) enum Transition {
     /// Idle connlib for a while.
     Idle,
 
+    /// Idle connlib until its WireGuard sessions are due for a rekey but haven't expired yet.
+    ///
+    /// The next packet on each connection performs a new handshake which must use the same preshared key as the current session.
+    IdleUntilRekey,
+
     /// Simulate all relays rebooting while we are network partitioned from the portal.
     ///
     /// In this case, we won't receive a `relays_presence` but instead we will receive relays with the same ID yet different credentials.
@@ -86,9 +93,20 @@ pub(crate) enum Transition {
 
     /// De-authorize access to a resource whilst the Gateway is network-partitioned from the portal.
     DeauthorizeWhileGatewayIsPartitioned(ResourceId),
+
+    /// Take a Gateway offline for good.
+    ///
+    /// If enabled, the client fails over to its standby Gateway in the same site.
+    /// Otherwise, the connection fails and the next packet connects us to another Gateway.
+    FailGateway(GatewayId),
+
+    /// Toggle whether a Gateway negotiates post-quantum preshared keys, i.e. simulate a Gateway being up- or downgraded.
+    ///
+    /// Existing connections keep their preshared key.
+    TogglePostQuantumPsk(GatewayId),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct DnsQuery {
     pub(crate) domain: DomainName,
     /// The type of DNS query we should send.
@@ -99,30 +117,30 @@ pub(crate) struct DnsQuery {
     pub(crate) transport: DnsTransport,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum DnsTransport {
     Udp,
     Tcp,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub(crate) struct Seq(pub u16);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub(crate) struct Identifier(pub u16);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub(crate) struct SPort(pub u16);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub(crate) struct DPort(pub u16);
 
-#[derive(Clone, derive_more::Debug)]
+#[derive(Clone, derive_more::Debug, Serialize, Deserialize)]
 #[expect(clippy::large_enum_variant)]
 pub(crate) enum Destination {
     DomainName {
         #[debug(skip)]
-        resolved_ip: sample::Selector,
+        resolved_ip: Selector,
         name: DomainName,
     },
     IpAddr(IpAddr),
@@ -208,7 +226,7 @@ impl From<IpAddr> for PacketDestination {
 }
 
 impl PacketDestination {
-    fn into_destination(self, resolved_ip: sample::Selector) -> Destination {
+    fn into_destination(self, resolved_ip: Selector) -> Destination {
         match self {
             PacketDestination::DomainName(name) => Destination::DomainName { resolved_ip, name },
             PacketDestination::IpAddr(addr) => Destination::IpAddr(addr),
@@ -230,7 +248,7 @@ where
         dst.prop_map(Into::into),
         any::<u16>(),
         any::<u16>(),
-        any::<sample::Selector>(),
+        any::<Selector>(),
         any::<u64>(),
     )
         .prop_map(|(src, dst, seq, identifier, resolved_ip, payload)| {
@@ -258,7 +276,7 @@ where
         dst.prop_map(Into::into),
         any::<u16>(),
         non_dns_ports(),
-        any::<sample::Selector>(),
+        any::<Selector>(),
         any::<u64>(),
     )
         .prop_map(
@@ -284,7 +302,7 @@ where
         dst,
         any::<NonZeroU16>().prop_map(|p| p.get()),
         non_dns_ports().prop_filter("avoid zero port", |p| *p != 0),
-        any::<sample::Selector>(),
+        any::<Selector>(),
     )
         .prop_map(
             |(src, name, sport, dport, resolved_ip)| Transition::ConnectTcp {
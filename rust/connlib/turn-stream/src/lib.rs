@@ -0,0 +1,148 @@
+//! Framing of STUN and channel-data messages on TURN streams, see <https://www.rfc-editor.org/rfc/rfc8656#section-3.1>.
+//!
+//! Over UDP, every datagram carries exactly one STUN or channel-data message.
+//! Over a stream, the receiver needs to delimit the messages itself:
+//! STUN messages carry their length in the header and channel-data messages are padded to a multiple of 4 bytes, see <https://www.rfc-editor.org/rfc/rfc8656#section-12.5>.
+
+use std::io;
+
+use bytes::{Buf as _, BytesMut};
+
+const STUN_HEADER_LEN: usize = 20;
+const CHANNEL_DATA_HEADER_LEN: usize = 4;
+
+/// Splits a TURN stream into individual STUN and channel-data messages.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    buffer: BytesMut,
+}
+
+impl StreamDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete message with any padding removed.
+    ///
+    /// An error means the stream is corrupted and should be closed.
+    pub fn next_message(&mut self) -> io::Result<Option<BytesMut>> {
+        let Some((message_len, padded_len)) = message_len(&self.buffer)? else {
+            return Ok(None);
+        };
+
+        if self.buffer.len() < padded_len {
+            return Ok(None);
+        }
+
+        let message = self.buffer.split_to(message_len);
+        self.buffer.advance(padded_len - message_len);
+
+        Ok(Some(message))
+    }
+}
+
+/// Appends the given message to `dst` in the framing required for stream transports.
+pub fn encode_stream_message(message: &[u8], dst: &mut BytesMut) {
+    let padding = match message.first() {
+        Some(64..=79) => message.len().next_multiple_of(4) - message.len(),
+        _ => 0, // STUN messages are always a multiple of 4 bytes.
+    };
+
+    dst.reserve(message.len() + padding);
+    dst.extend_from_slice(message);
+    dst.resize(dst.len() + padding, 0);
+}
+
+/// Computes the length of the message at the front of `buffer`, without and with padding.
+fn message_len(buffer: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let Some(length_field) = buffer.get(2..4) else {
+        return Ok(None);
+    };
+    let length = u16::from_be_bytes([length_field[0], length_field[1]]) as usize;
+
+    match buffer[0] {
+        // STUN method range
+        0..=3 => Ok(Some((STUN_HEADER_LEN + length, STUN_HEADER_LEN + length))),
+        // Channel data number range
+        64..=79 => {
+            let message_len = CHANNEL_DATA_HEADER_LEN + length;
+
+            Ok(Some((message_len, message_len.next_multiple_of(4))))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Stream is neither STUN nor channel-data (first byte: {other:#04x})"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_stun_message_split_across_reads() {
+        let message = stun_message();
+        let mut decoder = StreamDecoder::default();
+
+        decoder.push(&message[..7]);
+        assert!(decoder.next_message().unwrap().is_none());
+
+        decoder.push(&message[7..]);
+        assert_eq!(decoder.next_message().unwrap().unwrap(), message.as_slice());
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn strips_padding_of_channel_data_messages() {
+        let mut stream = BytesMut::new();
+        encode_stream_message(&[0x40, 0x00, 0x00, 0x03, 1, 2, 3], &mut stream);
+        encode_stream_message(&stun_message(), &mut stream);
+
+        assert_eq!(stream.len(), 8 + 28);
+
+        let mut decoder = StreamDecoder::default();
+        decoder.push(&stream);
+
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap(),
+            [0x40, 0x00, 0x00, 0x03, 1, 2, 3].as_slice()
+        );
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap(),
+            stun_message().as_slice()
+        );
+    }
+
+    #[test]
+    fn waits_for_padding_before_yielding_channel_data() {
+        let mut decoder = StreamDecoder::default();
+
+        decoder.push(&[0x40, 0x00, 0x00, 0x01, 1]);
+        assert!(decoder.next_message().unwrap().is_none());
+
+        decoder.push(&[0, 0, 0]);
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap(),
+            [0x40, 0x00, 0x00, 0x01, 1].as_slice()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_messages() {
+        let mut decoder = StreamDecoder::default();
+
+        decoder.push(&[0x80, 0x00, 0x00, 0x00]);
+
+        assert!(decoder.next_message().is_err());
+    }
+
+    /// A BINDING request with an 8-byte attribute.
+    fn stun_message() -> Vec<u8> {
+        let mut message = vec![0x00, 0x01, 0x00, 0x08, 0x21, 0x12, 0xA4, 0x42];
+        message.extend_from_slice(&[0xAB; 12]); // Transaction ID
+        message.extend_from_slice(&[0x80, 0x22, 0x00, 0x04, b'f', b'z', b'f', b'z']);
+
+        message
+    }
+}
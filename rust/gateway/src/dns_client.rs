@@ -0,0 +1,236 @@
+//! A minimal async DNS client for resolving the domains of DNS resources via a specific DNS server.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{Context as _, Result};
+use dns_types::{DomainName, Query, RecordData, RecordType, Response, ResponseCode};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+/// Resolves the A and AAAA records of the given domain via the given DNS server.
+///
+/// Just like with `getaddrinfo`, we only fail if both queries fail and return IPv6 addresses first.
+pub async fn resolve(server: SocketAddr, domain: DomainName) -> Result<Vec<IpAddr>> {
+    tracing::debug!(%domain, %server, "Resolving DNS");
+
+    let (ipv4, ipv6) = futures::future::join(
+        query(server, Query::new(domain.clone(), RecordType::A)),
+        query(server, Query::new(domain, RecordType::AAAA)),
+    )
+    .await;
+
+    match (ipv4, ipv6) {
+        (Ok(ipv4), Ok(ipv6)) => Ok(ipv6.into_iter().chain(ipv4).collect()),
+        (Ok(ipv4), Err(_)) => Ok(ipv4),
+        (Err(_), Ok(ipv6)) => Ok(ipv6),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+async fn query(server: SocketAddr, query: Query) -> Result<Vec<IpAddr>> {
+    let qtype = query.qtype();
+    let qid = query.id();
+
+    let response = send_udp(server, query.clone())
+        .await
+        .with_context(|| format!("Failed to send {qtype} query via UDP"))?;
+
+    let response = if response.truncated() {
+        tracing::debug!(%server, %qtype, "Response was truncated, retrying via TCP");
+
+        send_tcp(server, query)
+            .await
+            .with_context(|| format!("Failed to send {qtype} query via TCP"))?
+    } else {
+        response
+    };
+
+    anyhow::ensure!(response.id() == qid, "Response ID does not match query ID");
+
+    let rcode = response.response_code();
+    anyhow::ensure!(
+        rcode == ResponseCode::NOERROR,
+        "DNS server responded with {rcode}"
+    );
+
+    let ips = response
+        .records()
+        .filter_map(|record| {
+            #[expect(clippy::wildcard_enum_match_arm)]
+            match record.data() {
+                RecordData::A(a) => Some(IpAddr::from(a.addr())),
+                RecordData::Aaaa(aaaa) => Some(IpAddr::from(aaaa.addr())),
+                _ => None,
+            }
+        })
+        .collect();
+
+    Ok(ips)
+}
+
+async fn send_udp(server: SocketAddr, query: Query) -> Result<Response> {
+    // To avoid fragmentation, IP and thus also UDP packets can only reliably sent with an MTU of <= 1500 on the public Internet.
+    const BUF_SIZE: usize = 1500;
+
+    let bind_addr = match server {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+
+    let response = socket_factory::udp(bind_addr)?
+        .handshake::<BUF_SIZE>(server, query.as_bytes())
+        .await?;
+
+    let response = Response::parse(&response)?;
+
+    Ok(response)
+}
+
+async fn send_tcp(server: SocketAddr, query: Query) -> Result<Response> {
+    let mut tcp_stream = socket_factory::tcp(server)?.connect(server).await?;
+
+    let query = query.into_bytes();
+    let dns_message_length = (query.len() as u16).to_be_bytes();
+
+    tcp_stream.write_all(&dns_message_length).await?;
+    tcp_stream.write_all(&query).await?;
+
+    let mut response_length = [0u8; 2];
+    tcp_stream.read_exact(&mut response_length).await?;
+    let response_length = u16::from_be_bytes(response_length) as usize;
+
+    // A u16 is at most 65k, meaning we are okay to allocate here based on what the remote is sending.
+    let mut response = vec![0u8; response_length];
+    tcp_stream.read_exact(&mut response).await?;
+
+    let response = Response::parse(&response)?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_types::{ResponseBuilder, records};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    use super::*;
+
+    const IPV4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const IPV6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+
+    #[tokio::test]
+    async fn resolves_a_and_aaaa_records() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = udp_stub_server(|query| {
+            let record = match query.qtype() {
+                RecordType::A => records::a(IPV4),
+                RecordType::AAAA => records::aaaa(IPV6),
+                other => panic!("Unexpected query type: {other}"),
+            };
+
+            ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+                .with_records([(query.domain(), 60, record)])
+                .build()
+        })
+        .await;
+
+        let ips = resolve(server, "foo.corp.example".parse().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(ips, vec![IpAddr::from(IPV6), IpAddr::from(IPV4)]);
+    }
+
+    #[tokio::test]
+    async fn retries_truncated_responses_via_tcp() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = udp_stub_server(|query| {
+            ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+                .with_records(std::iter::repeat_n(
+                    (query.domain(), 60, records::a(IPV4)),
+                    200,
+                ))
+                .build()
+        })
+        .await;
+        tcp_stub_server(server, |query| {
+            let records = match query.qtype() {
+                RecordType::A => vec![(query.domain(), 60, records::a(IPV4))],
+                _ => vec![],
+            };
+
+            ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+                .with_records(records)
+                .build()
+        })
+        .await;
+
+        let ips = resolve(server, "foo.corp.example".parse().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(ips, vec![IpAddr::from(IPV4)]);
+    }
+
+    #[tokio::test]
+    async fn fails_on_nxdomain() {
+        let _guard = firezone_logging::test("debug");
+
+        let server = udp_stub_server(|query| Response::nxdomain(&query)).await;
+
+        let result = resolve(server, "foo.corp.example".parse().unwrap()).await;
+
+        assert!(result.is_err());
+    }
+
+    /// A stub DNS server that answers all UDP queries using the provided function.
+    async fn udp_stub_server(
+        make_response: impl Fn(Query) -> Response + Send + 'static,
+    ) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let query = Query::parse(&buf[..len]).unwrap();
+                let response = make_response(query).into_bytes(512);
+
+                socket.send_to(&response, from).await.unwrap();
+            }
+        });
+
+        server
+    }
+
+    /// A stub DNS server that answers all TCP queries on the given address using the provided function.
+    async fn tcp_stub_server(
+        server: SocketAddr,
+        make_response: impl Fn(Query) -> Response + Send + 'static,
+    ) {
+        let listener = TcpListener::bind(server).await.unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut query_length = [0u8; 2];
+                stream.read_exact(&mut query_length).await.unwrap();
+                let mut query = vec![0u8; u16::from_be_bytes(query_length) as usize];
+                stream.read_exact(&mut query).await.unwrap();
+
+                let query = Query::parse(&query).unwrap();
+                let response = make_response(query).into_bytes(u16::MAX);
+
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+    }
+}
@@ -19,23 +19,30 @@ use phoenix_channel::{PhoenixChannel, PublicKeyParam};
 use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::future::Future;
-use std::net::{IpAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 use std::{io, mem};
 use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
 
 use crate::RELEASE;
+use crate::dns_client;
+use crate::flow_log::FlowLog;
+use crate::packet_capture::PacketCapture;
 
 pub const PHOENIX_TOPIC: &str = "gateway";
 
-/// How long we allow a DNS resolution via `libc::get_addr_info`.
+/// How long we allow a DNS resolution via `libc::get_addr_info` or a resource's DNS server.
 const DNS_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Cache DNS responses for 30 seconds.
 const DNS_TTL: Duration = Duration::from_secs(30);
 
+/// How often we log statistics about our connections to Clients.
+const LOG_CONNECTION_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
 // DNS resolution happens as part of every connection setup.
 // For a connection to succeed, DNS resolution must be less than `snownet`'s handshake timeout.
 static_assertions::const_assert!(
@@ -56,10 +63,15 @@ pub struct Eventloop {
 
     resolve_tasks:
         futures_bounded::FuturesTupleSet<Result<Vec<IpAddr>, Arc<anyhow::Error>>, ResolveTrigger>,
-    dns_cache: moka::future::Cache<DomainName, Vec<IpAddr>>,
+    dns_cache: moka::future::Cache<(DomainName, Option<SocketAddr>), Vec<IpAddr>>,
 
     set_interface_tasks: futures_bounded::FuturesSet<Result<Interface>>,
 
+    flow_log: Option<FlowLog>,
+    packet_capture: Option<PacketCapture>,
+
+    log_connection_stats_interval: tokio::time::Interval,
+
     logged_permission_denied: bool,
 }
 
@@ -68,21 +80,32 @@ impl Eventloop {
         tunnel: GatewayTunnel,
         mut portal: PhoenixChannel<(), IngressMessages, (), PublicKeyParam>,
         tun_device_manager: TunDeviceManager,
+        flow_log: Option<FlowLog>,
+        packet_capture: Option<PacketCapture>,
     ) -> Self {
         portal.connect(PublicKeyParam(tunnel.public_key().to_bytes()));
 
+        let mut log_connection_stats_interval =
+            tokio::time::interval(LOG_CONNECTION_STATS_INTERVAL);
+        log_connection_stats_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         Self {
             tunnel,
             portal,
             tun_device_manager: Arc::new(Mutex::new(tun_device_manager)),
             resolve_tasks: futures_bounded::FuturesTupleSet::new(DNS_RESOLUTION_TIMEOUT, 1000),
             set_interface_tasks: futures_bounded::FuturesSet::new(Duration::from_secs(5), 10),
+            flow_log,
+            packet_capture,
+            log_connection_stats_interval,
             logged_permission_denied: false,
             dns_cache: moka::future::Cache::builder()
                 .name("DNS queries")
                 .time_to_live(DNS_TTL)
-                .eviction_listener(|domain, ips, cause| {
-                    tracing::debug!(%domain, ?ips, ?cause, "DNS cache entry evicted");
+                .eviction_listener(|key, ips, cause| {
+                    let (domain, resolver) = &*key;
+
+                    tracing::debug!(%domain, ?resolver, ?ips, ?cause, "DNS cache entry evicted");
                 })
                 .build(),
         }
@@ -204,6 +227,23 @@ impl Eventloop {
                 Poll::Pending => {}
             }
 
+            if let Some(packet_capture) = self.packet_capture.as_mut()
+                && let Poll::Ready(result) = packet_capture.poll_toggle(cx)
+            {
+                match result {
+                    Ok(Some(writer)) => {
+                        if let Err(e) = self.tunnel.start_capture(writer) {
+                            packet_capture.on_start_failed();
+                            tracing::warn!("Failed to start packet capture: {e:#}");
+                        }
+                    }
+                    Ok(None) => self.tunnel.stop_capture(),
+                    Err(e) => tracing::warn!("Failed to start packet capture: {e:#}"),
+                }
+
+                continue;
+            }
+
             match self.portal.poll(cx) {
                 Poll::Ready(result) => {
                     let event = result.context("Failed to login to portal")?;
@@ -214,6 +254,14 @@ impl Eventloop {
                 Poll::Pending => {}
             }
 
+            if self.log_connection_stats_interval.poll_tick(cx).is_ready() {
+                for (client, stats) in self.tunnel.state_mut().connection_stats() {
+                    tracing::debug!(%client, ?stats, "Connection statistics");
+                }
+
+                continue;
+            }
+
             return Poll::Pending;
         }
     }
@@ -248,7 +296,7 @@ impl Eventloop {
                 if self
                     .resolve_tasks
                     .try_push(
-                        self.resolve(setup_nat.domain().clone()),
+                        self.resolve(setup_nat.domain().clone(), setup_nat.resolver()),
                         ResolveTrigger::SetupNat(setup_nat),
                     )
                     .is_err()
@@ -256,6 +304,28 @@ impl Eventloop {
                     tracing::warn!("Too many dns resolution requests, dropping existing one");
                 };
             }
+            firezone_tunnel::GatewayEvent::FlowCompleted(record) => {
+                let Some(flow_log) = self.flow_log.as_ref() else {
+                    return;
+                };
+
+                if let Err(e) = flow_log.write(record) {
+                    tracing::warn!("Failed to log flow: {e:#}");
+                }
+            }
+            firezone_tunnel::GatewayEvent::ConnectionPathChanged {
+                conn_id: client,
+                old,
+                new,
+            } => {
+                tracing::info!(
+                    %client,
+                    old = old.map(tracing::field::display),
+                    %new,
+                    relayed = %new.is_relayed(),
+                    "Connection path changed"
+                );
+            }
         }
     }
 
@@ -306,7 +376,10 @@ impl Eventloop {
 
                 if self
                     .resolve_tasks
-                    .try_push(self.resolve(domain), ResolveTrigger::RequestConnection(req))
+                    .try_push(
+                        self.resolve(domain, req.resource.dns_resolver()),
+                        ResolveTrigger::RequestConnection(req),
+                    )
                     .is_err()
                 {
                     tracing::warn!("Too many connections requests, dropping existing one");
@@ -323,7 +396,10 @@ impl Eventloop {
 
                 if self
                     .resolve_tasks
-                    .try_push(self.resolve(domain), ResolveTrigger::AllowAccess(req))
+                    .try_push(
+                        self.resolve(domain, req.resource.dns_resolver()),
+                        ResolveTrigger::AllowAccess(req),
+                    )
                     .is_err()
                 {
                     tracing::warn!("Too many allow access requests, dropping existing one");
@@ -606,14 +682,25 @@ impl Eventloop {
         };
     }
 
+    /// Resolves the given domain, either via the resource's DNS server or the system resolver.
     fn resolve(
         &self,
         domain: DomainName,
+        resolver: Option<SocketAddr>,
     ) -> impl Future<Output = Result<Vec<IpAddr>, Arc<anyhow::Error>>> + use<> {
-        let do_resolve = resolve(domain.clone());
+        let do_resolve = {
+            let domain = domain.clone();
+
+            async move {
+                match resolver {
+                    Some(server) => dns_client::resolve(server, domain).await,
+                    None => resolve(domain).await,
+                }
+            }
+        };
         let cache = self.dns_cache.clone();
 
-        async move { cache.try_get_with(domain, do_resolve).await }
+        async move { cache.try_get_with((domain, resolver), do_resolve).await }
     }
 }
 
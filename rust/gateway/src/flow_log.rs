@@ -0,0 +1,118 @@
+//! Writes completed flows as JSON lines to a file.
+
+use std::{net::SocketAddr, path::Path, time::Instant};
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{DateTime, Utc};
+use connlib_model::{ClientId, ResourceId};
+use firezone_tunnel::FlowRecord;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncWriteExt as _, BufWriter},
+    sync::mpsc,
+};
+
+/// How many flows we buffer for the writer before dropping new ones.
+const MAX_PENDING_FLOWS: usize = 10_000;
+
+/// Hands completed flows to a background task that appends them to a file.
+///
+/// This keeps the eventloop from blocking on disk IO.
+pub struct FlowLog {
+    entries: mpsc::Sender<Entry>,
+}
+
+impl FlowLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open flow log at `{}`", path.display()))?;
+
+        let (entries, rx) = mpsc::channel(MAX_PENDING_FLOWS);
+        tokio::spawn(write_entries(tokio::fs::File::from_std(file), rx));
+
+        Ok(Self { entries })
+    }
+
+    pub fn write(&self, record: FlowRecord) -> Result<()> {
+        let entry = Entry::new(record, Instant::now(), Utc::now());
+
+        self.entries.try_send(entry).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow!("Flow log writer is falling behind"),
+            mpsc::error::TrySendError::Closed(_) => anyhow!("Flow log writer has stopped"),
+        })
+    }
+}
+
+async fn write_entries(file: tokio::fs::File, mut rx: mpsc::Receiver<Entry>) {
+    let mut writer = BufWriter::new(file);
+
+    while let Some(entry) = rx.recv().await {
+        if let Err(e) = write_entry(&mut writer, &entry).await {
+            tracing::warn!("Failed to write flow log: {e:#}");
+        }
+
+        // Flush once we've caught up so entries hit the disk in a timely manner.
+        if rx.is_empty()
+            && let Err(e) = writer.flush().await
+        {
+            tracing::warn!("Failed to flush flow log: {e:#}");
+        }
+    }
+}
+
+async fn write_entry(writer: &mut BufWriter<tokio::fs::File>, entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry).context("Failed to serialize flow")?;
+    line.push(b'\n');
+
+    writer.write_all(&line).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    client_id: ClientId,
+    resource_id: ResourceId,
+    protocol: &'static str,
+
+    inside_src: SocketAddr,
+    inside_dst: SocketAddr,
+    outside_src: SocketAddr,
+    outside_dst: SocketAddr,
+
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+
+    tx_packets: u64,
+    tx_bytes: u64,
+    rx_packets: u64,
+    rx_bytes: u64,
+}
+
+impl Entry {
+    /// The tunnel tracks flows using [`Instant`]s; convert them to wall-clock time relative to `now`.
+    fn new(record: FlowRecord, now: Instant, utc_now: DateTime<Utc>) -> Self {
+        let to_utc = |instant: Instant| {
+            utc_now - chrono::Duration::from_std(now.duration_since(instant)).unwrap_or_default()
+        };
+
+        Self {
+            client_id: record.client,
+            resource_id: record.resource,
+            protocol: record.protocol.as_str(),
+            inside_src: record.inside_src,
+            inside_dst: record.inside_dst,
+            outside_src: record.outside_src,
+            outside_dst: record.outside_dst,
+            start: to_utc(record.start),
+            end: to_utc(record.end),
+            tx_packets: record.tx.packets,
+            tx_bytes: record.tx.bytes,
+            rx_packets: record.rx.packets,
+            rx_bytes: record.rx.bytes,
+        }
+    }
+}
@@ -1,16 +1,22 @@
+#![cfg_attr(test, allow(clippy::unwrap_used))]
+
 use crate::eventloop::{Eventloop, PHOENIX_TOPIC};
+use crate::flow_log::FlowLog;
+use crate::packet_capture::PacketCapture;
 use anyhow::{Context, Result};
 use backoff::ExponentialBackoffBuilder;
 use clap::Parser;
 use firezone_bin_shared::{
     TunDeviceManager, device_id, http_health_check,
     platform::{UdpSocketFactory, tcp_socket_factory},
+    prometheus_metrics::PrometheusMetrics,
 };
 
 use firezone_telemetry::{
     MaybePushMetricsExporter, NoopPushMetricsExporter, Telemetry, feature_flags, otel,
 };
 use firezone_tunnel::GatewayTunnel;
+use firezone_tunnel::messages::gateway::RateLimit;
 use ip_packet::IpPacket;
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
@@ -20,8 +26,11 @@ use phoenix_channel::get_user_agent;
 use futures::{TryFutureExt, future};
 use phoenix_channel::PhoenixChannel;
 use secrecy::Secret;
-use std::{collections::BTreeSet, path::Path};
-use std::{fmt, pin::pin};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use std::{fmt, net::IpAddr, pin::pin};
 use std::{process::ExitCode, str::FromStr};
 use std::{sync::Arc, time::Duration};
 use tokio::signal::ctrl_c;
@@ -29,7 +38,10 @@ use tracing_subscriber::layer;
 use tun::Tun;
 use url::Url;
 
+mod dns_client;
 mod eventloop;
+mod flow_log;
+mod packet_capture;
 
 const ID_PATH: &str = "/var/lib/firezone/gateway_id";
 const RELEASE: &str = concat!("gateway@", env!("CARGO_PKG_VERSION"));
@@ -118,24 +130,31 @@ async fn try_main(cli: Cli, telemetry: &mut Telemetry) -> Result<()> {
             .await;
     }
 
-    if let Some(backend) = cli.metrics {
+    let (prometheus_metrics, prometheus_exporter) = cli
+        .prometheus_metrics
+        .then(PrometheusMetrics::new)
+        .transpose()?
+        .unzip();
+
+    if cli.metrics.is_some() || prometheus_exporter.is_some() {
         let resource = otel::default_resource_with([
             otel::attr::service_name!(),
             otel::attr::service_version!(),
             otel::attr::service_instance_id(firezone_id.clone()),
         ]);
 
-        let provider = match (backend, cli.otlp_grpc_endpoint) {
-            (MetricsExporter::Stdout, _) => SdkMeterProvider::builder()
-                .with_periodic_exporter(opentelemetry_stdout::MetricExporter::default())
-                .with_resource(resource)
-                .build(),
-            (MetricsExporter::OtelCollector, Some(endpoint)) => SdkMeterProvider::builder()
-                .with_periodic_exporter(tonic_otlp_exporter(endpoint)?)
-                .with_resource(resource)
-                .build(),
-            (MetricsExporter::OtelCollector, None) => SdkMeterProvider::builder()
-                .with_periodic_exporter(MaybePushMetricsExporter {
+        let mut provider = SdkMeterProvider::builder().with_resource(resource);
+
+        provider = match (cli.metrics, cli.otlp_grpc_endpoint) {
+            (None, _) => provider,
+            (Some(MetricsExporter::Stdout), _) => {
+                provider.with_periodic_exporter(opentelemetry_stdout::MetricExporter::default())
+            }
+            (Some(MetricsExporter::OtelCollector), Some(endpoint)) => {
+                provider.with_periodic_exporter(tonic_otlp_exporter(endpoint)?)
+            }
+            (Some(MetricsExporter::OtelCollector), None) => {
+                provider.with_periodic_exporter(MaybePushMetricsExporter {
                     inner: {
                         // TODO: Once Firezone has a hosted OTLP exporter, it will go here.
 
@@ -143,11 +162,14 @@ async fn try_main(cli: Cli, telemetry: &mut Telemetry) -> Result<()> {
                     },
                     should_export: feature_flags::export_metrics,
                 })
-                .with_resource(resource)
-                .build(),
+            }
         };
 
-        opentelemetry::global::set_meter_provider(provider);
+        if let Some(exporter) = prometheus_exporter {
+            provider = provider.with_reader(exporter);
+        }
+
+        opentelemetry::global::set_meter_provider(provider.build());
     }
 
     let login = LoginUrl::gateway(cli.api_url, &cli.token, firezone_id, cli.firezone_name)
@@ -182,6 +204,19 @@ async fn try_main(cli: Cli, telemetry: &mut Telemetry) -> Result<()> {
     )
     .context("Failed to resolve portal URL")?;
 
+    let flow_log = cli.flow_log.as_deref().map(FlowLog::open).transpose()?;
+    let packet_capture = cli.capture_file.map(PacketCapture::new).transpose()?;
+    tunnel.state_mut().set_flow_logs_enabled(flow_log.is_some());
+    let client_rate_limit = cli
+        .client_rate_limit
+        .map(|bytes_per_second| RateLimit::new(bytes_per_second, None))
+        .transpose()
+        .context("Invalid client rate limit")?;
+    tunnel.state_mut().set_client_rate_limit(client_rate_limit);
+    tunnel
+        .state_mut()
+        .set_egress_ips(cli.egress_ips.iter().copied());
+
     let mut tun_device_manager = TunDeviceManager::new(ip_packet::MAX_IP_SIZE, cli.tun_threads.0)
         .context("Failed to create TUN device manager")?;
     let tun = tun_device_manager
@@ -195,7 +230,8 @@ async fn try_main(cli: Cli, telemetry: &mut Telemetry) -> Result<()> {
     }
 
     let eventloop = future::poll_fn({
-        let mut eventloop = Eventloop::new(tunnel, portal, tun_device_manager);
+        let mut eventloop =
+            Eventloop::new(tunnel, portal, tun_device_manager, flow_log, packet_capture);
 
         move |cx| eventloop.poll(cx)
     });
@@ -204,6 +240,7 @@ async fn try_main(cli: Cli, telemetry: &mut Telemetry) -> Result<()> {
     tokio::spawn(http_health_check::serve(
         cli.health_check.health_check_addr,
         || true,
+        prometheus_metrics,
     ));
 
     match future::try_select(eventloop, ctrl_c)
@@ -289,6 +326,20 @@ struct Cli {
     #[arg(long, hide = true, env = "FIREZONE_METRICS")]
     metrics: Option<MetricsExporter>,
 
+    /// Serve metrics at `/metrics` on the health-check endpoint for Prometheus to scrape.
+    ///
+    /// Metrics contain per-client series, make sure the health-check endpoint is not publicly reachable.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(
+        long,
+        env = "FIREZONE_PROMETHEUS_METRICS",
+        hide = true,
+        default_value_t = false
+    )]
+    prometheus_metrics: bool,
+
     /// Send metrics to a custom OTLP collector.
     ///
     /// By default, Firezone's hosted OTLP collector is used.
@@ -306,6 +357,41 @@ struct Cli {
         default_value_t = false
     )]
     validate_checksums: bool,
+
+    /// Append a JSON line for every completed client flow to this file.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(long, hide = true, env = "FIREZONE_FLOW_LOG")]
+    flow_log: Option<PathBuf>,
+
+    /// Write a pcapng capture of all tunnel traffic to this file, started and stopped by sending `SIGUSR1`.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(long, hide = true, env = "FIREZONE_CAPTURE_FILE")]
+    capture_file: Option<PathBuf>,
+
+    /// Limit the traffic of each client to this many bytes per second.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(long, hide = true, env = "FIREZONE_CLIENT_RATE_LIMIT")]
+    client_rate_limit: Option<u64>,
+
+    /// Additional source IPs to use once a client has used up all NAT ports towards a resource.
+    ///
+    /// Return traffic for these IPs must be routed to the gateway's TUN device.
+    ///
+    /// This configuration option is private API and has no stability guarantees.
+    /// It may be removed / changed anytime.
+    #[arg(
+        long = "egress-ip",
+        hide = true,
+        env = "FIREZONE_EGRESS_IPS",
+        value_delimiter = ','
+    )]
+    egress_ips: Vec<IpAddr>,
 }
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
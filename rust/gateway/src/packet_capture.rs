@@ -0,0 +1,68 @@
+//! Toggles a pcapng capture of the tunnel's traffic whenever we receive `SIGUSR1`.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    task::{Context, Poll},
+};
+
+use anyhow::{Context as _, Result};
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+pub struct PacketCapture {
+    path: PathBuf,
+    toggle: Signal,
+    running: bool,
+}
+
+impl PacketCapture {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let toggle =
+            signal(SignalKind::user_defined1()).context("Failed to listen for `SIGUSR1`")?;
+
+        Ok(Self {
+            path,
+            toggle,
+            running: false,
+        })
+    }
+
+    /// Resolves once the capture should be toggled.
+    ///
+    /// Yields the writer for a new capture or [`None`] if the running capture should be stopped.
+    /// A new capture counts as running unless [`PacketCapture::on_start_failed`] is called.
+    pub fn poll_toggle(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<Box<dyn Write + Send>>>> {
+        if self.toggle.poll_recv(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        if self.running {
+            self.running = false;
+
+            return Poll::Ready(Ok(None));
+        }
+
+        let file = File::create(&self.path)
+            .with_context(|| format!("Failed to create `{}`", self.path.display()))?;
+        self.running = true;
+
+        tracing::info!(path = %self.path.display(), "Capturing packets; send `SIGUSR1` again to stop");
+
+        Poll::Ready(Ok(Some(Box::new(file))))
+    }
+
+    /// Must be called if the tunnel failed to start the capture for the writer yielded by [`PacketCapture::poll_toggle`].
+    ///
+    /// Removes the empty capture file so the next `SIGUSR1` tries again.
+    pub fn on_start_failed(&mut self) {
+        self.running = false;
+
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::debug!(path = %self.path.display(), "Failed to remove capture file: {e}");
+        }
+    }
+}
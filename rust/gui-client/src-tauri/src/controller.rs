@@ -9,7 +9,7 @@ use crate::{
     view::{GeneralSettingsForm, SessionViewModel},
 };
 use anyhow::{Context, Result, anyhow, bail};
-use connlib_model::ResourceView;
+use connlib_model::{ResourceId, ResourceView};
 use firezone_logging::FilterReloadHandle;
 use firezone_telemetry::Telemetry;
 use futures::{
@@ -17,7 +17,9 @@ use futures::{
     stream::{self, BoxStream},
 };
 use secrecy::{ExposeSecret as _, SecretString};
-use std::{collections::BTreeSet, ops::ControlFlow, path::PathBuf, task::Poll, time::Duration};
+use std::{
+    collections::BTreeSet, mem, ops::ControlFlow, path::PathBuf, task::Poll, time::Duration,
+};
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 use url::Url;
@@ -655,6 +657,28 @@ impl<I: GuiIntegration> Controller<I> {
                 self.refresh_ui_state();
                 self.update_disabled_resources().await?;
             }
+            service::ServerMsg::OnUpdateResourceTraffic(traffic) => {
+                let Status::TunnelReady { resources } = &mut self.status else {
+                    return Ok(ControlFlow::Continue(()));
+                };
+
+                let previously_active = active_resources(resources);
+
+                *resources = mem::take(resources)
+                    .into_iter()
+                    .map(|r| {
+                        let traffic = traffic.get(&r.id()).copied().unwrap_or_default();
+
+                        r.with_traffic(traffic)
+                    })
+                    .collect();
+
+                // Rebuilding the tray menu closes it on Linux and Windows, so don't do that just because the counters changed.
+                // The counters shown in the menu catch up with the next rebuild.
+                if active_resources(resources) != previously_active {
+                    self.refresh_ui_state();
+                }
+            }
             service::ServerMsg::TerminatingGracefully => {
                 tracing::info!("Tunnel service exited gracefully");
                 self.integration
@@ -923,6 +947,15 @@ impl<I: GuiIntegration> Controller<I> {
     }
 }
 
+/// The resources that have seen any traffic, which is what decides whether the tray menu shows their counters.
+fn active_resources(resources: &[ResourceView]) -> BTreeSet<ResourceId> {
+    resources
+        .iter()
+        .filter(|r| r.traffic().is_active())
+        .map(|r| r.id())
+        .collect()
+}
+
 async fn receive_hello(ipc_rx: &mut ipc::ClientRead<service::ServerMsg>) -> Result<()> {
     const TIMEOUT: Duration = Duration::from_secs(5);
 
@@ -8,7 +8,7 @@
 use crate::updates::Release;
 use anyhow::{Context as _, Result};
 use compositor::Image;
-use connlib_model::{ResourceId, ResourceStatus, ResourceView};
+use connlib_model::{ConnectionPath, ResourceId, ResourceStatus, ResourceView};
 use std::collections::HashSet;
 use tauri::AppHandle;
 use url::Url;
@@ -36,6 +36,8 @@ const QUIT_TEXT_SIGNED_OUT: &str = "Quit Firezone";
 const NO_ACTIVITY: &str = "[-] No activity";
 const GATEWAY_CONNECTED: &str = "[O] Gateway connected";
 const ALL_GATEWAYS_OFFLINE: &str = "[X] All Gateways offline";
+const DIRECT_CONNECTION: &str = "Direct connection";
+const RELAYED_CONNECTION: &str = "Relayed connection";
 
 const ENABLED_SYMBOL: &str = "<->";
 const DISABLED_SYMBOL: &str = "—";
@@ -340,6 +342,15 @@ impl SignedIn {
             self.add_favorite_toggle(&mut submenu, res.id());
         }
 
+        let traffic = res.traffic();
+
+        if traffic.is_active() {
+            submenu = submenu
+                .separator()
+                .disabled("Traffic")
+                .copyable(&traffic.to_string());
+        }
+
         if let Some(site) = res.sites().first() {
             // Emojis may be causing an issue on some Ubuntu desktop environments.
             let status = match res.status() {
@@ -348,11 +359,17 @@ impl SignedIn {
                 ResourceStatus::Offline => ALL_GATEWAYS_OFFLINE,
             };
 
-            submenu
+            let submenu = submenu
                 .separator()
                 .disabled("Site")
                 .copyable(&site.name) // Hope this is okay - The code is simpler if every enabled item sends an `Event` on click
-                .copyable(status)
+                .copyable(status);
+
+            match res.path() {
+                Some(ConnectionPath::Direct) => submenu.copyable(DIRECT_CONNECTION),
+                Some(ConnectionPath::Relayed) => submenu.copyable(RELAYED_CONNECTION),
+                None => submenu,
+            }
         } else {
             submenu
         }
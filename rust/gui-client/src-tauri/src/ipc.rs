@@ -1,14 +1,12 @@
 //! Defines a reusable, bi-directional, cross-platform IPC framework that uses JSON for message serialisation.
 
-use anyhow::{Context as _, Result};
+use anyhow::Result;
 use platform::{ClientStream, ServerStream};
 use serde::{Serialize, de::DeserializeOwned};
 use tokio::io::{ReadHalf, WriteHalf};
-use tokio_util::{
-    bytes::BytesMut,
-    codec::{FramedRead, FramedWrite, LengthDelimitedCodec},
-};
+use tokio_util::codec::{FramedRead, FramedWrite};
 
+pub use firezone_bin_shared::ipc_framing::{Decoder, Encoder};
 pub(crate) use platform::Server;
 
 pub type ClientRead<M> = FramedRead<ReadHalf<ClientStream>, Decoder<M>>;
@@ -76,58 +74,6 @@ pub enum SocketId {
     Test(&'static str),
 }
 
-pub struct Decoder<D> {
-    inner: LengthDelimitedCodec,
-    _decode_type: std::marker::PhantomData<D>,
-}
-
-pub struct Encoder<E> {
-    inner: LengthDelimitedCodec,
-    _encode_type: std::marker::PhantomData<E>,
-}
-
-impl<D> Default for Decoder<D> {
-    fn default() -> Self {
-        Self {
-            inner: LengthDelimitedCodec::new(),
-            _decode_type: Default::default(),
-        }
-    }
-}
-
-impl<E> Default for Encoder<E> {
-    fn default() -> Self {
-        Self {
-            inner: LengthDelimitedCodec::new(),
-            _encode_type: Default::default(),
-        }
-    }
-}
-
-impl<D: serde::de::DeserializeOwned> tokio_util::codec::Decoder for Decoder<D> {
-    type Error = anyhow::Error;
-    type Item = D;
-
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<D>> {
-        let Some(msg) = self.inner.decode(buf)? else {
-            return Ok(None);
-        };
-        let msg = serde_json::from_slice(&msg)
-            .with_context(|| format!("Error while deserializing {}", std::any::type_name::<D>()))?;
-        Ok(Some(msg))
-    }
-}
-
-impl<E: serde::Serialize> tokio_util::codec::Encoder<&E> for Encoder<E> {
-    type Error = anyhow::Error;
-
-    fn encode(&mut self, msg: &E, buf: &mut BytesMut) -> Result<()> {
-        let msg = serde_json::to_string(msg)?;
-        self.inner.encode(msg.into(), buf)?;
-        Ok(())
-    }
-}
-
 pub struct ConnectOptions {
     pub num_attempts: usize,
 }
@@ -196,7 +142,7 @@ impl platform::Server {
 #[cfg(test)]
 mod tests {
     use super::{platform::Server, *};
-    use anyhow::{Result, bail, ensure};
+    use anyhow::{Context as _, Result, bail, ensure};
     use futures::{SinkExt, StreamExt};
     use std::time::Duration;
     use tokio::{task::JoinHandle, time::timeout};
@@ -5,7 +5,7 @@ use crate::{
 use anyhow::{Context as _, Result, bail};
 use atomicwrites::{AtomicFile, OverwriteBehavior};
 use backoff::ExponentialBackoffBuilder;
-use connlib_model::{ResourceId, ResourceView};
+use connlib_model::{ResourceId, ResourceTraffic, ResourceView};
 use firezone_bin_shared::{
     DnsControlMethod, DnsController, TunDeviceManager,
     device_id::{self, DeviceId},
@@ -24,7 +24,7 @@ use futures::{
 use phoenix_channel::{DeviceInfo, LoginUrl, PhoenixChannel, get_user_agent};
 use secrecy::{Secret, SecretString};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     io::{self, Write},
     mem,
     pin::pin,
@@ -80,6 +80,7 @@ pub enum ServerMsg {
         is_authentication_error: bool,
     },
     OnUpdateResources(Vec<ResourceView>),
+    OnUpdateResourceTraffic(BTreeMap<ResourceId, ResourceTraffic>),
     /// The Tunnel service is terminating, maybe due to a software update
     ///
     /// This is a hint that the Client should exit with a message like,
@@ -492,6 +493,10 @@ impl<'a> Handler<'a> {
                 self.send_ipc(ServerMsg::OnUpdateResources(resources))
                     .await?;
             }
+            client_shared::Event::ResourceTrafficUpdated(traffic) => {
+                self.send_ipc(ServerMsg::OnUpdateResourceTraffic(traffic))
+                    .await?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,326 @@
+//! A local control API for a running headless Client
+//!
+//! On Linux, this is served on a Unix Domain Socket using the same framing as the GUI Client's IPC.
+//! The `status`, `resources`, `disable` and `enable` subcommands talk to it.
+
+use anyhow::{Context as _, Result};
+use connlib_model::{ResourceId, ResourceView};
+use firezone_bin_shared::known_dirs;
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::{Command, Server, default_socket_path, run_command};
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) enum ClientMsg {
+    Status,
+    Resources,
+    DisableResource(ResourceId),
+    EnableResource(ResourceId),
+    Reset,
+    ApplyLogFilter { directives: String },
+    StartCapture,
+    StopCapture,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) enum ServerMsg {
+    Status(Status),
+    Resources(Vec<ResourceView>),
+    /// The result of a request that has nothing else to reply with.
+    Done(Result<(), String>),
+    /// Where the requested capture is being written to.
+    CaptureStarted(Result<PathBuf, String>),
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct Status {
+    pub version: String,
+    /// Whether the tunnel interface has been configured and traffic can flow.
+    pub tunnel_ready: bool,
+    pub num_resources: usize,
+    /// How many Resources we have exchanged traffic with.
+    pub num_active_resources: usize,
+    pub disabled_resources: BTreeSet<ResourceId>,
+}
+
+/// Creates a new path for a packet capture requested via the control API.
+///
+/// Control clients cannot choose the path themselves.
+/// We run as root and would otherwise overwrite any file they ask for.
+pub(crate) fn new_capture_path() -> Result<PathBuf> {
+    let dir = known_dirs::tunnel_service_logs()
+        .context("Failed to compute log directory")?
+        .join("captures");
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(dir.join(format!("{}.pcapng", now.as_millis())))
+}
+
+/// A message from a control client, to be answered by the main loop.
+pub(crate) struct Request {
+    pub msg: ClientMsg,
+    pub reply: oneshot::Sender<ServerMsg>,
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ClientMsg, Request, ServerMsg, Status};
+    use anyhow::{Context as _, Result, bail};
+    use connlib_model::ResourceId;
+    use firezone_bin_shared::{
+        BUNDLE_ID,
+        ipc_framing::{Decoder, Encoder},
+    };
+    use futures::{SinkExt as _, StreamExt as _};
+    use std::{
+        os::unix::fs::PermissionsExt as _,
+        path::{Path, PathBuf},
+    };
+    use tokio::{
+        io::{ReadHalf, WriteHalf},
+        net::{UnixListener, UnixStream},
+        sync::{mpsc, oneshot},
+    };
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    /// Lives next to the GUI Client's `tunnel.sock` so systemd can create the dir for us.
+    pub(crate) fn default_socket_path() -> PathBuf {
+        PathBuf::from("/run").join(BUNDLE_ID).join("headless.sock")
+    }
+
+    /// Subcommands that control an already running headless Client.
+    #[derive(clap::Subcommand, Clone)]
+    pub(crate) enum Command {
+        /// Show whether the tunnel is up and which Resources are disabled.
+        Status,
+        /// List all Resources, their status and how much traffic was exchanged with them.
+        Resources,
+        /// Stop routing traffic for the given Resource.
+        Disable { id: ResourceId },
+        /// Resume routing traffic for the given Resource.
+        Enable { id: ResourceId },
+        /// Start writing all tunnel traffic to a new pcapng file in the log directory.
+        StartCapture,
+        /// Stop writing tunnel traffic to the pcapng file.
+        StopCapture,
+    }
+
+    pub(crate) struct Server {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                tracing::debug!(path = %self.path.display(), "Failed to delete control socket: {e}");
+            }
+        }
+    }
+
+    impl Server {
+        pub(crate) fn new(path: &Path) -> Result<Self> {
+            tracing::debug!(socket = %path.display(), "Creating control socket");
+
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                bail!(
+                    "Another headless Client is already listening on `{}`",
+                    path.display()
+                );
+            }
+
+            // Remove the socket if a previous run left it there
+            std::fs::remove_file(path).ok();
+            let dir = path
+                .parent()
+                .context("Control socket path should always have a parent")?;
+            std::fs::create_dir_all(dir).context("Failed to create socket parent directory")?;
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Couldn't bind UDS `{}`", path.display()))?;
+            let perms = std::fs::Permissions::from_mode(0o660);
+            std::fs::set_permissions(path, perms).context("Failed to set permissions on UDS")?;
+
+            Ok(Self {
+                listener,
+                path: path.to_owned(),
+            })
+        }
+
+        /// Accepts control clients and forwards their messages to the main loop.
+        pub(crate) async fn run(self, requests: mpsc::Sender<Request>) {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::debug!("Failed to accept control connection: {e}");
+                        continue;
+                    }
+                };
+
+                tokio::spawn({
+                    let requests = requests.clone();
+
+                    async move {
+                        if let Err(e) = handle_client(stream, requests).await {
+                            tracing::debug!("Control connection failed: {e:#}");
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    async fn handle_client(stream: UnixStream, requests: mpsc::Sender<Request>) -> Result<()> {
+        let cred = stream.peer_cred()?;
+        tracing::debug!(
+            uid = cred.uid(),
+            gid = cred.gid(),
+            pid = cred.pid(),
+            "Accepted a control connection"
+        );
+
+        let (rx, tx) = tokio::io::split(stream);
+        let mut rx = FramedRead::new(rx, Decoder::<ClientMsg>::default());
+        let mut tx = FramedWrite::new(tx, Encoder::<ServerMsg>::default());
+
+        while let Some(msg) = rx.next().await {
+            let (reply, response) = oneshot::channel();
+
+            requests
+                .send(Request { msg: msg?, reply })
+                .await
+                .context("Main loop is gone")?;
+            let response = response.await.context("Main loop dropped request")?;
+
+            tx.send(&response).await?;
+        }
+
+        Ok(())
+    }
+
+    struct Client {
+        rx: FramedRead<ReadHalf<UnixStream>, Decoder<ServerMsg>>,
+        tx: FramedWrite<WriteHalf<UnixStream>, Encoder<ClientMsg>>,
+    }
+
+    impl Client {
+        async fn connect(path: &Path) -> Result<Self> {
+            let stream = UnixStream::connect(path).await.with_context(|| {
+                format!(
+                    "Couldn't connect to `{}`, is the headless Client running?",
+                    path.display()
+                )
+            })?;
+            let (rx, tx) = tokio::io::split(stream);
+
+            Ok(Self {
+                rx: FramedRead::new(rx, Decoder::default()),
+                tx: FramedWrite::new(tx, Encoder::default()),
+            })
+        }
+
+        async fn request(&mut self, msg: ClientMsg) -> Result<ServerMsg> {
+            self.tx.send(&msg).await?;
+
+            self.rx
+                .next()
+                .await
+                .context("Headless Client closed the control connection")?
+        }
+
+        async fn status(&mut self) -> Result<Status> {
+            match self.request(ClientMsg::Status).await? {
+                ServerMsg::Status(status) => Ok(status),
+                ServerMsg::Resources(_) | ServerMsg::Done(_) | ServerMsg::CaptureStarted(_) => {
+                    bail!("Unexpected response")
+                }
+            }
+        }
+
+        async fn done(&mut self, msg: ClientMsg) -> Result<()> {
+            match self.request(msg).await? {
+                ServerMsg::Done(result) => result.map_err(anyhow::Error::msg),
+                ServerMsg::Status(_) | ServerMsg::Resources(_) | ServerMsg::CaptureStarted(_) => {
+                    bail!("Unexpected response")
+                }
+            }
+        }
+
+        async fn start_capture(&mut self) -> Result<PathBuf> {
+            match self.request(ClientMsg::StartCapture).await? {
+                ServerMsg::CaptureStarted(result) => result.map_err(anyhow::Error::msg),
+                ServerMsg::Status(_) | ServerMsg::Resources(_) | ServerMsg::Done(_) => {
+                    bail!("Unexpected response")
+                }
+            }
+        }
+    }
+
+    /// Runs the given subcommand against the headless Client listening on `socket` and prints the result.
+    #[expect(clippy::print_stdout)]
+    pub(crate) async fn run_command(command: Command, socket: &Path) -> Result<()> {
+        let mut client = Client::connect(socket).await?;
+
+        match command {
+            Command::Status => {
+                let status = client.status().await?;
+
+                println!("version: {}", status.version);
+                println!("tunnel ready: {}", status.tunnel_ready);
+                println!("resources: {}", status.num_resources);
+                println!("active resources: {}", status.num_active_resources);
+                for id in status.disabled_resources {
+                    println!("disabled: {id}");
+                }
+            }
+            Command::Resources => {
+                let ServerMsg::Resources(resources) = client.request(ClientMsg::Resources).await?
+                else {
+                    bail!("Unexpected response");
+                };
+
+                for resource in resources {
+                    let path = resource
+                        .path()
+                        .map_or_else(|| "-".to_owned(), |p| p.to_string());
+
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        resource.id(),
+                        resource.status(),
+                        path,
+                        resource.name(),
+                        resource.pastable(),
+                        resource.traffic()
+                    );
+                }
+            }
+            Command::Disable { id } => {
+                client.done(ClientMsg::DisableResource(id)).await?;
+            }
+            Command::Enable { id } => {
+                client.done(ClientMsg::EnableResource(id)).await?;
+            }
+            Command::StartCapture => {
+                let path = client.start_capture().await?;
+
+                println!("Capturing packets to `{}`", path.display());
+            }
+            Command::StopCapture => {
+                client.done(ClientMsg::StopCapture).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use firezone_bin_shared::{
     DnsControlMethod, DnsController, TOKEN_ENV_KEY, TunDeviceManager, device_id, device_info,
     new_dns_notifier, new_network_notifier,
     platform::{UdpSocketFactory, tcp_socket_factory},
+    prometheus_metrics::PrometheusMetricsArgs,
     signals,
 };
 use firezone_telemetry::{Telemetry, analytics, otel};
@@ -18,10 +19,13 @@ use phoenix_channel::get_user_agent;
 use phoenix_channel::{DeviceInfo, LoginUrl};
 use secrecy::{Secret, SecretString};
 use std::{
+    collections::BTreeSet,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::time::Instant;
+use tokio::{sync::mpsc, time::Instant};
+
+mod control;
 
 #[cfg(target_os = "linux")]
 #[path = "linux.rs"]
@@ -39,10 +43,8 @@ mod platform;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    // Needed to preserve CLI arg compatibility
-    // TODO: Remove when we can break CLI compatibility for headless Clients
     #[command(subcommand)]
-    _command: Option<Cmd>,
+    command: Option<Cmd>,
 
     #[cfg(target_os = "linux")]
     #[arg(long, env = "FIREZONE_DNS_CONTROL", default_value = "systemd-resolved")]
@@ -108,6 +110,9 @@ struct Cli {
     #[arg(long, hide = true, env = "FIREZONE_METRICS")]
     metrics: Option<MetricsExporter>,
 
+    #[command(flatten)]
+    prometheus: PrometheusMetricsArgs,
+
     /// A filesystem path where the token can be found
     // Apparently passing secrets through stdin is the most secure method, but
     // until anyone asks for it, env vars are okay and files on disk are slightly better.
@@ -115,6 +120,11 @@ struct Cli {
     // on disk somewhere anyway.)
     #[arg(default_value = platform::default_token_path().display().to_string(), env = "FIREZONE_TOKEN_PATH", long)]
     token_path: PathBuf,
+
+    /// Where to serve the control API used by the `status`, `resources`, `disable` and `enable` subcommands.
+    #[cfg(target_os = "linux")]
+    #[arg(default_value = control::default_socket_path().display().to_string(), env = "FIREZONE_CONTROL_SOCKET", long)]
+    control_socket: PathBuf,
 }
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
@@ -128,10 +138,15 @@ impl Cli {
     }
 }
 
-#[derive(clap::Subcommand, Clone, Copy)]
-#[clap(hide = true)]
+#[derive(clap::Subcommand, Clone)]
 enum Cmd {
+    // Needed to preserve CLI arg compatibility
+    // TODO: Remove when we can break CLI compatibility for headless Clients
+    #[command(hide = true)]
     Standalone,
+    #[cfg(target_os = "linux")]
+    #[command(flatten)]
+    Control(control::Command),
 }
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -144,6 +159,14 @@ fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
+    #[cfg(target_os = "linux")]
+    if let Some(Cmd::Control(command)) = cli.command {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(control::run_command(command, &cli.control_socket));
+    }
+
     // Modifying the environment of a running process is unsafe. If any other
     // thread is reading or writing the environment, something bad can happen.
     // So `run` must take over as early as possible during startup, and
@@ -167,7 +190,8 @@ fn main() -> Result<()> {
         .as_deref()
         .map(|dir| firezone_logging::file::layer(dir, "firezone-headless-client"))
         .unzip();
-    firezone_logging::setup_global_subscriber(layer).context("Failed to set up logging")?;
+    let log_filter_reloader =
+        firezone_logging::setup_global_subscriber(layer).context("Failed to set up logging")?;
 
     // Deactivate DNS control before starting telemetry or connecting to the portal,
     // in case a previous run of Firezone left DNS control on and messed anything up.
@@ -231,19 +255,26 @@ fn main() -> Result<()> {
     let mut last_connlib_start_instant = Some(Instant::now());
 
     rt.block_on(async {
-        if let Some(MetricsExporter::Stdout) = cli.metrics {
-            let exporter = opentelemetry_stdout::MetricExporter::default();
-            let reader = PeriodicReader::builder(exporter).build();
-            let provider = SdkMeterProvider::builder()
-                .with_reader(reader)
-                .with_resource(otel::default_resource_with([
+        let prometheus_exporter = cli.prometheus.spawn_server().await?;
+
+        if cli.metrics.is_some() || prometheus_exporter.is_some() {
+            let mut provider =
+                SdkMeterProvider::builder().with_resource(otel::default_resource_with([
                     otel::attr::service_name!(),
                     otel::attr::service_version!(),
                     otel::attr::service_instance_id(firezone_id.clone()),
-                ]))
-                .build();
+                ]));
+
+            if let Some(MetricsExporter::Stdout) = cli.metrics {
+                let exporter = opentelemetry_stdout::MetricExporter::default();
+                provider = provider.with_reader(PeriodicReader::builder(exporter).build());
+            }
 
-            opentelemetry::global::set_meter_provider(provider);
+            if let Some(exporter) = prometheus_exporter {
+                provider = provider.with_reader(exporter);
+            }
+
+            opentelemetry::global::set_meter_provider(provider.build());
         }
 
         // The Headless Client will bail out here if there's no Internet, because `PhoenixChannel` will try to
@@ -289,6 +320,23 @@ fn main() -> Result<()> {
         session.set_tun(tun);
         session.set_dns(dns_controller.system_resolvers());
 
+        let (control_requests_tx, mut control_requests) = mpsc::channel::<control::Request>(10);
+
+        #[cfg(target_os = "linux")]
+        match control::Server::new(&cli.control_socket) {
+            Ok(server) => {
+                tokio::spawn(server.run(control_requests_tx));
+            }
+            Err(e) => {
+                tracing::warn!("Failed to create control socket, continuing without it: {e:#}");
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        drop(control_requests_tx);
+
+        let mut resources = Vec::new();
+        let mut disabled_resources = BTreeSet::new();
+
         let result = loop {
             let event = tokio::select! {
                 () = terminate.recv() => {
@@ -312,15 +360,81 @@ fn main() -> Result<()> {
                     session.reset("network changed".to_owned());
                     continue;
                 },
+                Some(request) = control_requests.recv() => {
+                    let response = match request.msg {
+                        control::ClientMsg::Status => control::ServerMsg::Status(control::Status {
+                            version: VERSION.to_owned(),
+                            tunnel_ready: last_connlib_start_instant.is_none(),
+                            num_resources: resources.len(),
+                            num_active_resources: resources.iter().filter(|r| r.traffic().is_active()).count(),
+                            disabled_resources: disabled_resources.clone(),
+                        }),
+                        control::ClientMsg::Resources => control::ServerMsg::Resources(resources.clone()),
+                        control::ClientMsg::DisableResource(id) => {
+                            if disabled_resources.insert(id) {
+                                session.set_disabled_resources(disabled_resources.clone());
+                            }
+                            control::ServerMsg::Done(Ok(()))
+                        }
+                        control::ClientMsg::EnableResource(id) => {
+                            if disabled_resources.remove(&id) {
+                                session.set_disabled_resources(disabled_resources.clone());
+                            }
+                            control::ServerMsg::Done(Ok(()))
+                        }
+                        control::ClientMsg::Reset => {
+                            session.reset("control socket".to_owned());
+                            control::ServerMsg::Done(Ok(()))
+                        }
+                        control::ClientMsg::StartCapture => {
+                            let result = async {
+                                let path = control::new_capture_path()?;
+                                session.start_capture(path.clone()).await?;
+
+                                anyhow::Ok(path)
+                            }
+                            .await;
+
+                            control::ServerMsg::CaptureStarted(result.map_err(|e| format!("{e:#}")))
+                        }
+                        control::ClientMsg::StopCapture => {
+                            session.stop_capture();
+                            control::ServerMsg::Done(Ok(()))
+                        }
+                        control::ClientMsg::ApplyLogFilter { directives } => {
+                            let result = log_filter_reloader.reload(&directives);
+
+                            if result.is_ok() {
+                                tracing::info!(%directives, "Applied new logging directives");
+                            }
+
+                            control::ServerMsg::Done(result.map_err(|e| format!("{e:#}")))
+                        }
+                    };
+
+                    let _ = request.reply.send(response);
+                    continue;
+                },
                 event = event_stream.next() => event.context("event stream unexpectedly ran empty")?,
             };
 
             match event {
                 // TODO: Headless Client shouldn't be using messages labelled `Ipc`
                 client_shared::Event::Disconnected(error) => break Err(anyhow!(error).context("Firezone disconnected")),
-                client_shared::Event::ResourcesUpdated(_) => {
+                client_shared::Event::ResourcesUpdated(new_resources) => {
                     // On every Resources update, flush DNS to mitigate <https://github.com/firezone/firezone/issues/5052>
                     dns_controller.flush()?;
+                    resources = new_resources;
+                }
+                client_shared::Event::ResourceTrafficUpdated(traffic) => {
+                    resources = resources
+                        .into_iter()
+                        .map(|r| {
+                            let traffic = traffic.get(&r.id()).copied().unwrap_or_default();
+
+                            r.with_traffic(traffic)
+                        })
+                        .collect();
                 }
                 client_shared::Event::TunInterfaceUpdated {
                     ipv4,
@@ -422,4 +536,34 @@ mod tests {
         assert!(actual.check);
         assert_eq!(actual.log_dir, Some(PathBuf::from("bogus_log_dir")));
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn control_subcommands() {
+        use super::{Cmd, control};
+
+        let exe_name = "firezone-headless-client";
+        let id = "73037362-715d-4a83-a749-f18eadd970e6";
+
+        let actual = Cli::try_parse_from([exe_name, "status"]).unwrap();
+        assert!(matches!(
+            actual.command,
+            Some(Cmd::Control(control::Command::Status))
+        ));
+
+        let actual = Cli::try_parse_from([exe_name, "disable", id]).unwrap();
+        let Some(Cmd::Control(control::Command::Disable { id: actual })) = actual.command else {
+            panic!("Expected `disable` subcommand");
+        };
+        assert_eq!(actual.to_string(), id);
+
+        assert!(Cli::try_parse_from([exe_name, "enable", "not-a-uuid"]).is_err());
+
+        let actual = Cli::try_parse_from([exe_name, "start-capture"]).unwrap();
+        assert!(matches!(
+            actual.command,
+            Some(Cmd::Control(control::Command::StartCapture))
+        ));
+        assert!(Cli::try_parse_from([exe_name, "start-capture", "/etc/shadow"]).is_err());
+    }
 }
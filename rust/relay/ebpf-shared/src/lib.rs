@@ -149,6 +149,32 @@ impl PortAndPeerV6 {
     }
 }
 
+/// Identifies a channel by its allocation, regardless of the IP versions of client and peer.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct AllocationAndChannel {
+    allocation_port: [u8; 2],
+    channel: [u8; 2],
+}
+
+impl AllocationAndChannel {
+    pub fn new(allocation_port: u16, channel: u16) -> Self {
+        Self {
+            allocation_port: allocation_port.to_be_bytes(),
+            channel: channel.to_be_bytes(),
+        }
+    }
+
+    pub fn allocation_port(&self) -> u16 {
+        u16::from_be_bytes(self.allocation_port)
+    }
+
+    pub fn channel(&self) -> u16 {
+        u16::from_be_bytes(self.channel)
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -232,5 +258,7 @@ mod userspace {
 
     unsafe impl aya::Pod for PortAndPeerV6 {}
 
+    unsafe impl aya::Pod for AllocationAndChannel {}
+
     unsafe impl aya::Pod for Config {}
 }
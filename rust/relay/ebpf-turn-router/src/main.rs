@@ -205,6 +205,9 @@ fn try_handle_ipv4_channel_data_to_udp(
         cd.length(),
     );
 
+    // `cd` points into the header we are about to remove.
+    stats::count_channel_bytes(port_and_peer.allocation_port(), cd.number(), cd.length());
+
     remove_channel_data_header_ipv4(ctx)?;
 
     Ok(())
@@ -258,6 +261,8 @@ fn try_handle_ipv4_udp_to_channel_data(
         },
     )?;
 
+    stats::count_channel_bytes(key.allocation_port(), channel_number, channel_data_length);
+
     Ok(())
 }
 
@@ -351,6 +356,8 @@ fn try_handle_ipv6_udp_to_channel_data(
         },
     )?;
 
+    stats::count_channel_bytes(key.allocation_port(), channel_number, channel_data_length);
+
     Ok(())
 }
 
@@ -392,6 +399,9 @@ fn try_handle_ipv6_channel_data_to_udp(
         cd.length(),
     );
 
+    // `cd` points into the header we are about to remove.
+    stats::count_channel_bytes(port_and_peer.allocation_port(), cd.number(), cd.length());
+
     remove_channel_data_header_ipv6(ctx)?;
 
     Ok(())
@@ -1,9 +1,24 @@
-use aya_ebpf::{macros::map, maps::PerfEventArray, programs::XdpContext};
-use ebpf_shared::StatsEvent;
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    macros::map,
+    maps::{PerCpuHashMap, PerfEventArray},
+    programs::XdpContext,
+};
+use ebpf_shared::{AllocationAndChannel, StatsEvent};
+
+use crate::NUM_ENTRIES;
 
 #[map]
 static STATS: PerfEventArray<StatsEvent> = PerfEventArray::new(0);
 
+/// The number of bytes relayed per channel, in both directions combined.
+///
+/// Entries are reset by userspace whenever the channel binding changes.
+/// Per-CPU maps are allocated for every CPU, hence we only allocate entries once they are used.
+#[map]
+static CHANNEL_BYTES: PerCpuHashMap<AllocationAndChannel, u64> =
+    PerCpuHashMap::with_max_entries(NUM_ENTRIES, BPF_F_NO_PREALLOC);
+
 pub fn emit_data_relayed(ctx: &XdpContext, bytes: impl Into<u64>) {
     STATS.output(
         ctx,
@@ -13,3 +28,16 @@ pub fn emit_data_relayed(ctx: &XdpContext, bytes: impl Into<u64>) {
         0,
     );
 }
+
+pub fn count_channel_bytes(allocation_port: u16, channel: u16, bytes: impl Into<u64>) {
+    let key = AllocationAndChannel::new(allocation_port, channel);
+    let bytes = bytes.into();
+
+    match CHANNEL_BYTES.get_ptr_mut(&key) {
+        // Safety: The map is per-CPU, nobody else is writing to this value concurrently.
+        Some(counter) => unsafe { *counter += bytes },
+        None => {
+            let _ = CHANNEL_BYTES.insert(&key, &bytes, 0);
+        }
+    }
+}
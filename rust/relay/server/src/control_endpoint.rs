@@ -1,30 +1,82 @@
-use axum::Router;
-use axum::extract::{Query, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
-use axum::routing::post;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use firezone_bin_shared::prometheus_metrics::PrometheusMetrics;
 use firezone_logging::FilterReloadHandle;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
 
-/// Runs an HTTP server that responds to `POST /log_filter?directives=` and sets the given directives as the new log-filter.
+use crate::{AllocationInfo, AllocationPort, ChannelInfo};
+
+/// Runs an HTTP server for operating the relay.
+///
+/// - `POST /log_filter?directives=` sets the given directives as the new log-filter.
+/// - `GET /stats` returns the number of allocations, channels and relayed bytes, including the eBPF offload status.
+/// - `GET /allocations` lists all allocations with their channel bindings and relayed bytes, including the ones relayed by eBPF per channel.
+/// - `DELETE /allocations/{port}` forcibly frees the allocation on the given port.
+/// - `POST /drain` stops accepting new allocations and shuts the relay down once all existing ones have expired.
+/// - `GET /metrics` serves the given metrics, if any.
+///
+/// All requests other than `/log_filter` are forwarded to the event-loop via the given channel.
 pub async fn serve(
     addr: impl Into<SocketAddr>,
     filter_reload_handle: FilterReloadHandle,
+    requests: mpsc::Sender<Request>,
+    metrics: Option<PrometheusMetrics>,
 ) -> std::io::Result<()> {
     let addr = addr.into();
 
-    let service = Router::new()
+    let mut router = Router::new()
         .route("/log_filter", post(set_log_filter))
+        .route("/stats", get(get_stats))
+        .route("/allocations", get(list_allocations))
+        .route("/allocations/{port}", delete(free_allocation))
+        .route("/drain", post(drain))
         .with_state(AppState {
             handle: Arc::new(filter_reload_handle),
-        })
-        .into_make_service();
+            requests,
+        });
+
+    if let Some(metrics) = metrics {
+        router = router.merge(metrics.router());
+    }
 
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, service).await?;
+    axum::serve(
+        tokio::net::TcpListener::bind(addr).await?,
+        router.into_make_service(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// A request from the control endpoint to the event-loop that owns the [`Server`](crate::Server).
+#[derive(Debug)]
+pub enum Request {
+    Stats(oneshot::Sender<Stats>),
+    Allocations(oneshot::Sender<Vec<AllocationInfo>>),
+    /// Reply with `false` if there is no allocation on the given port.
+    FreeAllocation(AllocationPort, oneshot::Sender<bool>),
+    Drain(oneshot::Sender<()>),
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Stats {
+    pub num_allocations: usize,
+    pub num_active_channels: usize,
+    pub num_relayed_bytes: u64,
+    pub draining: bool,
+    /// [`None`] if the eBPF program is not loaded.
+    pub ebpf: Option<EbpfStats>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct EbpfStats {
+    pub num_relayed_bytes: u64,
+}
+
 async fn set_log_filter(Query(params): Query<QueryParams>, state: State<AppState>) -> StatusCode {
     let directives = params.directives;
 
@@ -42,12 +94,107 @@ async fn set_log_filter(Query(params): Query<QueryParams>, state: State<AppState
     }
 }
 
+async fn get_stats(state: State<AppState>) -> Result<Json<Stats>, StatusCode> {
+    let stats = state.request(Request::Stats).await?;
+
+    Ok(Json(stats))
+}
+
+async fn list_allocations(state: State<AppState>) -> Result<Json<Vec<Allocation>>, StatusCode> {
+    let allocations = state.request(Request::Allocations).await?;
+
+    Ok(Json(
+        allocations.into_iter().map(Allocation::from).collect(),
+    ))
+}
+
+async fn free_allocation(Path(port): Path<u16>, state: State<AppState>) -> StatusCode {
+    let port = AllocationPort::new(port);
+
+    match state
+        .request(|reply| Request::FreeAllocation(port, reply))
+        .await
+    {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(code) => code,
+    }
+}
+
+async fn drain(state: State<AppState>) -> StatusCode {
+    match state.request(Request::Drain).await {
+        Ok(()) => StatusCode::ACCEPTED,
+        Err(code) => code,
+    }
+}
+
 #[derive(Clone)]
 struct AppState {
     handle: Arc<FilterReloadHandle>,
+    requests: mpsc::Sender<Request>,
+}
+
+impl AppState {
+    async fn request<T>(
+        &self,
+        make_request: impl FnOnce(oneshot::Sender<T>) -> Request,
+    ) -> Result<T, StatusCode> {
+        let (tx, rx) = oneshot::channel();
+
+        self.requests
+            .send(make_request(tx))
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+        rx.await.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+    }
 }
 
 #[derive(serde::Deserialize)]
 struct QueryParams {
     directives: String,
 }
+
+#[derive(serde::Serialize)]
+struct Allocation {
+    port: u16,
+    client: SocketAddr,
+    relay_addresses: Vec<IpAddr>,
+    expires_in_secs: u64,
+    num_relayed_bytes: u64,
+    channels: Vec<Channel>,
+}
+
+#[derive(serde::Serialize)]
+struct Channel {
+    number: u16,
+    peer: SocketAddr,
+    bound: bool,
+    expires_in_secs: u64,
+    num_ebpf_relayed_bytes: u64,
+}
+
+impl From<AllocationInfo> for Allocation {
+    fn from(info: AllocationInfo) -> Self {
+        Self {
+            port: info.port.value(),
+            client: info.client.into_socket(),
+            relay_addresses: info.relay_addresses,
+            expires_in_secs: info.expires_in.as_secs(),
+            num_relayed_bytes: info.num_relayed_bytes,
+            channels: info.channels.into_iter().map(Channel::from).collect(),
+        }
+    }
+}
+
+impl From<ChannelInfo> for Channel {
+    fn from(info: ChannelInfo) -> Self {
+        Self {
+            number: info.number.value(),
+            peer: info.peer.into_socket(),
+            bound: info.bound,
+            expires_in_secs: info.expires_in.as_secs(),
+            num_ebpf_relayed_bytes: info.num_ebpf_relayed_bytes,
+        }
+    }
+}
@@ -1,15 +1,18 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::{Context as _, Result};
 use aya::{
     Pod,
-    maps::{Array, AsyncPerfEventArray, HashMap, MapData},
+    maps::{Array, AsyncPerfEventArray, HashMap, MapData, PerCpuHashMap},
     programs::{Xdp, XdpFlags},
 };
 use aya_log::EbpfLogger;
 use bytes::BytesMut;
 use ebpf_shared::{
-    ClientAndChannelV4, ClientAndChannelV6, Config, PortAndPeerV4, PortAndPeerV6, StatsEvent,
+    AllocationAndChannel, ClientAndChannelV4, ClientAndChannelV6, Config, PortAndPeerV4,
+    PortAndPeerV6, StatsEvent,
 };
 use stun_codec::rfc5766::attributes::ChannelNumber;
 
@@ -27,6 +30,9 @@ pub struct Program {
 
     #[expect(dead_code, reason = "We are just keeping it alive.")]
     stats: AsyncPerfEventArray<MapData>,
+
+    /// Keep a separate counter because `Counter` doesn't expose the current value.
+    num_relayed_bytes: Arc<AtomicU64>,
 }
 
 impl Program {
@@ -55,6 +61,7 @@ impl Program {
             .with_description("The number of bytes relayed by the eBPF kernel")
             .with_unit("b")
             .build();
+        let num_relayed_bytes = Arc::new(AtomicU64::new(0));
 
         for cpu_id in aya::util::online_cpus()
             .map_err(|(_, error)| error)
@@ -68,6 +75,7 @@ impl Program {
             // process each perf buffer in a separate task
             tokio::task::spawn({
                 let data_relayed = data_relayed.clone();
+                let num_relayed_bytes = num_relayed_bytes.clone();
 
                 async move {
                     let mut buffers = (0..PAGE_COUNT)
@@ -100,6 +108,7 @@ impl Program {
                             };
 
                             data_relayed.add(stats.relayed_data, &[]);
+                            num_relayed_bytes.fetch_add(stats.relayed_data, Ordering::Relaxed);
                         }
                     }
                 }
@@ -108,7 +117,43 @@ impl Program {
 
         tracing::info!("eBPF TURN router loaded and attached to interface {interface}");
 
-        Ok(Self { ebpf, stats })
+        Ok(Self {
+            ebpf,
+            stats,
+            num_relayed_bytes,
+        })
+    }
+
+    /// The number of bytes relayed by the eBPF kernel since the program was loaded.
+    pub fn num_relayed_bytes(&self) -> u64 {
+        self.num_relayed_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes relayed by the eBPF kernel through each channel since it was bound.
+    pub fn relayed_bytes_by_channel(&self) -> Result<Vec<(AllocationPort, ChannelNumber, u64)>> {
+        let map = self
+            .ebpf
+            .map("CHANNEL_BYTES")
+            .context("Map `CHANNEL_BYTES` not found")?;
+        let map = PerCpuHashMap::<_, AllocationAndChannel, u64>::try_from(map)
+            .context("Failed to convert map")?;
+
+        let mut relayed_bytes = Vec::new();
+
+        for entry in map.iter() {
+            let (key, per_cpu_bytes) = entry.context("Failed to read map entry")?;
+            let Ok(channel_number) = ChannelNumber::new(key.channel()) else {
+                continue;
+            };
+
+            relayed_bytes.push((
+                AllocationPort::new(key.allocation_port()),
+                channel_number,
+                per_cpu_bytes.iter().sum(),
+            ));
+        }
+
+        Ok(relayed_bytes)
     }
 
     pub fn add_channel_binding(
@@ -118,6 +163,8 @@ impl Program {
         peer: PeerSocket,
         allocation_port: AllocationPort,
     ) -> Result<()> {
+        self.reset_relayed_bytes(channel_number, allocation_port)?;
+
         let client = client.into_socket();
         let peer = peer.into_socket();
 
@@ -174,6 +221,8 @@ impl Program {
         peer: PeerSocket,
         allocation_port: AllocationPort,
     ) -> Result<()> {
+        self.reset_relayed_bytes(channel_number, allocation_port)?;
+
         let client = client.into_socket();
         let peer = peer.into_socket();
 
@@ -221,6 +270,27 @@ impl Program {
         Ok(())
     }
 
+    fn reset_relayed_bytes(
+        &mut self,
+        channel_number: ChannelNumber,
+        allocation_port: AllocationPort,
+    ) -> Result<()> {
+        let map = self
+            .ebpf
+            .map_mut("CHANNEL_BYTES")
+            .context("Map `CHANNEL_BYTES` not found")?;
+        let mut map = PerCpuHashMap::<_, AllocationAndChannel, u64>::try_from(map)
+            .context("Failed to convert map")?;
+
+        // The kernel only creates an entry once it relayed data through the channel.
+        let _ = map.remove(&AllocationAndChannel::new(
+            allocation_port.value(),
+            channel_number.value(),
+        ));
+
+        Ok(())
+    }
+
     fn chan_to_udp_44_map_mut(
         &mut self,
     ) -> Result<HashMap<&mut MapData, ClientAndChannelV4, PortAndPeerV4>> {
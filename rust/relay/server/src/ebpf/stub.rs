@@ -40,6 +40,14 @@ impl Program {
         Ok(())
     }
 
+    pub fn num_relayed_bytes(&self) -> u64 {
+        0
+    }
+
+    pub fn relayed_bytes_by_channel(&self) -> Result<Vec<(AllocationPort, ChannelNumber, u64)>> {
+        Ok(Vec::new())
+    }
+
     pub fn config(&self) -> Config {
         Config::default()
     }
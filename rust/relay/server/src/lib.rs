@@ -14,8 +14,8 @@ pub mod sockets;
 
 pub use net_ext::IpAddrExt;
 pub use server::{
-    Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData, ClientMessage, Command,
-    CreatePermission, Refresh, Server,
+    Allocate, AllocationInfo, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
+    ChannelInfo, ClientMessage, Command, CreatePermission, Quotas, Refresh, Server,
 };
 pub use sleep::Sleep;
 use stun_codec::rfc5389::attributes::Software;
@@ -5,25 +5,29 @@ use backoff::ExponentialBackoffBuilder;
 use clap::Parser;
 use ebpf_shared::Config;
 use firezone_bin_shared::http_health_check;
+use firezone_bin_shared::prometheus_metrics::PrometheusMetrics;
 use firezone_logging::{FilterReloadHandle, err_with_src, sentry_layer};
-use firezone_relay::sockets::Sockets;
+use firezone_relay::sockets::{Sockets, StreamInput};
 use firezone_relay::{
-    AddressFamily, AllocationPort, ChannelData, ClientSocket, Command, IpStack, PeerSocket, Server,
-    Sleep, VERSION, control_endpoint, ebpf, sockets,
+    AddressFamily, AllocationPort, ChannelData, ClientSocket, Command, IpStack, PeerSocket, Quotas,
+    Server, Sleep, VERSION, control_endpoint, ebpf, sockets,
 };
 use firezone_telemetry::{RELAY_DSN, Telemetry};
 use futures::{FutureExt, future};
+use opentelemetry_prometheus::PrometheusExporter;
 use phoenix_channel::{Event, LoginUrl, NoParams, PhoenixChannel};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use secrecy::{ExposeSecret, Secret, SecretString};
 use std::borrow::Cow;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Poll, ready};
 use std::time::{Duration, Instant};
 use stun_codec::rfc5766::attributes::ChannelNumber;
+use tokio::sync::mpsc;
 use tracing::Subscriber;
 use tracing_core::Dispatch;
 use tracing_stackdriver::CloudTraceConfiguration;
@@ -31,6 +35,7 @@ use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 use url::Url;
 
 const STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+const EBPF_STATS_INTERVAL: Duration = Duration::from_secs(1);
 
 const MAX_PARTITION_TIME: Duration = Duration::from_secs(60 * 15);
 
@@ -45,6 +50,30 @@ struct Args {
     /// The port to listen on for STUN messages.
     #[arg(long, env, hide = true, default_value = "3478")]
     listen_port: u16,
+    /// The port to listen on for TURN over TLS.
+    #[arg(long, env, hide = true, default_value = "443")]
+    tls_listen_port: u16,
+
+    /// An additional UDP port for answering RFC 5780 NAT behaviour discovery requests.
+    ///
+    /// If unset, we don't advertise an `OTHER-ADDRESS` in BINDING responses.
+    #[arg(long, env, hide = true)]
+    nat_discovery_port: Option<u16>,
+
+    /// The relay to redirect new allocations to while draining.
+    ///
+    /// If unset, new allocations are rejected with 508 (Insufficient Capacity) while draining.
+    #[arg(long, env, hide = true)]
+    alternate_server: Option<SocketAddr>,
+    /// Path to a PEM-encoded certificate chain for TURN over TLS.
+    ///
+    /// Clients only know relays by their IP, thus the certificate must be valid for the public IPs of the relay.
+    /// TURN over TLS is disabled unless both this and `tls_private_key` are set.
+    #[arg(long, env, hide = true, requires = "tls_private_key")]
+    tls_certificate: Option<PathBuf>,
+    /// Path to the PEM-encoded private key of `tls_certificate`.
+    #[arg(long, env, hide = true, requires = "tls_certificate")]
+    tls_private_key: Option<PathBuf>,
     // See https://www.rfc-editor.org/rfc/rfc8656.html#name-allocations
     /// The lowest port used for TURN allocations.
     #[arg(long, env, hide = true, default_value = "49152")]
@@ -88,6 +117,21 @@ struct Args {
     #[arg(long, env, hide = true)]
     ebpf_offloading: Option<String>,
 
+    /// The maximum number of allocations a single user can hold at the same time.
+    #[arg(long, env, hide = true)]
+    max_allocations_per_user: Option<usize>,
+
+    /// The maximum number of channels a single allocation can bind.
+    #[arg(long, env, hide = true)]
+    max_channels_per_allocation: Option<usize>,
+
+    /// The maximum number of bytes per second relayed through a single allocation.
+    ///
+    /// Allocations exceeding this are no longer offloaded to eBPF until their quota recovered.
+    /// Must be at least the size of the largest datagram we relay.
+    #[arg(long, env, hide = true, value_parser = parse_max_bytes_per_second)]
+    max_bytes_per_second_per_allocation: Option<u64>,
+
     #[command(flatten)]
     health_check: http_health_check::HealthCheckArgs,
 
@@ -95,6 +139,10 @@ struct Args {
     #[arg(long, env, hide = true, default_value = "127.0.0.1:9999")]
     control_endpoint: SocketAddr,
 
+    /// Serve metrics at `/metrics` on the control endpoint for Prometheus to scrape.
+    #[arg(long, env, hide = true, default_value_t = false)]
+    prometheus_metrics: bool,
+
     /// Enable sentry.io crash-reporting agent.
     #[arg(long, env = "FIREZONE_TELEMETRY", default_value_t = false)]
     telemetry: bool,
@@ -141,7 +189,13 @@ fn main() {
 }
 
 async fn try_main(args: Args) -> Result<()> {
-    let filter_reload_handle = setup_tracing(&args)?;
+    let (prometheus_metrics, prometheus_exporter) = args
+        .prometheus_metrics
+        .then(PrometheusMetrics::new)
+        .transpose()?
+        .unzip();
+
+    let filter_reload_handle = setup_tracing(&args, prometheus_exporter)?;
 
     let mut ebpf = args
         .ebpf_offloading
@@ -169,23 +223,52 @@ async fn try_main(args: Args) -> Result<()> {
         }
     };
 
-    let server = Server::new(
+    let tls = match (
+        args.tls_certificate.as_deref(),
+        args.tls_private_key.as_deref(),
+    ) {
+        (Some(certificate), Some(private_key)) => Some((
+            args.tls_listen_port,
+            load_tls_config(certificate, private_key).context("Failed to load TLS config")?,
+        )),
+        _ => None,
+    };
+
+    let mut server = Server::new(
         public_addr,
         make_rng(args.rng_seed),
         args.listen_port,
         args.lowest_port..=args.highest_port,
-    );
+    )
+    .with_quotas(Quotas {
+        max_allocations_per_user: args.max_allocations_per_user,
+        max_channels_per_allocation: args.max_channels_per_allocation,
+        max_bytes_per_second_per_allocation: args.max_bytes_per_second_per_allocation,
+    });
+
+    if let Some(port) = args.nat_discovery_port {
+        server = server.with_alternate_port(port);
+    }
+
+    if let Some(alternate_server) = args.alternate_server {
+        server = server.with_alternate_server(alternate_server);
+    }
 
     let last_heartbeat_sent = Arc::new(Mutex::new(Option::<Instant>::None));
 
     tokio::spawn(http_health_check::serve(
         args.health_check.health_check_addr,
         make_is_healthy(last_heartbeat_sent.clone()),
+        None,
     ));
 
+    let (control_requests_tx, control_requests_rx) = mpsc::channel(10);
+
     tokio::spawn(control_endpoint::serve(
         args.control_endpoint,
         filter_reload_handle,
+        control_requests_tx,
+        prometheus_metrics,
     ));
 
     let login = LoginUrl::relay(
@@ -213,9 +296,26 @@ async fn try_main(args: Args) -> Result<()> {
     )?;
     channel.connect(NoParams);
 
-    let mut eventloop = Eventloop::new(server, ebpf, channel, public_addr, last_heartbeat_sent)?;
+    let tls_listen_port = tls.as_ref().map(|(port, _)| *port);
+    let mut eventloop = Eventloop::new(
+        server,
+        ebpf,
+        channel,
+        public_addr,
+        tls,
+        last_heartbeat_sent,
+        control_requests_rx,
+    )?;
+
+    tracing::info!(target: "relay", "Listening for incoming traffic on UDP and TCP port {0}", args.listen_port);
 
-    tracing::info!(target: "relay", "Listening for incoming traffic on UDP port {0}", args.listen_port);
+    if let Some(port) = tls_listen_port {
+        tracing::info!(target: "relay", "Listening for incoming traffic on TLS port {port}");
+    }
+
+    if let Some(port) = args.nat_discovery_port {
+        tracing::info!(target: "relay", "Listening for NAT behaviour discovery requests on UDP port {port}");
+    }
 
     future::poll_fn(|cx| eventloop.poll(cx))
         .await
@@ -226,6 +326,30 @@ async fn try_main(args: Args) -> Result<()> {
     Ok(())
 }
 
+fn load_tls_config(certificate: &Path, private_key: &Path) -> Result<Arc<rustls::ServerConfig>> {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _};
+
+    let certificate = std::fs::read(certificate)
+        .with_context(|| format!("Failed to read `{}`", certificate.display()))?;
+    let private_key = std::fs::read(private_key)
+        .with_context(|| format!("Failed to read `{}`", private_key.display()))?;
+
+    let certificate_chain = CertificateDer::pem_slice_iter(&certificate)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse certificate chain")?;
+    let private_key =
+        PrivateKeyDer::from_pem_slice(&private_key).context("Failed to parse private key")?;
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certificate_chain, private_key)?;
+
+    Ok(Arc::new(config))
+}
+
 /// Sets up our tracing infrastructure.
 ///
 /// See [`log_layer`] for details on the base log layer.
@@ -233,7 +357,14 @@ async fn try_main(args: Args) -> Result<()> {
 /// ## Integration with OTLP
 ///
 /// If the user has specified [`TraceCollector::Otlp`], we will set up an OTLP-exporter that connects to an OTLP collector specified at `Args.otlp_grpc_endpoint`.
-fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
+///
+/// ## Integration with Prometheus
+///
+/// The given [`PrometheusExporter`] is registered as an additional metrics reader, independently of OTLP.
+fn setup_tracing(
+    args: &Args,
+    prometheus_exporter: Option<PrometheusExporter>,
+) -> Result<FilterReloadHandle> {
     use opentelemetry::{global, trace::TracerProvider as _};
     use opentelemetry_otlp::WithExportConfig;
 
@@ -247,6 +378,15 @@ fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
 
     let (dispatch, reload_handle) = match args.otlp_grpc_endpoint.clone() {
         None => {
+            if let Some(prometheus_exporter) = prometheus_exporter {
+                global::set_meter_provider(
+                    opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+                        .with_resource(make_otel_metadata())
+                        .with_reader(prometheus_exporter)
+                        .build(),
+                );
+            }
+
             let (filter, reload_handle) = firezone_logging::try_filter(&directives)?;
 
             let dispatch: Dispatch = tracing_subscriber::registry()
@@ -283,12 +423,15 @@ fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
                 .build()
                 .context("Failed to build OTLP metric exporter")?;
 
-            let meter_provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+            let mut meter_provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
                 .with_resource(metadata)
-                .with_periodic_exporter(exporter)
-                .build();
+                .with_periodic_exporter(exporter);
 
-            global::set_meter_provider(meter_provider);
+            if let Some(prometheus_exporter) = prometheus_exporter {
+                meter_provider = meter_provider.with_reader(prometheus_exporter);
+            }
+
+            global::set_meter_provider(meter_provider.build());
 
             tracing::trace!(target: "relay", "Successfully initialized metric provider on tokio runtime");
 
@@ -364,6 +507,17 @@ fn make_rng(seed: Option<u64>) -> StdRng {
 
 const MAX_UDP_SIZE: usize = 65536;
 
+/// An allocation's quota allows bursts of one second worth of bytes, a smaller quota would never let the largest datagrams through.
+fn parse_max_bytes_per_second(s: &str) -> Result<u64> {
+    let bytes_per_second = s.parse::<u64>()?;
+
+    if bytes_per_second < MAX_UDP_SIZE as u64 {
+        bail!("Must be at least {MAX_UDP_SIZE} bytes, the size of the largest datagram we relay");
+    }
+
+    Ok(bytes_per_second)
+}
+
 struct Eventloop<R> {
     sockets: Sockets,
 
@@ -379,9 +533,12 @@ struct Eventloop<R> {
 
     stats_log_interval: tokio::time::Interval,
     last_num_bytes_relayed: u64,
+    ebpf_stats_interval: tokio::time::Interval,
 
     last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
 
+    control_requests: mpsc::Receiver<control_endpoint::Request>,
+
     buffer: [u8; MAX_UDP_SIZE],
 }
 
@@ -394,29 +551,37 @@ where
         ebpf: Option<ebpf::Program>,
         channel: PhoenixChannel<JoinMessage, IngressMessage, (), NoParams>,
         public_address: IpStack,
+        tls: Option<(u16, Arc<rustls::ServerConfig>)>,
         last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
+        control_requests: mpsc::Receiver<control_endpoint::Request>,
     ) -> Result<Self> {
         let mut sockets = Sockets::new();
 
-        if public_address.as_v4().is_some() {
+        let families = [
+            public_address.as_v4().map(|_| AddressFamily::V4),
+            public_address.as_v6().map(|_| AddressFamily::V6),
+        ];
+
+        for family in families.into_iter().flatten() {
             sockets
-                .bind(server.listen_port(), AddressFamily::V4)
+                .bind(server.listen_port(), family)
                 .with_context(|| {
                     format!(
-                        "Failed to bind to port {0} on IPv4 interfaces",
+                        "Failed to bind to port {0} on {family} interfaces",
                         server.listen_port()
                     )
                 })?;
-        }
-        if public_address.as_v6().is_some() {
-            sockets
-                .bind(server.listen_port(), AddressFamily::V6)
-                .with_context(|| {
-                    format!(
-                        "Failed to bind to port {0} on IPv6 interfaces",
-                        server.listen_port()
-                    )
+            sockets.listen_stream(server.listen_port(), family, None)?;
+
+            if let Some(alternate_port) = server.alternate_port() {
+                sockets.bind(alternate_port, family).with_context(|| {
+                    format!("Failed to bind to port {alternate_port} on {family} interfaces")
                 })?;
+            }
+
+            if let Some((tls_port, tls_config)) = tls.as_ref() {
+                sockets.listen_stream(*tls_port, family, Some(tls_config.clone()))?;
+            }
         }
 
         Ok(Self {
@@ -425,10 +590,12 @@ where
             sleep: Sleep::default(),
             stats_log_interval: tokio::time::interval(STATS_LOG_INTERVAL),
             last_num_bytes_relayed: 0,
+            ebpf_stats_interval: tokio::time::interval(EBPF_STATS_INTERVAL),
             sockets,
             ebpf,
             buffer: [0u8; MAX_UDP_SIZE],
             last_heartbeat_sent,
+            control_requests,
             #[cfg(unix)]
             sigterm: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
             shutting_down: false,
@@ -449,7 +616,7 @@ where
             if let Some(next_command) = self.server.next_command() {
                 match next_command {
                     Command::SendMessage { payload, recipient } => {
-                        if let Err(e) = self.sockets.try_send(
+                        if let Err(e) = self.sockets.try_send_to_client(
                             self.server.listen_port(),
                             recipient.into_socket(),
                             Cow::Owned(payload),
@@ -457,6 +624,19 @@ where
                             tracing::warn!(target: "relay", %recipient, "Failed to send message: {}", err_with_src(&e));
                         }
                     }
+                    Command::SendMessageFrom {
+                        payload,
+                        recipient,
+                        port,
+                    } => {
+                        if let Err(e) = self.sockets.try_send(
+                            port,
+                            recipient.into_socket(),
+                            Cow::Owned(payload),
+                        ) {
+                            tracing::warn!(target: "relay", %recipient, %port, "Failed to send message: {}", err_with_src(&e));
+                        }
+                    }
                     Command::CreateAllocation { port, family } => {
                         self.sockets.bind(port.value(), family).with_context(|| {
                             format!(
@@ -561,6 +741,16 @@ where
 
                     ready = true;
                 }
+                Poll::Ready(Ok(sockets::Received {
+                    port, // Packets coming in on the alternate port are NAT behaviour discovery requests from clients.
+                    from,
+                    packet,
+                })) if Some(port) == self.server.alternate_port() => {
+                    self.server
+                        .handle_alternate_port_input(packet, ClientSocket::new(from));
+
+                    ready = true;
+                }
                 Poll::Ready(Ok(sockets::Received {
                     port, // Packets coming in on any other port are from peers.
                     from,
@@ -570,6 +760,7 @@ where
                         packet,
                         PeerSocket::new(from),
                         AllocationPort::new(port),
+                        Instant::now(),
                     ) {
                         let total_length = ChannelData::encode_header_to_slice(
                             channel,
@@ -577,7 +768,7 @@ where
                             header,
                         );
 
-                        if let Err(e) = self.sockets.try_send(
+                        if let Err(e) = self.sockets.try_send_to_client(
                             self.server.listen_port(), // Packets coming in from peers always go out on the TURN port
                             client.into_socket(),
                             Cow::Borrowed(&self.buffer[..total_length]),
@@ -597,20 +788,58 @@ where
                 Poll::Pending => {}
             }
 
-            // Priority 3: Check when we need to next be woken. This needs to happen after all state modifications.
+            // Priority 3: Read from clients connected via TCP or TLS.
+            match self.sockets.poll_stream(cx) {
+                Poll::Ready(StreamInput::Connected(from)) => {
+                    self.server.handle_stream_connected(ClientSocket::new(from));
+
+                    ready = true;
+                }
+                Poll::Ready(StreamInput::Message(from, message)) => {
+                    if let Some((port, peer)) = self.server.handle_client_input(
+                        &message,
+                        ClientSocket::new(from),
+                        Instant::now(),
+                    ) {
+                        let payload = ChannelData::parse(&message)
+                            .expect("valid ChannelData if we should relay it")
+                            .data();
+
+                        // The allocation itself is always UDP.
+                        if let Err(e) = self.sockets.try_send(
+                            port.value(),
+                            peer.into_socket(),
+                            Cow::Borrowed(payload),
+                        ) {
+                            tracing::warn!(target: "relay", %peer, "Failed to relay data to peer: {}", err_with_src(&e));
+                        }
+                    };
+
+                    ready = true;
+                }
+                Poll::Ready(StreamInput::Disconnected(from)) => {
+                    self.server
+                        .handle_stream_disconnected(ClientSocket::new(from));
+
+                    ready = true;
+                }
+                Poll::Pending => {}
+            }
+
+            // Priority 4: Check when we need to next be woken. This needs to happen after all state modifications.
             if let Some(timeout) = self.server.poll_timeout() {
                 Pin::new(&mut self.sleep).reset(timeout);
                 // Purposely no `ready = true` because we just change the state of `sleep` and we poll it below.
             }
 
-            // Priority 4: Handle time-sensitive tasks:
+            // Priority 5: Handle time-sensitive tasks:
             if let Poll::Ready(deadline) = self.sleep.poll_unpin(cx) {
                 self.server.handle_timeout(deadline);
 
                 ready = true;
             }
 
-            // Priority 5: Handle portal messages
+            // Priority 6: Handle portal messages
             match self.channel.as_mut().map(|c| c.poll(cx)) {
                 Some(Poll::Ready(result)) => {
                     let event = result.context("Portal connection failed")?;
@@ -621,6 +850,13 @@ where
                 Some(Poll::Pending) | None => {}
             }
 
+            // Priority 7: Handle requests from the control endpoint
+            if let Poll::Ready(Some(request)) = self.control_requests.poll_recv(cx) {
+                self.handle_control_request(request);
+
+                ready = true;
+            }
+
             #[cfg(unix)]
             match self.sigterm.poll_recv(cx) {
                 Poll::Ready(Some(())) => {
@@ -632,22 +868,19 @@ where
 
                     tracing::info!(active_allocations = %self.server.num_allocations(), "Received SIGTERM, initiating graceful shutdown");
 
-                    self.shutting_down = true;
-
-                    if let Some(portal) = self.channel.as_mut() {
-                        match portal.close() {
-                            Ok(()) => {}
-                            Err(phoenix_channel::Connecting) => {
-                                self.channel = None; // If we are still connecting, just discard the websocket connection.
-                            }
-                        }
-                    }
+                    self.start_draining();
 
                     ready = true;
                 }
                 Poll::Ready(None) | Poll::Pending => {}
             }
 
+            if self.ebpf_stats_interval.poll_tick(cx).is_ready() {
+                self.sync_ebpf_relayed_bytes();
+
+                ready = true;
+            }
+
             if self.stats_log_interval.poll_tick(cx).is_ready() {
                 let num_allocations = self.server.num_allocations();
                 let num_channels = self.server.num_active_channels();
@@ -669,6 +902,71 @@ where
         }
     }
 
+    /// Stops accepting new allocations and shuts down once all existing ones have expired.
+    fn start_draining(&mut self) {
+        self.shutting_down = true;
+        self.server.drain();
+
+        if let Some(portal) = self.channel.as_mut() {
+            match portal.close() {
+                Ok(()) => {}
+                Err(phoenix_channel::Connecting) => {
+                    self.channel = None; // If we are still connecting, just discard the websocket connection.
+                }
+            }
+        }
+    }
+
+    fn handle_control_request(&mut self, request: control_endpoint::Request) {
+        // The HTTP handler may have gone away in the meantime, ignore those errors.
+        match request {
+            control_endpoint::Request::Stats(reply) => {
+                let _ = reply.send(control_endpoint::Stats {
+                    num_allocations: self.server.num_allocations(),
+                    num_active_channels: self.server.num_active_channels(),
+                    num_relayed_bytes: self.server.num_relayed_bytes(),
+                    draining: self.server.is_draining(),
+                    ebpf: self.ebpf.as_ref().map(|ebpf| control_endpoint::EbpfStats {
+                        num_relayed_bytes: ebpf.num_relayed_bytes(),
+                    }),
+                });
+            }
+            control_endpoint::Request::Allocations(reply) => {
+                self.sync_ebpf_relayed_bytes();
+
+                let _ = reply.send(self.server.allocations(Instant::now()));
+            }
+            control_endpoint::Request::FreeAllocation(port, reply) => {
+                let _ = reply.send(self.server.free_allocation(port));
+            }
+            control_endpoint::Request::Drain(reply) => {
+                if !self.shutting_down {
+                    tracing::info!(active_allocations = %self.server.num_allocations(), "Drain requested via control endpoint, initiating graceful shutdown");
+
+                    self.start_draining();
+                }
+
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    /// Hands the per-channel byte counters of our eBPF kernel to the [`Server`].
+    fn sync_ebpf_relayed_bytes(&mut self) {
+        let Some(ebpf) = self.ebpf.as_ref() else {
+            return;
+        };
+
+        match ebpf.relayed_bytes_by_channel() {
+            Ok(relayed_bytes) => self
+                .server
+                .handle_ebpf_relayed_bytes(relayed_bytes, Instant::now()),
+            Err(e) => {
+                tracing::warn!(target: "relay", "Failed to read relayed bytes from eBPF: {e:#}")
+            }
+        }
+    }
+
     fn create_channel_binding_in_ebpf_map(
         &mut self,
         client: ClientSocket,
@@ -793,6 +1091,12 @@ mod tests {
         assert_eq!(fmt_human_throughput(100_000_000_000.0), "100.00 GB/s");
     }
 
+    #[test]
+    fn rejects_byte_rate_quota_smaller_than_a_datagram() {
+        assert!(parse_max_bytes_per_second("1000").is_err());
+        assert_eq!(parse_max_bytes_per_second("1000000").unwrap(), 1_000_000);
+    }
+
     // If we are running in standalone mode, we are always healthy.
     #[test]
     fn given_no_heartbeat_is_healthy() {
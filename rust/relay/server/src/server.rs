@@ -1,13 +1,16 @@
 mod channel_data;
 mod client_message;
+mod quota;
 
 pub use crate::server::channel_data::ChannelData;
 pub use crate::server::client_message::{
     Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh,
 };
+pub use crate::server::quota::Quotas;
 
 use crate::auth::{self, AuthenticatedMessage, FIREZONE, MessageIntegrityExt, Nonces};
 use crate::net_ext::IpAddrExt;
+use crate::server::quota::ByteRateLimiter;
 use crate::{ClientSocket, IpStack, PeerSocket, SOFTWARE};
 use anyhow::Result;
 use bytecodec::EncodeExt;
@@ -19,21 +22,28 @@ use opentelemetry::metrics::{Counter, UpDownCounter};
 use rand::Rng;
 use secrecy::SecretString;
 use smallvec::SmallVec;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::iter;
 use std::net::{IpAddr, SocketAddr};
 use std::ops::RangeInclusive;
 use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{
-    ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username, XorMappedAddress,
+    AlternateServer, ErrorCode, MessageIntegrity, Nonce, Realm, Software, Username,
+    XorMappedAddress,
+};
+use stun_codec::rfc5389::errors::{
+    BadRequest, ServerError, StaleNonce, TryAlternate, Unauthorized, UnknownAttribute,
 };
-use stun_codec::rfc5389::errors::{BadRequest, ServerError, StaleNonce, Unauthorized};
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
     ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
 };
-use stun_codec::rfc5766::errors::{AllocationMismatch, InsufficientCapacity};
+use stun_codec::rfc5766::errors::{
+    AllocationMismatch, AllocationQuotaReached, InsufficientCapacity,
+};
 use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5780::attributes::{ChangeRequest, OtherAddress, ResponseOrigin};
 use stun_codec::rfc8656::attributes::{
     AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
 };
@@ -44,7 +54,9 @@ use uuid::Uuid;
 
 /// A sans-IO STUN & TURN server.
 ///
-/// A [`Server`] is bound to an IPv4 address and assumes to only operate on UDP.
+/// A [`Server`] is bound to an IPv4 address and always relays via UDP.
+/// Clients may talk to it via UDP or via a TCP / TLS stream, see [`Server::handle_stream_connected`].
+/// We assume that a client's [`SocketAddr`] is never in use for UDP and a stream at the same time.
 /// Thus, 3 out of the 5 components of a "5-tuple" are unique to an instance of [`Server`] and
 /// we can index data simply by the sender's [`SocketAddr`].
 ///
@@ -61,6 +73,10 @@ pub struct Server<R> {
         HashMap<(AllocationPort, PeerSocket), (ClientSocket, ChannelNumber)>,
 
     listen_port: u16,
+    /// The port we answer RFC 5780 NAT behaviour discovery requests on, see [`Server::with_alternate_port`].
+    alternate_port: Option<u16>,
+    /// The relay we redirect new allocations to while draining, see [`Server::with_alternate_server`].
+    alternate_server: Option<SocketAddr>,
 
     ports: RangeInclusive<u16>,
 
@@ -71,16 +87,50 @@ pub struct Server<R> {
 
     pending_commands: VecDeque<Command>,
 
+    /// Clients that are connected via a TCP or TLS stream instead of UDP.
+    stream_clients: HashSet<ClientSocket>,
+
     rng: R,
 
     auth_secret: SecretString,
 
     nonces: Nonces,
 
+    quotas: Quotas,
+
+    /// Whether we are draining, see [`Server::drain`].
+    draining: bool,
+
     allocations_up_down_counter: UpDownCounter<i64>,
     data_relayed_counter: Counter<u64>,
     data_relayed: u64, // Keep a separate counter because `Counter` doesn't expose the current value :(
     responses_counter: Counter<u64>,
+    quota_exceeded_counter: Counter<u64>,
+}
+
+/// A snapshot of an allocation, see [`Server::allocations`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationInfo {
+    pub port: AllocationPort,
+    pub client: ClientSocket,
+    pub relay_addresses: Vec<IpAddr>,
+    pub expires_in: Duration,
+    /// The number of bytes relayed through this allocation, including the ones relayed by the eBPF kernel.
+    ///
+    /// The latter are only accounted for as of the last [`Server::handle_ebpf_relayed_bytes`].
+    pub num_relayed_bytes: u64,
+    pub channels: Vec<ChannelInfo>,
+}
+
+/// A snapshot of a channel binding, see [`Server::allocations`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub number: ChannelNumber,
+    pub peer: PeerSocket,
+    pub bound: bool,
+    pub expires_in: Duration,
+    /// The number of bytes the eBPF kernel relayed through this channel, see [`Server::handle_ebpf_relayed_bytes`].
+    pub num_ebpf_relayed_bytes: u64,
 }
 
 /// The commands returned from a [`Server`].
@@ -92,6 +142,14 @@ pub enum Command {
         payload: Vec<u8>,
         recipient: ClientSocket,
     },
+    /// Send a message to the client from the given port instead of [`Server::listen_port`].
+    ///
+    /// Only used for answering NAT behaviour discovery requests, see [`Server::with_alternate_port`].
+    SendMessageFrom {
+        payload: Vec<u8>,
+        recipient: ClientSocket,
+        port: u16,
+    },
     /// Listen for traffic on the provided port [AddressFamily].
     ///
     /// Any incoming data should be handed to the [`Server`] via [`Server::handle_peer_traffic`].
@@ -154,6 +212,9 @@ const CHANNEL_BINDING_DURATION: Duration = Duration::from_secs(600);
 /// See <https://www.rfc-editor.org/rfc/rfc8656#section-12-14>.
 const CHANNEL_REBIND_TIMEOUT: Duration = Duration::from_secs(300);
 
+/// The minimum lifetime we grant on refresh while draining, see [`Server::drain`].
+const MIN_DRAINING_REFRESH_LIFETIME: Duration = Duration::from_secs(60);
+
 impl<R> Server<R>
 where
     R: Rng,
@@ -189,27 +250,89 @@ where
             .with_description("The number of bytes relayed")
             .with_unit("b")
             .build();
+        let quota_exceeded_counter = meter
+            .u64_counter("quota_exceeded_total")
+            .with_description("The number of requests or packets rejected because of a quota")
+            .build();
 
         Self {
             public_address: public_address.into(),
             allocations: Default::default(),
             clients_by_allocation: Default::default(),
             listen_port,
+            alternate_port: None,
+            alternate_server: None,
             ports,
             channels_by_client_and_number: Default::default(),
             channel_numbers_by_client_and_peer: Default::default(),
             pending_commands: Default::default(),
+            stream_clients: Default::default(),
             auth_secret: SecretString::from(hex::encode(rng.r#gen::<[u8; 32]>())),
             rng,
             nonces: Default::default(),
+            quotas: Quotas::default(),
+            draining: false,
             allocations_up_down_counter,
             responses_counter,
             data_relayed_counter,
+            quota_exceeded_counter,
             data_relayed: 0,
             channel_and_client_by_port_and_peer: Default::default(),
         }
     }
 
+    /// Enforces the given [`Quotas`] on all users of this [`Server`].
+    ///
+    /// Channel data offloaded to eBPF is only charged to the byte-rate quota via [`Server::handle_ebpf_relayed_bytes`].
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+
+        self
+    }
+
+    /// Enables NAT behaviour discovery as per [RFC 5780](https://www.rfc-editor.org/rfc/rfc5780).
+    ///
+    /// BINDING responses on UDP will include an `OTHER-ADDRESS` pointing to the given port and a `RESPONSE-ORIGIN`.
+    /// The caller must listen on this port and hand all traffic to [`Server::handle_alternate_port_input`].
+    ///
+    /// We only have a single public IP per address family.
+    /// Hence, `OTHER-ADDRESS` differs from our primary address only in the port and a `CHANGE-REQUEST` asking us to change the IP is rejected with 420 (Unknown Attribute).
+    pub fn with_alternate_port(mut self, port: u16) -> Self {
+        self.alternate_port = Some(port);
+
+        self
+    }
+
+    /// Redirects new allocations to the given relay while draining, see [`Server::drain`].
+    pub fn with_alternate_server(mut self, server: SocketAddr) -> Self {
+        self.alternate_server = Some(server);
+
+        self
+    }
+
+    /// Starts draining this [`Server`] in preparation of shutting it down.
+    ///
+    /// While draining, we:
+    /// - Reject new allocations with 300 (Try Alternate) and an `ALTERNATE-SERVER` if we know about one, otherwise with 508 (Insufficient Capacity).
+    /// - Stop handing out nonces to clients without an allocation.
+    /// - No longer extend the lifetime of existing allocations on refresh.
+    ///
+    /// Existing allocations and their channels continue to be served until they expire.
+    /// Once [`Server::num_allocations`] drops to 0, the server can be shut down without disrupting any clients.
+    pub fn drain(&mut self) {
+        if self.draining {
+            return;
+        }
+
+        tracing::info!(target: "relay", active_allocations = %self.allocations.len(), "Draining relay");
+
+        self.draining = true;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
     pub fn auth_secret(&self) -> &SecretString {
         &self.auth_secret
     }
@@ -230,6 +353,10 @@ where
         self.listen_port
     }
 
+    pub fn alternate_port(&self) -> Option<u16> {
+        self.alternate_port
+    }
+
     /// Registers a new, valid nonce.
     ///
     /// Each nonce is valid for 10 requests.
@@ -252,6 +379,53 @@ where
             .count()
     }
 
+    /// Returns a snapshot of all current allocations and their channels.
+    pub fn allocations(&self, now: Instant) -> Vec<AllocationInfo> {
+        self.allocations
+            .iter()
+            .map(|(client, allocation)| AllocationInfo {
+                port: allocation.port,
+                client: *client,
+                relay_addresses: iter::once(allocation.first_relay_addr)
+                    .chain(allocation.second_relay_addr)
+                    .collect(),
+                expires_in: allocation.expires_at.saturating_duration_since(now),
+                num_relayed_bytes: allocation.num_relayed_bytes,
+                channels: self
+                    .channels_by_client_and_number
+                    .iter()
+                    .filter(|(_, c)| c.allocation == allocation.port)
+                    .map(|((_, number), c)| ChannelInfo {
+                        number: *number,
+                        peer: c.peer_address,
+                        bound: c.bound,
+                        expires_in: c.expiry.saturating_duration_since(now),
+                        num_ebpf_relayed_bytes: c.num_ebpf_relayed_bytes,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Forcibly frees the allocation on the given port.
+    ///
+    /// The client is not notified and will only learn about this on its next refresh.
+    ///
+    /// # Returns
+    ///
+    /// Whether an allocation on this port existed.
+    pub fn free_allocation(&mut self, port: AllocationPort) -> bool {
+        if !self.clients_by_allocation.contains_key(&port) {
+            return false;
+        }
+
+        tracing::info!(target: "relay", allocation = %port, "Forcibly freeing allocation");
+
+        self.delete_allocation(port);
+
+        true
+    }
+
     /// Process the bytes received from a client.
     ///
     /// # Returns
@@ -297,6 +471,35 @@ where
         None
     }
 
+    /// Process the bytes received on our alternate port, see [`Server::with_alternate_port`].
+    ///
+    /// Only BINDING requests are answered there, everything else is dropped.
+    pub fn handle_alternate_port_input(&mut self, bytes: &[u8], sender: ClientSocket) {
+        match client_message::decode(bytes) {
+            Ok(Ok(message)) => self.handle_alternate_port_message(message, sender),
+            Ok(Err(error_response)) => {
+                tracing::debug!(target: "relay", %sender, method = %error_response.method(), "Failed to decode message on alternate port");
+            }
+            Err(e) => {
+                tracing::debug!(target: "relay", %sender, "Failed to decode message on alternate port: {e:?}");
+            }
+        }
+    }
+
+    pub fn handle_alternate_port_message(&mut self, message: ClientMessage, sender: ClientSocket) {
+        let Some(alternate_port) = self.alternate_port else {
+            tracing::debug!(target: "relay", %sender, "NAT behaviour discovery is disabled");
+            return;
+        };
+
+        let ClientMessage::Binding(request) = message else {
+            tracing::debug!(target: "relay", %sender, "Dropping non-BINDING message on alternate port");
+            return;
+        };
+
+        self.handle_binding_request(&request, sender, alternate_port);
+    }
+
     pub fn handle_client_message(
         &mut self,
         message: ClientMessage,
@@ -313,11 +516,11 @@ where
                 self.handle_create_permission_request(request, sender)
             }
             ClientMessage::Binding(request) => {
-                self.handle_binding_request(request, sender);
+                self.handle_binding_request(request, sender, self.listen_port);
                 return None;
             }
             ClientMessage::ChannelData(msg) => {
-                return self.handle_channel_data_message(msg, sender);
+                return self.handle_channel_data_message(msg, sender, now);
             }
         };
 
@@ -332,8 +535,11 @@ where
                     || error_code == &ErrorCode::from(StaleNonce)
             });
 
+        // Clients that we won't give an allocation to anyway don't need a new nonce.
+        let issue_nonce = !self.draining || self.allocations.contains_key(&sender);
+
         // In case of a 401 or 438 response, attach a realm and nonce.
-        if is_auth_error {
+        if is_auth_error && issue_nonce {
             error_response.add_attribute((*FIREZONE).clone());
             error_response.add_attribute(self.new_nonce_attribute());
         }
@@ -371,6 +577,7 @@ where
         msg: &[u8],
         sender: PeerSocket,
         allocation: AllocationPort,
+        now: Instant,
     ) -> Option<(ClientSocket, ChannelNumber)> {
         let Some((client, channel_number)) = self
             .channel_and_client_by_port_and_peer
@@ -381,6 +588,17 @@ where
             return None;
         };
 
+        if !self
+            .allocations
+            .get_mut(client)
+            .is_none_or(|a| a.try_relay(msg.len(), now))
+        {
+            tracing::trace!(target: "relay", %sender, %allocation, "Byte-rate quota exceeded, dropping packet");
+            record_quota_exceeded(&self.quota_exceeded_counter, "bandwidth");
+
+            return None;
+        }
+
         self.data_relayed_counter.add(msg.len() as u64, &[]);
         self.data_relayed += msg.len() as u64;
 
@@ -389,11 +607,117 @@ where
         Some((*client, *channel_number))
     }
 
+    /// A client connected to us via a TCP or TLS stream.
+    ///
+    /// All messages on the stream must be passed to [`Server::handle_client_input`] individually.
+    /// Data relayed to this client must be sent via the stream, see <https://www.rfc-editor.org/rfc/rfc8656#section-12.5> for how to pad channel-data messages.
+    pub fn handle_stream_connected(&mut self, client: ClientSocket) {
+        tracing::debug!(target: "relay", %client, "Client connected via stream");
+
+        self.stream_clients.insert(client);
+    }
+
+    /// A client's TCP or TLS stream got closed.
+    ///
+    /// Allocations made over a stream only live as long as the stream itself.
+    pub fn handle_stream_disconnected(&mut self, client: ClientSocket) {
+        if !self.stream_clients.remove(&client) {
+            return;
+        }
+
+        tracing::debug!(target: "relay", %client, "Client disconnected from stream");
+
+        if let Some(port) = self.allocations.get(&client).map(|a| a.port) {
+            self.delete_allocation(port);
+        }
+    }
+
     /// An allocation failed.
     pub fn handle_allocation_failed(&mut self, allocation: AllocationPort) {
         self.delete_allocation(allocation)
     }
 
+    /// Accounts for the bytes our eBPF kernel relayed on behalf of allocations.
+    ///
+    /// `relayed_bytes` are the kernel's counters per channel, see [`Command::CreateChannelBinding`].
+    /// The kernel doesn't enforce any byte-rate quota, hence we charge these bytes to the allocation instead.
+    /// Allocations that exceed their quota are no longer offloaded until their quota has fully recovered.
+    pub fn handle_ebpf_relayed_bytes(
+        &mut self,
+        relayed_bytes: impl IntoIterator<Item = (AllocationPort, ChannelNumber, u64)>,
+        now: Instant,
+    ) {
+        for (port, number, bytes) in relayed_bytes {
+            let Some(client) = self.clients_by_allocation.get(&port).copied() else {
+                continue;
+            };
+            let Some(channel) = self
+                .channels_by_client_and_number
+                .get_mut(&(client, number))
+                .filter(|c| c.allocation == port)
+            else {
+                continue;
+            };
+
+            // The kernel's counter got reset since we last looked at it.
+            let delta = bytes
+                .checked_sub(channel.ebpf_relayed_bytes)
+                .unwrap_or(bytes);
+            channel.ebpf_relayed_bytes = bytes;
+            channel.num_ebpf_relayed_bytes += delta;
+
+            let Some(allocation) = self.allocations.get_mut(&client) else {
+                continue;
+            };
+
+            allocation.num_relayed_bytes += delta;
+
+            if let Some(limiter) = allocation.rate_limiter.as_mut() {
+                limiter.consume(delta, now);
+            }
+        }
+
+        let to_suspend = self
+            .allocations
+            .iter_mut()
+            .filter(|(_, a)| !a.offloading_suspended)
+            .filter(|(_, a)| a.rate_limiter.as_ref().is_some_and(|l| l.is_exhausted()))
+            .map(|(client, a)| {
+                a.offloading_suspended = true;
+
+                (*client, a.port)
+            })
+            .collect::<Vec<_>>();
+
+        for (client, port) in to_suspend {
+            tracing::info!(target: "relay", allocation = %port, %client, "Byte-rate quota exceeded, no longer offloading channel data to eBPF");
+            record_quota_exceeded(&self.quota_exceeded_counter, "bandwidth");
+
+            self.set_allocation_offloaded(client, false);
+        }
+
+        let to_resume = self
+            .allocations
+            .iter_mut()
+            .filter(|(_, a)| a.offloading_suspended)
+            .filter_map(|(client, a)| {
+                if !a.rate_limiter.as_mut().is_none_or(|l| l.is_full(now)) {
+                    return None;
+                }
+
+                a.offloading_suspended = false;
+
+                Some((*client, a.port))
+            })
+            .collect::<Vec<_>>();
+
+        for (client, port) in to_resume {
+            tracing::debug!(target: "relay", allocation = %port, %client, "Byte-rate quota recovered, offloading channel data to eBPF again");
+
+            self.set_allocation_offloaded(client, true);
+        }
+    }
+
     /// Return the next command to be executed.
     pub fn next_command(&mut self) -> Option<Command> {
         self.pending_commands.pop_front()
@@ -433,13 +757,21 @@ where
         {
             tracing::info!(target: "relay", channel = %number.value(), %client, peer = %channel.peer_address, allocation = %channel.allocation, "Channel is now expired");
 
-            self.pending_commands
-                .push_back(Command::DeleteChannelBinding {
-                    client: *client,
-                    channel_number: *number,
-                    peer: channel.peer_address,
-                    allocation_port: channel.allocation,
-                });
+            // Inlined version of `can_offload_to_ebpf` because we are mutably borrowing the channels.
+            if !self.stream_clients.contains(client)
+                && self
+                    .allocations
+                    .get(client)
+                    .is_none_or(|a| !a.offloading_suspended)
+            {
+                self.pending_commands
+                    .push_back(Command::DeleteChannelBinding {
+                        client: *client,
+                        channel_number: *number,
+                        peer: channel.peer_address,
+                        allocation_port: channel.allocation,
+                    });
+            }
 
             channel.bound = false;
             if let Some((cs, n)) = self
@@ -463,15 +795,82 @@ where
     }
 
     #[tracing::instrument(level = "info", skip_all, fields(software = request.software().map(|s| field::display(s.description())), tid = %format_args!("{:X}", request.transaction_id().as_bytes().hex()), %sender))]
-    fn handle_binding_request(&mut self, request: &Binding, sender: ClientSocket) {
+    fn handle_binding_request(&mut self, request: &Binding, sender: ClientSocket, local_port: u16) {
         let mut message = success_response(BINDING, request.transaction_id());
         message.add_attribute(XorMappedAddress::new(sender.0));
 
-        tracing::info!("Handled BINDING request");
+        let public_ip = match sender.0 {
+            SocketAddr::V4(_) => self.public_ip4(),
+            SocketAddr::V6(_) => self.public_ip6(),
+        };
+
+        // NAT behaviour discovery only makes sense over UDP.
+        let nat_discovery = self
+            .alternate_port
+            .zip(public_ip)
+            .filter(|_| !self.stream_clients.contains(&sender));
+
+        let Some((alternate_port, public_ip)) = nat_discovery else {
+            if request.change_request().is_some() {
+                self.reject_change_request(request, sender, local_port);
+                return;
+            }
+
+            tracing::info!("Handled BINDING request");
+
+            self.send_message(
+                AuthenticatedMessage::new_dangerous_unauthenticated(message),
+                sender,
+            );
+            return;
+        };
+
+        let other_port = if local_port == self.listen_port {
+            alternate_port
+        } else {
+            self.listen_port
+        };
+
+        let response_port = match request.change_request() {
+            Some(change_request) if change_request.ip() => {
+                self.reject_change_request(request, sender, local_port);
+                return;
+            }
+            Some(change_request) if change_request.port() => other_port,
+            Some(_) | None => local_port,
+        };
+
+        message.add_attribute(OtherAddress::new(SocketAddr::new(public_ip, other_port)));
+        message.add_attribute(ResponseOrigin::new(SocketAddr::new(
+            public_ip,
+            response_port,
+        )));
+
+        tracing::info!(%local_port, %response_port, "Handled BINDING request");
 
-        self.send_message(
+        self.send_message_from(
             AuthenticatedMessage::new_dangerous_unauthenticated(message),
             sender,
+            response_port,
+        );
+    }
+
+    /// Rejects a `CHANGE-REQUEST` we cannot honor.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc5780#section-7.2>.
+    fn reject_change_request(&mut self, request: &Binding, sender: ClientSocket, local_port: u16) {
+        let error_response = error_response(
+            BINDING,
+            request.transaction_id(),
+            ErrorCode::from(UnknownAttribute),
+        );
+
+        tracing::debug!(target: "relay", %sender, "Unable to honor CHANGE-REQUEST");
+
+        self.send_message_from(
+            AuthenticatedMessage::new_dangerous_unauthenticated(error_response),
+            sender,
+            local_port,
         );
     }
 
@@ -484,6 +883,24 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
+        if self.draining && !self.allocations.contains_key(&sender) {
+            // RFC 5389 requires an `ALTERNATE-SERVER` on every 300 response.
+            let Some(alternate_server) = self.alternate_server else {
+                let (error_response, msg) = make_error_response(InsufficientCapacity, request);
+
+                tracing::info!(target: "relay", %sender, "{msg}: Relay is draining");
+
+                return Err(error_response);
+            };
+
+            let (mut error_response, msg) = make_error_response(TryAlternate, request);
+            error_response.add_attribute(AlternateServer::new(alternate_server));
+
+            tracing::info!(target: "relay", %sender, %alternate_server, "{msg}: Relay is draining");
+
+            return Err(error_response);
+        }
+
         let username = self.verify_auth(request)?;
 
         if let Some(allocation) = self.allocations.get(&sender) {
@@ -494,6 +911,30 @@ where
             return Err(error_response);
         }
 
+        let (_, username_salt) = auth::split_username(username.name()).map_err(|e| {
+            let (error_response, msg) = make_error_response(Unauthorized, request);
+            tracing::warn!(target: "relay", %sender, "{msg}: {e}");
+
+            error_response
+        })?;
+
+        if let Some(max_allocations) = self.quotas.max_allocations_per_user {
+            let num_allocations = self
+                .allocations
+                .values()
+                .filter(|a| a.username_salt == username_salt)
+                .count();
+
+            if num_allocations >= max_allocations {
+                let (error_response, msg) = make_error_response(AllocationQuotaReached, request);
+
+                tracing::info!(target: "relay", %sender, %num_allocations, %max_allocations, "{msg}: User has too many allocations");
+                record_quota_exceeded(&self.quota_exceeded_counter, "allocations");
+
+                return Err(error_response);
+            }
+        }
+
         let max_available_ports = self.max_available_ports() as usize;
         if self.clients_by_allocation.len() == max_available_ports {
             let (error_response, msg) = make_error_response(InsufficientCapacity, request);
@@ -533,6 +974,7 @@ where
             &effective_lifetime,
             first_relay_address,
             maybe_second_relay_addr,
+            username_salt.to_owned(),
         );
 
         let mut message = success_response(ALLOCATE, request.transaction_id());
@@ -626,9 +1068,24 @@ where
             return Ok(());
         }
 
-        allocation.expires_at = now + effective_lifetime.lifetime();
+        let effective_lifetime = if self.draining {
+            // Don't extend the allocation, it should expire eventually so we can shut down.
+            // Clients refresh after half of the granted lifetime, hence granting only the remaining lifetime would make them refresh more and more often as the allocation approaches its expiry.
+            // Instead, we grant at least `MIN_DRAINING_REFRESH_LIFETIME` which also prevents us from responding with a lifetime of 0 which would mean the allocation got deleted.
+            let remaining_lifetime = allocation
+                .expires_at
+                .saturating_duration_since(now)
+                .max(MIN_DRAINING_REFRESH_LIFETIME);
+
+            Lifetime::new(remaining_lifetime)
+                .expect("remaining lifetime is at most the lifetime we previously granted")
+        } else {
+            allocation.expires_at = now + effective_lifetime.lifetime();
+
+            effective_lifetime
+        };
 
-        tracing::info!(target: "relay", allocation = %allocation.port, %sender, "Refreshed allocation");
+        tracing::info!(target: "relay", allocation = %allocation.port, %sender, lifetime = ?effective_lifetime.lifetime(), "Refreshed allocation");
 
         self.authenticate_and_send(
             &username,
@@ -724,9 +1181,25 @@ where
         // Channel binding does not exist yet, create it.
 
         // TODO: Any additional validations would go here.
-        // TODO: Capacity checking would go here.
 
         let port = allocation.port;
+
+        if let Some(max_channels) = self.quotas.max_channels_per_allocation {
+            let num_channels = self
+                .channels_by_client_and_number
+                .values()
+                .filter(|c| c.allocation == port && c.bound)
+                .count();
+
+            if num_channels >= max_channels {
+                let (error_response, msg) = make_error_response(AllocationQuotaReached, request);
+
+                tracing::info!(target: "relay", allocation = %port, peer = %peer_address, channel = %requested_channel.value(), %num_channels, %max_channels, "{msg}: Allocation has too many channels");
+                record_quota_exceeded(&self.quota_exceeded_counter, "channels");
+
+                return Err(error_response);
+            }
+        }
         self.create_channel_binding(sender, requested_channel, peer_address, port, now);
         self.authenticate_and_send(
             &username,
@@ -767,6 +1240,7 @@ where
         &mut self,
         message: &ChannelData,
         sender: ClientSocket,
+        now: Instant,
     ) -> Option<(AllocationPort, PeerSocket)> {
         let channel_number = message.channel();
         let data = message.data();
@@ -787,6 +1261,17 @@ where
             return None;
         }
 
+        if !self
+            .allocations
+            .get_mut(&sender)
+            .is_none_or(|a| a.try_relay(data.len(), now))
+        {
+            tracing::trace!(target: "relay", channel = %channel_number.value(), "Byte-rate quota exceeded, dropping packet");
+            record_quota_exceeded(&self.quota_exceeded_counter, "bandwidth");
+
+            return None;
+        }
+
         tracing::trace!(target: "wire", num_bytes = %data.len());
 
         self.data_relayed_counter.add(data.len() as u64, &[]);
@@ -861,6 +1346,7 @@ where
         lifetime: &Lifetime,
         first_relay_addr: IpAddr,
         second_relay_addr: Option<IpAddr>,
+        username_salt: String,
     ) -> Allocation {
         assert!(
             self.clients_by_allocation.len() < self.max_available_ports() as usize,
@@ -880,6 +1366,63 @@ where
             expires_at: now + lifetime.lifetime(),
             first_relay_addr,
             second_relay_addr,
+            username_salt,
+            offloading_suspended: false,
+            num_relayed_bytes: 0,
+            rate_limiter: self
+                .quotas
+                .max_bytes_per_second_per_allocation
+                .map(|bytes_per_second| ByteRateLimiter::new(bytes_per_second, now)),
+        }
+    }
+
+    /// Our eBPF program can only relay to clients via UDP and doesn't enforce any byte-rate quota.
+    ///
+    /// See [`Server::handle_ebpf_relayed_bytes`] for how we enforce the quota of offloaded allocations.
+    fn can_offload_to_ebpf(&self, client: &ClientSocket) -> bool {
+        !self.stream_clients.contains(client)
+            && self
+                .allocations
+                .get(client)
+                .is_none_or(|a| !a.offloading_suspended)
+    }
+
+    /// Creates or deletes the eBPF channel bindings of all bound channels of the given allocation.
+    fn set_allocation_offloaded(&mut self, client: ClientSocket, offloaded: bool) {
+        if self.stream_clients.contains(&client) {
+            return;
+        }
+
+        for ((_, number), channel) in self
+            .channels_by_client_and_number
+            .range_mut(
+                (
+                    client,
+                    ChannelNumber::new(ChannelNumber::MIN).expect("MIN is a valid channel number"),
+                )..,
+            )
+            .take_while(|((c, _), _)| *c == client)
+            .filter(|(_, c)| c.bound)
+        {
+            channel.ebpf_relayed_bytes = 0;
+
+            let command = if offloaded {
+                Command::CreateChannelBinding {
+                    client,
+                    channel_number: *number,
+                    peer: channel.peer_address,
+                    allocation_port: channel.allocation,
+                }
+            } else {
+                Command::DeleteChannelBinding {
+                    client,
+                    channel_number: *number,
+                    peer: channel.peer_address,
+                    allocation_port: channel.allocation,
+                }
+            };
+
+            self.pending_commands.push_back(command);
         }
     }
 
@@ -904,15 +1447,19 @@ where
                 peer_address: peer,
                 allocation: id,
                 bound: true,
+                ebpf_relayed_bytes: 0,
+                num_ebpf_relayed_bytes: 0,
             },
         );
-        self.pending_commands
-            .push_back(Command::CreateChannelBinding {
-                client,
-                channel_number: requested_channel,
-                peer,
-                allocation_port: id,
-            });
+        if self.can_offload_to_ebpf(&client) {
+            self.pending_commands
+                .push_back(Command::CreateChannelBinding {
+                    client,
+                    channel_number: requested_channel,
+                    peer,
+                    allocation_port: id,
+                });
+        }
 
         debug_assert!(existing.is_none());
 
@@ -954,6 +1501,15 @@ where
     }
 
     fn send_message(&mut self, message: AuthenticatedMessage, recipient: ClientSocket) {
+        self.send_message_from(message, recipient, self.listen_port);
+    }
+
+    fn send_message_from(
+        &mut self,
+        message: AuthenticatedMessage,
+        recipient: ClientSocket,
+        port: u16,
+    ) {
         debug_assert!(message.get_attribute::<Software>().is_some());
 
         let method = message.method();
@@ -968,10 +1524,18 @@ where
 
         tracing::trace!(target: "wire", num_bytes = %bytes.len());
 
-        self.pending_commands.push_back(Command::SendMessage {
-            payload: bytes,
-            recipient,
-        });
+        if port == self.listen_port {
+            self.pending_commands.push_back(Command::SendMessage {
+                payload: bytes,
+                recipient,
+            });
+        } else {
+            self.pending_commands.push_back(Command::SendMessageFrom {
+                payload: bytes,
+                recipient,
+                port,
+            });
+        }
 
         // record metrics
         let response_class = match class {
@@ -1089,6 +1653,10 @@ where
     }
 }
 
+fn record_quota_exceeded(counter: &Counter<u64>, quota: &'static str) {
+    counter.add(1, &[KeyValue::new("quota", quota)]);
+}
+
 fn make_error_response(
     error_code: impl Into<ErrorCode>,
     request: &impl StunRequest,
@@ -1131,6 +1699,14 @@ struct Allocation {
 
     first_relay_addr: IpAddr,
     second_relay_addr: Option<IpAddr>,
+
+    /// The salt of the username that created this allocation, identifying the user for the purposes of [`Quotas`].
+    username_salt: String,
+    rate_limiter: Option<ByteRateLimiter>,
+    /// Whether we stopped offloading this allocation's channels to eBPF because it exceeded its byte-rate quota.
+    offloading_suspended: bool,
+
+    num_relayed_bytes: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -1154,6 +1730,13 @@ struct Channel {
     ///
     /// With the data structure still existing while the channel is unbound, our existing validations cover the above requirement.
     bound: bool,
+
+    /// The eBPF kernel's counter of bytes relayed through this channel, as of our last [`Server::handle_ebpf_relayed_bytes`].
+    ///
+    /// The kernel resets its counter whenever we create or delete the channel binding.
+    ebpf_relayed_bytes: u64,
+    /// The total number of bytes the eBPF kernel relayed through this channel.
+    num_ebpf_relayed_bytes: u64,
 }
 
 impl Channel {
@@ -1191,6 +1774,21 @@ impl Allocation {
     fn is_expired(&self, now: Instant) -> bool {
         self.expires_at <= now
     }
+
+    /// Checks whether relaying the given number of bytes stays within this allocation's byte-rate quota and if so, accounts for them.
+    fn try_relay(&mut self, num_bytes: usize, now: Instant) -> bool {
+        if !self
+            .rate_limiter
+            .as_mut()
+            .is_none_or(|limiter| limiter.try_consume(num_bytes, now))
+        {
+            return false;
+        }
+
+        self.num_relayed_bytes += num_bytes as u64;
+
+        true
+    }
 }
 
 /// Derive the relay address for the client based on the request and the supported IP stack of the relay server.
@@ -1341,7 +1939,11 @@ stun_codec::define_attribute_enums!(
         Username,
         RequestedAddressFamily,
         AdditionalAddressFamily,
-        Software
+        Software,
+        ChangeRequest,
+        OtherAddress,
+        ResponseOrigin,
+        AlternateServer
     ]
 );
 
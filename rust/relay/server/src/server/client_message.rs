@@ -14,6 +14,7 @@ use stun_codec::rfc5766::attributes::{
     ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress,
 };
 use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5780::attributes::ChangeRequest;
 use stun_codec::rfc8656::attributes::{
     AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
 };
@@ -113,6 +114,7 @@ impl ClientMessage<'_> {
 pub struct Binding {
     transaction_id: TransactionId,
     software: Option<Software>,
+    change_request: Option<ChangeRequest>,
 }
 
 impl Binding {
@@ -120,16 +122,25 @@ impl Binding {
         Self {
             transaction_id,
             software: None,
+            change_request: None,
         }
     }
 
+    pub fn with_change_request(mut self, change_request: ChangeRequest) -> Self {
+        self.change_request = Some(change_request);
+
+        self
+    }
+
     pub fn parse(message: &Message<Attribute>) -> Self {
         let transaction_id = message.transaction_id();
         let software = message.get_attribute::<Software>().cloned();
+        let change_request = message.get_attribute::<ChangeRequest>().cloned();
 
         Binding {
             transaction_id,
             software,
+            change_request,
         }
     }
 
@@ -140,6 +151,11 @@ impl Binding {
     pub fn software(&self) -> Option<&Software> {
         self.software.as_ref()
     }
+
+    /// The RFC 5780 `CHANGE-REQUEST` attribute, if any.
+    pub fn change_request(&self) -> Option<&ChangeRequest> {
+        self.change_request.as_ref()
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+/// Per-user limits enforced by the [`Server`](crate::Server).
+///
+/// A user is identified by the salt of their TURN username, see [`crate::auth`].
+/// A limit of [`None`] means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    /// The maximum number of allocations a single user can hold at the same time.
+    pub max_allocations_per_user: Option<usize>,
+    /// The maximum number of bound channels per allocation.
+    pub max_channels_per_allocation: Option<usize>,
+    /// The maximum number of bytes per second relayed through a single allocation, in both directions combined.
+    pub max_bytes_per_second_per_allocation: Option<u64>,
+}
+
+/// A token bucket that allows bursts of up to one second worth of data.
+#[derive(Debug, Clone)]
+pub(crate) struct ByteRateLimiter {
+    bytes_per_second: u64,
+    available: u64,
+    last_refill: Instant,
+}
+
+impl ByteRateLimiter {
+    pub(crate) fn new(bytes_per_second: u64, now: Instant) -> Self {
+        Self {
+            bytes_per_second,
+            available: bytes_per_second,
+            last_refill: now,
+        }
+    }
+
+    /// Returns `true` if the given number of bytes may be relayed.
+    pub(crate) fn try_consume(&mut self, num_bytes: usize, now: Instant) -> bool {
+        self.refill(now);
+
+        let num_bytes = num_bytes as u64;
+
+        if num_bytes > self.available {
+            return false;
+        }
+
+        self.available -= num_bytes;
+
+        true
+    }
+
+    /// Charges bytes that have already been relayed, i.e. by our eBPF kernel.
+    pub(crate) fn consume(&mut self, num_bytes: u64, now: Instant) {
+        self.refill(now);
+
+        self.available = self.available.saturating_sub(num_bytes);
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.available == 0
+    }
+
+    pub(crate) fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        self.available == self.bytes_per_second
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if self.bytes_per_second == 0 {
+            return;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill).as_nanos();
+        let rate = u128::from(self.bytes_per_second);
+        let refill = elapsed * rate / NANOS_PER_SEC;
+
+        // Only move the refill timestamp forward once we actually credited some bytes, otherwise frequent small packets would never refill the bucket.
+        if refill == 0 {
+            return;
+        }
+
+        let capacity = self.bytes_per_second - self.available;
+
+        if refill >= u128::from(capacity) {
+            self.available = self.bytes_per_second;
+            self.last_refill = now;
+
+            return;
+        }
+
+        self.available += refill as u64;
+
+        // Only advance by the time it took to earn the credited bytes, otherwise we'd lose the fraction of a byte that was earned in the remainder.
+        let credited = refill * NANOS_PER_SEC / rate;
+        self.last_refill += Duration::from_nanos(credited as u64);
+    }
+}
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_burst_of_one_second() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(1000, now);
+
+        assert!(limiter.try_consume(600, now));
+        assert!(limiter.try_consume(400, now));
+        assert!(!limiter.try_consume(1, now));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(1000, now);
+
+        assert!(limiter.try_consume(1000, now));
+        assert!(!limiter.try_consume(500, now + Duration::from_millis(100)));
+        assert!(limiter.try_consume(500, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn does_not_accumulate_more_than_one_second() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(1000, now);
+
+        assert!(!limiter.try_consume(1001, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn many_small_packets_still_refill() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(1000, now);
+        assert!(limiter.try_consume(1000, now));
+
+        // A packet every 100µs is less than one byte worth of refill each time.
+        let rejected = (1..=10_000)
+            .filter(|i| !limiter.try_consume(0, now + Duration::from_micros(100 * i)))
+            .count();
+        assert_eq!(rejected, 0);
+
+        assert!(limiter.try_consume(900, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn keeps_fractional_credit_at_low_rates() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(10, now);
+        assert!(limiter.try_consume(10, now));
+
+        // Every probe earns 1.5 bytes, the half byte must not be lost.
+        for i in 1..=6 {
+            limiter.try_consume(0, now + Duration::from_millis(150 * i));
+        }
+
+        assert!(limiter.try_consume(10, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn consumes_already_relayed_bytes() {
+        let now = Instant::now();
+        let mut limiter = ByteRateLimiter::new(1000, now);
+
+        limiter.consume(5000, now);
+        assert!(limiter.is_exhausted());
+        assert!(!limiter.is_full(now + Duration::from_millis(500)));
+        assert!(limiter.is_full(now + Duration::from_secs(1)));
+    }
+}
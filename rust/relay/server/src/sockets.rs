@@ -1,19 +1,25 @@
-use anyhow::{Result, bail};
+use anyhow::{Context as _, Result, bail};
 use std::{
     borrow::Cow,
     collections::{HashMap, VecDeque},
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
     task::{Context, Poll, Waker, ready},
     time::Duration,
 };
 use stun_codec::rfc8656::attributes::AddressFamily;
 use tokio::sync::mpsc;
 
+mod stream;
+
 /// A dynamic collection of UDP sockets, listening on all interfaces of a particular IP family.
 ///
 /// Internally, [`Sockets`] is powered by [`mio`] and uses a separate thread to poll for readiness of a socket.
 /// Whenever a socket is ready for reading, we send a message to the foreground task which then reads from the socket until it emits [`io::ErrorKind::WouldBlock`].
+///
+/// Clients that cannot use UDP may also connect via TCP or TLS, see [`Sockets::listen_stream`].
+/// Those connections are served by Tokio tasks and reported via [`Sockets::poll_stream`].
 pub struct Sockets {
     /// All currently active sockets.
     ///
@@ -33,6 +39,11 @@ pub struct Sockets {
     event_rx: mpsc::Receiver<Event>,
 
     pending_packets: VecDeque<PendingPacket>,
+
+    /// The sending half of all TCP and TLS connections, indexed by the client's address.
+    streams: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>,
+    stream_event_tx: mpsc::Sender<stream::Event>,
+    stream_event_rx: mpsc::Receiver<stream::Event>,
 }
 
 /// A packet that could not be sent and is buffered until the socket is ready again.
@@ -52,6 +63,7 @@ impl Sockets {
     pub fn new() -> Self {
         let (cmd_tx, cmd_rx) = mpsc::channel(1_000_000); // Commands are really small and this channel should really never fill up unless we have serious problems in the "mio" worker thread.
         let (event_tx, event_rx) = mpsc::channel(1_024);
+        let (stream_event_tx, stream_event_rx) = mpsc::channel(stream::MAX_BUFFERED_MESSAGES);
 
         std::thread::spawn(move || {
             if let Err(e) = mio_worker_task(event_tx.clone(), cmd_rx) {
@@ -66,6 +78,9 @@ impl Sockets {
             current_ready_socket: None,
             pending_packets: Default::default(),
             flush_waker: None,
+            streams: Default::default(),
+            stream_event_tx,
+            stream_event_rx,
         }
     }
 
@@ -81,6 +96,29 @@ impl Sockets {
         Ok(())
     }
 
+    /// Starts accepting TURN clients via TCP on the given port and address family.
+    ///
+    /// If a TLS config is given, every connection must complete a TLS handshake first.
+    /// Must be called within a Tokio runtime context.
+    pub fn listen_stream(
+        &mut self,
+        port: u16,
+        address_family: AddressFamily,
+        tls: Option<Arc<rustls::ServerConfig>>,
+    ) -> Result<()> {
+        let listener = make_wildcard_listener(address_family, port)
+            .with_context(|| format!("Failed to listen on TCP port {port} ({address_family})"))?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+
+        tokio::spawn(stream::accept(
+            listener,
+            tls.map(tokio_rustls::TlsAcceptor::from),
+            self.stream_event_tx.clone(),
+        ));
+
+        Ok(())
+    }
+
     /// Attempts to unbind a socket on the given port and address family.
     ///
     /// Fails if the channel is:
@@ -132,6 +170,33 @@ impl Sockets {
         }
     }
 
+    /// Sends a message to a client, via its TCP or TLS connection if it has one.
+    ///
+    /// Clients without a stream are sent a UDP datagram from the given port.
+    pub fn try_send_to_client(
+        &mut self,
+        port: u16,
+        client: SocketAddr,
+        msg: Cow<'_, [u8]>,
+    ) -> io::Result<()> {
+        let Some(stream) = self.streams.get(&client) else {
+            return self.try_send(port, client, msg);
+        };
+
+        match stream.try_send(msg.into_owned()) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!(%client, "Dropping message to client; stream is congested");
+
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("Stream to {client} is closed"),
+            )),
+        }
+    }
+
     fn try_send_internal(&mut self, port: u16, dest: SocketAddr, msg: &[u8]) -> io::Result<()> {
         let address_family = match dest {
             SocketAddr::V4(_) => AddressFamily::V4,
@@ -212,6 +277,35 @@ impl Sockets {
             };
         }
     }
+
+    pub fn poll_stream(&mut self, cx: &mut Context<'_>) -> Poll<StreamInput> {
+        let event = ready!(self.stream_event_rx.poll_recv(cx)).expect("we hold a sender ourselves");
+
+        let input = match event {
+            stream::Event::Connected(from, sender) => {
+                self.streams.insert(from, sender);
+
+                StreamInput::Connected(from)
+            }
+            stream::Event::Message(from, message) => StreamInput::Message(from, message),
+            stream::Event::Disconnected(from) => {
+                self.streams.remove(&from);
+
+                StreamInput::Disconnected(from)
+            }
+        };
+
+        Poll::Ready(input)
+    }
+}
+
+/// Something that happened on a TCP or TLS connection with a client.
+#[derive(Debug)]
+pub enum StreamInput {
+    Connected(SocketAddr),
+    /// A single STUN or channel-data message, without any padding.
+    Message(SocketAddr, Vec<u8>),
+    Disconnected(SocketAddr),
 }
 
 /// A packet read from a socket.
@@ -355,3 +449,29 @@ fn make_wildcard_socket(family: AddressFamily, port: u16) -> io::Result<std::net
 
     Ok(socket.into())
 }
+
+/// Creates a [std::net::TcpListener] via the [socket2] library, see [`make_wildcard_socket`].
+fn make_wildcard_listener(family: AddressFamily, port: u16) -> io::Result<std::net::TcpListener> {
+    use socket2::*;
+
+    let domain = match family {
+        AddressFamily::V4 => Domain::IPV4,
+        AddressFamily::V6 => Domain::IPV6,
+    };
+    let address = match family {
+        AddressFamily::V4 => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        AddressFamily::V6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+    if family == AddressFamily::V6 {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SockAddr::from(SocketAddr::new(address, port)))?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
+}
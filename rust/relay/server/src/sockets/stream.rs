@@ -0,0 +1,156 @@
+//! TURN over TCP and TLS, see <https://www.rfc-editor.org/rfc/rfc8656#section-3.1>.
+//!
+//! Clients that cannot use UDP connect to us via a stream.
+//! We only use the stream to talk to the client, the allocation itself always relays via UDP.
+
+use std::{io, net::SocketAddr, pin::pin, time::Duration};
+
+use bytes::BytesMut;
+use futures::future::Either;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::TlsAcceptor;
+use turn_stream::{StreamDecoder, encode_stream_message};
+
+/// How many messages we buffer per client before we start dropping them.
+///
+/// Like with UDP, a dropped message is recovered by the client's retransmissions.
+pub(super) const MAX_BUFFERED_MESSAGES: usize = 1024;
+
+/// How long a client has to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a stream may be silent before we close it.
+///
+/// Clients refresh their allocation and channel bindings well within this time, see <https://www.rfc-editor.org/rfc/rfc8656#section-3.2>.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+pub(super) enum Event {
+    Connected(SocketAddr, mpsc::Sender<Vec<u8>>),
+    Message(SocketAddr, Vec<u8>),
+    Disconnected(SocketAddr),
+}
+
+/// Accepts connections on the given listener until the event channel is closed.
+pub(super) async fn accept(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    events: mpsc::Sender<Event>,
+) {
+    loop {
+        let (stream, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::debug!("Failed to accept TCP connection: {e}");
+                continue;
+            }
+        };
+
+        if events.is_closed() {
+            return;
+        }
+
+        tokio::spawn(serve(stream, from, tls.clone(), events.clone()));
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    from: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    events: mpsc::Sender<Event>,
+) {
+    let result = match tls {
+        Some(acceptor) => {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(tls_stream)) => run(tls_stream, from, &events).await,
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "TLS handshake timed out",
+                )),
+            }
+        }
+        None => run(stream, from, &events).await,
+    };
+
+    match result {
+        Ok(()) => tracing::debug!(%from, "Client closed stream"),
+        Err(e) => tracing::debug!(%from, "Stream to client failed: {e}"),
+    }
+
+    let _ = events.send(Event::Disconnected(from)).await;
+}
+
+async fn run(
+    stream: impl AsyncRead + AsyncWrite,
+    from: SocketAddr,
+    events: &mpsc::Sender<Event>,
+) -> io::Result<()> {
+    let (outbound_tx, outbound_rx) = mpsc::channel(MAX_BUFFERED_MESSAGES);
+
+    events
+        .send(Event::Connected(from, outbound_tx))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+    let (reader, writer) = tokio::io::split(stream);
+
+    match futures::future::select(
+        pin!(read_messages(reader, from, events)),
+        pin!(write_messages(writer, outbound_rx)),
+    )
+    .await
+    {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+async fn read_messages(
+    mut reader: impl AsyncRead + Unpin,
+    from: SocketAddr,
+    events: &mpsc::Sender<Event>,
+) -> io::Result<()> {
+    let mut decoder = StreamDecoder::default();
+    let mut buffer = vec![0u8; u16::MAX as usize];
+
+    loop {
+        let num_read = tokio::time::timeout(IDLE_TIMEOUT, reader.read(&mut buffer))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Stream has been idle"))??;
+
+        if num_read == 0 {
+            return Ok(());
+        }
+
+        decoder.push(&buffer[..num_read]);
+
+        while let Some(message) = decoder.next_message()? {
+            events
+                .send(Event::Message(from, message.to_vec()))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+    }
+}
+
+async fn write_messages(
+    mut writer: impl AsyncWrite + Unpin,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+) -> io::Result<()> {
+    let mut buffer = BytesMut::new();
+
+    // The sender is dropped once we no longer want to talk to this client.
+    while let Some(message) = outbound.recv().await {
+        buffer.clear();
+        encode_stream_message(&message, &mut buffer);
+
+        writer.write_all(&buffer).await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
@@ -3,8 +3,9 @@
 use Output::{CreateAllocation, FreeAllocation};
 use bytecodec::{DecodeExt, EncodeExt};
 use firezone_relay::{
-    AddressFamily, Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
-    ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Refresh, SOFTWARE, Server,
+    AddressFamily, Allocate, AllocationInfo, AllocationPort, Attribute, Binding, ChannelBind,
+    ChannelData, ChannelInfo, ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Quotas,
+    Refresh, SOFTWARE, Server,
 };
 use rand::rngs::mock::StepRng;
 use secrecy::SecretString;
@@ -12,13 +13,15 @@ use std::iter;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{
-    ErrorCode, MessageIntegrity, Nonce, Realm, Username, XorMappedAddress,
+    AlternateServer, ErrorCode, MessageIntegrity, Nonce, Realm, Username, XorMappedAddress,
 };
-use stun_codec::rfc5389::errors::Unauthorized;
+use stun_codec::rfc5389::errors::{TryAlternate, Unauthorized, UnknownAttribute};
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{ChannelNumber, Lifetime, XorPeerAddress, XorRelayAddress};
+use stun_codec::rfc5766::errors::{AllocationQuotaReached, InsufficientCapacity};
 use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, REFRESH};
-use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
+use stun_codec::rfc5780::attributes::{ChangeRequest, OtherAddress, ResponseOrigin};
+use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, Method, TransactionId};
 use test_strategy::proptest;
 use uuid::Uuid;
 
@@ -474,39 +477,860 @@ fn ping_pong_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
+    );
+
+    assert_eq!(
+        maybe_forward,
+        Some((
+            ClientSocket::new(source.into()),
+            client_to_peer_ping.channel()
+        ))
+    );
+}
+
+#[proptest]
+fn stream_client_relays_without_ebpf_channel_binding(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::channel_data())] client_to_peer_ping: ChannelData<'static>,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _guard = firezone_logging::test("debug");
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(stream_connected(source), []);
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    // The eBPF program only handles UDP, thus there must not be a channel binding for it.
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                client_to_peer_ping.channel(),
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            channel_bind_response(channel_bind_transaction_id),
+        )],
+    );
+
+    let maybe_forward = server.server.handle_client_input(
+        client_to_peer_ping.as_msg(),
+        ClientSocket::new(source.into()),
+        now,
+    );
+
+    assert_eq!(
+        maybe_forward,
+        Some((AllocationPort::new(49152), PeerSocket::new(peer.into())))
+    );
+
+    let maybe_forward = server.server.handle_peer_traffic(
+        peer_to_client_ping.as_slice(),
+        PeerSocket::new(peer.into()),
+        AllocationPort::new(49152),
+        now,
+    );
+
+    assert_eq!(
+        maybe_forward,
+        Some((
+            ClientSocket::new(source.into()),
+            client_to_peer_ping.channel()
+        ))
+    );
+}
+
+#[proptest]
+fn stream_disconnect_frees_allocation(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _guard = firezone_logging::test("debug");
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(stream_connected(source), []);
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        stream_disconnected(source),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+
+    assert_eq!(server.server.num_allocations(), 0);
+
+    // Disconnecting again is a no-op.
+    server.assert_commands(stream_disconnected(source), []);
+}
+
+#[proptest]
+fn allows_rebind_channel_after_expiry(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_2_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    #[strategy(firezone_relay::proptest::channel_number())] channel: ChannelNumber,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    peer2: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _guard = firezone_logging::test("debug");
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap(); // Lifetime longer than channel expiry
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    assert_eq!(
+        server.server.poll_timeout(),
+        Some(now + lifetime.lifetime())
+    );
+
+    let now = now + Duration::from_secs(1);
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel, peer, 49152),
+            send_message(source, channel_bind_response(channel_bind_transaction_id)),
+        ],
+    );
+
+    let channel_expiry = now + Duration::from_secs(60 * 10);
+    let channel_rebind = channel_expiry + Duration::from_secs(60 * 5);
+
+    assert_eq!(server.server.poll_timeout(), Some(channel_expiry));
+
+    let now = now + Duration::from_secs(60 * 10 + 1);
+
+    server.assert_commands(
+        forward_time_to(now),
+        [delete_channel_binding(source, channel, peer, 49152)],
+    );
+    assert_eq!(server.server.poll_timeout(), Some(channel_rebind));
+
+    let now = now + Duration::from_secs(60 * 5 + 1);
+
+    server.server.handle_timeout(now);
+
+    let now = now + Duration::from_secs(1);
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_2_transaction_id,
+                channel,
+                XorPeerAddress::new(peer2.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel, peer2, 49152),
+            send_message(source, channel_bind_response(channel_bind_2_transaction_id)),
+        ],
+    );
+
+    assert_eq!(
+        server.server.poll_timeout(),
+        Some(now + Duration::from_secs(60 * 10)) // For channel expiry
+    );
+}
+
+#[proptest]
+fn allocation_quota_rejects_further_allocations_of_same_user(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id_1: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id_2: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let other_source = SocketAddrV4::new(*source.ip(), source.port().wrapping_add(1));
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(Quotas {
+            max_allocations_per_user: Some(1),
+            ..Default::default()
+        });
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id_1,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    transaction_id_1,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            other_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id_2,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            other_source,
+            error_response(ALLOCATE, transaction_id_2, AllocationQuotaReached),
+        )],
+    );
+}
+
+#[proptest]
+fn channel_quota_rejects_further_channels_on_allocation(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_1_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_2_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let peer2 = SocketAddrV4::new(*peer.ip(), peer.port().wrapping_add(1));
+    let channel1 = ChannelNumber::new(ChannelNumber::MIN).unwrap();
+    let channel2 = ChannelNumber::new(ChannelNumber::MIN + 1).unwrap();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(Quotas {
+            max_channels_per_allocation: Some(1),
+            ..Default::default()
+        });
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_1_transaction_id,
+                channel1,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel1, peer, 49152),
+            send_message(source, channel_bind_response(channel_bind_1_transaction_id)),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_2_transaction_id,
+                channel2,
+                XorPeerAddress::new(peer2.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            error_response(
+                CHANNEL_BIND,
+                channel_bind_2_transaction_id,
+                AllocationQuotaReached,
+            ),
+        )],
+    );
+}
+
+#[proptest]
+fn byte_rate_quota_drops_excess_traffic(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let channel = ChannelNumber::new(ChannelNumber::MIN).unwrap();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(Quotas {
+            max_bytes_per_second_per_allocation: Some(32),
+            ..Default::default()
+        });
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel, peer, 49152),
+            send_message(source, channel_bind_response(channel_bind_transaction_id)),
+        ],
+    );
+
+    let relay_ping = |server: &mut TestServer, now: Instant| {
+        server.server.handle_peer_traffic(
+            peer_to_client_ping.as_slice(),
+            PeerSocket::new(peer.into()),
+            AllocationPort::new(49152),
+            now,
+        )
+    };
+
+    assert_eq!(
+        relay_ping(&mut server, now),
+        Some((ClientSocket::new(source.into()), channel))
+    );
+    assert_eq!(relay_ping(&mut server, now), None);
+    assert_eq!(
+        relay_ping(&mut server, now + Duration::from_secs(1)),
+        Some((ClientSocket::new(source.into()), channel))
+    );
+}
+
+#[proptest]
+fn byte_rate_quota_suspends_ebpf_offloading_until_recovered(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let channel = ChannelNumber::new(ChannelNumber::MIN).unwrap();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(Quotas {
+            max_bytes_per_second_per_allocation: Some(32),
+            ..Default::default()
+        });
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel, peer, 49152),
+            send_message(source, channel_bind_response(channel_bind_transaction_id)),
+        ],
+    );
+
+    server.assert_commands(
+        ebpf_relayed_bytes(49152, channel, 64, now),
+        [delete_channel_binding(source, channel, peer, 49152)],
+    );
+
+    // The quota has not fully recovered yet.
+    server.assert_commands(
+        ebpf_relayed_bytes(49152, channel, 0, now + Duration::from_millis(500)),
+        [],
+    );
+
+    server.assert_commands(
+        ebpf_relayed_bytes(49152, channel, 0, now + Duration::from_secs(2)),
+        [create_channel_binding(source, channel, peer, 49152)],
+    );
+}
+
+#[proptest]
+fn forcibly_freeing_allocation_deletes_it(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    #[strategy(firezone_relay::proptest::channel_number())] channel: ChannelNumber,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                channel,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_channel_binding(source, channel, peer, 49152),
+            send_message(source, channel_bind_response(channel_bind_transaction_id)),
+        ],
+    );
+
+    server.assert_commands(ebpf_relayed_bytes(49152, channel, 100, now), []);
+    server.assert_commands(ebpf_relayed_bytes(49152, channel, 150, now), []);
+
+    assert_eq!(
+        server.server.allocations(now),
+        vec![AllocationInfo {
+            port: AllocationPort::new(49152),
+            client: ClientSocket::new(source.into()),
+            relay_addresses: vec![public_relay_addr.into()],
+            expires_in: lifetime.lifetime(),
+            num_relayed_bytes: 150,
+            channels: vec![ChannelInfo {
+                number: channel,
+                peer: PeerSocket::new(peer.into()),
+                bound: true,
+                expires_in: Duration::from_secs(60 * 10),
+                num_ebpf_relayed_bytes: 150,
+            }],
+        }]
+    );
+
+    assert!(server.server.free_allocation(AllocationPort::new(49152)));
+    assert_eq!(
+        server.server.next_command(),
+        Some(Command::FreeAllocation {
+            port: AllocationPort::new(49152),
+            family: AddressFamily::V4
+        })
+    );
+    assert_eq!(server.server.allocations(now), vec![]);
+    assert!(!server.server.free_allocation(AllocationPort::new(49152)));
+}
+
+#[proptest]
+fn draining_rejects_new_allocations_with_try_alternate(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    alternate_server: SocketAddrV4,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_alternate_server(alternate_server);
+    let secret = server.auth_secret().to_owned();
+
+    server.server.drain();
+
+    // No new nonce for clients that won't get an allocation anyway.
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_unauthenticated_udp(transaction_id, Some(lifetime.clone())),
+            now,
+        ),
+        [send_message(
+            source,
+            try_alternate_response(ALLOCATE, transaction_id, alternate_server),
+        )],
     );
 
-    assert_eq!(
-        maybe_forward,
-        Some((
-            ClientSocket::new(source.into()),
-            client_to_peer_ping.channel()
-        ))
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            try_alternate_response(ALLOCATE, transaction_id, alternate_server),
+        )],
     );
 }
 
 #[proptest]
-fn allows_rebind_channel_after_expiry(
+fn draining_without_alternate_server_rejects_new_allocations_with_insufficient_capacity(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+
+    server.server.drain();
+
+    // No new nonce for clients that won't get an allocation anyway.
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_unauthenticated_udp(transaction_id, Some(lifetime.clone())),
+            now,
+        ),
+        [send_message(
+            source,
+            error_response(ALLOCATE, transaction_id, InsufficientCapacity),
+        )],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            error_response(ALLOCATE, transaction_id, InsufficientCapacity),
+        )],
+    );
+}
+
+#[proptest]
+fn draining_keeps_existing_allocation_until_it_expires(
     #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] refresh_transaction_id: TransactionId,
     #[strategy(firezone_relay::proptest::transaction_id())]
-    channel_bind_transaction_id: TransactionId,
-    #[strategy(firezone_relay::proptest::transaction_id())]
-    channel_bind_2_transaction_id: TransactionId,
+    second_refresh_transaction_id: TransactionId,
     #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
-    #[strategy(firezone_relay::proptest::channel_number())] channel: ChannelNumber,
     source: SocketAddrV4,
-    peer: SocketAddrV4,
-    peer2: SocketAddrV4,
     public_relay_addr: Ipv4Addr,
     #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
 ) {
     let now = Instant::now();
-
-    let _guard = firezone_logging::test("debug");
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 10)).unwrap();
 
     let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
     let secret = server.auth_secret().to_owned();
-    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap(); // Lifetime longer than channel expiry
 
     server.assert_commands(
         from_client(
@@ -536,75 +1360,156 @@ fn allows_rebind_channel_after_expiry(
         ],
     );
 
-    assert_eq!(
-        server.server.poll_timeout(),
-        Some(now + lifetime.lifetime())
+    server.server.drain();
+
+    // Refreshing succeeds but doesn't extend the allocation.
+    let now = now + Duration::from_secs(60);
+    server.assert_commands(
+        from_client(
+            source,
+            Refresh::new(
+                refresh_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            refresh_response(
+                refresh_transaction_id,
+                Lifetime::new(Duration::from_secs(60 * 9)).unwrap(),
+            ),
+        )],
     );
 
-    let now = now + Duration::from_secs(1);
+    assert_eq!(server.server.num_allocations(), 1);
 
+    // Close to the expiry, we still grant a minimum lifetime so clients don't refresh more and more often.
     server.assert_commands(
         from_client(
             source,
-            ChannelBind::new(
-                channel_bind_transaction_id,
-                channel,
-                XorPeerAddress::new(peer.into()),
+            Refresh::new(
+                second_refresh_transaction_id,
+                Some(lifetime.clone()),
                 valid_username(&username_salt),
                 &secret,
                 nonce,
             )
             .unwrap(),
-            now,
+            now + Duration::from_secs(60 * 9 - 10),
         ),
-        [
-            create_channel_binding(source, channel, peer, 49152),
-            send_message(source, channel_bind_response(channel_bind_transaction_id)),
-        ],
+        [send_message(
+            source,
+            refresh_response(
+                second_refresh_transaction_id,
+                Lifetime::new(Duration::from_secs(60)).unwrap(),
+            ),
+        )],
     );
 
-    let channel_expiry = now + Duration::from_secs(60 * 10);
-    let channel_rebind = channel_expiry + Duration::from_secs(60 * 5);
+    server.assert_commands(
+        forward_time_to(now + Duration::from_secs(60 * 9)),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
 
-    assert_eq!(server.server.poll_timeout(), Some(channel_expiry));
+    assert_eq!(server.server.num_allocations(), 0);
+}
 
-    let now = now + Duration::from_secs(60 * 10 + 1);
+#[proptest]
+fn binding_response_advertises_other_address_if_nat_discovery_is_enabled(
+    #[strategy(firezone_relay::proptest::binding())] request: Binding,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+) {
+    let mut server = TestServer::new(public_relay_addr).with_alternate_port(3479);
+
+    let transaction_id = request.transaction_id();
+
+    let mut response = binding_response(transaction_id, source);
+    response.add_attribute(OtherAddress::new(SocketAddr::from((
+        public_relay_addr,
+        3479,
+    ))));
+    response.add_attribute(ResponseOrigin::new(SocketAddr::from((
+        public_relay_addr,
+        3478,
+    ))));
 
     server.assert_commands(
-        forward_time_to(now),
-        [delete_channel_binding(source, channel, peer, 49152)],
+        from_client(source, request, Instant::now()),
+        [send_message(source, response)],
     );
-    assert_eq!(server.server.poll_timeout(), Some(channel_rebind));
+}
 
-    let now = now + Duration::from_secs(60 * 5 + 1);
+#[proptest]
+fn change_port_request_is_answered_from_other_port(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+) {
+    let mut server = TestServer::new(public_relay_addr).with_alternate_port(3479);
+
+    let mut response = binding_response(transaction_id, source);
+    response.add_attribute(OtherAddress::new(SocketAddr::from((
+        public_relay_addr,
+        3479,
+    ))));
+    response.add_attribute(ResponseOrigin::new(SocketAddr::from((
+        public_relay_addr,
+        3479,
+    ))));
 
-    server.server.handle_timeout(now);
+    server.assert_commands(
+        from_client(
+            source,
+            Binding::new(transaction_id).with_change_request(ChangeRequest::new(false, true)),
+            Instant::now(),
+        ),
+        [send_message_from(source, 3479, response)],
+    );
 
-    let now = now + Duration::from_secs(1);
+    // Requests on the alternate port are answered from our primary port.
+    let mut response = binding_response(transaction_id, source);
+    response.add_attribute(OtherAddress::new(SocketAddr::from((
+        public_relay_addr,
+        3478,
+    ))));
+    response.add_attribute(ResponseOrigin::new(SocketAddr::from((
+        public_relay_addr,
+        3478,
+    ))));
 
     server.assert_commands(
-        from_client(
+        on_alternate_port(
             source,
-            ChannelBind::new(
-                channel_bind_2_transaction_id,
-                channel,
-                XorPeerAddress::new(peer2.into()),
-                valid_username(&username_salt),
-                &secret,
-                nonce,
-            )
-            .unwrap(),
-            now,
+            Binding::new(transaction_id).with_change_request(ChangeRequest::new(false, true)),
         ),
-        [
-            create_channel_binding(source, channel, peer2, 49152),
-            send_message(source, channel_bind_response(channel_bind_2_transaction_id)),
-        ],
+        [send_message(source, response)],
     );
+}
 
-    assert_eq!(
-        server.server.poll_timeout(),
-        Some(now + Duration::from_secs(60 * 10)) // For channel expiry
+#[proptest]
+fn change_ip_request_is_rejected(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+) {
+    let mut server = TestServer::new(public_relay_addr).with_alternate_port(3479);
+
+    server.assert_commands(
+        from_client(
+            source,
+            Binding::new(transaction_id).with_change_request(ChangeRequest::new(true, true)),
+            Instant::now(),
+        ),
+        [send_message(
+            source,
+            error_response(BINDING, transaction_id, UnknownAttribute),
+        )],
     );
 }
 
@@ -710,6 +1615,7 @@ fn ping_pong_ip6_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
     );
 
     assert_eq!(
@@ -735,6 +1641,24 @@ impl TestServer {
         self
     }
 
+    fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.server = self.server.with_quotas(quotas);
+
+        self
+    }
+
+    fn with_alternate_port(mut self, port: u16) -> Self {
+        self.server = self.server.with_alternate_port(port);
+
+        self
+    }
+
+    fn with_alternate_server(mut self, server: impl Into<SocketAddr>) -> Self {
+        self.server = self.server.with_alternate_server(server.into());
+
+        self
+    }
+
     fn auth_secret(&self) -> &SecretString {
         self.server.auth_secret()
     }
@@ -744,9 +1668,22 @@ impl TestServer {
             Input::Client(sender, message, now) => {
                 self.server.handle_client_message(message, sender, now);
             }
+            Input::AlternatePort(sender, message) => {
+                self.server.handle_alternate_port_message(message, sender);
+            }
             Input::Time(now) => {
                 self.server.handle_timeout(now);
             }
+            Input::StreamConnected(client) => {
+                self.server.handle_stream_connected(client);
+            }
+            Input::StreamDisconnected(client) => {
+                self.server.handle_stream_disconnected(client);
+            }
+            Input::EbpfRelayedBytes(port, channel, bytes, now) => {
+                self.server
+                    .handle_ebpf_relayed_bytes([(port, channel, bytes)], now);
+            }
         }
 
         for expected_output in output {
@@ -755,6 +1692,9 @@ impl TestServer {
                     Output::SendMessage((recipient, msg)) => {
                         format!("to send message {msg:?} to {recipient}")
                     }
+                    Output::SendMessageFrom((recipient, port, msg)) => {
+                        format!("to send message {msg:?} to {recipient} from port {port}")
+                    }
                     CreateAllocation(port, family) => {
                         format!("to create allocation on port {port} for address family {family}")
                     }
@@ -801,6 +1741,28 @@ impl TestServer {
 
                     assert_eq!(recipient, to);
                 }
+                (
+                    Output::SendMessageFrom((to, expected_port, message)),
+                    Command::SendMessageFrom {
+                        payload,
+                        recipient,
+                        port,
+                    },
+                ) => {
+                    let expected_bytes = MessageEncoder::new()
+                        .encode_into_bytes(message.clone())
+                        .unwrap();
+
+                    if expected_bytes != payload {
+                        let expected_message = format!("{message:?}");
+                        let actual_message = format!("{:?}", parse_message(&payload));
+
+                        difference::assert_diff!(&expected_message, &actual_message, "\n", 0);
+                    }
+
+                    assert_eq!(recipient, to);
+                    assert_eq!(port, expected_port);
+                }
                 (
                     CreateAllocation(expected_port, expected_family),
                     Command::CreateAllocation {
@@ -943,6 +1905,30 @@ fn channel_bind_response(transaction_id: TransactionId) -> Message<Attribute> {
     message
 }
 
+fn error_response(
+    method: Method,
+    transaction_id: TransactionId,
+    error_code: impl Into<ErrorCode>,
+) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, method, transaction_id);
+    message.add_attribute(SOFTWARE.clone());
+    message.add_attribute(error_code.into());
+
+    message
+}
+
+fn try_alternate_response(
+    method: Method,
+    transaction_id: TransactionId,
+    alternate_server: impl Into<SocketAddr>,
+) -> Message<Attribute> {
+    let mut message = error_response(method, transaction_id, TryAlternate);
+    message.add_attribute(AlternateServer::new(alternate_server.into()));
+
+    message
+}
+
 fn parse_message(message: &[u8]) -> Message<Attribute> {
     MessageDecoder::new()
         .decode_from_bytes(message)
@@ -952,7 +1938,11 @@ fn parse_message(message: &[u8]) -> Message<Attribute> {
 
 enum Input<'a> {
     Client(ClientSocket, ClientMessage<'a>, Instant),
+    AlternatePort(ClientSocket, ClientMessage<'a>),
     Time(Instant),
+    StreamConnected(ClientSocket),
+    StreamDisconnected(ClientSocket),
+    EbpfRelayedBytes(AllocationPort, ChannelNumber, u64, Instant),
 }
 
 fn from_client<'a>(
@@ -963,13 +1953,38 @@ fn from_client<'a>(
     Input::Client(ClientSocket::new(from.into()), message.into(), now)
 }
 
+fn on_alternate_port<'a>(
+    from: impl Into<SocketAddr>,
+    message: impl Into<ClientMessage<'a>>,
+) -> Input<'a> {
+    Input::AlternatePort(ClientSocket::new(from.into()), message.into())
+}
+
 fn forward_time_to<'a>(when: Instant) -> Input<'a> {
     Input::Time(when)
 }
 
+fn stream_connected<'a>(from: impl Into<SocketAddr>) -> Input<'a> {
+    Input::StreamConnected(ClientSocket::new(from.into()))
+}
+
+fn stream_disconnected<'a>(from: impl Into<SocketAddr>) -> Input<'a> {
+    Input::StreamDisconnected(ClientSocket::new(from.into()))
+}
+
+fn ebpf_relayed_bytes<'a>(
+    port: u16,
+    channel: ChannelNumber,
+    bytes: u64,
+    now: Instant,
+) -> Input<'a> {
+    Input::EbpfRelayedBytes(AllocationPort::new(port), channel, bytes, now)
+}
+
 #[derive(Debug)]
 enum Output {
     SendMessage((ClientSocket, Message<Attribute>)),
+    SendMessageFrom((ClientSocket, u16, Message<Attribute>)),
     CreateAllocation(AllocationPort, AddressFamily),
     FreeAllocation(AllocationPort, AddressFamily),
     CreateChannelBinding(ClientSocket, ChannelNumber, PeerSocket, AllocationPort),
@@ -988,6 +2003,14 @@ fn send_message(source: impl Into<SocketAddr>, message: Message<Attribute>) -> O
     Output::SendMessage((ClientSocket::new(source.into()), message))
 }
 
+fn send_message_from(
+    source: impl Into<SocketAddr>,
+    port: u16,
+    message: Message<Attribute>,
+) -> Output {
+    Output::SendMessageFrom((ClientSocket::new(source.into()), port, message))
+}
+
 fn create_channel_binding(
     client: impl Into<SocketAddr>,
     channel: ChannelNumber,
@@ -43,6 +43,10 @@ pub mod attr {
         KeyValue::new("network.transport", "udp")
     }
 
+    pub fn network_transport_tcp() -> KeyValue {
+        KeyValue::new("network.transport", "tcp")
+    }
+
     pub fn network_type_for_packet(p: &IpPacket) -> KeyValue {
         match p.version() {
             IpVersion::V4 => network_type_ipv4(),
@@ -102,7 +106,7 @@ pub mod attr {
 }
 
 pub mod metrics {
-    use opentelemetry::metrics::Counter;
+    use opentelemetry::metrics::{Counter, Gauge};
 
     pub fn network_packet_dropped() -> Counter<u64> {
         opentelemetry::global::meter("connlib")
@@ -111,6 +115,30 @@ pub mod metrics {
             .with_unit("{packet}")
             .build()
     }
+
+    pub fn network_nat_sessions() -> Gauge<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_gauge("network.nat.sessions")
+            .with_description("Number of active NAT sessions on the gateway")
+            .with_unit("{session}")
+            .build()
+    }
+
+    pub fn dns_cache_hits() -> Counter<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_counter("dns.cache.hits")
+            .with_description("Count of DNS queries answered from the cache")
+            .with_unit("{query}")
+            .build()
+    }
+
+    pub fn dns_cache_misses() -> Counter<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_counter("dns.cache.misses")
+            .with_description("Count of DNS queries that could not be answered from the cache")
+            .with_unit("{query}")
+            .build()
+    }
 }
 
 pub fn default_resource_with<const N: usize>(attributes: [KeyValue; N]) -> Resource {